@@ -0,0 +1 @@
+ALTER TABLE pending_identities ADD COLUMN transaction_nonce BIGINT;
@@ -0,0 +1,8 @@
+-- Block ranges whose events are fully present in `logs`, including ranges
+-- without any events.
+CREATE TABLE cached_log_ranges
+(
+    from_block BIGINT NOT NULL,
+    to_block   BIGINT NOT NULL
+);
+CREATE INDEX cached_log_ranges_from_block ON cached_log_ranges (from_block);
@@ -0,0 +1,2 @@
+-- Cached `MemberRemoved` events, which reset `leaf` to the initial value.
+ALTER TABLE logs ADD COLUMN removed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+-- The instance allowed to commit identities of a group. A lease expires unless
+-- its holder renews it, so a crashed instance is taken over.
+CREATE TABLE writer_leases
+(
+    group_id   BIGINT NOT NULL PRIMARY KEY,
+    holder     TEXT   NOT NULL,
+    expires_at BIGINT NOT NULL
+);
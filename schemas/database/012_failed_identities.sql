@@ -0,0 +1,2 @@
+-- Why the committer gave up on an identity, which is then no longer submitted.
+ALTER TABLE pending_identities ADD COLUMN failure TEXT;
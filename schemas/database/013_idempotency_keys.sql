@@ -0,0 +1,12 @@
+-- Responses to inserts made with an `Idempotency-Key` header, returned again
+-- when a request with the same key is repeated until the key expires. Keys are
+-- scoped to the commitment they were used for, so that clients choosing the
+-- same key neither collide nor see each other's responses.
+CREATE TABLE idempotency_keys
+(
+    key        TEXT   NOT NULL,
+    commitment BYTEA  NOT NULL,
+    response   TEXT   NOT NULL,
+    expires_at BIGINT NOT NULL,
+    PRIMARY KEY (key, commitment)
+);
@@ -0,0 +1,9 @@
+-- The block and transaction each leaf was inserted in, for reconciling the
+-- tree with the chain after reorgs.
+CREATE TABLE leaf_origins
+(
+    leaf_index       BIGINT NOT NULL PRIMARY KEY,
+    commitment       BYTEA  NOT NULL,
+    block_number     BIGINT NOT NULL,
+    transaction_hash TEXT   NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Cache the transaction of each log, so leaf origins can be recorded when the
+-- tree is rebuilt from the cache. Logs cached before this have none.
+ALTER TABLE logs ADD COLUMN transaction_hash TEXT;
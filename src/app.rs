@@ -1,41 +1,387 @@
 use crate::{
+    commitment_filter::CommitmentFilter,
     contracts,
     contracts::{
         batching::Contract as BatchingContract, legacy::Contract as LegacyContract,
-        IdentityManager, SharedIdentityManager,
+        root_validator::RootValidator, InvalidRoot, SharedIdentityManager,
     },
-    database::{self, Database},
-    ethereum::{self, Ethereum},
-    ethereum_subscriber::{Error as SubscriberError, EthereumSubscriber},
-    identity_committer::IdentityCommitter,
+    database::{self, ConfirmedIdentityEvent, Database, Error as DatabaseError},
+    ethereum::{self, Ethereum, RpcTimeout, TxSigner},
+    ethereum_subscriber::{
+        report_root_mismatch, Error as SubscriberError, EthereumSubscriber, SyncProgress,
+    },
+    identity_committer::{EnqueueError, IdentityCommitter, QueueLimit},
     identity_tree::{Hash, SharedTreeState, TreeState},
+    load_shedder::{LatencyBudget, LoadShedder},
+    proof_cache::ProofCache,
+    proof_limiter::ProofLimiter,
     prover,
     server::{Error as ServerError, ToResponseCode},
     timed_rw_lock::TimedRwLock,
+    utils::{serialize_redacted_option, spawn_or_abort},
 };
 use anyhow::{anyhow, Result as AnyhowResult};
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use cli_batteries::await_shutdown;
-use ethers::types::U256;
-use futures::TryFutureExt;
+use ethers::{
+    signers::Signer,
+    types::{Address, Signature, H256, U256},
+};
+use futures::{TryFutureExt, TryStreamExt};
 use hyper::StatusCode;
-use semaphore::{poseidon_tree::Proof, Field};
-use serde::{ser::SerializeStruct, Serialize, Serializer};
-use std::{sync::Arc, time::Duration};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use semaphore::{
+    merkle_tree::{self, Branch},
+    poseidon_tree::Proof,
+    Field,
+};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use subtle::ConstantTimeEq;
 use tokio::{select, try_join};
-use tracing::{error, info, instrument, warn};
+use tracing::{error, info, info_span, instrument, warn, Instrument, Span};
 
+#[derive(Clone)]
 pub enum InclusionProofResponse {
-    Proof { root: Field, proof: Proof },
-    Pending,
+    Proof {
+        /// Group of the tree the proof is in, echoed from the request. Absent
+        /// in responses from older servers.
+        group_id:    Option<usize>,
+        root:        Field,
+        proof:       Proof,
+        /// Index of the leaf the proof is for. Added after the initial
+        /// release, so absent in responses from older servers.
+        index:       Option<usize>,
+        /// Signature over [`inclusion_proof_message`], sent in the
+        /// `X-Signature` header when response signing is enabled.
+        signature:   Option<Signature>,
+        /// Whether `root` is not yet known to be valid on chain. Only set when
+        /// the client allowed unconfirmed proofs.
+        unconfirmed: bool,
+    },
+    Pending {
+        /// Group the identity was queued in, echoed from the request. Only
+        /// included in detailed bodies.
+        group_id:    Option<usize>,
+        /// Suggested delay before polling again, sent in the `Retry-After`
+        /// header.
+        retry_after: Option<Duration>,
+        /// Whether the delay is also included in the body. Otherwise the body
+        /// is the bare `"pending"` string older clients expect.
+        detailed:    bool,
+    },
 }
 
 impl ToResponseCode for InclusionProofResponse {
     fn to_response_code(&self) -> StatusCode {
         match self {
             Self::Proof { .. } => StatusCode::OK,
-            Self::Pending => StatusCode::ACCEPTED,
+            Self::Pending { .. } => StatusCode::ACCEPTED,
+        }
+    }
+
+    fn signature(&self) -> Option<Signature> {
+        match self {
+            Self::Proof { signature, .. } => *signature,
+            Self::Pending { .. } => None,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Proof { .. } => None,
+            Self::Pending { retry_after, .. } => *retry_after,
+        }
+    }
+}
+
+/// An inclusion proof in the flat form some verifier libraries expect: the
+/// sibling hashes from the leaf up, and for each a path index of `0` if the
+/// path goes left, i.e. the sibling is on the right, and `1` otherwise. The
+/// path indices spell out the leaf index, least significant bit first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FlatInclusionProof {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id:     Option<usize>,
+    #[schemars(with = "String")]
+    pub root:         Field,
+    #[schemars(with = "Vec<String>")]
+    pub siblings:     Vec<Field>,
+    pub path_indices: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index:        Option<usize>,
+    /// Whether `root` is not yet known to be valid on chain.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unconfirmed:  bool,
+}
+
+impl FlatInclusionProof {
+    #[must_use]
+    pub fn new(
+        group_id: Option<usize>,
+        root: Field,
+        proof: &Proof,
+        index: Option<usize>,
+        unconfirmed: bool,
+    ) -> Self {
+        let (siblings, path_indices) = proof
+            .0
+            .iter()
+            .map(|branch| match branch {
+                Branch::Left(hash) => (*hash, 0),
+                Branch::Right(hash) => (*hash, 1),
+            })
+            .unzip();
+        Self {
+            group_id,
+            root,
+            siblings,
+            path_indices,
+            index,
+            unconfirmed,
+        }
+    }
+}
+
+/// The canonical serialization of an inclusion proof that response signatures
+/// are made over: the group id and leaf index as big-endian 64-bit integers, a
+/// `1` byte if the proof is unconfirmed or `0` if not, the big-endian root,
+/// followed by each branch of the proof as a `0` (left) or `1` (right) byte and
+/// the big-endian sibling hash.
+#[must_use]
+pub fn inclusion_proof_message(
+    group_id: usize,
+    index: usize,
+    root: &Field,
+    proof: &Proof,
+    unconfirmed: bool,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(17 + 32 + 33 * proof.0.len());
+    message.extend_from_slice(&(group_id as u64).to_be_bytes());
+    message.extend_from_slice(&(index as u64).to_be_bytes());
+    message.push(u8::from(unconfirmed));
+    message.extend_from_slice(&root.to_be_bytes::<32>());
+    for branch in &proof.0 {
+        let (side, hash) = match branch {
+            Branch::Left(hash) => (0, hash),
+            Branch::Right(hash) => (1, hash),
+        };
+        message.push(side);
+        message.extend_from_slice(&hash.to_be_bytes::<32>());
+    }
+    message
+}
+
+/// Encodes a proof in the compact binary format served to clients accepting
+/// `application/octet-stream`: the number of branches as one byte, a flags
+/// byte with bit 0 set for unconfirmed proofs, the big-endian root, a bitmap
+/// with bit `i` (least significant first) set if branch `i` is a right branch,
+/// and the big-endian sibling hash of each branch. The bitmap spells out the
+/// leaf index.
+///
+/// # Panics
+///
+/// Panics if the proof has more than 255 branches.
+#[must_use]
+pub fn encode_binary_proof(root: &Field, proof: &Proof, unconfirmed: bool) -> Vec<u8> {
+    let depth = u8::try_from(proof.0.len()).expect("Proof depth exceeds 255");
+    let bitmap_len = (proof.0.len() + 7) / 8;
+    let mut bytes = Vec::with_capacity(2 + 32 + bitmap_len + 32 * proof.0.len());
+    bytes.push(depth);
+    bytes.push(u8::from(unconfirmed));
+    bytes.extend_from_slice(&root.to_be_bytes::<32>());
+    let mut bitmap = vec![0_u8; bitmap_len];
+    for (level, branch) in proof.0.iter().enumerate() {
+        if matches!(branch, Branch::Right(_)) {
+            bitmap[level / 8] |= 1 << (level % 8);
+        }
+    }
+    bytes.extend_from_slice(&bitmap);
+    for branch in &proof.0 {
+        let (Branch::Left(hash) | Branch::Right(hash)) = branch;
+        bytes.extend_from_slice(&hash.to_be_bytes::<32>());
+    }
+    bytes
+}
+
+/// Decodes a proof encoded by [`encode_binary_proof`]. Returns `None` if
+/// `bytes` is not a valid encoding.
+#[must_use]
+pub fn decode_binary_proof(bytes: &[u8]) -> Option<InclusionProofResponse> {
+    let (&depth_byte, rest) = bytes.split_first()?;
+    let (&flags, rest) = rest.split_first()?;
+    let depth = usize::from(depth_byte);
+    let bitmap_len = (depth + 7) / 8;
+    if flags > 1 || rest.len() != 32 + bitmap_len + 32 * depth {
+        return None;
+    }
+    let (root, rest) = rest.split_at(32);
+    let (bitmap, hashes) = rest.split_at(bitmap_len);
+    let branches = hashes
+        .chunks_exact(32)
+        .enumerate()
+        .map(|(level, hash)| {
+            let hash = Field::try_from_be_slice(hash)?;
+            Some(if bitmap[level / 8] & (1 << (level % 8)) == 0 {
+                Branch::Left(hash)
+            } else {
+                Branch::Right(hash)
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // Indices of trees deeper than a `usize` can not be represented.
+    let index = (u32::from(depth_byte) <= usize::BITS).then(|| {
+        branches.iter().rev().fold(0, |index: usize, branch| {
+            (index << 1) | usize::from(matches!(branch, Branch::Right(_)))
+        })
+    });
+    Some(InclusionProofResponse::Proof {
+        group_id: None,
+        root: Field::try_from_be_slice(root)?,
+        proof: merkle_tree::Proof(branches),
+        index,
+        signature: None,
+        unconfirmed: flags == 1,
+    })
+}
+
+/// Whether the branches of `proof`, from the leaf up, spell out the bits of
+/// `index` from the least significant one: a left branch for a `0` bit and a
+/// right branch for a `1` bit.
+fn proof_path_matches_index(proof: &Proof, index: usize) -> bool {
+    let mut remaining = index;
+    for branch in &proof.0 {
+        if matches!(branch, Branch::Right(_)) != (remaining & 1 == 1) {
+            return false;
         }
+        remaining >>= 1;
+    }
+    remaining == 0
+}
+
+/// Checks that `proof` proves `commitment` at `index` against `root`.
+///
+/// # Panics
+///
+/// Panics if it does not, as the tree is then corrupted.
+fn check_proof_locally(commitment: &Hash, index: usize, root: &Field, proof: &Proof) {
+    if proof.root(*commitment) != *root {
+        error!(?commitment, ?index, ?root, "Proof does not verify locally.");
+        panic!("Proof does not verify locally.");
+    }
+    if !proof_path_matches_index(proof, index) {
+        let path = proof
+            .0
+            .iter()
+            .map(|branch| {
+                if matches!(branch, Branch::Left(_)) {
+                    'L'
+                } else {
+                    'R'
+                }
+            })
+            .collect::<String>();
+        error!(?commitment, ?index, %path, "Proof path does not match the leaf index.");
+        panic!("Proof path does not match the leaf index.");
+    }
+}
+
+/// The wire format of [`InclusionProofResponse`].
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum InclusionProofRepr {
+    Proof {
+        /// Group of the tree the proof is in.
+        #[serde(default, rename = "groupId")]
+        group_id:    Option<usize>,
+        #[schemars(with = "String")]
+        root:        Field,
+        #[schemars(with = "Vec<BranchRepr>")]
+        proof:       Proof,
+        /// Index of the leaf the proof is for.
+        #[serde(default)]
+        index:       Option<usize>,
+        /// Whether the root is not yet known to be valid on chain.
+        #[serde(default)]
+        unconfirmed: bool,
+    },
+    Pending(PendingRepr),
+    DetailedPending {
+        /// Group the identity was queued in.
+        #[serde(default, rename = "groupId")]
+        group_id:    Option<usize>,
+        status:      PendingRepr,
+        /// Suggested delay before polling again, in seconds.
+        #[serde(rename = "retryAfter")]
+        retry_after: u64,
+    },
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+pub(crate) enum BranchRepr {
+    Left(String),
+    Right(String),
+}
+
+#[derive(Deserialize, JsonSchema)]
+enum PendingRepr {
+    #[serde(rename = "pending")]
+    Pending,
+}
+
+impl<'de> Deserialize<'de> for InclusionProofResponse {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match InclusionProofRepr::deserialize(deserializer)? {
+            InclusionProofRepr::Proof {
+                group_id,
+                root,
+                proof,
+                index,
+                unconfirmed,
+            } => Self::Proof {
+                group_id,
+                root,
+                proof,
+                index,
+                signature: None,
+                unconfirmed,
+            },
+            InclusionProofRepr::Pending(PendingRepr::Pending) => Self::Pending {
+                group_id:    None,
+                retry_after: None,
+                detailed:    false,
+            },
+            InclusionProofRepr::DetailedPending {
+                group_id,
+                retry_after,
+                ..
+            } => Self::Pending {
+                group_id,
+                retry_after: Some(Duration::from_secs(retry_after)),
+                detailed: true,
+            },
+        })
+    }
+}
+
+impl JsonSchema for InclusionProofResponse {
+    fn schema_name() -> String {
+        "InclusionProofResponse".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        InclusionProofRepr::json_schema(gen)
     }
 }
 
@@ -45,329 +391,2385 @@ impl Serialize for InclusionProofResponse {
         S: Serializer,
     {
         match self {
-            Self::Proof { root, proof } => {
-                let mut state = serializer.serialize_struct("InclusionProof", 2)?;
+            Self::Proof {
+                group_id,
+                root,
+                proof,
+                index,
+                unconfirmed,
+                ..
+            } => {
+                let len = 2
+                    + usize::from(group_id.is_some())
+                    + usize::from(index.is_some())
+                    + usize::from(*unconfirmed);
+                let mut state = serializer.serialize_struct("InclusionProof", len)?;
+                if let Some(group_id) = group_id {
+                    state.serialize_field("groupId", group_id)?;
+                }
                 state.serialize_field("root", root)?;
                 state.serialize_field("proof", proof)?;
+                if let Some(index) = index {
+                    state.serialize_field("index", index)?;
+                }
+                // Omitted when confirmed, so strict responses are unchanged.
+                if *unconfirmed {
+                    state.serialize_field("unconfirmed", unconfirmed)?;
+                }
+                state.end()
+            }
+            Self::Pending {
+                group_id,
+                retry_after: Some(retry_after),
+                detailed: true,
+            } => {
+                let len = 2 + usize::from(group_id.is_some());
+                let mut state = serializer.serialize_struct("Pending", len)?;
+                if let Some(group_id) = group_id {
+                    state.serialize_field("groupId", group_id)?;
+                }
+                state.serialize_field("status", "pending")?;
+                state.serialize_field("retryAfter", &retry_after.as_secs())?;
                 state.end()
             }
-            Self::Pending => serializer.serialize_str("pending"),
+            Self::Pending { .. } => serializer.serialize_str("pending"),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Parser)]
-#[group(skip)]
-pub struct Options {
-    #[clap(flatten)]
-    pub ethereum: ethereum::Options,
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingIdentity {
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+    pub created_at:          String,
+    pub prospective_index:   usize,
+}
 
-    #[clap(flatten)]
-    pub contracts: contracts::Options,
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct PendingIdentitiesResponse(pub Vec<PendingIdentity>);
 
-    #[clap(flatten)]
-    pub database: database::Options,
+impl ToResponseCode for PendingIdentitiesResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-    #[clap(flatten)]
-    pub prover: prover::Options,
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TreeLeaf {
+    pub index:      usize,
+    #[schemars(with = "String")]
+    pub commitment: Hash,
+}
 
-    /// Block number to start syncing from
-    #[clap(long, env, default_value = "0")]
-    pub starting_block: u64,
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct TreeLeavesResponse(pub Vec<TreeLeaf>);
 
-    /// Timeout for the tree lock (seconds).
-    #[clap(long, env, default_value = "120")]
-    pub lock_timeout: u64,
+impl ToResponseCode for TreeLeavesResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
 }
 
-pub struct App {
-    database:           Arc<Database>,
-    #[allow(dead_code)]
-    ethereum:           Ethereum,
-    identity_manager:   SharedIdentityManager,
-    identity_committer: Arc<IdentityCommitter>,
-    #[allow(dead_code)]
-    chain_subscriber:   EthereumSubscriber,
-    tree_state:         SharedTreeState,
-    snark_scalar_field: Hash,
+/// The block and transaction a leaf was inserted in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafOriginResponse {
+    pub index:            usize,
+    #[schemars(with = "String")]
+    pub commitment:       Hash,
+    pub block_number:     u64,
+    pub transaction_hash: String,
 }
 
-impl App {
-    /// # Errors
-    ///
-    /// Will return `Err` if the internal Ethereum handler errors or if the
-    /// `options.storage_file` is not accessible.
-    #[allow(clippy::missing_panics_doc)] // TODO
-    #[instrument(name = "App::new", level = "debug")]
-    pub async fn new(options: Options) -> AnyhowResult<Self> {
-        let refresh_rate = options.ethereum.refresh_rate;
-        let cache_recovery_step_size = options.ethereum.cache_recovery_step_size;
-
-        // Connect to Ethereum and Database
-        let (database, (ethereum, identity_manager)) = {
-            let db = Database::new(options.database);
-
-            let eth = Ethereum::new(options.ethereum).and_then(|ethereum| async move {
-                let identity_manager = if cfg!(feature = "batching-contract") {
-                    BatchingContract::new(options.contracts, ethereum.clone()).await?;
-                    panic!("The batching contract does not yet exist but was requested.");
-                } else {
-                    LegacyContract::new(options.contracts, ethereum.clone()).await?
-                };
-                Ok((ethereum, Arc::new(identity_manager)))
-            });
+impl ToResponseCode for LeafOriginResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-            // Connect to both in parallel
-            try_join!(db, eth)?
-        };
-        let database = Arc::new(database);
+/// The proof for one commitment of a multi-group proof request, or why there
+/// is none.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupInclusionProof {
+    pub group_id:            usize,
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof:               Option<InclusionProofResponse>,
+    /// Why no proof was returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:               Option<String>,
+}
 
-        // Poseidon tree depth is one more than the contract's tree depth
-        let tree_state = Arc::new(TimedRwLock::new(
-            Duration::from_secs(options.lock_timeout),
-            TreeState::new(
-                identity_manager.tree_depth() + 1,
-                identity_manager.initial_leaf_value(),
-            ),
-        ));
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct GroupInclusionProofsResponse(pub Vec<GroupInclusionProof>);
 
-        let identity_committer = Arc::new(IdentityCommitter::new(
-            database.clone(),
-            identity_manager.clone(),
-            tree_state.clone(),
-        ));
-        let chain_subscriber = EthereumSubscriber::new(
-            options.starting_block,
-            database.clone(),
-            identity_manager.clone(),
-            tree_state.clone(),
-            identity_committer.clone(),
-        );
+impl ToResponseCode for GroupInclusionProofsResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-        let snark_scalar_field = Hash::from_str_radix(
-            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
-            10,
-        )
-        .expect("This should just parse.");
+/// The response to a queued insert.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertIdentityResponse {
+    /// The index the identity is expected to be inserted at, assuming every
+    /// identity queued before it is committed in order.
+    pub identity_index: usize,
+}
 
-        // Sync with chain on start up
-        let mut app = Self {
-            database,
-            ethereum,
-            identity_manager,
-            identity_committer,
-            chain_subscriber,
-            tree_state,
-            snark_scalar_field,
-        };
+/// The outcome of validating one commitment of a batch insert.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct InsertIdentityResult {
+    pub valid: bool,
+    /// Why the commitment was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
 
-        select! {
-            _ = app.load_initial_events(options.lock_timeout, options.starting_block, cache_recovery_step_size) => {},
-            _ = await_shutdown() => return Err(anyhow!("Interrupted"))
+impl InsertIdentityResult {
+    const fn valid() -> Self {
+        Self {
+            valid: true,
+            error: None,
         }
+    }
 
-        // Basic sanity checks on the merkle tree
-        app.chain_subscriber.check_health().await;
+    fn rejected(error: &ServerError) -> Self {
+        Self {
+            valid: false,
+            error: Some(error.to_string()),
+        }
+    }
+}
 
-        // Listen to Ethereum events
-        app.chain_subscriber.start(refresh_rate).await;
+/// The response to a batch insert. The batch is only queued if every
+/// commitment in it is valid.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct InsertIdentitiesResponse {
+    pub queued:  bool,
+    /// One result per commitment, in request order.
+    pub results: Vec<InsertIdentityResult>,
+}
 
-        // Process to push new identities to Ethereum
-        app.identity_committer.start().await;
+/// The outcome of flushing the pending queue.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FlushResponse {
+    /// Number of identities committed by the flush.
+    pub flushed:            usize,
+    /// The transactions the identities were committed in.
+    #[schemars(with = "Vec<String>")]
+    pub transaction_hashes: Vec<H256>,
+}
 
-        Ok(app)
+impl ToResponseCode for FlushResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
     }
+}
 
-    async fn load_initial_events(
-        &mut self,
-        lock_timeout: u64,
-        starting_block: u64,
-        cache_recovery_step_size: usize,
-    ) -> AnyhowResult<()> {
-        let mut root_mismatch_count = 0;
-        loop {
-            if root_mismatch_count == 1 {
-                error!(cache_recovery_step_size, "Removing most recent cache.");
-                self.database
-                    .delete_most_recent_cached_events(cache_recovery_step_size as i64)
-                    .await?;
-            } else if root_mismatch_count == 2 {
-                error!("Wiping out the entire cache.");
-                self.database.wipe_cache().await?;
-            } else if root_mismatch_count >= 3 {
-                return Err(SubscriberError::RootMismatch.into());
-            }
+/// Maximum number of blocks events are fetched for by [`App::logs`].
+pub const MAX_LOG_BLOCK_RANGE: u64 = 10_000;
 
-            match self.chain_subscriber.process_initial_events().await {
-                Err(SubscriberError::RootMismatch) => {
-                    error!("Error when rebuilding tree from cache.");
-                    root_mismatch_count += 1;
-
-                    // Create a new empty MerkleTree
-                    self.tree_state = Arc::new(TimedRwLock::new(
-                        Duration::from_secs(lock_timeout),
-                        TreeState::new(
-                            self.identity_manager.tree_depth() + 1,
-                            self.identity_manager.initial_leaf_value(),
-                        ),
-                    ));
-
-                    // Retry
-                    self.chain_subscriber = EthereumSubscriber::new(
-                        starting_block,
-                        self.database.clone(),
-                        self.identity_manager.clone(),
-                        self.tree_state.clone(),
-                        self.identity_committer.clone(),
-                    );
-                }
-                Err(e) => return Err(e.into()),
-                Ok(_) => return Ok(()),
-            }
-        }
+/// A membership event of the group, as fetched from chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafEvent {
+    pub block:   u64,
+    #[schemars(with = "String")]
+    pub leaf:    Hash,
+    /// Index of `leaf` in the tree served by this sequencer, if it is in it.
+    pub index:   Option<usize>,
+    /// Whether the event removed `leaf` from the group rather than adding it.
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct LogsResponse(pub Vec<LeafEvent>);
+
+impl ToResponseCode for LogsResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
     }
+}
 
-    fn identity_is_reduced(&self, commitment: Hash) -> bool {
-        commitment.lt(&self.snark_scalar_field)
+/// Whether the root of the local tree is valid on chain, with the contract's
+/// current root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RootCheckResponse {
+    #[schemars(with = "String")]
+    pub local_root:    Field,
+    #[schemars(with = "String")]
+    pub on_chain_root: Field,
+    #[serde(rename = "match")]
+    pub matches:       bool,
+}
+
+impl ToResponseCode for RootCheckResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
     }
+}
 
-    /// Queues an insert into the merkle tree.
-    ///
-    /// # Errors
-    ///
-    /// Will return `Err` if identity is already queued, or in the tree, or the
-    /// queue malfunctions.
-    #[instrument(level = "debug", skip_all)]
-    pub async fn insert_identity(
-        &self,
-        group_id: usize,
-        commitment: Hash,
-    ) -> Result<(), ServerError> {
-        if U256::from(group_id) != self.identity_manager.group_id() {
-            return Err(ServerError::InvalidGroupId);
-        }
+/// The parameters and current state of a group's tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupInfoResponse {
+    pub group_id:   usize,
+    pub tree_depth: usize,
+    #[schemars(with = "String")]
+    pub root:       Field,
+    /// Number of leaves in use, including removed ones.
+    pub leaf_count: usize,
+}
 
-        if commitment == self.identity_manager.initial_leaf_value() {
-            warn!(?commitment, "Attempt to insert initial leaf.");
-            return Err(ServerError::InvalidCommitment);
-        }
+impl ToResponseCode for GroupInfoResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-        if !self.identity_is_reduced(commitment) {
-            warn!(
-                ?commitment,
-                "The provided commitment is not an element of the field."
-            );
-            return Err(ServerError::UnreducedCommitment);
-        }
+/// The operational state of the sequencer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResponse {
+    /// Whether broadcasting transactions is paused. Identities are still
+    /// accepted and queued while paused.
+    pub committer_paused: bool,
+    /// Whether the instance only serves proofs and refuses inserts.
+    pub read_only:        bool,
+    /// Address of the wallet signing transactions, or `null` on read-only
+    /// instances.
+    #[schemars(with = "Option<String>")]
+    pub wallet_address:   Option<Address>,
+    /// Balance of the wallet in wei, or `null` if it can not be looked up.
+    #[schemars(with = "Option<String>")]
+    pub wallet_balance:   Option<U256>,
+    /// Percentage of the tree's leaves in use, or `null` if the tree could
+    /// not be read.
+    pub capacity_used:    Option<f64>,
+}
 
-        // Note the ordering of duplicate checks: since we never want to lose data,
-        // pending identities are removed from the DB _after_ they are inserted into the
-        // tree. Therefore this order of checks guarantees we will not insert a
-        // duplicate.
-        if self
-            .database
-            .pending_identity_exists(group_id, &commitment)
-            .await?
-        {
-            warn!(?commitment, "Pending identity already exists.");
-            return Err(ServerError::DuplicateCommitment);
-        }
+impl ToResponseCode for StatusResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-        {
-            let tree = self.tree_state.read().await?;
-            if let Some(existing) = tree
-                .merkle_tree
-                .leaves()
-                .iter()
-                .position(|&x| x == commitment)
-            {
-                warn!(?existing, ?commitment, next = %tree.next_leaf, "Commitment already exists in tree.");
-                return Err(ServerError::DuplicateCommitment);
-            }
-        }
+/// Progress of the initial sync with the chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncResponse {
+    /// Whether all blocks up to `target_block` are processed.
+    pub synced:           bool,
+    /// Latest confirmed block at the start of the sync, or `null` before it
+    /// is known.
+    pub target_block:     Option<u64>,
+    /// Number of blocks processed so far.
+    pub blocks_processed: u64,
+    /// Number of blocks up to `target_block`.
+    pub blocks_total:     u64,
+}
 
-        self.database
-            .insert_pending_identity(group_id, &commitment)
-            .await?;
+impl ToResponseCode for SyncResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-        self.identity_committer.notify_queued().await;
+/// The tree compared against the contract, as checked at startup.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    /// Whether the root of the tree is a valid root of the contract, or `null`
+    /// if it could not be checked. The root of an empty tree is not checked.
+    pub root_matches:       Option<bool>,
+    /// Root of the tree built from events.
+    #[schemars(with = "String")]
+    pub expected_root:      Field,
+    /// Latest root of the contract, or `null` if it could not be read.
+    #[schemars(with = "Option<String>")]
+    pub onchain_root:       Option<Field>,
+    /// Number of leaves inserted into the tree.
+    pub next_leaf:          usize,
+    /// Number of leaves inserted into the contract's tree, or `null` if it
+    /// could not be read.
+    pub onchain_leaf_count: Option<usize>,
+    /// Leaves holding the initial value before the last inserted one.
+    pub skipped_leaves:     usize,
+    /// Leaves holding a commitment that is also at a lower index.
+    pub duplicate_leaves:   usize,
+    /// Fraction of the tree's leaves in use.
+    pub fill:               f64,
+    /// Whether the instance started although the tree does not match the
+    /// chain. Degraded instances refuse inserts and proofs, and neither sync
+    /// nor commit.
+    pub degraded:           bool,
+    /// Depth of a reorg too deep to handle automatically, which halted
+    /// syncing until an operator intervenes, or `null`.
+    pub halting_reorg:      Option<u64>,
+}
 
-        Ok(())
+impl HealthReport {
+    /// Whether the tree can not be served, as the contract rejected its root.
+    #[must_use]
+    pub const fn is_critical(&self) -> bool {
+        matches!(self.root_matches, Some(false))
     }
+}
 
-    /// # Errors
-    ///
-    /// Will return `Err` if the provided index is out of bounds.
-    #[instrument(level = "debug", skip_all)]
-    pub async fn inclusion_proof(
-        &self,
-        group_id: usize,
-        commitment: &Hash,
-    ) -> Result<InclusionProofResponse, ServerError> {
-        if U256::from(group_id) != self.identity_manager.group_id() {
-            return Err(ServerError::InvalidGroupId);
+impl ToResponseCode for HealthReport {
+    fn to_response_code(&self) -> StatusCode {
+        if self.halting_reorg.is_some() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
         }
+    }
+}
 
-        if commitment == &self.identity_manager.initial_leaf_value() {
-            return Err(ServerError::InvalidCommitment);
-        }
+impl ToResponseCode for InsertIdentityResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
 
-        {
-            let tree = self.tree_state.read().await.map_err(|e| {
-                error!(?e, "Failed to obtain tree lock in inclusion_proof.");
-                panic!("Sequencer potentially deadlocked, terminating.");
-                #[allow(unreachable_code)]
-                e
+impl ToResponseCode for InsertIdentitiesResponse {
+    fn to_response_code(&self) -> StatusCode {
+        if self.queued {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_REQUEST
+        }
+    }
+}
+
+/// The lifecycle stage of an identity commitment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum IdentityStatus {
+    /// Accepted and waiting for the committer.
+    Queued,
+    /// Sent to the chain in a transaction that has not been mined yet.
+    Submitted,
+    /// Mined, but not yet past the confirmation delay.
+    Mined,
+    /// Part of the tree served by this sequencer.
+    Confirmed,
+    /// Given up on, because its transaction reverted.
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityStatusResponse {
+    pub status:           IdentityStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number:     Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index:            Option<usize>,
+    /// Why the identity was given up on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure:          Option<String>,
+}
+
+impl ToResponseCode for IdentityStatusResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
+
+/// How a root supplied for proof verification relates to the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RootStatus {
+    /// The latest root of the tree.
+    Current,
+    /// A prior root that is still retained.
+    Historical,
+    /// Not a root of the tree, or no longer retained.
+    Unknown,
+}
+
+/// The outcome of verifying a client-supplied inclusion proof.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyProofResponse {
+    /// Whether the proof proves inclusion of the commitment under the root.
+    pub valid:          bool,
+    pub root:           RootStatus,
+    /// Whether the root is valid on chain. Only checked on request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_on_chain: Option<bool>,
+}
+
+impl ToResponseCode for VerifyProofResponse {
+    fn to_response_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Parser, Serialize)]
+#[group(skip)]
+pub struct Options {
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub ethereum: ethereum::Options,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub contracts: contracts::Options,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub database: database::Options,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub prover: prover::Options,
+
+    /// Block number to start syncing from
+    #[clap(long, env, default_value = "0")]
+    pub starting_block: u64,
+
+    /// Timeout for the tree lock (seconds).
+    #[clap(long, env, default_value = "120")]
+    pub lock_timeout: u64,
+
+    /// Timeout for reading the tree, such as to serve proofs (seconds).
+    /// Defaults to `lock_timeout`, which then only applies to writes.
+    #[clap(long, env)]
+    pub read_lock_timeout: Option<u64>,
+
+    /// Time an `Idempotency-Key` of an insert is remembered, during which
+    /// repeated requests with the key return the original response (seconds).
+    #[clap(long, env, default_value = "86400")]
+    pub idempotency_key_ttl: u64,
+
+    /// Maximum number of commitments accepted in one batch insert.
+    #[clap(long, env, default_value = "10000")]
+    pub max_insert_batch_size: usize,
+
+    /// Maximum number of entries in one `/inclusionProofsMultiGroup` request.
+    #[clap(long, env, default_value = "100")]
+    pub max_multi_group_proofs: usize,
+
+    /// Maximum number of leaves returned by one `/tree/leaves` request.
+    #[clap(long, env, default_value = "1000")]
+    pub max_tree_leaves_count: usize,
+
+    /// Sign inclusion proof responses with the Ethereum signing key. The
+    /// signature is returned in the `X-Signature` header.
+    #[clap(long, env)]
+    pub sign_responses: bool,
+
+    /// Include the suggested retry delay in the body of pending inclusion
+    /// proof responses, instead of only in the `Retry-After` header. Older
+    /// clients expect the bare `"pending"` string.
+    #[clap(long, env)]
+    pub detailed_pending_responses: bool,
+
+    /// Time after which inserts are refused while the Ethereum provider is
+    /// unreachable (seconds).
+    #[clap(long, env, default_value = "300")]
+    pub chain_unavailable_timeout: u64,
+
+    /// Number of recent roots that inclusion proofs can be requested against.
+    #[clap(long, env, default_value = "1000")]
+    pub root_history_size: usize,
+
+    /// Number of inclusion proofs against the latest root kept in memory, so
+    /// repeated requests are served without recomputing them. The cache is
+    /// cleared whenever the root changes. Disabled if not set.
+    #[clap(long, env)]
+    pub proof_cache_size: Option<usize>,
+
+    /// Number of inclusion proofs computed at once, protecting the Ethereum
+    /// provider from bursts of requests. Unlimited if not set.
+    #[clap(long, env)]
+    pub max_concurrent_proofs: Option<usize>,
+
+    /// Time a proof request waits for one of the `max_concurrent_proofs`
+    /// before it is refused with 503 Service Unavailable (seconds).
+    #[clap(long, env, default_value = "5")]
+    pub proof_queue_timeout: u64,
+
+    /// Moving average latencies endpoints are kept under, as a comma separated
+    /// list of `path=milliseconds`, e.g. `/inclusionProof=500`. While an
+    /// endpoint exceeds its budget, a share of its requests growing with the
+    /// excess is refused with 503 Service Unavailable.
+    #[clap(long, env, value_delimiter = ',')]
+    pub latency_budgets: Vec<LatencyBudget>,
+
+    /// Number of identities waiting to be committed at which inserts into a
+    /// group are refused. Unlimited if not set.
+    #[clap(long, env)]
+    pub max_pending_identities: Option<usize>,
+
+    /// Number of waiting identities below which a full queue accepts inserts
+    /// again. Defaults to 90% of `max_pending_identities`.
+    #[clap(long, env, requires = "max_pending_identities")]
+    pub pending_identities_low_water: Option<usize>,
+
+    /// Maximum number of identities registered in one transaction. A batch
+    /// that reverts is split up to find the identities making it revert, which
+    /// are then given up on. Startup is refused if the contract accepts fewer
+    /// identities per transaction, as the legacy contract accepts only one.
+    #[clap(long, env, default_value = "1")]
+    pub commit_batch_size: usize,
+
+    /// Check every inclusion proof before serving it, and terminate if one
+    /// does not verify. Disabling it saves hashing on every request, at the
+    /// risk of serving invalid proofs should the tree ever be corrupted: the
+    /// root is still checked against the contract, but the proof is not.
+    #[clap(long, env, default_value = "true", action = ArgAction::Set)]
+    pub verify_proofs_locally: bool,
+
+    /// Before queueing a commitment that is not in the tree, check whether it
+    /// was added on chain in a block that is not yet processed. Costs an
+    /// Ethereum RPC per inserted commitment.
+    #[clap(long, env)]
+    pub check_unconfirmed_members: bool,
+
+    /// Bearer token required by the `/admin` endpoints. They are disabled if
+    /// no token is set.
+    #[clap(long, env)]
+    #[serde(serialize_with = "serialize_redacted_option")]
+    pub admin_token: Option<String>,
+
+    /// Serve a JSON-RPC 2.0 endpoint at `/rpc` with the `insertIdentity`,
+    /// `inclusionProof` and `groupInfo` methods, for clients not speaking
+    /// the REST API.
+    #[clap(long, env)]
+    pub json_rpc: bool,
+
+    /// Maximum number of calls in one JSON-RPC batch request.
+    #[clap(long, env, default_value = "100")]
+    pub max_json_rpc_batch_size: usize,
+
+    /// Reduce inserted commitments modulo the SNARK scalar field instead of
+    /// rejecting ones that are not reduced. The reduced commitment is inserted
+    /// and checked for duplicates.
+    #[clap(long, env)]
+    pub normalize_commitments: bool,
+
+    /// Serve proofs from the synced tree without a signer, refusing inserts.
+    /// Lets extra instances scale reads without submitting transactions.
+    #[clap(long, env, conflicts_with = "sign_responses")]
+    pub read_only: bool,
+
+    /// Start degraded instead of refusing to when the tree does not match the
+    /// chain at startup. Degraded instances serve what they loaded, refuse
+    /// inserts and neither sync nor commit, and are flagged in `/health`.
+    #[clap(long, env)]
+    pub start_on_health_warning: bool,
+
+    /// Build the tree from the event cache alone at startup if its root is
+    /// valid on chain, syncing on from the last cached block instead of
+    /// fetching the events after it first. Falls back to a full rebuild if
+    /// the cache can not be trusted.
+    #[clap(long, env)]
+    pub trust_cache_on_start: bool,
+
+    /// File listing the only commitments that may be inserted, one hex
+    /// encoded commitment per line. Reloaded on SIGHUP.
+    #[clap(long, env)]
+    pub commitment_allowlist: Option<PathBuf>,
+
+    /// File listing commitments that are never inserted, one hex encoded
+    /// commitment per line. Reloaded on SIGHUP.
+    #[clap(long, env)]
+    pub commitment_denylist: Option<PathBuf>,
+
+    /// Rebuild the tree from cached and on-chain events, check it against the
+    /// contract and exit, without starting the server. Exits with an error on
+    /// any discrepancy.
+    #[clap(long)]
+    pub verify: bool,
+}
+
+pub struct App {
+    database:                   Arc<Database>,
+    ethereum:                   Ethereum,
+    identity_manager:           SharedIdentityManager,
+    root_validator:             RootValidator,
+    identity_committer:         Arc<IdentityCommitter>,
+    chain_subscriber:           EthereumSubscriber,
+    sync_progress:              Arc<SyncProgress>,
+    tree_state:                 SharedTreeState,
+    response_signer:            Option<TxSigner>,
+    proof_cache:                Option<ProofCache>,
+    proof_limiter:              Option<ProofLimiter>,
+    load_shedder:               LoadShedder,
+    max_insert_batch_size:      usize,
+    max_multi_group_proofs:     usize,
+    max_tree_leaves_count:      usize,
+    idempotency_key_ttl:        Duration,
+    refresh_rate:               Duration,
+    detailed_pending_responses: bool,
+    chain_unavailable_timeout:  Duration,
+    check_unconfirmed_members:  bool,
+    normalize_commitments:      bool,
+    verify_proofs_locally:      bool,
+    admin_token:                Option<String>,
+    json_rpc:                   bool,
+    max_json_rpc_batch_size:    usize,
+    read_only:                  bool,
+    commitment_filter:          Arc<CommitmentFilter>,
+    snark_scalar_field:         Hash,
+    /// Findings of the health check at startup.
+    health_report:              Option<HealthReport>,
+}
+
+/// How the event cache is trusted at startup, and repaired when rebuilding the
+/// tree from it fails.
+#[derive(Clone, Copy, Debug)]
+struct CacheRecovery {
+    /// Number of most recent blocks removed on the first attempt. Every
+    /// following attempt removes twice as many.
+    step_size:    usize,
+    /// Number of partial removals before the entire cache is wiped.
+    max_attempts: usize,
+    /// Whether the entire cache is wiped after the partial removals, rather
+    /// than giving up.
+    wipe:         bool,
+    /// Whether the tree is built from the cache alone if its root is valid
+    /// on chain.
+    trust:        bool,
+}
+
+/// Timeouts for acquiring the tree lock.
+#[derive(Clone, Copy, Debug)]
+struct LockTimeouts {
+    read:  Duration,
+    write: Duration,
+}
+
+impl LockTimeouts {
+    fn new(options: &Options) -> Self {
+        Self {
+            read:  Duration::from_secs(options.read_lock_timeout.unwrap_or(options.lock_timeout)),
+            write: Duration::from_secs(options.lock_timeout),
+        }
+    }
+
+    fn tree_state(self, tree: TreeState) -> SharedTreeState {
+        Arc::new(TimedRwLock::new(self.write, tree).with_read_timeout(self.read))
+    }
+}
+
+/// Runs one phase of startup in its own span and logs how long it took, so
+/// slow boots can be attributed to a phase.
+async fn startup_phase<F: Future>(phase: &'static str, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.instrument(info_span!("startup_phase", phase)).await;
+    info!(phase, elapsed = ?start.elapsed(), "Startup phase completed.");
+    output
+}
+
+/// Connects to the database and to the identity manager contract. Read-only
+/// instances connect without a signer.
+async fn connect(
+    database: database::Options,
+    ethereum: ethereum::Options,
+    contracts: contracts::Options,
+    read_only: bool,
+) -> AnyhowResult<(Database, Ethereum, SharedIdentityManager)> {
+    let db = Database::new(database);
+
+    let eth = Ethereum::new(ethereum, read_only).and_then(|ethereum| async move {
+        let identity_manager = if cfg!(feature = "batching-contract") {
+            BatchingContract::new(contracts, ethereum.clone()).await?;
+            panic!("The batching contract does not yet exist but was requested.");
+        } else {
+            LegacyContract::new(contracts, ethereum.clone()).await?
+        };
+        Ok((ethereum, Arc::new(identity_manager)))
+    });
+
+    // Connect to both in parallel
+    let (database, (ethereum, identity_manager)) = try_join!(db, eth)?;
+    Ok((database, ethereum, identity_manager))
+}
+
+/// The bound on waiting identities per group set in `options`, if any. The
+/// low-water mark must be reachable by a draining queue and not above the
+/// bound, or a full queue would never accept inserts again.
+fn queue_limit(options: &Options) -> AnyhowResult<Option<QueueLimit>> {
+    let Some(max) = options.max_pending_identities else {
+        return Ok(None);
+    };
+    let low_water = options
+        .pending_identities_low_water
+        .unwrap_or(max - max / 10);
+    if !(1..=max).contains(&low_water) {
+        return Err(anyhow!(
+            "Pending identities low-water mark {low_water} must be between 1 and the maximum of \
+             {max} pending identities"
+        ));
+    }
+    Ok(Some(QueueLimit { max, low_water }))
+}
+
+/// Rebuilds the tree from cached and on-chain events without writing to the
+/// database, and checks its root and size against the contract as of the
+/// latest confirmed block, the last one the rebuild includes.
+async fn verify_tree(
+    database: &Arc<Database>,
+    identity_manager: &SharedIdentityManager,
+    lock_timeouts: LockTimeouts,
+    starting_block: u64,
+) -> AnyhowResult<()> {
+    let tree_state = lock_timeouts.tree_state(TreeState::new(
+        identity_manager.poseidon_tree_depth(),
+        identity_manager.initial_leaf_value(),
+    ));
+    // Never started, the subscriber merely requires one.
+    let identity_committer = Arc::new(IdentityCommitter::new(
+        database.clone(),
+        identity_manager.clone(),
+        tree_state.clone(),
+    ));
+    let mut chain_subscriber = EthereumSubscriber::new(
+        starting_block,
+        database.clone(),
+        identity_manager.clone(),
+        tree_state.clone(),
+        identity_committer,
+    )
+    .dry_run();
+    if let Err(error) = chain_subscriber.process_initial_events().await {
+        error!(%error, "Failed to rebuild the tree from cached and on-chain events.");
+        return Err(anyhow!("Failed to rebuild the tree: {error}"));
+    }
+    // Blocks after it may hold events that are not confirmed yet.
+    let block = chain_subscriber.next_block().saturating_sub(1);
+    let (root, next_leaf) = {
+        let tree = tree_state.read_retrying().await;
+        (tree.merkle_tree.root(), tree.next_leaf)
+    };
+
+    let mut discrepancies = 0;
+    match identity_manager.root_at(block).await {
+        Ok(onchain_root) if onchain_root == root => {
+            info!(?root, block, "Root matches the contract.");
+        }
+        Ok(onchain_root) => {
+            error!(
+                ?root,
+                ?onchain_root,
+                block,
+                "Root does not match the contract."
+            );
+            discrepancies += 1;
+        }
+        Err(error) => {
+            error!(?root, block, %error, "Failed to read the on-chain root.");
+            discrepancies += 1;
+        }
+    }
+    match identity_manager.leaf_count_at(block).await {
+        Ok(leaf_count) if leaf_count == next_leaf => {
+            info!(leaf_count, block, "Leaf count matches the contract.");
+        }
+        Ok(leaf_count) => {
+            error!(
+                next_leaf,
+                leaf_count, block, "Leaf count does not match the contract."
+            );
+            discrepancies += 1;
+        }
+        Err(error) => {
+            error!(next_leaf, block, %error, "Failed to read the on-chain leaf count.");
+            discrepancies += 1;
+        }
+    }
+
+    if discrepancies > 0 {
+        return Err(anyhow!(
+            "Tree does not match the contract, found {discrepancies} discrepancies"
+        ));
+    }
+    Ok(())
+}
+
+/// Whether loading the tree failed because it does not match the chain, as
+/// opposed to the chain or database being unavailable.
+fn is_tree_mismatch(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<SubscriberError>(),
+        Some(SubscriberError::RootMismatch | SubscriberError::LeafGap(_))
+    )
+}
+
+/// Whether startup continues in degraded mode, given the `mismatch` loading
+/// the tree failed with and the health of the tree. Without
+/// `start_on_health_warning`, a tree not matching the chain refuses startup.
+fn start_degraded(
+    mismatch: Option<anyhow::Error>,
+    report: &HealthReport,
+    start_on_health_warning: bool,
+) -> AnyhowResult<bool> {
+    let warning = match mismatch {
+        Some(error) => error,
+        None if report.is_critical() => anyhow!(
+            "Root {:?} of the tree is not valid on chain",
+            report.expected_root
+        ),
+        None => return Ok(false),
+    };
+    if !start_on_health_warning {
+        return Err(warning);
+    }
+    error!(%warning, "Tree does not match the chain, starting degraded and read-only.");
+    Ok(true)
+}
+
+/// Builds the tree from cached and on-chain events into `tree_state`, which
+/// is only replaced once the rebuilt tree is consistent. If the cache is
+/// trusted, it alone is used as long as its root is valid on chain.
+///
+/// On a root mismatch or missing event, progressively larger chunks of the most
+/// recent cached events are removed and the tree is rebuilt, before resorting
+/// to wiping the entire cache.
+async fn load_initial_events(
+    database: &Arc<Database>,
+    identity_manager: &SharedIdentityManager,
+    identity_committer: &Arc<IdentityCommitter>,
+    sync_progress: &Arc<SyncProgress>,
+    tree_state: &SharedTreeState,
+    starting_block: u64,
+    cache_recovery: CacheRecovery,
+) -> AnyhowResult<EthereumSubscriber> {
+    let new_subscriber = || {
+        EthereumSubscriber::new(
+            starting_block,
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+            identity_committer.clone(),
+        )
+        .with_sync_progress(sync_progress.clone())
+    };
+
+    if cache_recovery.trust {
+        let mut chain_subscriber = new_subscriber();
+        if chain_subscriber.process_trusted_cache().await? {
+            return Ok(chain_subscriber);
+        }
+        warn!("Not trusting the cache, rebuilding the tree.");
+    }
+
+    let mut root_mismatch_count = 0;
+    loop {
+        let mut chain_subscriber = new_subscriber();
+        match chain_subscriber.process_initial_events().await {
+            Err(error @ (SubscriberError::RootMismatch | SubscriberError::LeafGap(_))) => {
+                error!(%error, "Error when rebuilding tree from cache.");
+                root_mismatch_count += 1;
+            }
+            Err(e) => return Err(e.into()),
+            Ok(_) => return Ok(chain_subscriber),
+        }
+
+        if root_mismatch_count <= cache_recovery.max_attempts {
+            let blocks = cache_recovery
+                .step_size
+                .saturating_mul(1 << (root_mismatch_count - 1).min(32));
+            error!(
+                attempt = root_mismatch_count,
+                max_attempts = cache_recovery.max_attempts,
+                blocks,
+                "Removing most recent cache."
+            );
+            database
+                .delete_most_recent_cached_events(i64::try_from(blocks).unwrap_or(i64::MAX))
+                .await?;
+        } else if cache_recovery.wipe && root_mismatch_count == cache_recovery.max_attempts + 1 {
+            error!("Wiping out the entire cache.");
+            database.wipe_cache().await?;
+        } else {
+            error!(
+                attempts = root_mismatch_count,
+                "Giving up on root mismatch, the cache needs to be repaired."
+            );
+            return Err(SubscriberError::RootMismatch.into());
+        }
+    }
+}
+
+/// Refuses an insert of `count` identities into a group with a full queue.
+fn queue_full(group_id: usize, count: usize) -> ServerError {
+    warn!(
+        group_id,
+        count, "Refusing insert, pending identity queue full."
+    );
+    ServerError::QueueFull
+}
+
+/// Passes through the outcome of validating one commitment of a batch, unless
+/// it is an error that fails the whole batch instead of rejecting the
+/// commitment.
+fn rejection(outcome: Result<(), ServerError>) -> Result<Result<(), ServerError>, ServerError> {
+    match outcome {
+        Err(
+            ServerError::InvalidCommitment
+            | ServerError::UnreducedCommitment
+            | ServerError::ForbiddenCommitment
+            | ServerError::DuplicateCommitment,
+        )
+        | Ok(()) => Ok(outcome),
+        Err(error) => Err(error),
+    }
+}
+
+/// A proof copied out of the tree, so it can be checked without holding the
+/// tree lock.
+struct TreeProof {
+    index: usize,
+    root:  Field,
+    proof: Proof,
+}
+
+/// Looks up the proof of `commitment` against `root`, or against the latest
+/// root if `root` is not given. Returns `None` if `commitment` is not in the
+/// tree. The read lock is only held for the lookup.
+async fn tree_proof(
+    tree_state: &SharedTreeState,
+    commitment: &Hash,
+    root: Option<&Field>,
+) -> Result<Option<TreeProof>, ServerError> {
+    let tree = tree_state.read().await.map_err(|error| {
+        warn!(%error, "Tree busy, not serving the inclusion proof.");
+        error
+    })?;
+
+    let Some(index) = tree
+        .merkle_tree
+        .leaves()
+        .iter()
+        .position(|&x| x == *commitment)
+    else {
+        return Ok(None);
+    };
+
+    let (root, proof) = match root {
+        Some(root) if *root != tree.merkle_tree.root() => {
+            let proof = tree
+                .historical_proof(root, index)
+                .ok_or(ServerError::RootNotRetained)?;
+            (*root, proof)
+        }
+        _ => {
+            let proof = tree
+                .merkle_tree
+                .proof(index)
+                .ok_or(ServerError::IndexOutOfBounds)?;
+            (tree.merkle_tree.root(), proof)
+        }
+    };
+    Ok(Some(TreeProof { index, root, proof }))
+}
+
+impl App {
+    /// # Errors
+    ///
+    /// Will return `Err` if the internal Ethereum handler errors or if the
+    /// `options.storage_file` is not accessible.
+    pub async fn new(options: Options) -> AnyhowResult<Self> {
+        Self::with_sync_progress(options, Arc::default()).await
+    }
+
+    /// Like [`Self::new`], but reports the progress of the initial sync to
+    /// `sync_progress` while it runs.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the internal Ethereum handler errors or if the
+    /// `options.storage_file` is not accessible.
+    #[allow(clippy::missing_panics_doc)] // TODO
+    #[instrument(name = "App::new", level = "debug", skip(sync_progress))]
+    pub async fn with_sync_progress(
+        options: Options,
+        sync_progress: Arc<SyncProgress>,
+    ) -> AnyhowResult<Self> {
+        let refresh_rate = options.ethereum.refresh_rate;
+        let max_retry_interval = options.ethereum.max_retry_interval;
+        let max_auto_reorg_depth = options.ethereum.max_auto_reorg_depth;
+        let cache_recovery = CacheRecovery {
+            step_size:    options.ethereum.cache_recovery_step_size,
+            max_attempts: options.ethereum.cache_recovery_max_attempts,
+            wipe:         options.ethereum.cache_recovery_wipe,
+            trust:        options.trust_cache_on_start,
+        };
+        let lock_timeouts = LockTimeouts::new(&options);
+        let queue_limit = queue_limit(&options)?;
+        let commitment_filter = Arc::new(CommitmentFilter::new(
+            options.commitment_allowlist,
+            options.commitment_denylist,
+        )?);
+        // Connect to Ethereum and Database
+        let (database, ethereum, identity_manager) = startup_phase(
+            "connect",
+            connect(
+                options.database,
+                options.ethereum,
+                options.contracts,
+                options.read_only,
+            ),
+        )
+        .await?;
+        let database = Arc::new(database);
+
+        let max_batch_size = identity_manager.max_batch_size();
+        if options.commit_batch_size > max_batch_size {
+            return Err(anyhow!(
+                "Commit batch size {} exceeds the {max_batch_size} identities the contract \
+                 accepts per transaction",
+                options.commit_batch_size
+            ));
+        }
+
+        // Responses are signed with the same key as transactions.
+        let response_signer = options.sign_responses.then(|| {
+            let signer = ethereum.provider().signer().clone();
+            info!(address = ?signer.address(), "Signing inclusion proof responses");
+            signer
+        });
+
+        let tree_state = lock_timeouts.tree_state(
+            TreeState::new(
+                identity_manager.poseidon_tree_depth(),
+                identity_manager.initial_leaf_value(),
+            )
+            .with_root_history(options.root_history_size),
+        );
+
+        let mut identity_committer = IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        )
+        .with_batch_size(options.commit_batch_size);
+        if let Some(limit) = queue_limit {
+            identity_committer = identity_committer.with_queue_limit(limit);
+        }
+        let identity_committer = Arc::new(identity_committer);
+        let chain_subscriber = EthereumSubscriber::new(
+            options.starting_block,
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+            identity_committer.clone(),
+        )
+        .with_sync_progress(sync_progress.clone());
+
+        let snark_scalar_field = Hash::from_str_radix(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+            10,
+        )
+        .expect("This should just parse.");
+
+        // Sync with chain on start up
+        let mut app = Self {
+            database,
+            ethereum,
+            root_validator: RootValidator::new(identity_manager.clone()),
+            identity_manager,
+            identity_committer,
+            chain_subscriber,
+            sync_progress,
+            tree_state,
+            response_signer,
+            proof_cache: options.proof_cache_size.map(ProofCache::new),
+            proof_limiter: options.max_concurrent_proofs.map(|max| {
+                ProofLimiter::new(max, Duration::from_secs(options.proof_queue_timeout))
+            }),
+            load_shedder: LoadShedder::new(&options.latency_budgets),
+            max_insert_batch_size: options.max_insert_batch_size,
+            max_multi_group_proofs: options.max_multi_group_proofs,
+            max_tree_leaves_count: options.max_tree_leaves_count,
+            idempotency_key_ttl: Duration::from_secs(options.idempotency_key_ttl),
+            refresh_rate,
+            detailed_pending_responses: options.detailed_pending_responses,
+            chain_unavailable_timeout: Duration::from_secs(options.chain_unavailable_timeout),
+            check_unconfirmed_members: options.check_unconfirmed_members,
+            normalize_commitments: options.normalize_commitments,
+            verify_proofs_locally: options.verify_proofs_locally,
+            admin_token: options.admin_token,
+            json_rpc: options.json_rpc,
+            max_json_rpc_batch_size: options.max_json_rpc_batch_size,
+            read_only: options.read_only,
+            commitment_filter,
+            snark_scalar_field,
+            health_report: None,
+        };
+
+        let load_initial_events = app.load_initial_events(options.starting_block, cache_recovery);
+        let loaded = select! {
+            result = startup_phase("load_events", load_initial_events) => result,
+            _ = await_shutdown() => return Err(anyhow!("Interrupted"))
+        };
+        let mismatch = match loaded {
+            Ok(()) => None,
+            Err(error) if is_tree_mismatch(&error) => Some(error),
+            Err(error) => return Err(error),
+        };
+
+        // Basic sanity checks on the merkle tree
+        let mut health_report =
+            startup_phase("health_check", app.chain_subscriber.check_health()).await;
+        health_report.degraded =
+            start_degraded(mismatch, &health_report, options.start_on_health_warning)?;
+        info!(?health_report, "Checked the health of the tree.");
+        let degraded = health_report.degraded;
+        app.health_report = Some(health_report);
+        if degraded {
+            app.read_only = true;
+        }
+
+        startup_phase("start_workers", async {
+            // A degraded tree would only diverge further from the chain.
+            if degraded {
+                return;
+            }
+
+            // Listen to Ethereum events
+            app.chain_subscriber
+                .start(refresh_rate, max_retry_interval, max_auto_reorg_depth)
+                .await;
+
+            // Process to push new identities to Ethereum. Read-only instances
+            // never submit transactions.
+            if !app.read_only {
+                app.identity_committer.start().await;
+            }
+
+            if app.commitment_filter.is_configured() {
+                spawn_or_abort(app.commitment_filter.clone().reload_on_hangup());
+            }
+        })
+        .await;
+
+        Ok(app)
+    }
+
+    /// Decides which requests to shed while endpoints exceed their latency
+    /// budget.
+    #[must_use]
+    pub const fn load_shedder(&self) -> &LoadShedder {
+        &self.load_shedder
+    }
+
+    /// Whether the JSON-RPC endpoint is served.
+    #[must_use]
+    pub const fn json_rpc_enabled(&self) -> bool {
+        self.json_rpc
+    }
+
+    /// Maximum number of calls in one JSON-RPC batch request.
+    #[must_use]
+    pub const fn max_json_rpc_batch_size(&self) -> usize {
+        self.max_json_rpc_batch_size
+    }
+
+    /// The findings of the health check at startup, along with any reorg
+    /// that halted syncing since.
+    #[must_use]
+    pub fn health_report(&self) -> Option<HealthReport> {
+        let mut report = self.health_report.clone()?;
+        report.halting_reorg = self.chain_subscriber.chain_health().halting_reorg();
+        Some(report)
+    }
+
+    /// Refuses to serve the tree of a degraded instance. The rebuilt tree
+    /// failed to match the chain, so it was never loaded.
+    fn serving_tree(&self) -> Result<(), ServerError> {
+        match &self.health_report {
+            Some(report) if report.degraded => Err(ServerError::Degraded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rebuilds the tree from cached and on-chain events and checks its root
+    /// and size against the contract, without starting any workers or
+    /// repairing the cache.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tree can not be rebuilt or does not match the
+    /// contract.
+    pub async fn verify(options: Options) -> AnyhowResult<()> {
+        let lock_timeouts = LockTimeouts::new(&options);
+        let (database, _ethereum, identity_manager) = connect(
+            options.database,
+            options.ethereum,
+            options.contracts,
+            options.read_only,
+        )
+        .await?;
+        verify_tree(
+            &Arc::new(database),
+            &identity_manager,
+            lock_timeouts,
+            options.starting_block,
+        )
+        .await
+    }
+
+    async fn load_initial_events(
+        &mut self,
+        starting_block: u64,
+        cache_recovery: CacheRecovery,
+    ) -> AnyhowResult<()> {
+        self.chain_subscriber = load_initial_events(
+            &self.database,
+            &self.identity_manager,
+            &self.identity_committer,
+            &self.sync_progress,
+            &self.tree_state,
+            starting_block,
+            cache_recovery,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn identity_is_reduced(&self, commitment: Hash) -> bool {
+        commitment.lt(&self.snark_scalar_field)
+    }
+
+    /// Reduces `commitment` into the SNARK scalar field if normalization is
+    /// enabled, leaving it to be rejected otherwise.
+    fn normalize_commitment(&self, commitment: Hash) -> Hash {
+        if !self.normalize_commitments || self.identity_is_reduced(commitment) {
+            return commitment;
+        }
+        let normalized = commitment % self.snark_scalar_field;
+        info!(
+            ?commitment,
+            ?normalized,
+            "Reduced commitment into the field."
+        );
+        normalized
+    }
+
+    /// Refuses new identities on read-only instances.
+    fn ensure_writable(&self) -> Result<(), ServerError> {
+        if self.read_only {
+            warn!("Refusing insert, instance is read-only.");
+            return Err(ServerError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Refuses new identities once the Ethereum provider has been unreachable
+    /// for longer than `chain_unavailable_timeout`, or a deep reorg halted
+    /// syncing, as they could not be confirmed.
+    fn ensure_chain_available(&self) -> Result<(), ServerError> {
+        let chain_health = self.chain_subscriber.chain_health();
+        if let Some(depth) = chain_health.halting_reorg() {
+            warn!(depth, "Refusing insert, syncing halted by a deep reorg.");
+            return Err(ServerError::SyncHalted);
+        }
+        match chain_health.unreachable_for() {
+            Some(duration) if duration >= self.chain_unavailable_timeout => {
+                warn!(?duration, "Refusing insert, Ethereum provider unreachable.");
+                Err(ServerError::ChainUnavailable)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses `count` new identities while too many are waiting to be
+    /// committed in `group_id`. Room is only taken when they are queued, see
+    /// [`IdentityCommitter::enqueue_and`].
+    fn ensure_queue_has_room(&self, group_id: usize, count: usize) -> Result<(), ServerError> {
+        if self.identity_committer.queue_has_room(group_id, count) {
+            Ok(())
+        } else {
+            Err(queue_full(group_id, count))
+        }
+    }
+
+    /// Refuses `count` new identities if they would not fit in the tree
+    /// together with the identities of `group_id` already queued.
+    async fn ensure_tree_has_room(&self, group_id: usize, count: usize) -> Result<(), ServerError> {
+        let queued = self.database.count_queued_identities(group_id).await?;
+        if self.tree_state.read().await?.has_room(queued + count) {
+            Ok(())
+        } else {
+            warn!(count, queued, "Refusing insert, tree is full.");
+            Err(ServerError::TreeFull)
+        }
+    }
+
+    /// Queues an insert into the merkle tree.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the instance is read-only, the identity is already
+    /// queued, or in the tree, the queue or tree is full, or the queue
+    /// malfunctions.
+    /// Repeating an insert of the same commitment with the same
+    /// `idempotency_key` returns the original response until the key expires.
+    /// The key is stored in the transaction that queues the identity.
+    /// With `normalize_commitments`, unreduced commitments are reduced first.
+    #[instrument(level = "debug", skip_all, fields(commitment = ?commitment))]
+    pub async fn insert_identity(
+        &self,
+        group_id: usize,
+        commitment: Hash,
+        idempotency_key: Option<&str>,
+    ) -> Result<InsertIdentityResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+        let commitment = self.normalize_commitment(commitment);
+
+        if let Some(key) = idempotency_key {
+            if let Some(response) = self.idempotent_response(key, &commitment).await? {
+                return Ok(response);
+            }
+        }
+
+        self.ensure_writable()?;
+        self.ensure_chain_available()?;
+        self.ensure_queue_has_room(group_id, 1)?;
+        self.ensure_tree_has_room(group_id, 1).await?;
+        self.validate_commitment(group_id, commitment).await?;
+
+        let next_leaf = self.tree_state.read().await?.next_leaf;
+        let saved_key = idempotency_key.map(|key| (key.to_owned(), self.idempotency_key_ttl));
+        let queued = self
+            .identity_committer
+            .enqueue_and(group_id, &[commitment], move |tx| {
+                Box::pin(async move {
+                    let queued = tx.pending_commitments_up_to(group_id, &commitment).await?;
+                    let response = InsertIdentityResponse {
+                        identity_index: next_leaf + queued.len().saturating_sub(1),
+                    };
+                    if let Some((key, ttl)) = &saved_key {
+                        let stored = serde_json::to_string(&response).expect("response serializes");
+                        tx.save_idempotent_response(key, &commitment, &stored, *ttl)
+                            .await?;
+                    }
+                    Ok(response)
+                })
+            })
+            .await;
+
+        match queued {
+            Err(EnqueueError::QueueFull) => Err(queue_full(group_id, 1)),
+            Err(EnqueueError::Database(DatabaseError::DuplicateCommitment)) => {
+                // A concurrent retry with the same key may have queued it.
+                if let Some(key) = idempotency_key {
+                    if let Some(response) = self.idempotent_response(key, &commitment).await? {
+                        return Ok(response);
+                    }
+                }
+                warn!(?commitment, "Pending identity inserted concurrently.");
+                Err(ServerError::DuplicateCommitment)
+            }
+            Err(EnqueueError::Database(error)) => Err(error.into()),
+            Ok(response) => Ok(response),
+        }
+    }
+
+    /// Returns the stored response to an insert of `commitment` with
+    /// idempotency `key`, if there is one.
+    async fn idempotent_response(
+        &self,
+        key: &str,
+        commitment: &Hash,
+    ) -> Result<Option<InsertIdentityResponse>, ServerError> {
+        let Some(response) = self
+            .database
+            .get_idempotent_response(key, commitment)
+            .await?
+        else {
+            return Ok(None);
+        };
+        info!(?commitment, key, "Repeating response to idempotent insert.");
+        serde_json::from_str(&response)
+            .map(Some)
+            .map_err(|error| ServerError::Other(error.into()))
+    }
+
+    /// Queues a batch of inserts into the merkle tree. Either all commitments
+    /// are queued or, if any of them is rejected, none are.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the instance is read-only, the batch is too large
+    /// or does not fit in the tree, or the queue malfunctions.
+    /// Rejected commitments are reported per index in the response.
+    #[instrument(level = "debug", skip_all, fields(count = commitments.len()))]
+    pub async fn insert_identities(
+        &self,
+        group_id: usize,
+        commitments: Vec<Hash>,
+    ) -> Result<InsertIdentitiesResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+
+        if commitments.len() > self.max_insert_batch_size {
+            return Err(ServerError::BatchTooLarge(self.max_insert_batch_size));
+        }
+        let commitments = commitments
+            .into_iter()
+            .map(|commitment| self.normalize_commitment(commitment))
+            .collect::<Vec<_>>();
+
+        self.ensure_writable()?;
+        self.ensure_chain_available()?;
+        self.ensure_queue_has_room(group_id, commitments.len())?;
+        self.ensure_tree_has_room(group_id, commitments.len())
+            .await?;
+
+        let mut outcomes = Vec::with_capacity(commitments.len());
+        for (index, commitment) in commitments.iter().enumerate() {
+            let outcome = if commitments[..index].contains(commitment) {
+                warn!(?commitment, "Commitment occurs twice in batch.");
+                Err(ServerError::DuplicateCommitment)
+            } else {
+                self.validate_queueable(group_id, *commitment).await
+            };
+            outcomes.push(rejection(outcome)?);
+        }
+
+        // The tree is only checked once every commitment passed the pending
+        // checks, see `validate_commitment`.
+        let candidates = commitments
+            .iter()
+            .zip(&outcomes)
+            .filter(|(_, outcome)| outcome.is_ok())
+            .map(|(commitment, _)| *commitment)
+            .collect();
+        let in_tree = self.commitments_in_tree(&candidates).await?;
+        let unconfirmed = self.unconfirmed_members(&candidates).await?;
+        for (commitment, outcome) in commitments.iter().zip(&mut outcomes) {
+            if outcome.is_err() {
+                continue;
+            }
+            if in_tree.contains(commitment) || unconfirmed.contains(commitment) {
+                *outcome = Err(ServerError::DuplicateCommitment);
+            }
+        }
+
+        let results = outcomes
+            .iter()
+            .map(|outcome| match outcome {
+                Ok(()) => InsertIdentityResult::valid(),
+                Err(error) => InsertIdentityResult::rejected(error),
+            })
+            .collect::<Vec<_>>();
+
+        if results.iter().any(|result| !result.valid) {
+            return Ok(InsertIdentitiesResponse {
+                queued: false,
+                results,
+            });
+        }
+
+        match self
+            .identity_committer
+            .enqueue(group_id, &commitments)
+            .await
+        {
+            Err(EnqueueError::QueueFull) => return Err(queue_full(group_id, commitments.len())),
+            Err(EnqueueError::Database(DatabaseError::DuplicateCommitment)) => {
+                warn!("Pending identity in batch inserted concurrently.");
+                return Err(ServerError::DuplicateCommitment);
+            }
+            Err(EnqueueError::Database(error)) => return Err(error.into()),
+            Ok(()) => {}
+        }
+
+        Ok(InsertIdentitiesResponse {
+            queued: true,
+            results,
+        })
+    }
+
+    /// Checks that `commitment` can be queued for insertion.
+    async fn validate_commitment(
+        &self,
+        group_id: usize,
+        commitment: Hash,
+    ) -> Result<(), ServerError> {
+        // Note the ordering of duplicate checks: since we never want to lose data,
+        // pending identities are removed from the DB _after_ they are inserted into the
+        // tree. Therefore this order of checks guarantees we will not insert a
+        // duplicate. Concurrent inserts of the same commitment can both pass these
+        // checks, so the final insert relies on the table's primary key.
+        self.validate_queueable(group_id, commitment).await?;
+        let commitments = HashSet::from([commitment]);
+        if !self.commitments_in_tree(&commitments).await?.is_empty()
+            || !self.unconfirmed_members(&commitments).await?.is_empty()
+        {
+            return Err(ServerError::DuplicateCommitment);
+        }
+        Ok(())
+    }
+
+    /// Checks `commitment` itself, and that it is neither pending nor being
+    /// committed.
+    async fn validate_queueable(
+        &self,
+        group_id: usize,
+        commitment: Hash,
+    ) -> Result<(), ServerError> {
+        if commitment == self.identity_manager.initial_leaf_value() {
+            warn!(?commitment, "Attempt to insert initial leaf.");
+            return Err(ServerError::InvalidCommitment);
+        }
+
+        if !self.identity_is_reduced(commitment) {
+            warn!(
+                ?commitment,
+                "The provided commitment is not an element of the field."
+            );
+            return Err(ServerError::UnreducedCommitment);
+        }
+
+        if !self.commitment_filter.permits(&commitment) {
+            warn!(?commitment, "Commitment is not permitted.");
+            return Err(ServerError::ForbiddenCommitment);
+        }
+
+        if self
+            .database
+            .pending_identity_exists(group_id, &commitment)
+            .await?
+        {
+            warn!(?commitment, "Pending identity already exists.");
+            return Err(ServerError::DuplicateCommitment);
+        }
+
+        if self.identity_committer.is_in_flight(&commitment) {
+            warn!(?commitment, "Identity is being committed.");
+            return Err(ServerError::DuplicateCommitment);
+        }
+
+        Ok(())
+    }
+
+    /// Returns those of `commitments` that are already in the tree, scanning
+    /// its leaves once under a single read lock.
+    async fn commitments_in_tree(
+        &self,
+        commitments: &HashSet<Hash>,
+    ) -> Result<HashSet<Hash>, ServerError> {
+        if commitments.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let tree = self.tree_state.read().await?;
+        let existing = tree
+            .merkle_tree
+            .leaves()
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| commitments.contains(leaf))
+            .map(|(existing, commitment)| {
+                warn!(?existing, ?commitment, next = %tree.next_leaf, "Commitment already exists in tree.");
+                *commitment
+            })
+            .collect();
+        Ok(existing)
+    }
+
+    /// Returns the `commitments` that were added in a block the subscriber has
+    /// not processed yet. The unconfirmed members are looked up once, however many
+    /// commitments are checked.
+    async fn unconfirmed_members(
+        &self,
+        commitments: &HashSet<Hash>,
+    ) -> Result<HashSet<Hash>, ServerError> {
+        // Commitments added in blocks that are not processed yet are not in the
+        // tree, but inserting them again would still be a duplicate.
+        if !self.check_unconfirmed_members || commitments.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let members = self
+            .identity_manager
+            .unconfirmed_members(self.chain_subscriber.next_block())
+            .await
+            .map_err(|error| {
+                if RpcTimeout::is_cause_of(&error) {
+                    warn!(?error, "Looking up unconfirmed members timed out.");
+                    ServerError::RpcTimeout
+                } else {
+                    error.into()
+                }
             })?;
+        let existing = commitments
+            .intersection(&members)
+            .copied()
+            .collect::<HashSet<_>>();
+        if !existing.is_empty() {
+            warn!(
+                ?existing,
+                "Commitments already exist in an unconfirmed block."
+            );
+        }
+        Ok(existing)
+    }
+
+    /// Returns the inclusion proof of `commitment` against the latest root, or
+    /// against `root` if given.
+    ///
+    /// With `allow_unconfirmed`, a proof against a root that is not valid on
+    /// chain is returned flagged as unconfirmed instead of failing, and queued
+    /// identities are proven against the root the tree will have once every
+    /// identity queued up to them is committed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the provided index is out of bounds, `root` is no
+    /// longer retained or predates the commitment, or the root could not be
+    /// checked on chain.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(commitment = ?commitment, index = tracing::field::Empty)
+    )]
+    pub async fn inclusion_proof(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+        root: Option<&Field>,
+        allow_unconfirmed: bool,
+    ) -> Result<InclusionProofResponse, ServerError> {
+        self.inclusion_proof_checking(
+            group_id,
+            commitment,
+            root,
+            allow_unconfirmed,
+            &mut HashMap::new(),
+        )
+        .await
+    }
+
+    /// Like [`Self::inclusion_proof`], but looks up whether a root is valid on
+    /// chain in `checked_roots` before asking the contract, and records the
+    /// answer there.
+    async fn inclusion_proof_checking(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+        root: Option<&Field>,
+        allow_unconfirmed: bool,
+        checked_roots: &mut HashMap<Field, bool>,
+    ) -> Result<InclusionProofResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+        self.serving_tree()?;
+
+        if commitment == &self.identity_manager.initial_leaf_value() {
+            return Err(ServerError::InvalidCommitment);
+        }
+
+        // Proofs against the latest root are cached until the root changes.
+        let latest_root = match &self.proof_cache {
+            Some(cache) => {
+                let latest_root = self.tree_state.read().await?.merkle_tree.root();
+                let cached = cache.get(
+                    group_id,
+                    commitment,
+                    root.unwrap_or(&latest_root),
+                    &latest_root,
+                );
+                if let Some(response) = cached {
+                    return Ok(response);
+                }
+                Some(latest_root)
+            }
+            None => None,
+        };
+
+        // Held until the proof is computed and its root checked.
+        let _slot = match &self.proof_limiter {
+            Some(limiter) => Some(limiter.acquire().await.ok_or_else(|| {
+                warn!("Refusing inclusion proof, too many in flight.");
+                ServerError::TooManyProofs
+            })?),
+            None => None,
+        };
+
+        // Only the lookup happens under the lock, the proof is checked after
+        // it is released.
+        if let Some(TreeProof { index, root, proof }) =
+            tree_proof(&self.tree_state, commitment, root).await?
+        {
+            Span::current().record("index", index);
+            if self.verify_proofs_locally {
+                check_proof_locally(commitment, index, &root, &proof);
+            }
+
+            // Verify the root on chain
+            let valid = match checked_roots.get(&root) {
+                Some(&valid) => valid,
+                None => {
+                    let valid = self.root_is_valid(&root, allow_unconfirmed).await?;
+                    checked_roots.insert(root, valid);
+                    valid
+                }
+            };
+            if !valid && !allow_unconfirmed {
+                return Err(ServerError::RootMismatch);
+            }
+            let unconfirmed = !valid;
+            let response = self
+                .proof_response(group_id, root, proof, index, unconfirmed)
+                .await?;
+            if let Some(cache) = &self.proof_cache {
+                // The tree may have changed since the root was looked up.
+                if !unconfirmed && latest_root == Some(root) {
+                    cache.insert(group_id, commitment, &root, response.clone());
+                }
+            }
+            return Ok(response);
+        }
+
+        if allow_unconfirmed && root.is_none() {
+            let queued = self
+                .database
+                .pending_commitments_up_to(group_id, commitment)
+                .await?;
+            if !queued.is_empty() {
+                let tree = self.tree_state.read().await?;
+                let leaf_index = tree.next_leaf + queued.len() - 1;
+                let proof = tree
+                    .prospective_proof(&queued, leaf_index)
+                    .ok_or(ServerError::IndexOutOfBounds)?;
+                drop(tree);
+                let root = proof.root(*commitment);
+                return self
+                    .proof_response(group_id, root, proof, leaf_index, true)
+                    .await;
+            }
+        }
+
+        if self
+            .database
+            .pending_identity_exists(group_id, commitment)
+            .await?
+        {
+            // Queued identities enter the tree when the chain subscriber next
+            // picks up their event.
+            Ok(InclusionProofResponse::Pending {
+                group_id:    Some(group_id),
+                retry_after: Some(self.refresh_rate),
+                detailed:    self.detailed_pending_responses,
+            })
+        } else {
+            Err(ServerError::IdentityCommitmentNotFound)
+        }
+    }
+
+    /// Returns whether `root` is valid on chain, or an error if the contract
+    /// could not be asked. The latest root of the tree not being valid is
+    /// reported as a mismatch unless `allow_unconfirmed` is set. Older roots,
+    /// such as ones supplied by clients, expire on chain, so them not being
+    /// valid is no mismatch.
+    async fn root_is_valid(
+        &self,
+        root: &Field,
+        allow_unconfirmed: bool,
+    ) -> Result<bool, ServerError> {
+        match self.root_validator.assert_valid_root(*root).await {
+            Ok(()) => Ok(true),
+            Err(error) if RpcTimeout::is_cause_of(&error) => {
+                warn!(?error, "Root validation timed out.");
+                Err(ServerError::RpcTimeout)
+            }
+            Err(error) if allow_unconfirmed && InvalidRoot::is_cause_of(&error) => {
+                warn!(computed_root = ?root, ?error, "Returning unconfirmed proof.");
+                Ok(false)
+            }
+            Err(error) if InvalidRoot::is_cause_of(&error) => {
+                warn!(?root, ?error, "Root not valid on chain.");
+                let is_latest = self
+                    .tree_state
+                    .read()
+                    .await
+                    .map_or(false, |tree| tree.merkle_tree.root() == *root);
+                if !is_latest {
+                    return Ok(false);
+                }
+                let latest_root = self.identity_manager.latest_root().await.ok();
+                let block = self.database.get_block_number().await.ok();
+                report_root_mismatch("contract", root, latest_root.as_ref(), block);
+                Ok(false)
+            }
+            Err(error) => {
+                // Not a mismatch: the contract was never asked.
+                warn!(?error, "Failed to validate root.");
+                Err(ServerError::RootUnchecked)
+            }
+        }
+    }
+
+    /// Returns inclusion proofs for commitments spread across groups, one per
+    /// entry and in request order. Each entry is proven against its own
+    /// group's tree and root, so an entry that can not be proven carries the
+    /// reason instead of failing the others. Each distinct root is checked on
+    /// chain once per request.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are more than `max_multi_group_proofs`
+    /// entries, or a proof fails for reasons other than the entry itself.
+    #[instrument(level = "debug", skip_all, fields(entries = entries.len()))]
+    pub async fn inclusion_proofs_multi_group(
+        &self,
+        entries: Vec<(usize, Hash)>,
+    ) -> Result<GroupInclusionProofsResponse, ServerError> {
+        if entries.len() > self.max_multi_group_proofs {
+            return Err(ServerError::BatchTooLarge(self.max_multi_group_proofs));
+        }
+
+        let mut checked_roots = HashMap::new();
+        let mut proofs = Vec::with_capacity(entries.len());
+        for (group_id, identity_commitment) in entries {
+            let (proof, error) = match self
+                .inclusion_proof_checking(
+                    group_id,
+                    &identity_commitment,
+                    None,
+                    false,
+                    &mut checked_roots,
+                )
+                .await
+            {
+                Ok(proof) => (Some(proof), None),
+                Err(
+                    error @ (ServerError::InvalidGroupId
+                    | ServerError::InvalidCommitment
+                    | ServerError::IdentityCommitmentNotFound
+                    | ServerError::RootMismatch),
+                ) => (None, Some(error.to_string())),
+                Err(error) => return Err(error),
+            };
+            proofs.push(GroupInclusionProof {
+                group_id,
+                identity_commitment,
+                proof,
+                error,
+            });
+        }
+        Ok(GroupInclusionProofsResponse(proofs))
+    }
+
+    /// Returns the proof of the first unset leaf, which holds the initial leaf
+    /// value, so clients can precompute the witness of the next insertion.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is invalid or the tree is full.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn next_empty_proof(
+        &self,
+        group_id: usize,
+    ) -> Result<InclusionProofResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+        self.serving_tree()?;
+
+        let tree = self.tree_state.read().await?;
+        let index = tree.next_leaf;
+        let root = tree.merkle_tree.root();
+        let proof = tree.merkle_tree.proof(index).ok_or(ServerError::TreeFull)?;
+        drop(tree);
+        self.proof_response(group_id, root, proof, index, false)
+            .await
+    }
+
+    /// Builds a proof response, signed if response signing is enabled.
+    async fn proof_response(
+        &self,
+        group_id: usize,
+        root: Field,
+        proof: Proof,
+        index: usize,
+        unconfirmed: bool,
+    ) -> Result<InclusionProofResponse, ServerError> {
+        let signature = match &self.response_signer {
+            Some(signer) => Some(
+                signer
+                    .sign_message(inclusion_proof_message(
+                        group_id,
+                        index,
+                        &root,
+                        &proof,
+                        unconfirmed,
+                    ))
+                    .await
+                    .map_err(|error| ServerError::Other(error.into()))?,
+            ),
+            None => None,
+        };
+        Ok(InclusionProofResponse::Proof {
+            group_id: Some(group_id),
+            root,
+            proof,
+            index: Some(index),
+            signature,
+            unconfirmed,
+        })
+    }
+
+    /// Checks a client-supplied proof of `commitment` against `root`, and
+    /// whether `root` is the latest root of the tree. Nothing is modified.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id or commitment is invalid, or if the
+    /// root was to be checked on chain and the contract could not be asked.
+    #[instrument(level = "debug", skip(self, proof))]
+    pub async fn verify_proof(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+        root: &Field,
+        proof: &Proof,
+        check_on_chain: bool,
+    ) -> Result<VerifyProofResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+        self.serving_tree()?;
+
+        if commitment == &self.identity_manager.initial_leaf_value() {
+            return Err(ServerError::InvalidCommitment);
+        }
 
-            if let Some(identity_index) = tree
-                .merkle_tree
-                .leaves()
+        let valid = proof.root(*commitment) == *root;
+        let root_status = {
+            let tree = self.tree_state.read().await?;
+            if *root == tree.merkle_tree.root() {
+                RootStatus::Current
+            } else if tree.retains_root(root) {
+                RootStatus::Historical
+            } else {
+                RootStatus::Unknown
+            }
+        };
+        let valid_on_chain = if check_on_chain {
+            match self.root_validator.assert_valid_root(*root).await {
+                Ok(()) => Some(true),
+                Err(error) if InvalidRoot::is_cause_of(&error) => Some(false),
+                Err(error) if RpcTimeout::is_cause_of(&error) => {
+                    warn!(?error, "Root validation timed out.");
+                    return Err(ServerError::RpcTimeout);
+                }
+                Err(error) => {
+                    warn!(?error, "Failed to validate root.");
+                    return Err(ServerError::RootUnchecked);
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(VerifyProofResponse {
+            valid,
+            root: root_status,
+            valid_on_chain,
+        })
+    }
+
+    /// Reports where `commitment` is in its lifecycle from queue to tree.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is invalid, the commitment is unknown
+    /// or the database query fails.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn identity_status(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+    ) -> Result<IdentityStatusResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+
+        if commitment == &self.identity_manager.initial_leaf_value() {
+            return Err(ServerError::InvalidCommitment);
+        }
+
+        // Check the database first: pending identities are only removed after they
+        // are inserted into the tree, so the reverse order could miss an identity.
+        let pending = self
+            .database
+            .get_pending_identity_state(group_id, commitment)
+            .await?;
+
+        let Some(pending) = pending else {
+            let tree = self.tree_state.read().await?;
+            let index = tree.merkle_tree.leaves()[..tree.next_leaf]
                 .iter()
-                .position(|&x| x == *commitment)
+                .position(|leaf| leaf == commitment)
+                .ok_or(ServerError::IdentityCommitmentNotFound)?;
+            return Ok(IdentityStatusResponse {
+                status:           IdentityStatus::Confirmed,
+                transaction_hash: None,
+                block_number:     None,
+                index:            Some(index),
+                failure:          None,
+            });
+        };
+
+        let status = match (&pending.transaction_hash, pending.mined_in_block) {
+            _ if pending.failure.is_some() => IdentityStatus::Failed,
+            (_, Some(_)) => IdentityStatus::Mined,
+            (Some(_), None) => IdentityStatus::Submitted,
+            (None, None) => IdentityStatus::Queued,
+        };
+        Ok(IdentityStatusResponse {
+            status,
+            transaction_hash: pending.transaction_hash,
+            block_number: pending.mined_in_block,
+            index: None,
+            failure: pending.failure,
+        })
+    }
+
+    /// Checks the bearer `token` of a request to an admin endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no admin token is configured or `token` does not
+    /// match it.
+    pub fn authorize_admin(&self, token: Option<&str>) -> Result<(), ServerError> {
+        match (&self.admin_token, token) {
+            // Compared in constant time, so the token can not be guessed byte by
+            // byte from response times.
+            (Some(admin_token), Some(token))
+                if bool::from(admin_token.as_bytes().ct_eq(token.as_bytes())) =>
             {
-                let proof = tree
-                    .merkle_tree
-                    .proof(identity_index)
-                    .ok_or(ServerError::IndexOutOfBounds)?;
-                let root = tree.merkle_tree.root();
-
-                // Locally check the proof
-                // TODO: Check the leaf index / path
-                if !tree.merkle_tree.verify(*commitment, &proof) {
-                    error!(
-                        ?commitment,
-                        ?identity_index,
-                        ?root,
-                        "Proof does not verify locally."
-                    );
-                    panic!("Proof does not verify locally.");
-                }
+                Ok(())
+            }
+            _ => {
+                warn!("Unauthorized admin request.");
+                Err(ServerError::Unauthorized)
+            }
+        }
+    }
 
-                drop(tree);
+    /// Fetches the membership events of the group in `from_block..=to_block`
+    /// from chain, as the chain subscriber would ingest them, along with where
+    /// each leaf is in the tree.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the range is reversed or longer than
+    /// [`MAX_LOG_BLOCK_RANGE`], or fetching the events fails.
+    #[instrument(level = "info", skip(self))]
+    pub async fn logs(&self, from_block: u64, to_block: u64) -> Result<LogsResponse, ServerError> {
+        if to_block < from_block || to_block - from_block >= MAX_LOG_BLOCK_RANGE {
+            return Err(ServerError::InvalidBlockRange(MAX_LOG_BLOCK_RANGE));
+        }
+        let logs = self
+            .identity_manager
+            .fetch_events(from_block, Some(to_block))
+            .ok_or_else(|| anyhow!("The identity manager does not fetch events"))?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|error| ServerError::Other(error.into()))?;
+
+        let events = logs
+            .into_iter()
+            .map(|log| {
+                let block = log.block_index.as_u64();
+                let event = ConfirmedIdentityEvent::try_from(log)
+                    .map_err(|error| ServerError::Other(error.into()))?;
+                Ok((block, event))
+            })
+            .collect::<Result<Vec<_>, ServerError>>()?;
 
-                // Verify the root on chain
-                if let Err(error) = self.identity_manager.assert_valid_root(root).await {
-                    error!(
-                        computed_root = ?root,
-                        ?error,
-                        "Root mismatch between tree and contract."
-                    );
-                    return Err(ServerError::RootMismatch);
+        // Find every leaf in one pass over the tree, keeping the first index.
+        let mut indices = events
+            .iter()
+            .map(|(_, event)| (event.leaf, None))
+            .collect::<HashMap<Field, Option<usize>>>();
+        {
+            let tree = self.tree_state.read().await?;
+            for (index, leaf) in tree.merkle_tree.leaves()[..tree.next_leaf].iter().enumerate() {
+                if let Some(slot) = indices.get_mut(leaf) {
+                    slot.get_or_insert(index);
                 }
-                return Ok(InclusionProofResponse::Proof { root, proof });
             }
         }
 
-        if self
+        let events = events
+            .into_iter()
+            .map(|(block, event)| LeafEvent {
+                block,
+                leaf: event.leaf,
+                index: indices[&event.leaf],
+                removed: event.removed,
+            })
+            .collect();
+        Ok(LogsResponse(events))
+    }
+
+    /// Commits all queued identities immediately instead of waiting for the
+    /// committer to be woken up.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the instance is read-only or committing fails.
+    #[instrument(level = "info", skip(self))]
+    pub async fn flush_identities(&self) -> Result<FlushResponse, ServerError> {
+        self.ensure_writable()?;
+        let transaction_hashes = self.identity_committer.flush().await?;
+        info!(
+            flushed = transaction_hashes.len(),
+            "Flushed pending identities."
+        );
+        Ok(FlushResponse {
+            flushed: transaction_hashes.len(),
+            transaction_hashes,
+        })
+    }
+
+    /// Stops broadcasting transactions while still queueing inserts, for
+    /// example during chain maintenance.
+    #[instrument(level = "info", skip(self))]
+    pub async fn pause_committer(&self) -> StatusResponse {
+        self.identity_committer.pause();
+        self.status().await
+    }
+
+    /// Resumes broadcasting transactions, committing everything queued while
+    /// paused.
+    #[instrument(level = "info", skip(self))]
+    pub async fn resume_committer(&self) -> StatusResponse {
+        self.identity_committer.resume().await;
+        self.status().await
+    }
+
+    /// Checks that the root of the local tree is valid on chain, and reports
+    /// the contract's current root alongside. A tree behind the contract, such
+    /// as while events await confirmations, still matches as long as its root
+    /// has not expired.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tree lock times out or the contract can not
+    /// be read.
+    pub async fn root_check(&self) -> Result<RootCheckResponse, ServerError> {
+        let (local_root, next_leaf) = {
+            let tree = self.tree_state.read().await?;
+            (tree.merkle_tree.root(), tree.next_leaf)
+        };
+        let on_chain_root = self.identity_manager.latest_root().await.map_err(|error| {
+            if RpcTimeout::is_cause_of(&error) {
+                ServerError::RpcTimeout
+            } else {
+                ServerError::Other(error)
+            }
+        })?;
+        let matches = if next_leaf == 0 {
+            // The root of an empty tree is not in the contract's history.
+            local_root == on_chain_root
+        } else {
+            self.root_is_valid(&local_root, false).await?
+        };
+        if !matches {
+            warn!(
+                ?local_root,
+                ?on_chain_root,
+                "Local root is not valid on chain."
+            );
+        }
+        Ok(RootCheckResponse {
+            local_root,
+            on_chain_root,
+            matches,
+        })
+    }
+
+    /// Returns the depth, root and number of used leaves of the group's tree.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is invalid or the tree lock times
+    /// out.
+    pub async fn group_info(&self, group_id: usize) -> Result<GroupInfoResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+
+        let tree = self.tree_state.read().await?;
+        Ok(GroupInfoResponse {
+            group_id,
+            tree_depth: self.identity_manager.tree_depth(),
+            root: tree.merkle_tree.root(),
+            leaf_count: tree.next_leaf,
+        })
+    }
+
+    pub async fn status(&self) -> StatusResponse {
+        let capacity_used = self
+            .tree_state
+            .read()
+            .await
+            .map_err(|error| warn!(?error, "Failed to read the tree capacity."))
+            .ok()
+            .map(|tree| tree.capacity_used());
+        if self.read_only {
+            return StatusResponse {
+                committer_paused: self.identity_committer.is_paused(),
+                read_only: true,
+                wallet_address: None,
+                wallet_balance: None,
+                capacity_used,
+            };
+        }
+        let wallet_balance = self
+            .ethereum
+            .balance()
+            .await
+            .map_err(|error| warn!(?error, "Failed to look up the wallet balance."))
+            .ok();
+        StatusResponse {
+            committer_paused: self.identity_committer.is_paused(),
+            read_only: false,
+            wallet_address: Some(self.ethereum.address()),
+            wallet_balance,
+            capacity_used,
+        }
+    }
+
+    /// Returns the progress of the initial sync with the chain.
+    #[must_use]
+    pub fn sync_progress(&self) -> SyncResponse {
+        self.sync_progress.response()
+    }
+
+    /// Lists identities queued for insertion but not yet in the tree.
+    ///
+    /// The prospective index assumes every queued identity is committed in
+    /// order, and is therefore only indicative.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is invalid or the database query
+    /// fails.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn pending_identities(
+        &self,
+        group_id: usize,
+        limit: usize,
+        offset: usize,
+    ) -> Result<PendingIdentitiesResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+
+        let next_leaf = self.tree_state.read().await?.next_leaf;
+        let pending = self
             .database
-            .pending_identity_exists(group_id, commitment)
+            .list_pending_identities(group_id, limit, offset)
             .await?
-        {
-            Ok(InclusionProofResponse::Pending)
-        } else {
-            Err(ServerError::IdentityCommitmentNotFound)
+            .into_iter()
+            .enumerate()
+            .map(|(position, identity)| PendingIdentity {
+                identity_commitment: identity.commitment,
+                created_at:          identity.created_at,
+                prospective_index:   next_leaf + offset + position,
+            })
+            .collect();
+
+        Ok(PendingIdentitiesResponse(pending))
+    }
+
+    /// Lists up to `count` inserted leaves starting at index `from`, capped at
+    /// `max_tree_leaves_count`. Leaves holding the initial value, such as
+    /// removed ones, are only listed if `include_empty` is set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is unknown or the tree lock times
+    /// out.
+    pub async fn tree_leaves(
+        &self,
+        group_id: usize,
+        from: usize,
+        count: usize,
+        include_empty: bool,
+    ) -> Result<TreeLeavesResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
+        }
+        self.serving_tree()?;
+
+        let initial_leaf = self.identity_manager.initial_leaf_value();
+        let count = count.min(self.max_tree_leaves_count);
+        let tree = self.tree_state.read().await?;
+        let end = from.saturating_add(count).min(tree.next_leaf);
+        let leaves = tree
+            .merkle_tree
+            .leaves()
+            .get(from..end)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| include_empty || **leaf != initial_leaf)
+            .map(|(offset, leaf)| TreeLeaf {
+                index:      from + offset,
+                commitment: *leaf,
+            })
+            .collect();
+        drop(tree);
+
+        Ok(TreeLeavesResponse(leaves))
+    }
+
+    /// Returns the block and transaction the leaf at `index` was inserted in.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the group id is unknown, no insertion of the leaf
+    /// was recorded, or the database query fails.
+    pub async fn leaf_origin(
+        &self,
+        group_id: usize,
+        index: usize,
+    ) -> Result<LeafOriginResponse, ServerError> {
+        if U256::from(group_id) != self.identity_manager.group_id() {
+            return Err(ServerError::InvalidGroupId);
         }
+
+        let origin = self
+            .database
+            .get_leaf_origin(index)
+            .await?
+            .ok_or(ServerError::LeafOriginUnknown)?;
+        Ok(LeafOriginResponse {
+            index,
+            commitment: origin.commitment,
+            block_number: origin.block_number,
+            transaction_hash: origin.transaction_hash,
+        })
     }
 
     /// # Errors
@@ -380,3 +2782,622 @@ impl App {
         self.identity_committer.shutdown().await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        contracts::{mock::MockIdentityManager, IdentityManager},
+        ethereum_subscriber::ROOT_MISMATCHES,
+    };
+    use clap::Parser;
+    use semaphore::{
+        merkle_tree::Hasher,
+        poseidon_tree::{PoseidonHash, PoseidonTree},
+    };
+    use serde_json::json;
+    use tracing_test::traced_test;
+
+    const LOCK_TIMEOUTS: LockTimeouts = LockTimeouts {
+        read:  Duration::from_secs(10),
+        write: Duration::from_secs(10),
+    };
+
+    #[tokio::test]
+    async fn busy_tree_refuses_proofs_instead_of_panicking() {
+        let tree_state = LockTimeouts {
+            read:  Duration::from_millis(20),
+            write: Duration::from_secs(10),
+        }
+        .tree_state(TreeState::new(11, Field::from(0)));
+        let _writer = tree_state.write().await.unwrap();
+
+        let result = tree_proof(&tree_state, &Field::from(1), None).await;
+
+        assert!(matches!(result, Err(ServerError::LockTimeout(_))));
+    }
+
+    #[test]
+    fn pending_response_body_is_bare_unless_detailed() {
+        let pending = |detailed| InclusionProofResponse::Pending {
+            group_id: Some(1),
+            retry_after: Some(Duration::from_secs(60)),
+            detailed,
+        };
+        assert_eq!(
+            serde_json::to_value(pending(false)).unwrap(),
+            json!("pending")
+        );
+        assert_eq!(
+            serde_json::to_value(pending(true)).unwrap(),
+            json!({ "groupId": 1, "status": "pending", "retryAfter": 60 })
+        );
+        assert_eq!(pending(true).retry_after(), Some(Duration::from_secs(60)));
+
+        let parsed: InclusionProofResponse =
+            serde_json::from_value(json!({ "status": "pending", "retryAfter": 60 })).unwrap();
+        assert!(matches!(parsed, InclusionProofResponse::Pending {
+            group_id: None,
+            retry_after: Some(retry_after),
+            detailed: true,
+        } if retry_after == Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::disallowed_methods)] // False positive from macro
+    #[traced_test]
+    async fn startup_phases_are_logged_in_order() {
+        startup_phase("connect", async { info!("connecting") }).await;
+        let loaded = startup_phase("load_events", async { 42 }).await;
+        assert_eq!(loaded, 42);
+
+        logs_assert(|lines: &[&str]| {
+            let phases = lines
+                .iter()
+                .filter(|line| line.contains("Startup phase completed."))
+                .filter_map(|line| line.split("phase=").nth(1)?.split_whitespace().next())
+                .collect::<Vec<_>>();
+            if phases != ["\"connect\"", "\"load_events\""] {
+                return Err(format!("Unexpected phases: {phases:?}"));
+            }
+            let inside_span = lines.iter().any(|line| {
+                line.contains("startup_phase{phase=\"connect\"}") && line.contains("connecting")
+            });
+            if inside_span {
+                Ok(())
+            } else {
+                Err("Phase work is not logged inside its span".to_owned())
+            }
+        });
+    }
+
+    #[test]
+    fn binary_proof_round_trips() {
+        let mut tree = TreeState::new(12, Field::from(0));
+        for leaf in 0..20 {
+            tree.merkle_tree.set(leaf, Field::from(leaf as u64 + 1));
+        }
+        let proof = tree.merkle_tree.proof(13).unwrap();
+        let root = tree.merkle_tree.root();
+
+        let bytes = encode_binary_proof(&root, &proof, true);
+        assert_eq!(
+            bytes.len(),
+            2 + 32 + (proof.0.len() + 7) / 8 + 32 * proof.0.len()
+        );
+        let Some(InclusionProofResponse::Proof {
+            root: decoded_root,
+            proof: decoded_proof,
+            index,
+            unconfirmed,
+            ..
+        }) = decode_binary_proof(&bytes)
+        else {
+            panic!("Failed to decode binary proof");
+        };
+        assert_eq!(decoded_root, root);
+        assert!(decoded_proof == proof);
+        assert_eq!(index, Some(13));
+        assert!(unconfirmed);
+
+        assert!(decode_binary_proof(&bytes[..bytes.len() - 1]).is_none());
+        assert!(decode_binary_proof(&[]).is_none());
+    }
+
+    #[test]
+    fn flat_proof_reconstructs_the_root() {
+        let mut tree = TreeState::new(12, Field::from(0));
+        for leaf in 0..20 {
+            tree.merkle_tree.set(leaf, Field::from(leaf as u64 + 1));
+        }
+        let proof = tree.merkle_tree.proof(13).unwrap();
+        let root = tree.merkle_tree.root();
+
+        let flat = FlatInclusionProof::new(Some(1), root, &proof, Some(13), false);
+        assert_eq!(flat.siblings.len(), flat.path_indices.len());
+        let index = flat
+            .path_indices
+            .iter()
+            .rev()
+            .fold(0, |index, bit| index * 2 + usize::from(*bit));
+        assert_eq!(index, 13);
+
+        let reconstructed = flat.siblings.iter().zip(&flat.path_indices).fold(
+            Field::from(14),
+            |node, (sibling, path_index)| {
+                if *path_index == 0 {
+                    PoseidonHash::hash_node(&node, sibling)
+                } else {
+                    PoseidonHash::hash_node(sibling, &node)
+                }
+            },
+        );
+        assert_eq!(reconstructed, root);
+
+        let json = serde_json::to_value(&flat).unwrap();
+        assert_eq!(json["pathIndices"][0], json!(1));
+        assert!(json.get("unconfirmed").is_none());
+    }
+
+    #[test]
+    fn queue_low_water_mark_must_be_reachable() {
+        let limit = |args: &[&str]| queue_limit(&Options::try_parse_from(args).unwrap());
+        assert_eq!(limit(&[""]).unwrap(), None);
+        assert_eq!(
+            limit(&["", "--max-pending-identities", "100"]).unwrap(),
+            Some(QueueLimit {
+                max:       100,
+                low_water: 90,
+            })
+        );
+        for low_water in ["0", "101"] {
+            let args = [
+                "",
+                "--max-pending-identities",
+                "100",
+                "--pending-identities-low-water",
+                low_water,
+            ];
+            assert!(limit(&args).is_err(), "{low_water}");
+        }
+    }
+
+    #[test]
+    fn proofs_are_verified_locally_by_default() {
+        let options = Options::try_parse_from([""]).unwrap();
+        assert!(options.verify_proofs_locally);
+        let options = Options::try_parse_from(["", "--verify-proofs-locally", "false"]).unwrap();
+        assert!(!options.verify_proofs_locally);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proof does not verify locally.")]
+    fn tampered_proofs_fail_local_verification() {
+        let mut tree = TreeState::new(5, Field::from(0));
+        tree.merkle_tree.set(3, Field::from(4));
+        let root = tree.merkle_tree.root();
+        let proof = tree.merkle_tree.proof(3).unwrap();
+        check_proof_locally(&Field::from(4), 3, &root, &proof);
+
+        check_proof_locally(&Field::from(5), 3, &root, &proof);
+    }
+
+    #[test]
+    fn proof_path_follows_index_bits() {
+        let mut tree = TreeState::new(5, Field::from(0));
+        for leaf in 0..8 {
+            tree.merkle_tree.set(leaf, Field::from(leaf as u64 + 1));
+        }
+        for index in 0..8 {
+            let proof = tree.merkle_tree.proof(index).unwrap();
+            assert!(proof_path_matches_index(&proof, index));
+            assert!(!proof_path_matches_index(&proof, index ^ 1));
+            assert!(!proof_path_matches_index(
+                &proof,
+                index + (1 << proof.0.len())
+            ));
+        }
+        let proof = tree.merkle_tree.proof(0b110).unwrap();
+        let bits = proof
+            .0
+            .iter()
+            .take(3)
+            .map(|branch| matches!(branch, Branch::Right(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(bits, [false, true, true]);
+    }
+
+    #[tokio::test]
+    async fn tree_proof_releases_lock_before_verification() {
+        let mut tree = TreeState::new(10, Field::from(0)).with_root_history(10);
+        let mut historical_root = None;
+        for leaf in 1..=4 {
+            tree.merkle_tree.set(tree.next_leaf, Field::from(leaf));
+            tree.next_leaf += 1;
+            tree.record_root(tree.merkle_tree.root(), tree.next_leaf);
+            if leaf == 2 {
+                historical_root = Some(tree.merkle_tree.root());
+            }
+        }
+        let tree_state = LOCK_TIMEOUTS.tree_state(tree);
+
+        let found = tree_proof(&tree_state, &Field::from(2), None)
+            .await
+            .unwrap()
+            .unwrap();
+        // A writer takes the lock and changes the tree while the proof is
+        // checked.
+        let mut writer = tokio::time::timeout(Duration::from_millis(100), tree_state.write())
+            .await
+            .expect("Tree lock still held after the lookup")
+            .unwrap();
+        let verification = tokio::task::spawn_blocking(move || {
+            check_proof_locally(&Field::from(2), found.index, &found.root, &found.proof);
+            found
+        });
+        let next_leaf = writer.next_leaf;
+        writer.merkle_tree.set(next_leaf, Field::from(6));
+        writer.next_leaf += 1;
+        let found = verification.await.unwrap();
+        drop(writer);
+        assert_eq!(found.index, 1);
+        assert_eq!(found.proof.root(Field::from(2)), found.root);
+
+        let historical = tree_proof(&tree_state, &Field::from(2), historical_root.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(historical.root), historical_root);
+        assert_eq!(historical.proof.root(Field::from(2)), historical.root);
+
+        assert!(tree_proof(&tree_state, &Field::from(5), None)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            tree_proof(&tree_state, &Field::from(2), Some(&Field::from(1234))).await,
+            Err(ServerError::RootNotRetained)
+        ));
+    }
+
+    async fn test_database() -> Arc<Database> {
+        Arc::new(
+            Database::new(database::Options::try_parse_from([""]).unwrap())
+                .await
+                .unwrap(),
+        )
+    }
+
+    /// An empty tree of the depth of `identity_manager`, and a committer
+    /// over it.
+    fn test_committer(
+        database: &Arc<Database>,
+        identity_manager: &SharedIdentityManager,
+    ) -> (SharedTreeState, Arc<IdentityCommitter>) {
+        let tree_state = LOCK_TIMEOUTS.tree_state(TreeState::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        ));
+        let identity_committer = Arc::new(IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        ));
+        (tree_state, identity_committer)
+    }
+
+    /// A cache of one event per block up to block 100, with a corrupted leaf
+    /// at block 60.
+    async fn cache_with_bad_event() -> (
+        Arc<Database>,
+        SharedIdentityManager,
+        SharedTreeState,
+        Arc<IdentityCommitter>,
+    ) {
+        let database = test_database().await;
+        let identity_manager: SharedIdentityManager =
+            Arc::new(MockIdentityManager::mining_at(Some(200)));
+        let (tree_state, identity_committer) = test_committer(&database, &identity_manager);
+
+        let mut tree = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        for block in 1..=100_u32 {
+            let leaf = Field::from(block);
+            tree.set(block as usize - 1, leaf);
+            let cached_leaf = if block == 60 { Field::from(1234) } else { leaf };
+            database
+                .save_log(&ConfirmedIdentityEvent {
+                    block_index:       block.into(),
+                    transaction_index: 0,
+                    log_index:         0,
+                    raw_log:           String::new(),
+                    transaction_hash:  String::new(),
+                    leaf:              cached_leaf,
+                    root:              tree.root(),
+                    removed:           false,
+                })
+                .await
+                .unwrap();
+        }
+        (database, identity_manager, tree_state, identity_committer)
+    }
+
+    #[tokio::test]
+    async fn bad_cached_event_is_recovered_without_wipe() {
+        let (database, identity_manager, tree_state, identity_committer) =
+            cache_with_bad_event().await;
+
+        load_initial_events(
+            &database,
+            &identity_manager,
+            &identity_committer,
+            &Arc::default(),
+            &tree_state,
+            1,
+            CacheRecovery {
+                step_size:    10,
+                max_attempts: 5,
+                wipe:         true,
+                trust:        false,
+            },
+        )
+        .await
+        .unwrap();
+
+        // Blocks 100..90, 89..69 and 68..28 were removed, the rest is intact.
+        assert_eq!(database.get_block_number().await.unwrap(), 27);
+        assert_eq!(tree_state.read().await.unwrap().next_leaf, 27);
+    }
+
+    #[tokio::test]
+    async fn persistent_mismatch_is_not_wiped_if_disabled() {
+        let (database, identity_manager, tree_state, identity_committer) =
+            cache_with_bad_event().await;
+
+        let result = load_initial_events(
+            &database,
+            &identity_manager,
+            &identity_committer,
+            &Arc::default(),
+            &tree_state,
+            1,
+            CacheRecovery {
+                step_size:    10,
+                max_attempts: 1,
+                wipe:         false,
+                trust:        false,
+            },
+        )
+        .await;
+
+        assert!(matches!(
+            result.err().unwrap().downcast_ref::<SubscriberError>(),
+            Some(SubscriberError::RootMismatch)
+        ));
+        // Only blocks 100..91 were removed, and the tree was left untouched.
+        assert_eq!(database.get_block_number().await.unwrap(), 90);
+        assert_eq!(tree_state.read().await.unwrap().next_leaf, 0);
+    }
+
+    #[tokio::test]
+    async fn root_mismatch_is_counted() {
+        let (database, identity_manager, tree_state, identity_committer) =
+            cache_with_bad_event().await;
+        let mismatches = ROOT_MISMATCHES.with_label_values(&["cache"]);
+        let before = mismatches.get();
+
+        load_initial_events(
+            &database,
+            &identity_manager,
+            &identity_committer,
+            &Arc::default(),
+            &tree_state,
+            1,
+            CacheRecovery {
+                step_size:    10,
+                max_attempts: 0,
+                wipe:         false,
+                trust:        false,
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(mismatches.get() > before);
+    }
+
+    #[tokio::test]
+    async fn trusted_cache_start_skips_historical_events() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(200)));
+        let shared_identity_manager: SharedIdentityManager = identity_manager.clone();
+        let (tree_state, identity_committer) =
+            test_committer(&database, &shared_identity_manager);
+
+        let mut tree = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        for block in 1..=10_u32 {
+            let leaf = Field::from(block);
+            tree.set(block as usize - 1, leaf);
+            database
+                .save_log(&ConfirmedIdentityEvent {
+                    block_index: block.into(),
+                    transaction_index: 0,
+                    log_index: 0,
+                    raw_log: String::new(),
+                    transaction_hash: String::new(),
+                    leaf,
+                    root: tree.root(),
+                    removed: false,
+                })
+                .await
+                .unwrap();
+        }
+        let cache_recovery = |trust| CacheRecovery {
+            step_size: 10,
+            max_attempts: 0,
+            wipe: false,
+            trust,
+        };
+
+        load_initial_events(
+            &database,
+            &shared_identity_manager,
+            &identity_committer,
+            &Arc::default(),
+            &tree_state,
+            1,
+            cache_recovery(true),
+        )
+        .await
+        .unwrap();
+        assert!(identity_manager.fetches().is_empty());
+        assert_eq!(identity_manager.root_checks(), 1);
+        assert_eq!(
+            tree_state.read().await.unwrap().merkle_tree.root(),
+            tree.root()
+        );
+
+        // A full rebuild fetches the events after the cache first.
+        load_initial_events(
+            &database,
+            &shared_identity_manager,
+            &identity_committer,
+            &Arc::default(),
+            &tree_state,
+            1,
+            cache_recovery(false),
+        )
+        .await
+        .unwrap();
+        assert_eq!(identity_manager.fetches(), vec![(11, Some(200))]);
+    }
+
+    #[tokio::test]
+    async fn verify_ignores_unconfirmed_insertions() {
+        let database = test_database().await;
+        let identity_manager = MockIdentityManager::mining_at(Some(10)).emitting_registrations();
+        identity_manager
+            .register_identities(vec![Field::from(1), Field::from(2)])
+            .await
+            .unwrap();
+        // Mined after the confirmed block, so left out of the rebuild.
+        identity_manager.add_unconfirmed_member(Field::from(3));
+        let identity_manager: SharedIdentityManager = Arc::new(identity_manager);
+        assert_eq!(identity_manager.leaf_count().await.unwrap(), 3);
+
+        verify_tree(&database, &identity_manager, LOCK_TIMEOUTS, 1)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_detects_corrupted_cache() {
+        let database = test_database().await;
+        let identity_manager = MockIdentityManager::mining_at(Some(10)).emitting_registrations();
+        identity_manager
+            .register_identities(vec![Field::from(1), Field::from(2), Field::from(3)])
+            .await
+            .unwrap();
+        // Removed leaves still count towards the contract's leaf count.
+        identity_manager.remove_member(Field::from(1));
+        let identity_manager: SharedIdentityManager = Arc::new(identity_manager);
+        database
+            .insert_pending_identity(1, &Field::from(2))
+            .await
+            .unwrap();
+
+        verify_tree(&database, &identity_manager, LOCK_TIMEOUTS, 1)
+            .await
+            .unwrap();
+
+        // Nothing was written, so a running instance is not disturbed.
+        assert!(database
+            .load_logs(0, None, None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(database
+            .get_pending_identity_state(1, &Field::from(2))
+            .await
+            .unwrap()
+            .is_some());
+
+        // An event that never happened on chain.
+        database
+            .save_log(&ConfirmedIdentityEvent {
+                block_index:       5,
+                transaction_index: 0,
+                log_index:         0,
+                raw_log:           String::new(),
+                transaction_hash:  String::new(),
+                leaf:              Field::from(1234),
+                root:              Field::from(5678),
+                removed:           false,
+            })
+            .await
+            .unwrap();
+
+        assert!(verify_tree(&database, &identity_manager, LOCK_TIMEOUTS, 1)
+            .await
+            .is_err());
+    }
+}
+
+#[cfg(feature = "bench")]
+pub mod bench {
+    use super::*;
+    use crate::bench::runtime;
+    use criterion::{black_box, Criterion};
+
+    pub fn group(criterion: &mut Criterion) {
+        bench_proof_lock_hold(criterion);
+    }
+
+    /// Time a tree update waits for the lock while proofs are served
+    /// concurrently.
+    fn bench_proof_lock_hold(criterion: &mut Criterion) {
+        let runtime = runtime();
+        let mut tree = TreeState::new(20, Field::from(0));
+        for leaf in 1..=1024_u64 {
+            tree.merkle_tree.set(tree.next_leaf, Field::from(leaf));
+            tree.next_leaf += 1;
+        }
+        let tree_state = Arc::new(TimedRwLock::new(Duration::from_secs(10), tree));
+
+        criterion.bench_function("inclusion_proof_lock_hold", |bencher| {
+            bencher.to_async(&runtime).iter_custom(|iterations| {
+                let tree_state = tree_state.clone();
+                async move {
+                    let mut waited = Duration::ZERO;
+                    for _ in 0..iterations {
+                        let readers = (1..=32_u64)
+                            .map(|leaf| {
+                                let tree_state = tree_state.clone();
+                                tokio::spawn(async move {
+                                    let commitment = Field::from(leaf);
+                                    let found = tree_proof(&tree_state, &commitment, None)
+                                        .await
+                                        .unwrap()
+                                        .unwrap();
+                                    black_box(found.proof.root(commitment) == found.root);
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let start = Instant::now();
+                        drop(tree_state.write().await.unwrap());
+                        waited += start.elapsed();
+                        for reader in readers {
+                            reader.await.unwrap();
+                        }
+                    }
+                    waited
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,405 @@
+//! A typed client for the sequencer HTTP API.
+use crate::{
+    app::{
+        decode_binary_proof, inclusion_proof_message, IdentityStatusResponse,
+        InclusionProofResponse, InsertIdentityResponse, PendingIdentitiesResponse,
+        VerifyProofResponse,
+    },
+    identity_tree::Hash,
+    server::{
+        IdentityStatusRequest, InclusionProofRequest, InsertCommitmentRequest, ListPendingRequest,
+        VerifyProofRequest, CONTENT_BINARY_PROOF, SIGNATURE_HEADER,
+    },
+};
+use ethers::types::Address;
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
+use semaphore::{merkle_tree::Branch, poseidon_tree::Proof};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid request url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("error sending request: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("error encoding query string: {0}")]
+    Query(#[from] serde_urlencoded::ser::Error),
+    #[error("sequencer responded with {status}: {message}")]
+    Status {
+        status:  StatusCode,
+        message: String,
+    },
+    #[error("response is not signed")]
+    Unsigned,
+    #[error("invalid response signature")]
+    InvalidSignature,
+    #[error("malformed binary proof")]
+    InvalidBinaryProof,
+    #[error("identity commitment is not in the tree yet")]
+    NotIncluded,
+}
+
+/// A client for a running sequencer.
+#[derive(Clone, Debug)]
+pub struct SequencerClient {
+    client: Client,
+    url:    Url,
+}
+
+impl SequencerClient {
+    /// Creates a client for the sequencer listening at `url`.
+    #[must_use]
+    pub fn new(url: Url) -> Self {
+        Self::with_client(Client::new(), url)
+    }
+
+    /// Creates a client for the sequencer listening at `url` that sends its
+    /// requests through `client`. Routes are resolved below the path of `url`,
+    /// with or without a trailing slash.
+    #[must_use]
+    pub fn with_client(client: Client, mut url: Url) -> Self {
+        // Joining replaces the last path segment unless it ends with a slash.
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Self { client, url }
+    }
+
+    /// The URL of `route` below the sequencer's URL.
+    fn endpoint(&self, route: &str) -> Result<Url, Error> {
+        Ok(self.url.join(route)?)
+    }
+
+    /// Queues `identity_commitment` for insertion into the tree, returning the
+    /// index it is expected to be inserted at.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails or the sequencer rejects the
+    /// commitment.
+    pub async fn insert_identity(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<InsertIdentityResponse, Error> {
+        let request = InsertCommitmentRequest {
+            group_id,
+            identity_commitment,
+        };
+        Self::send(
+            self.client
+                .post(self.endpoint("insertIdentity")?)
+                .json(&request),
+        )
+        .await
+    }
+
+    /// Fetches an inclusion proof for `identity_commitment`, or
+    /// [`InclusionProofResponse::Pending`] with the suggested polling delay if
+    /// it is not in the tree yet.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails or the commitment is unknown.
+    pub async fn inclusion_proof(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<InclusionProofResponse, Error> {
+        self.inclusion_proof_at(group_id, identity_commitment, None)
+            .await
+    }
+
+    /// Like [`Self::inclusion_proof`], but proves inclusion against `root`
+    /// instead of the latest root if given.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails, the commitment is unknown, or
+    /// the sequencer no longer retains `root`.
+    pub async fn inclusion_proof_at(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+        root: Option<Hash>,
+    ) -> Result<InclusionProofResponse, Error> {
+        self.request_inclusion_proof(
+            InclusionProofRequest {
+                group_id,
+                identity_commitment,
+                root,
+                allow_unconfirmed: false,
+            },
+            false,
+        )
+        .await
+    }
+
+    /// Like [`Self::inclusion_proof`], but has the proof sent in the compact
+    /// binary format, which is decoded into the same response. The decoded
+    /// proof carries no group id.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails, the commitment is unknown, or
+    /// the binary proof is malformed.
+    pub async fn binary_inclusion_proof(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<InclusionProofResponse, Error> {
+        self.request_inclusion_proof(
+            InclusionProofRequest {
+                group_id,
+                identity_commitment,
+                root: None,
+                allow_unconfirmed: false,
+            },
+            true,
+        )
+        .await
+    }
+
+    /// Like [`Self::inclusion_proof`], but returns a proof flagged as
+    /// unconfirmed if its root is not yet valid on chain, or if the identity is
+    /// still queued.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails or the commitment is unknown.
+    pub async fn unconfirmed_inclusion_proof(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<InclusionProofResponse, Error> {
+        self.request_inclusion_proof(
+            InclusionProofRequest {
+                group_id,
+                identity_commitment,
+                root: None,
+                allow_unconfirmed: true,
+            },
+            false,
+        )
+        .await
+    }
+
+    async fn request_inclusion_proof(
+        &self,
+        request: InclusionProofRequest,
+        binary: bool,
+    ) -> Result<InclusionProofResponse, Error> {
+        let mut builder = self
+            .client
+            .post(self.endpoint("inclusionProof")?)
+            .json(&request);
+        if binary {
+            builder = builder.header(header::ACCEPT, CONTENT_BINARY_PROOF);
+        }
+        let response = builder.send().await?;
+        let signature = response
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or(Error::InvalidSignature)
+            })
+            .transpose()?;
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+        let is_binary = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map_or(false, |content_type| content_type == CONTENT_BINARY_PROOF);
+        let mut proof = if is_binary && response.status().is_success() {
+            decode_binary_proof(&response.bytes().await?).ok_or(Error::InvalidBinaryProof)?
+        } else {
+            Self::parse(response).await?
+        };
+        match &mut proof {
+            InclusionProofResponse::Proof {
+                signature: proof_signature,
+                ..
+            } => *proof_signature = signature,
+            InclusionProofResponse::Pending {
+                retry_after: pending_retry_after,
+                ..
+            } => *pending_retry_after = pending_retry_after.or(retry_after),
+        }
+        Ok(proof)
+    }
+
+    /// Fetches the proof of an identity that is already in the tree, returning
+    /// the root it proves inclusion against along with the proof.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails, the commitment is unknown, or
+    /// the identity is still pending.
+    pub async fn inclusion_proof_by_commitment(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<(Hash, Proof), Error> {
+        match self.inclusion_proof(group_id, identity_commitment).await? {
+            InclusionProofResponse::Proof { root, proof, .. } => Ok((root, proof)),
+            InclusionProofResponse::Pending { .. } => Err(Error::NotIncluded),
+        }
+    }
+
+    /// Has the sequencer verify `proof` of `identity_commitment` against
+    /// `root`, and optionally check that `root` is valid on chain.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails or the commitment is invalid.
+    pub async fn verify_proof(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+        root: Hash,
+        proof: Proof,
+        check_on_chain: bool,
+    ) -> Result<VerifyProofResponse, Error> {
+        let request = VerifyProofRequest {
+            group_id,
+            identity_commitment,
+            root,
+            proof,
+            check_on_chain,
+        };
+        Self::send(
+            self.client
+                .post(self.endpoint("proof/verify")?)
+                .json(&request),
+        )
+        .await
+    }
+
+    /// Fetches the lifecycle stage of `identity_commitment`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails or the commitment is unknown.
+    pub async fn identity_status(
+        &self,
+        group_id: usize,
+        identity_commitment: Hash,
+    ) -> Result<IdentityStatusResponse, Error> {
+        let request = IdentityStatusRequest {
+            group_id,
+            identity_commitment,
+        };
+        Self::send(
+            self.client
+                .post(self.endpoint("identityStatus")?)
+                .json(&request),
+        )
+        .await
+    }
+
+    /// Lists up to `limit` queued identities, skipping the first `offset`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails.
+    pub async fn pending_identities(
+        &self,
+        group_id: usize,
+        limit: usize,
+        offset: usize,
+    ) -> Result<PendingIdentitiesResponse, Error> {
+        let request = ListPendingRequest {
+            group_id,
+            limit,
+            offset,
+        };
+        let mut url = self.endpoint("pending")?;
+        url.set_query(Some(&serde_urlencoded::to_string(&request)?));
+        Self::send(self.client.get(url)).await
+    }
+
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        Self::parse(request.send().await?).await
+    }
+
+    async fn parse<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await?;
+            return Err(Error::Status { status, message });
+        }
+        Ok(response.json().await?)
+    }
+}
+
+/// Verifies that an inclusion proof of a member of `group_id` was signed by the
+/// sequencer with the Ethereum address `signer`.
+///
+/// # Errors
+///
+/// Will return `Err` if the response is not a signed proof or the signature was
+/// not made by `signer` over a proof for `group_id` in its confirmation state.
+pub fn verify_inclusion_proof(
+    response: &InclusionProofResponse,
+    group_id: usize,
+    signer: Address,
+) -> Result<(), Error> {
+    let InclusionProofResponse::Proof {
+        root,
+        proof,
+        signature: Some(signature),
+        unconfirmed,
+        ..
+    } = response
+    else {
+        return Err(Error::Unsigned);
+    };
+    let message =
+        inclusion_proof_message(group_id, proof_leaf_index(proof), root, proof, *unconfirmed);
+    signature
+        .verify(message, signer)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// The index of the leaf `proof` is for, spelled out by its branches from the
+/// leaf up: a left branch for a `0` bit and a right branch for a `1` bit.
+fn proof_leaf_index(proof: &Proof) -> usize {
+    proof.0.iter().rev().fold(0, |index, branch| {
+        (index << 1) | usize::from(matches!(branch, Branch::Right(_)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn routes_are_resolved_below_the_url_path() {
+        for url in ["http://localhost:8080", "http://localhost:8080/"] {
+            let client = SequencerClient::new(Url::parse(url).unwrap());
+            assert_eq!(
+                client.endpoint("inclusionProof").unwrap().as_str(),
+                "http://localhost:8080/inclusionProof"
+            );
+        }
+        for url in ["http://localhost/sequencer", "http://localhost/sequencer/"] {
+            let client = SequencerClient::new(Url::parse(url).unwrap());
+            assert_eq!(
+                client.endpoint("proof/verify").unwrap().as_str(),
+                "http://localhost/sequencer/proof/verify"
+            );
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{identity_tree::Hash, utils::reload_on_hangup};
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::info;
+
+/// A set of commitments read from a file with one hex encoded commitment per
+/// line. Empty lines and lines starting with `#` are ignored.
+#[derive(Debug)]
+struct CommitmentList {
+    path:        PathBuf,
+    commitments: RwLock<HashSet<Hash>>,
+}
+
+impl CommitmentList {
+    fn new(path: PathBuf) -> AnyhowResult<Self> {
+        let commitments = RwLock::new(read_commitments(&path)?);
+        Ok(Self { path, commitments })
+    }
+
+    fn reload(&self) -> AnyhowResult<()> {
+        let commitments = read_commitments(&self.path)?;
+        info!(path = %self.path.display(), count = commitments.len(), "Loaded commitment list.");
+        *self
+            .commitments
+            .write()
+            .expect("Commitment list lock poisoned") = commitments;
+        Ok(())
+    }
+
+    fn contains(&self, commitment: &Hash) -> bool {
+        self.commitments
+            .read()
+            .expect("Commitment list lock poisoned")
+            .contains(commitment)
+    }
+}
+
+fn read_commitments(path: &Path) -> AnyhowResult<HashSet<Hash>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let digits = line.strip_prefix("0x").unwrap_or(line);
+            Hash::from_str_radix(digits, 16).map_err(|error| {
+                anyhow!(
+                    "Invalid commitment on line {} of {}: {error}",
+                    index + 1,
+                    path.display()
+                )
+            })
+        })
+        .collect()
+}
+
+/// Decides which commitments may be inserted. Denylisted commitments are never
+/// accepted, and once an allowlist is configured only commitments on it are.
+#[derive(Debug, Default)]
+pub struct CommitmentFilter {
+    allowlist: Option<CommitmentList>,
+    denylist:  Option<CommitmentList>,
+}
+
+impl CommitmentFilter {
+    /// # Errors
+    ///
+    /// Will return `Err` if a configured list can not be read or parsed.
+    pub fn new(allowlist: Option<PathBuf>, denylist: Option<PathBuf>) -> AnyhowResult<Self> {
+        Ok(Self {
+            allowlist: allowlist.map(CommitmentList::new).transpose()?,
+            denylist:  denylist.map(CommitmentList::new).transpose()?,
+        })
+    }
+
+    #[must_use]
+    pub const fn is_configured(&self) -> bool {
+        self.allowlist.is_some() || self.denylist.is_some()
+    }
+
+    #[must_use]
+    pub fn permits(&self, commitment: &Hash) -> bool {
+        let denied = self
+            .denylist
+            .as_ref()
+            .map_or(false, |list| list.contains(commitment));
+        let allowed = self
+            .allowlist
+            .as_ref()
+            .map_or(true, |list| list.contains(commitment));
+        allowed && !denied
+    }
+
+    /// Reads both lists again. A list that fails to load keeps its previous
+    /// contents.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a list can not be read or parsed.
+    pub fn reload(&self) -> AnyhowResult<()> {
+        let allowlist = self
+            .allowlist
+            .as_ref()
+            .map_or(Ok(()), CommitmentList::reload);
+        let denylist = self
+            .denylist
+            .as_ref()
+            .map_or(Ok(()), CommitmentList::reload);
+        allowlist.and(denylist)
+    }
+
+    /// Reloads the lists on every SIGHUP until shutdown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the signal handler can not be installed.
+    pub async fn reload_on_hangup(self: Arc<Self>) -> AnyhowResult<()> {
+        reload_on_hangup("commitment_filter", || self.reload()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn list_file(name: &str, commitments: &[Hash]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("signup-sequencer-{name}-{}", std::process::id()));
+        let contents = commitments
+            .iter()
+            .map(|commitment| format!("{commitment:#x}\n"))
+            .collect::<String>();
+        fs::write(&path, format!("# {name}\n\n{contents}")).unwrap();
+        path
+    }
+
+    #[test]
+    fn denylisted_commitments_are_refused() {
+        let denied = Hash::from(1);
+        let other = Hash::from(2);
+        let denylist = list_file("denylist", &[denied]);
+        let filter = CommitmentFilter::new(None, Some(denylist.clone())).unwrap();
+
+        assert!(!filter.permits(&denied));
+        assert!(filter.permits(&other));
+
+        // Lists are replaced on reload.
+        list_file("denylist", &[other]);
+        filter.reload().unwrap();
+        assert!(filter.permits(&denied));
+        assert!(!filter.permits(&other));
+
+        fs::remove_file(denylist).unwrap();
+    }
+
+    #[test]
+    fn only_allowlisted_commitments_are_accepted() {
+        let allowed = Hash::from(1);
+        let denied = Hash::from(2);
+        let allowlist = list_file("allowlist", &[allowed, denied]);
+        let denylist = list_file("allowlist-denylist", &[denied]);
+        let filter =
+            CommitmentFilter::new(Some(allowlist.clone()), Some(denylist.clone())).unwrap();
+
+        assert!(filter.permits(&allowed));
+        assert!(!filter.permits(&denied));
+        assert!(!filter.permits(&Hash::from(3)));
+
+        // A broken list keeps its previous contents.
+        fs::write(&allowlist, "not a commitment").unwrap();
+        assert!(filter.reload().is_err());
+        assert!(filter.permits(&allowed));
+
+        assert!(CommitmentFilter::default().permits(&Hash::from(3)));
+
+        fs::remove_file(allowlist).unwrap();
+        fs::remove_file(denylist).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use crate::ethereum::ProviderStack;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use ethers::{providers::Middleware, types::Address};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use thiserror::Error;
+use tracing::{error, info};
+
+/// The address of a contract, either given directly or as an ENS name that is
+/// resolved once at startup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContractAddress {
+    Address(Address),
+    Ens(String),
+}
+
+#[derive(Debug, Error)]
+#[error("Expected a hex address or an ENS name, got {0:?}")]
+pub struct ParseError(String);
+
+impl FromStr for ContractAddress {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = s.parse() {
+            return Ok(Self::Address(address));
+        }
+        // ENS names are dot-separated labels, such as `semaphore.eth`.
+        if s.starts_with("0x") || !s.contains('.') || s.split('.').any(str::is_empty) {
+            return Err(ParseError(s.to_owned()));
+        }
+        Ok(Self::Ens(s.to_owned()))
+    }
+}
+
+impl Display for ContractAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "{address:?}"),
+            Self::Ens(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Serialize for ContractAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl From<Address> for ContractAddress {
+    fn from(address: Address) -> Self {
+        Self::Address(address)
+    }
+}
+
+impl ContractAddress {
+    /// Returns the address, resolving ENS names through `resolver`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the name can not be resolved.
+    pub async fn resolve<R: NameResolver + Sync>(&self, resolver: &R) -> anyhow::Result<Address> {
+        match self {
+            Self::Address(address) => Ok(*address),
+            Self::Ens(name) => {
+                let address = resolver.resolve_name(name).await.map_err(|error| {
+                    error!(%name, ?error, "Failed to resolve contract ENS name.");
+                    error
+                })?;
+                info!(%name, ?address, "Resolved contract ENS name.");
+                Ok(address)
+            }
+        }
+    }
+}
+
+/// Resolves ENS names to addresses.
+#[async_trait]
+pub trait NameResolver {
+    async fn resolve_name(&self, name: &str) -> anyhow::Result<Address>;
+}
+
+#[async_trait]
+impl NameResolver for ProviderStack {
+    async fn resolve_name(&self, name: &str) -> anyhow::Result<Address> {
+        Middleware::resolve_name(self, name)
+            .await
+            .map_err(|error| anyhow!("Failed to resolve ENS name {name}: {error}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct MockResolver(Address);
+
+    #[async_trait]
+    impl NameResolver for MockResolver {
+        async fn resolve_name(&self, name: &str) -> anyhow::Result<Address> {
+            match name {
+                "semaphore.eth" => Ok(self.0),
+                _ => Err(anyhow!("Unknown ENS name {name}")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ens_names_resolve_through_resolver() {
+        let known = Address::repeat_byte(0x42);
+        let resolver = MockResolver(known);
+
+        let hex: ContractAddress = "174ee9b5fBb5Eb68B6C61032946486dD9c2Dc4b6".parse().unwrap();
+        let ens: ContractAddress = "semaphore.eth".parse().unwrap();
+        let unknown: ContractAddress = "unknown.eth".parse().unwrap();
+        assert!(matches!(hex, ContractAddress::Address(_)));
+        assert_eq!(ens, ContractAddress::Ens("semaphore.eth".to_owned()));
+        assert!("0x1234".parse::<ContractAddress>().is_err());
+        assert!("semaphore".parse::<ContractAddress>().is_err());
+
+        assert_eq!(ens.resolve(&resolver).await.unwrap(), known);
+        assert!(unknown.resolve(&resolver).await.is_err());
+        assert_ne!(hex.resolve(&resolver).await.unwrap(), known);
+    }
+}
@@ -1,16 +1,19 @@
 mod abi;
 
-use self::abi::BatchingContract as ContractAbi;
+use self::abi::{BatchingContract as ContractAbi, ExpiredRoot, NonExistentRoot};
 use crate::{
-    contracts::{EventStream, IdentityManager, Options},
-    ethereum::{Ethereum, EventError, ProviderStack, TxError},
+    contracts::{EventStream, IdentityManager, InvalidRoot, Options},
+    ethereum::{Ethereum, EventError, ProviderStack, SentTransaction, TxError},
 };
 use async_trait::async_trait;
 use ethers::{
+    contract::EthError,
     providers::Middleware,
-    types::{TransactionReceipt, U256},
+    types::{TransactionReceipt, H256, U256},
+    utils::hex,
 };
 use semaphore::Field;
+use std::collections::HashSet;
 use tracing::{error, info, instrument};
 
 /// A structure representing the interface to the batch-based identity manager
@@ -23,15 +26,15 @@ pub struct Contract {
     tree_depth:         usize,
 }
 
-#[async_trait]
-impl IdentityManager for Contract {
+impl Contract {
+    /// Connects to the batching contract at the configured address.
     #[instrument(level = "debug", skip_all)]
-    async fn new(options: Options, ethereum: Ethereum) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
+    pub async fn new(options: Options, ethereum: Ethereum) -> anyhow::Result<Self> {
         // Check that there is code deployed at the target address.
-        let address = options.semaphore_address;
+        let address = options
+            .semaphore_address
+            .resolve(ethereum.provider().as_ref())
+            .await?;
         let code = ethereum.provider().get_code(address, None).await?;
         if code.as_ref().is_empty() {
             error!(
@@ -41,10 +44,10 @@ impl IdentityManager for Contract {
         }
 
         // Connect to the running batching contract.
-        let abi = ContractAbi::new(options.semaphore_address, ethereum.provider().clone());
+        let abi = ContractAbi::new(address, ethereum.provider().clone());
 
         let owner = abi.owner().call().await?;
-        if owner != ethereum.address() {
+        if !ethereum.is_read_only() && owner != ethereum.address() {
             error!(?owner, signer = ?ethereum.address(), "Signer is not the owner of the identity manager contract.");
             panic!("Cannot currently continue in read-only mode.")
         }
@@ -66,7 +69,10 @@ impl IdentityManager for Contract {
 
         Ok(identity_manager)
     }
+}
 
+#[async_trait]
+impl IdentityManager for Contract {
     fn tree_depth(&self) -> usize {
         self.tree_depth
     }
@@ -88,6 +94,10 @@ impl IdentityManager for Contract {
             .map(|num| num.as_u64())
     }
 
+    async fn block_hash(&self, block: u64) -> Result<Option<H256>, EventError> {
+        self.ethereum.block_hash(block).await
+    }
+
     #[instrument(level = "debug", skip_all)]
     async fn is_owner(&self) -> anyhow::Result<bool> {
         info!(address = ?self.ethereum.address(), "My address");
@@ -100,11 +110,26 @@ impl IdentityManager for Contract {
     async fn register_identities(
         &self,
         _identity_commitments: Vec<Field>,
-    ) -> Result<TransactionReceipt, TxError> {
+    ) -> Result<SentTransaction, TxError> {
         // TODO [Ara] Assert length of merkle tree proofs.
         todo!()
     }
 
+    fn max_batch_size(&self) -> usize {
+        usize::MAX
+    }
+
+    fn submits_proofs(&self) -> bool {
+        true
+    }
+
+    async fn await_transaction(
+        &self,
+        transaction: SentTransaction,
+    ) -> Result<TransactionReceipt, TxError> {
+        self.ethereum.wait_for_transaction(transaction).await
+    }
+
     async fn assert_latest_root(&self, root: Field) -> anyhow::Result<()> {
         let latest_root = self.abi.latest_root().call().await?;
         let processed_root: U256 = root.into();
@@ -115,13 +140,48 @@ impl IdentityManager for Contract {
         }
     }
 
+    async fn latest_root(&self) -> anyhow::Result<Field> {
+        let latest_root = self.abi.latest_root().call().await?;
+        Ok(latest_root.into())
+    }
+
+    async fn leaf_count(&self) -> anyhow::Result<usize> {
+        Err(anyhow::Error::msg("Unsupported operation: leaf_count"))
+    }
+
+    async fn root_at(&self, block: u64) -> anyhow::Result<Field> {
+        let root = self.abi.latest_root().block(block).call().await?;
+        Ok(root.into())
+    }
+
+    async fn leaf_count_at(&self, _block: u64) -> anyhow::Result<usize> {
+        Err(anyhow::Error::msg("Unsupported operation: leaf_count_at"))
+    }
+
+    async fn unconfirmed_members(&self, _from_block: u64) -> anyhow::Result<HashSet<Field>> {
+        Err(anyhow::Error::msg(
+            "Unsupported operation: unconfirmed_members",
+        ))
+    }
+
     #[instrument(level = "debug", skip_all)]
     async fn assert_valid_root(&self, root: Field) -> anyhow::Result<()> {
-        if self.abi.check_valid_root(root.into()).call().await? {
-            Ok(())
-        } else {
-            Err(anyhow::Error::msg("Root no longer valid"))
+        // The contract reverts, rather than returning `false`, for roots that
+        // are unknown or expired.
+        // HACK: There's really no good way to parse these errors
+        let error = match self.abi.check_valid_root(root.into()).call().await {
+            Ok(true) => return Ok(()),
+            Ok(false) => return Err(InvalidRoot("Root no longer valid").into()),
+            Err(error) => error,
+        };
+        let message = error.to_string();
+        if message.contains(&hex::encode(NonExistentRoot::selector())) {
+            return Err(InvalidRoot("Root does not exist").into());
+        }
+        if message.contains(&hex::encode(ExpiredRoot::selector())) {
+            return Err(InvalidRoot("Root expired").into());
         }
+        Err(error.into())
     }
 
     fn fetch_events(&self, _: u64, _: Option<u64>) -> Option<EventStream<'_>> {
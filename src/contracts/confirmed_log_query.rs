@@ -1,4 +1,4 @@
-use crate::ethereum::ProviderStack;
+use crate::ethereum::{ConfirmationStrategy, FinalBlock, ProviderStack};
 use async_stream::try_stream;
 use core::fmt::Debug;
 use ethers::{
@@ -6,18 +6,22 @@ use ethers::{
     types::{Filter, Log, U64},
 };
 use futures::{Stream, StreamExt};
-use std::{cmp::max, sync::Arc, time::Duration};
+use std::{
+    cmp::{max, min},
+    sync::Arc,
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::time::sleep;
 use tracing::{error, info};
 
 pub struct ConfirmedLogQuery {
-    provider:                  Arc<ProviderStack>,
-    filter:                    Filter,
-    start_page_size:           u64,
-    min_page_size:             u64,
-    max_backoff_time:          Duration,
-    confirmation_blocks_delay: u64,
+    provider:         Arc<ProviderStack>,
+    filter:           Filter,
+    start_page_size:  u64,
+    min_page_size:    u64,
+    max_backoff_time: Duration,
+    confirmation:     FinalBlock,
 }
 
 #[derive(Error, Debug)]
@@ -36,7 +40,7 @@ impl ConfirmedLogQuery {
             start_page_size: 10000,
             min_page_size: 1000,
             max_backoff_time: Duration::from_secs(32),
-            confirmation_blocks_delay: 0,
+            confirmation: FinalBlock::new(ConfirmationStrategy::Blocks(0)),
         }
     }
 
@@ -56,14 +60,20 @@ impl ConfirmedLogQuery {
         self
     }
 
-    pub const fn with_blocks_delay(mut self, confirmation_blocks_delay: u64) -> Self {
-        self.confirmation_blocks_delay = confirmation_blocks_delay;
+    pub fn with_confirmation(mut self, confirmation: FinalBlock) -> Self {
+        self.confirmation = confirmation;
         self
     }
 
     pub fn into_stream(self) -> impl Stream<Item = Result<Log, Error<ProviderError>>> {
         try_stream! {
-            let last_block = self.get_block_number().await?;
+            // Only confirmed blocks are loaded, the remaining ones are picked up by
+            // a later query once they are confirmed.
+            let confirmed_block = self.get_confirmed_block().await?;
+            let to_block = self
+                .filter
+                .get_to_block()
+                .map_or(confirmed_block, |to_block| min(to_block, confirmed_block));
 
             let mut retry_status = RetryStatus::new(self.start_page_size, self.min_page_size, self.max_backoff_time);
 
@@ -71,7 +81,7 @@ impl ConfirmedLogQuery {
                 let filter = self.filter.clone().from_block(max(
                     retry_status.last_block + 1,
                     self.filter.get_from_block().unwrap_or_default(),
-                ));
+                )).to_block(to_block);
 
                 info!(
                     page_size = retry_status.page_size,
@@ -100,11 +110,9 @@ impl ConfirmedLogQuery {
                         Some(block) => block,
                         None => continue,
                     };
-                    let to_filter = self.filter.get_to_block().expect("filter's to_block must be set");
-
-                    // get_logs_paginated ignores to_block filter. Check again if the block is confirmed
-                    let is_confirmed = log_block + self.confirmation_blocks_delay <= last_block && log_block <= to_filter;
-                    if is_confirmed {
+                    // get_logs_paginated may overshoot the to_block filter. Check again if the
+                    // block is confirmed.
+                    if log_block <= to_block {
                         yield log;
                     }
                 }
@@ -124,15 +132,16 @@ impl ConfirmedLogQuery {
             Err(e) if e.to_string().contains("Query timeout exceeded") => {
                 stats.attempt_restart(Error::LoadLogs(e)).await
             }
-            Err(e) => RetriableResult::Err(Error::LoadLogs(e)),
+            // Provider and transport errors are waited out, smaller pages
+            // would not help.
+            Err(e) => stats.attempt_backoff(Error::LoadLogs(e)).await,
             Ok(log) => RetriableResult::Ok(log),
         }
     }
 
-    async fn get_block_number(&self) -> Result<U64, Error<ProviderError>> {
-        let provider = self.provider.provider();
-        provider
-            .get_block_number()
+    async fn get_confirmed_block(&self) -> Result<U64, Error<ProviderError>> {
+        self.confirmation
+            .latest(self.provider.provider())
             .await
             .map_err(Error::LoadLastBlock)
     }
@@ -169,7 +178,16 @@ impl RetryStatus {
             error!(?error, "Retriable error, decreasing page size");
             self.page_size /= 2;
             RetriableResult::Restart
-        } else if self.backoff_time <= self.max_backoff_time {
+        } else {
+            self.attempt_backoff(error).await
+        }
+    }
+
+    async fn attempt_backoff<T, E>(&mut self, error: E) -> RetriableResult<T, E>
+    where
+        E: Debug + Sync + Send,
+    {
+        if self.backoff_time <= self.max_backoff_time {
             error!(?error, "Retriable error, backoff");
             sleep(self.backoff_time).await;
             self.backoff_time *= 2;
@@ -0,0 +1,211 @@
+use super::{abi::LEGACYCONTRACT_ABI, MemberAddedEvent, MemberEvent, MemberRemovedEvent};
+use anyhow::{anyhow, ensure, Context};
+use ethers::{
+    abi::{Abi, Error as AbiError, Event, LogParam, ParamType, RawLog},
+    contract::EthEvent,
+    types::{Address, Filter, ValueOrArray, H256, U256},
+};
+use std::{fs, path::Path};
+
+/// The events and calls of the identity manager contract, read from its ABI.
+/// Defaults to the built-in bindings, but can be loaded from a file to follow
+/// a contract upgrade without a new binary, as long as the events keep their
+/// `groupId`, `identityCommitment` and `root` parameters and the functions
+/// their signatures.
+#[derive(Clone, Debug)]
+pub struct Interface {
+    abi:            Abi,
+    member_added:   Event,
+    member_removed: Event,
+}
+
+impl Interface {
+    /// The interface of the built-in bindings.
+    pub fn built_in() -> Self {
+        Self::new(LEGACYCONTRACT_ABI.clone()).expect("The built-in ABI is compatible")
+    }
+
+    /// Reads the ABI at `path`, either a bare JSON ABI or a build artifact
+    /// with an `abi` field.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let json: serde_json::Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid JSON in {}", path.display()))?;
+        let abi = json.get("abi").cloned().unwrap_or(json);
+        let abi = serde_json::from_value(abi)
+            .with_context(|| format!("Invalid contract ABI in {}", path.display()))?;
+        Self::new(abi).with_context(|| format!("Incompatible contract ABI in {}", path.display()))
+    }
+
+    /// Checks that `abi` has every function of the built-in bindings and
+    /// member events with the parameters the sequencer reads.
+    fn new(abi: Abi) -> anyhow::Result<Self> {
+        for function in LEGACYCONTRACT_ABI.functions() {
+            let found = abi
+                .functions_by_name(&function.name)
+                .map_err(|_| anyhow!("Missing function {}", function.name))?
+                .iter()
+                .any(|candidate| candidate.short_signature() == function.short_signature());
+            ensure!(
+                found,
+                "Function {} has an incompatible signature",
+                function.name
+            );
+        }
+        let member_added = member_event(&abi, &MemberAddedEvent::name())?;
+        let member_removed = member_event(&abi, &MemberRemovedEvent::name())?;
+        Ok(Self {
+            abi,
+            member_added,
+            member_removed,
+        })
+    }
+
+    pub const fn abi(&self) -> &Abi {
+        &self.abi
+    }
+
+    /// A filter for the member events of `group_id` emitted by the contract at
+    /// `address`.
+    pub fn member_events(&self, address: Address, group_id: U256) -> Filter {
+        Filter::new()
+            .address(address)
+            .topic0(ValueOrArray::Array(vec![
+                Some(self.member_added.signature()),
+                Some(self.member_removed.signature()),
+            ]))
+            .topic1(H256::from_uint(&group_id))
+    }
+
+    /// Decodes a member event matched by [`Self::member_events`].
+    pub fn decode(&self, log: &RawLog) -> Result<MemberEvent, AbiError> {
+        let raw = || RawLog {
+            topics: log.topics.clone(),
+            data:   log.data.clone(),
+        };
+        match log.topics.first() {
+            Some(topic) if *topic == self.member_added.signature() => {
+                let log = self.member_added.parse_log(raw())?;
+                Ok(MemberEvent::MemberAddedFilter(MemberAddedEvent {
+                    group_id:            param(&log.params, "groupId")?,
+                    identity_commitment: param(&log.params, "identityCommitment")?,
+                    root:                param(&log.params, "root")?,
+                }))
+            }
+            Some(topic) if *topic == self.member_removed.signature() => {
+                let log = self.member_removed.parse_log(raw())?;
+                Ok(MemberEvent::MemberRemovedFilter(MemberRemovedEvent {
+                    group_id:            param(&log.params, "groupId")?,
+                    identity_commitment: param(&log.params, "identityCommitment")?,
+                    root:                param(&log.params, "root")?,
+                }))
+            }
+            _ => Err(AbiError::InvalidData),
+        }
+    }
+}
+
+/// Looks up the event `name`, which must have the group as its first indexed
+/// parameter, and the commitment and root among the others.
+fn member_event(abi: &Abi, name: &str) -> anyhow::Result<Event> {
+    let event = abi
+        .event(name)
+        .map_err(|_| anyhow!("Missing event {name}"))?;
+    let uint = |param: &str, indexed: bool| {
+        event.inputs.iter().any(|input| {
+            input.name == param && input.kind == ParamType::Uint(256) && input.indexed == indexed
+        })
+    };
+    let group_is_topic = event
+        .inputs
+        .iter()
+        .find(|input| input.indexed)
+        .map_or(false, |input| input.name == "groupId");
+    ensure!(
+        group_is_topic && uint("groupId", true),
+        "Event {name} must have groupId as its first indexed parameter"
+    );
+    ensure!(
+        uint("identityCommitment", false) && uint("root", false),
+        "Event {name} must have identityCommitment and root parameters"
+    );
+    Ok(event.clone())
+}
+
+fn param(params: &[LogParam], name: &str) -> Result<U256, AbiError> {
+    params
+        .iter()
+        .find(|param| param.name == name)
+        .and_then(|param| param.value.clone().into_uint())
+        .ok_or(AbiError::InvalidData)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::abi::{encode, AbiParser, Token};
+
+    const UPGRADED_ABI: &[&str] = &[
+        "event MemberAdded(uint256 indexed groupId, uint256 identityCommitment, uint256 root, \
+         uint256 index)",
+        "event MemberRemoved(uint256 indexed groupId, uint256 identityCommitment, uint256 root)",
+        "function manager() public view returns (address)",
+        "function getDepth(uint256 groupId) public view returns (uint8)",
+        "function getRoot(uint256 groupId) public view returns (uint256)",
+        "function getNumberOfLeaves(uint256 groupId) public view returns (uint256)",
+        "function createGroup(uint256 groupId, uint8 depth, uint256 zeroValue) public",
+        "function addMember(uint256 groupId, uint256 identityCommitment) public",
+        "function verifyProof(uint256 root, uint256 groupId, uint256 signalHash, uint256 \
+         nullifierHash, uint256 externalNullifierHash, uint256[8] proof) public view",
+    ];
+
+    fn abi_file(name: &str, abi: &[&str]) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("signup-sequencer-{name}-{}", std::process::id()));
+        let abi = AbiParser::default().parse(abi).unwrap();
+        let artifact = serde_json::json!({ "abi": abi });
+        fs::write(&path, artifact.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn upgraded_events_are_decoded() {
+        let interface = Interface::load(&abi_file("upgraded-abi", UPGRADED_ABI)).unwrap();
+        assert_ne!(
+            interface.member_added.signature(),
+            Interface::built_in().member_added.signature()
+        );
+
+        let log = RawLog {
+            topics: vec![
+                interface.member_added.signature(),
+                H256::from_uint(&U256::from(1)),
+            ],
+            data:   encode(&[
+                Token::Uint(U256::from(2)),
+                Token::Uint(U256::from(3)),
+                Token::Uint(U256::from(4)),
+            ]),
+        };
+        let MemberEvent::MemberAddedFilter(event) = interface.decode(&log).unwrap() else {
+            panic!("Expected a member added event");
+        };
+        assert_eq!(event.group_id, U256::from(1));
+        assert_eq!(event.identity_commitment, U256::from(2));
+        assert_eq!(event.root, U256::from(3));
+    }
+
+    #[test]
+    fn incompatible_abis_are_refused() {
+        let without_root = abi_file("abi-without-root", &[UPGRADED_ABI[0], UPGRADED_ABI[1]]);
+        let error = Interface::load(&without_root).unwrap_err();
+        assert!(format!("{error:#}").contains("Missing function"));
+
+        let mut renamed = UPGRADED_ABI.to_vec();
+        renamed[1] =
+            "event MemberRemoved(uint256 indexed group, uint256 identityCommitment, uint256 root)";
+        let error = Interface::load(&abi_file("abi-renamed-param", &renamed)).unwrap_err();
+        assert!(format!("{error:#}").contains("groupId"));
+    }
+}
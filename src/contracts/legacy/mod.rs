@@ -1,23 +1,86 @@
 mod abi;
+mod interface;
 
-use self::abi::{LegacyContract as ContractAbi, MemberAddedFilter};
+use self::{
+    abi::{
+        LegacyContract as ContractAbi, LegacyContractEvents, MemberAddedFilter, MemberRemovedFilter,
+    },
+    interface::Interface,
+};
 use crate::{
-    contracts::{EventStream, IdentityManager, Options},
-    ethereum::{Ethereum, EventError, ProviderStack, TxError},
+    contracts::{EventStream, IdentityManager, InvalidRoot, Options},
+    ethereum::{Ethereum, EventError, ProviderStack, SentTransaction, TxError},
     tx_sitter::Sitter,
 };
-use anyhow::anyhow;
+use anyhow::{anyhow, ensure};
 use async_trait::async_trait;
-use core::future;
 use ethers::{
+    abi::RawLog,
+    contract::{Contract as DynamicContract, EthEvent},
     providers::Middleware,
-    types::{TransactionReceipt, U256},
+    types::{TransactionReceipt, H256, U256},
 };
-use futures::TryStreamExt;
+use futures::StreamExt;
 use semaphore::Field;
-use tracing::{error, info, instrument};
+use std::collections::HashSet;
+use tracing::{error, info, instrument, warn};
 
 pub type MemberAddedEvent = MemberAddedFilter;
+pub type MemberRemovedEvent = MemberRemovedFilter;
+
+/// An event changing the members of a group: either a [`MemberAddedEvent`] or
+/// a [`MemberRemovedEvent`].
+pub type MemberEvent = LegacyContractEvents;
+
+/// Emitted when a group is created, with the value of its empty leaves.
+#[derive(Clone, Debug, EthEvent)]
+#[ethevent(name = "GroupCreated", abi = "GroupCreated(uint256,uint8,uint256)")]
+pub struct GroupCreatedEvent {
+    #[ethevent(indexed)]
+    pub group_id:   U256,
+    pub depth:      u8,
+    pub zero_value: U256,
+}
+
+/// Checks that the group was created with `initial_leaf` as the value of its
+/// empty leaves, as proofs would be wrong otherwise. The creation event is
+/// searched for in pages, like member events, as providers cap the range of
+/// a log query.
+async fn check_initial_leaf(
+    ethereum: &Ethereum,
+    abi: &ContractAbi<ProviderStack>,
+    group_id: U256,
+    initial_leaf: Field,
+) -> anyhow::Result<()> {
+    let filter = abi
+        .event::<GroupCreatedEvent>()
+        .topic1(H256::from_uint(&group_id))
+        .from_block(0)
+        .filter;
+    let mut events = Box::pin(ethereum.fetch_events::<GroupCreatedEvent>(&filter));
+    let created = events.next().await.transpose()?;
+    let Some(created) = created.map(|log| log.event) else {
+        warn!(
+            ?group_id,
+            "Group creation event not found, can not check the initial leaf."
+        );
+        return Ok(());
+    };
+    let zero_value = Field::from(created.zero_value);
+    if zero_value != initial_leaf {
+        error!(
+            ?group_id,
+            ?zero_value,
+            ?initial_leaf,
+            "Initial leaf value does not match the group's zero value."
+        );
+        return Err(anyhow!(
+            "Configured initial leaf {initial_leaf:#x} does not match the group's zero value \
+             {zero_value:#x}"
+        ));
+    }
+    Ok(())
+}
 
 /// A structure representing the interface to the legacy identity manager
 /// contract.
@@ -25,21 +88,24 @@ pub struct Contract {
     ethereum:     Ethereum,
     sitter:       Sitter,
     abi:          ContractAbi<ProviderStack>,
+    /// The events and root calls, dispatched against the configured ABI.
+    interface:    Interface,
+    dynamic:      DynamicContract<ProviderStack>,
     group_id:     U256,
     tree_depth:   usize,
     initial_leaf: Field,
 }
 
-#[async_trait]
-impl IdentityManager for Contract {
+impl Contract {
+    /// Connects to the Semaphore contract at the configured address.
     #[instrument(level = "debug", skip_all)]
-    async fn new(options: Options, ethereum: Ethereum) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
+    pub async fn new(options: Options, ethereum: Ethereum) -> anyhow::Result<Self> {
         // Sanity check the address
         // TODO: Check that the contract is actually a Semaphore by matching bytecode.
-        let address = options.semaphore_address;
+        let address = options
+            .semaphore_address
+            .resolve(ethereum.provider().as_ref())
+            .await?;
         let code = ethereum.provider().get_code(address, None).await?;
         if code.as_ref().is_empty() {
             error!(
@@ -50,11 +116,24 @@ impl IdentityManager for Contract {
         }
 
         // Connect to Contract
-        let semaphore = ContractAbi::new(options.semaphore_address, ethereum.provider().clone());
+        let semaphore = ContractAbi::new(address, ethereum.provider().clone());
+        let interface = match &options.contract_abi {
+            Some(path) => {
+                let interface = Interface::load(path)?;
+                info!(path = %path.display(), "Loaded contract ABI.");
+                interface
+            }
+            None => Interface::built_in(),
+        };
+        let dynamic = DynamicContract::new(
+            address,
+            interface.abi().clone(),
+            ethereum.provider().clone(),
+        );
 
         // Test contract by calling a view function and make sure we are manager.
         let manager = semaphore.manager().call().await?;
-        if manager != ethereum.address() {
+        if !ethereum.is_read_only() && manager != ethereum.address() {
             error!(?manager, signer = ?ethereum.address(), "Signer is not the manager of the Semaphore contract");
             // return Err(anyhow!("Signer is not manager"));
             // TODO: If not manager, proceed in read-only mode.
@@ -65,8 +144,18 @@ impl IdentityManager for Contract {
 
         // Make sure the group exists.
         let existing_tree_depth = semaphore.get_depth(options.group_id).call().await?;
+        let create_group_depth = options.create_group_depth.or_else(|| {
+            options
+                .create_group_if_missing
+                .then_some(options.tree_depth)
+        });
         let actual_tree_depth = if existing_tree_depth == 0 {
-            if let Some(new_depth) = options.create_group_depth {
+            if let Some(new_depth) = create_group_depth {
+                ensure!(
+                    !ethereum.is_read_only(),
+                    "Group does not exist, read-only instances can not create it"
+                );
+                info!(group_id = ?options.group_id, depth = new_depth, "Creating Semaphore group.");
                 let tx = semaphore
                     .create_group(
                         options.group_id,
@@ -82,15 +171,22 @@ impl IdentityManager for Contract {
             }
         } else {
             info!(group_id = ?options.group_id, ?existing_tree_depth, "Semaphore group found.");
+            check_initial_leaf(
+                &ethereum,
+                &semaphore,
+                options.group_id,
+                options.initial_leaf_value,
+            )
+            .await?;
             usize::from(existing_tree_depth)
         };
 
-        // TODO: Some way to check the initial leaf
-
         let identity_manager = Self {
             ethereum,
             sitter,
             abi: semaphore,
+            interface,
+            dynamic,
             group_id: options.group_id,
             tree_depth: actual_tree_depth,
             initial_leaf: options.initial_leaf_value,
@@ -99,6 +195,18 @@ impl IdentityManager for Contract {
         Ok(identity_manager)
     }
 
+    /// Reads the group's current root through the configured ABI.
+    async fn root(&self) -> anyhow::Result<U256> {
+        Ok(self
+            .dynamic
+            .method::<_, U256>("getRoot", self.group_id)?
+            .call()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl IdentityManager for Contract {
     fn tree_depth(&self) -> usize {
         self.tree_depth
     }
@@ -118,6 +226,10 @@ impl IdentityManager for Contract {
             .map(|num| num.as_u64())
     }
 
+    async fn block_hash(&self, block: u64) -> Result<Option<H256>, EventError> {
+        self.ethereum.block_hash(block).await
+    }
+
     #[instrument(level = "debug", skip_all)]
     async fn is_owner(&self) -> anyhow::Result<bool> {
         info!(address = ?self.ethereum.address(), "My address");
@@ -130,8 +242,8 @@ impl IdentityManager for Contract {
     async fn register_identities(
         &self,
         identity_commitments: Vec<Field>,
-    ) -> Result<TransactionReceipt, TxError> {
-        // TODO Make this loop over identities if it gets multiple.
+    ) -> Result<SentTransaction, TxError> {
+        // Larger batches are refused at startup, see `max_batch_size`.
         assert_eq!(
             identity_commitments.len(),
             1,
@@ -141,17 +253,94 @@ impl IdentityManager for Contract {
 
         // Send the registration transaction
         let commitment = U256::from(identity.to_be_bytes());
-        let receipt = self
-            .sitter
-            .send(self.abi.add_member(self.group_id, commitment).tx)
+        self.sitter
+            .broadcast(self.abi.add_member(self.group_id, commitment).tx)
+            .await
+    }
+
+    async fn await_transaction(
+        &self,
+        transaction: SentTransaction,
+    ) -> Result<TransactionReceipt, TxError> {
+        self.sitter.wait(transaction).await
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn assert_latest_root(&self, root: Field) -> anyhow::Result<()> {
+        let latest_root = self.root().await?;
+        if U256::from(root.to_be_bytes()) == latest_root {
+            Ok(())
+        } else {
+            Err(anyhow!("Not latest root, contract has {latest_root:#x}"))
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn latest_root(&self) -> anyhow::Result<Field> {
+        let latest_root = self.root().await?;
+        Ok(latest_root.into())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn leaf_count(&self) -> anyhow::Result<usize> {
+        let count = self.abi.get_number_of_leaves(self.group_id).call().await?;
+        Ok(count.as_usize())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn root_at(&self, block: u64) -> anyhow::Result<Field> {
+        let root: U256 = self
+            .dynamic
+            .method::<_, U256>("getRoot", self.group_id)?
+            .block(block)
+            .call()
+            .await?;
+        Ok(root.into())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn leaf_count_at(&self, block: u64) -> anyhow::Result<usize> {
+        let count = self
+            .abi
+            .get_number_of_leaves(self.group_id)
+            .block(block)
+            .call()
             .await?;
-        Ok(receipt)
+        Ok(count.as_usize())
     }
 
-    async fn assert_latest_root(&self, _: Field) -> anyhow::Result<()> {
-        Err(anyhow::Error::msg(
-            "Unsupported operation: assert_latest_root",
-        ))
+    #[instrument(level = "debug", skip_all)]
+    async fn unconfirmed_members(&self, from_block: u64) -> anyhow::Result<HashSet<Field>> {
+        let filter = self
+            .interface
+            .member_events(self.abi.address(), self.group_id)
+            .from_block(from_block);
+        let events = self
+            .ethereum
+            .provider()
+            .get_logs(&filter)
+            .await?
+            .into_iter()
+            .map(|log| {
+                self.interface.decode(&RawLog {
+                    topics: log.topics,
+                    data:   log.data.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut members = HashSet::new();
+        for event in events {
+            match event {
+                MemberEvent::MemberAddedFilter(added) => {
+                    members.insert(Field::from(added.identity_commitment));
+                }
+                MemberEvent::MemberRemovedFilter(removed) => {
+                    members.remove(&Field::from(removed.identity_commitment));
+                }
+                _ => {}
+            }
+        }
+        Ok(members)
     }
 
     // This is a total hack due to the contract not supporting a `get_root`
@@ -183,21 +372,23 @@ impl IdentityManager for Contract {
             return Ok(());
         }
         if error.contains("0x504570e3") {
-            return Err(anyhow!("Invalid root"));
+            return Err(InvalidRoot("Invalid root").into());
         }
         Err(anyhow!("Error verifiying root: {}", result))
     }
 
     fn fetch_events(&self, starting_block: u64, end_block: Option<u64>) -> Option<EventStream<'_>> {
-        // Start the MemberAdded event stream.
-        let mut filter = self.abi.member_added_filter().from_block(starting_block);
+        // Start the stream of MemberAdded and MemberRemoved events of the group.
+        let mut filter = self
+            .interface
+            .member_events(self.abi.address(), self.group_id)
+            .from_block(starting_block);
         if let Some(end_block) = end_block {
             filter = filter.to_block(end_block);
         }
         let stream = self
             .ethereum
-            .fetch_events::<MemberAddedEvent>(&filter.filter)
-            .try_filter(|event| future::ready(event.event.group_id == self.group_id));
+            .fetch_events_with(&filter, |log| self.interface.decode(log));
         Some(Box::pin(stream))
     }
 }
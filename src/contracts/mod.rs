@@ -1,30 +1,36 @@
 //! Functionality for interacting with smart contracts deployed on chain.
+pub mod address;
 pub mod batching;
 pub mod confirmed_log_query;
 pub mod legacy;
+pub mod root_validator;
 
 use crate::{
-    contracts::legacy::MemberAddedEvent,
-    ethereum::{Ethereum, EventError, Log, TxError},
+    contracts::{address::ContractAddress, legacy::MemberEvent},
+    ethereum::{EventError, Log, SentTransaction, TxError},
+    identity_tree::poseidon_tree_depth,
 };
 use async_trait::async_trait;
 use clap::Parser;
 use ethers::{
-    prelude::{Address, U256},
-    types::TransactionReceipt,
+    prelude::U256,
+    types::{TransactionReceipt, H256},
 };
 use futures::Stream;
 use semaphore::Field;
-use std::{pin::Pin, sync::Arc};
+use serde::Serialize;
+use std::{collections::HashSet, path::PathBuf, pin::Pin, sync::Arc};
+use thiserror::Error;
 
 /// Configuration options for the component responsible for interacting with the
 /// contract.
-#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[derive(Clone, Debug, PartialEq, Eq, Parser, Serialize)]
 #[group(skip)]
 pub struct Options {
-    /// The address of the identity manager contract.
+    /// The address of the identity manager contract, or an ENS name
+    /// resolving to it. Names are resolved once at startup.
     #[clap(long, env, default_value = "174ee9b5fBb5Eb68B6C61032946486dD9c2Dc4b6")]
-    pub semaphore_address: Address,
+    pub semaphore_address: ContractAddress,
 
     // TODO This option should be removed.
     /// The semaphore group identifier to use.
@@ -37,6 +43,12 @@ pub struct Options {
     #[clap(long, env)]
     pub create_group_depth: Option<usize>,
 
+    /// Create the group with `group_id`, `tree_depth` and
+    /// `initial_leaf_value` at startup if it does not exist, instead of
+    /// refusing to start. Requires the signer to be the contract's manager.
+    #[clap(long, env)]
+    pub create_group_if_missing: bool,
+
     /// The depth of the tree that the contract is working with. This needs to
     /// agree with the verifier in the deployed contract, and also with
     /// `semaphore-mtb`.
@@ -51,25 +63,28 @@ pub struct Options {
         default_value = "0000000000000000000000000000000000000000000000000000000000000000"
     )]
     pub initial_leaf_value: Field,
+
+    /// JSON ABI of the identity manager contract, or a build artifact with an
+    /// `abi` field, to read member events and roots with instead of the
+    /// built-in bindings. Lets an upgraded contract be followed without a new
+    /// binary, as long as its events and functions stay compatible.
+    #[clap(long, env)]
+    pub contract_abi: Option<PathBuf>,
 }
 
 /// A trait representing an identity manager that is able to submit user
 /// identities to a contract located on the blockchain.
 #[async_trait]
 pub trait IdentityManager {
-    /// Create and configure a new instance of the identity manager.
-    ///
-    /// # Arguments
-    ///
-    /// - `options`: The options used to configure the identity manager.
-    /// - `ethereum`: A connector for an ethereum-compatible blockchain.
-    async fn new(options: Options, ethereum: Ethereum) -> anyhow::Result<Self>
-    where
-        Self: Sized;
-
     /// Returns the depth of the merkle tree managed by this `IdentityManager`.
     fn tree_depth(&self) -> usize;
 
+    /// Returns the depth of the local tree mirroring the contract's tree. See
+    /// [`poseidon_tree_depth`] for how it relates to [`Self::tree_depth`].
+    fn poseidon_tree_depth(&self) -> usize {
+        poseidon_tree_depth(self.tree_depth())
+    }
+
     /// Returns the value used for a newly initialized merkle tree leaf.
     fn initial_leaf_value(&self) -> Field;
 
@@ -80,25 +95,71 @@ pub trait IdentityManager {
     /// mined.
     async fn confirmed_block_number(&self) -> Result<u64, EventError>;
 
+    /// Returns the hash of block number `block`, or `None` if the chain has
+    /// no such block.
+    async fn block_hash(&self, block: u64) -> Result<Option<H256>, EventError>;
+
     /// Returns `true` if this `IdentityManager` acts via the manager address of
     /// the on-chain contract it manages.
     async fn is_owner(&self) -> anyhow::Result<bool>;
 
-    /// Registers the provided `identity_commitments` with the contract on
-    /// chain.
+    /// Broadcasts a transaction registering the provided
+    /// `identity_commitments` with the contract on chain. The transaction is
+    /// not awaited; use [`Self::await_transaction`] for that.
     async fn register_identities(
         &self,
         identity_commitments: Vec<Field>,
+    ) -> Result<SentTransaction, TxError>;
+
+    /// Returns the maximum number of identities [`Self::register_identities`]
+    /// accepts in one transaction.
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Returns whether the transactions sent by [`Self::register_identities`]
+    /// carry a proof of the batch, so that a revert means the contract
+    /// rejected the proof.
+    fn submits_proofs(&self) -> bool {
+        false
+    }
+
+    /// Waits for a transaction sent by [`Self::register_identities`] to be
+    /// mined, possibly by an earlier run of the sequencer.
+    async fn await_transaction(
+        &self,
+        transaction: SentTransaction,
     ) -> Result<TransactionReceipt, TxError>;
 
     /// Asserts that the provided `root` is the current root held by the
     /// contract on the chain.
     async fn assert_latest_root(&self, root: Field) -> anyhow::Result<()>;
 
+    /// Returns the current root held by the contract on the chain.
+    async fn latest_root(&self) -> anyhow::Result<Field>;
+
+    /// Returns the number of leaves inserted into the contract's tree,
+    /// including any that were removed since.
+    async fn leaf_count(&self) -> anyhow::Result<usize>;
+
+    /// Returns the root held by the contract as of block `block`.
+    async fn root_at(&self, block: u64) -> anyhow::Result<Field>;
+
+    /// Like [`Self::leaf_count`], but as of block `block`.
+    async fn leaf_count_at(&self, block: u64) -> anyhow::Result<usize>;
+
+    /// Returns the commitments added to the group from `from_block` on, and
+    /// not removed since. Called with the first block the subscriber has not
+    /// processed, these are the members not yet in the tree, whether their
+    /// blocks are confirmed or not.
+    async fn unconfirmed_members(&self, from_block: u64) -> anyhow::Result<HashSet<Field>>;
+
     /// Asserts that the provided `root` is a valid root.
     ///
     /// A valid root is one that has not expired based on the time since it was
-    /// inserted into the history of roots on chain.
+    /// inserted into the history of roots on chain. Fails with [`InvalidRoot`]
+    /// if the contract rejects the root, and with another error if the check
+    /// itself fails.
     async fn assert_valid_root(&self, root: Field) -> anyhow::Result<()>;
 
     // TODO [Ara] Remove this once the OZ relay work is integrated.
@@ -113,7 +174,560 @@ pub trait IdentityManager {
 /// The type of the event stream used by the contracts to receive events from on
 /// chain.
 type EventStream<'a> =
-    Pin<Box<dyn Stream<Item = Result<Log<MemberAddedEvent>, EventError>> + Send + 'a>>;
+    Pin<Box<dyn Stream<Item = Result<Log<MemberEvent>, EventError>> + Send + 'a>>;
 
 /// A type for an identity manager object that can be sent across threads.
 pub type SharedIdentityManager = Arc<dyn IdentityManager + Send + Sync>;
+
+/// The contract rejected a root passed to
+/// [`IdentityManager::assert_valid_root`].
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct InvalidRoot(pub &'static str);
+
+impl InvalidRoot {
+    /// Whether `error` was caused by the contract rejecting a root, rather
+    /// than by failing to ask it.
+    #[must_use]
+    pub fn is_cause_of(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| cause.is::<Self>())
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use crate::contracts::legacy::{MemberAddedEvent, MemberRemovedEvent};
+    use ethers::{providers::ProviderError, types::U64};
+    use semaphore::poseidon_tree::PoseidonTree;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    };
+    use tokio::sync::Semaphore;
+
+    /// An identity manager that records registrations instead of sending
+    /// transactions.
+    pub struct MockIdentityManager {
+        /// Block in which awaited transactions are mined, or `None` to report
+        /// them as dropped.
+        mined_in_block:        Option<u64>,
+        /// Latest confirmed block, starting at `mined_in_block`.
+        confirmed_block:       AtomicU64,
+        /// Blocks from which the chain was reorganized, each changing the
+        /// hashes of the blocks from there on.
+        reorgs:                Mutex<Vec<u64>>,
+        registered:            Mutex<Vec<Vec<Field>>>,
+        /// Every registered and removed member in order, the latter flagged.
+        member_changes:        Mutex<Vec<(Field, bool)>>,
+        /// Number of the latest member changes mined after the confirmed
+        /// block, which are not fetched as events yet.
+        unconfirmed_changes:   AtomicUsize,
+        fetches:               Mutex<Vec<(u64, Option<u64>)>>,
+        root_checks:           AtomicUsize,
+        /// Whether fetched events include the registered identities.
+        emits_registrations:   bool,
+        emitted_registrations: AtomicUsize,
+        /// Number of upcoming `confirmed_block_number` calls that fail as if
+        /// the provider were unreachable.
+        unreachable_calls:     AtomicUsize,
+        block_number_calls:    Mutex<Vec<Instant>>,
+        /// Number of upcoming `fetch_events` calls that fail after their
+        /// first event, as if the provider became unreachable.
+        interrupted_fetches:   AtomicUsize,
+        /// Number of upcoming `fetch_events` calls that leave out their second
+        /// event, as a misbehaving provider might.
+        skipping_fetches:      AtomicUsize,
+        /// Closed to let `await_transaction` return, if held.
+        transaction_gate:      Option<Arc<Semaphore>>,
+        /// Whether registrations fail as if gas were above the maximum price.
+        gas_price_too_high:    AtomicBool,
+        /// Commitments whose registration reverts, along with every other
+        /// commitment sent in the same transaction.
+        reverting:             Vec<Field>,
+        /// Whether registrations carry a batch proof.
+        submits_proofs:        bool,
+        /// Positions of fetched events in the order they are emitted, if not
+        /// in chain order. Events not listed are left out.
+        event_order:           Option<Vec<usize>>,
+        /// Whether `assert_valid_root` rejects roots the group never had,
+        /// rather than accepting any.
+        checks_roots:          bool,
+        /// Whether `assert_valid_root` fails as if the provider were
+        /// unreachable.
+        failing_root_checks:   AtomicBool,
+    }
+
+    impl MockIdentityManager {
+        pub const fn mining_at(mined_in_block: Option<u64>) -> Self {
+            Self {
+                mined_in_block,
+                confirmed_block: AtomicU64::new(match mined_in_block {
+                    Some(block) => block,
+                    None => 0,
+                }),
+                reorgs: Mutex::new(Vec::new()),
+                registered: Mutex::new(Vec::new()),
+                member_changes: Mutex::new(Vec::new()),
+                unconfirmed_changes: AtomicUsize::new(0),
+                fetches: Mutex::new(Vec::new()),
+                root_checks: AtomicUsize::new(0),
+                emits_registrations: false,
+                emitted_registrations: AtomicUsize::new(0),
+                unreachable_calls: AtomicUsize::new(0),
+                block_number_calls: Mutex::new(Vec::new()),
+                interrupted_fetches: AtomicUsize::new(0),
+                skipping_fetches: AtomicUsize::new(0),
+                transaction_gate: None,
+                gas_price_too_high: AtomicBool::new(false),
+                reverting: Vec::new(),
+                submits_proofs: false,
+                event_order: None,
+                checks_roots: false,
+                failing_root_checks: AtomicBool::new(false),
+            }
+        }
+
+        /// Makes `assert_valid_root` reject roots the group never had.
+        #[must_use]
+        pub const fn checking_roots(mut self) -> Self {
+            self.checks_roots = true;
+            self
+        }
+
+        pub fn set_root_checks_failing(&self, failing: bool) {
+            self.failing_root_checks.store(failing, Ordering::SeqCst);
+        }
+
+        /// Every root the group had, starting with the empty tree's.
+        fn roots(&self) -> Vec<Field> {
+            let changes = self.member_changes.lock().unwrap().len();
+            self.roots_after(changes)
+        }
+
+        /// Every root the group had after its first `changes` member changes,
+        /// starting with the empty tree's.
+        fn roots_after(&self, changes: usize) -> Vec<Field> {
+            let mut tree = PoseidonTree::new(self.poseidon_tree_depth(), self.initial_leaf_value());
+            let mut roots = vec![tree.root()];
+            let mut next_leaf = 0;
+            for (leaf, removed) in self.member_changes.lock().unwrap()[..changes].iter() {
+                if *removed {
+                    let index = tree.leaves()[..next_leaf]
+                        .iter()
+                        .position(|member| member == leaf)
+                        .expect("Removed member is in the group");
+                    tree.set(index, self.initial_leaf_value());
+                } else {
+                    tree.set(next_leaf, *leaf);
+                    next_leaf += 1;
+                }
+                roots.push(tree.root());
+            }
+            roots
+        }
+
+        /// Makes transactions registering `commitment` revert.
+        #[must_use]
+        pub fn reverting(mut self, commitment: Field) -> Self {
+            self.reverting.push(commitment);
+            self
+        }
+
+        /// Makes registrations carry a batch proof, so reverts are rejections
+        /// of the proof.
+        #[must_use]
+        pub const fn submitting_proofs(mut self) -> Self {
+            self.submits_proofs = true;
+            self
+        }
+
+        /// Makes `await_transaction` wait until [`Self::release_transactions`].
+        #[must_use]
+        pub fn holding_transactions(mut self) -> Self {
+            self.transaction_gate = Some(Arc::new(Semaphore::new(0)));
+            self
+        }
+
+        pub fn set_gas_price_too_high(&self, too_high: bool) {
+            self.gas_price_too_high.store(too_high, Ordering::SeqCst);
+        }
+
+        pub fn release_transactions(&self) {
+            if let Some(gate) = &self.transaction_gate {
+                gate.close();
+            }
+        }
+
+        /// Makes the next `calls` calls of `confirmed_block_number` fail.
+        #[must_use]
+        pub const fn unreachable_for(mut self, calls: usize) -> Self {
+            self.unreachable_calls = AtomicUsize::new(calls);
+            self
+        }
+
+        /// Makes the next `fetches` calls of `fetch_events` fail after their
+        /// first event.
+        #[must_use]
+        pub const fn interrupting_fetches(mut self, fetches: usize) -> Self {
+            self.interrupted_fetches = AtomicUsize::new(fetches);
+            self
+        }
+
+        /// Makes the next `fetches` calls of `fetch_events` leave out their
+        /// second event.
+        #[must_use]
+        pub const fn skipping_in_fetches(mut self, fetches: usize) -> Self {
+            self.skipping_fetches = AtomicUsize::new(fetches);
+            self
+        }
+
+        /// Confirms the blocks up to `block`.
+        pub fn confirm_until(&self, block: u64) {
+            self.confirmed_block.store(block, Ordering::SeqCst);
+        }
+
+        /// Adds `identity_commitment` to the group in a block that is not
+        /// confirmed yet.
+        pub fn add_unconfirmed_member(&self, identity_commitment: Field) {
+            self.member_changes
+                .lock()
+                .unwrap()
+                .push((identity_commitment, false));
+            self.unconfirmed_changes.fetch_add(1, Ordering::SeqCst);
+        }
+
+        /// The number of member changes mined up to the confirmed block.
+        fn confirmed_changes(&self) -> usize {
+            self.member_changes.lock().unwrap().len()
+                - self.unconfirmed_changes.load(Ordering::SeqCst)
+        }
+
+        /// Replaces the blocks from `block` on with ones of another fork.
+        pub fn reorg_from(&self, block: u64) {
+            self.reorgs.lock().unwrap().push(block);
+        }
+
+        /// When `confirmed_block_number` was called, failing or not.
+        pub fn block_number_calls(&self) -> Vec<Instant> {
+            self.block_number_calls.lock().unwrap().clone()
+        }
+
+        /// Makes `fetch_events` return an event for every identity registered
+        /// or removed since the previous fetch, as if mined in
+        /// `mined_in_block`.
+        #[must_use]
+        pub const fn emitting_registrations(mut self) -> Self {
+            self.emits_registrations = true;
+            self
+        }
+
+        /// Makes `fetch_events` emit the events at `order` of every fetch,
+        /// in that order, as a misbehaving provider might.
+        #[must_use]
+        pub fn emitting_in_order(mut self, order: Vec<usize>) -> Self {
+            self.event_order = Some(order);
+            self
+        }
+
+        pub fn registered(&self) -> Vec<Vec<Field>> {
+            self.registered.lock().unwrap().clone()
+        }
+
+        /// Removes `identity_commitment` from the group, as the contract's
+        /// `removeMember` would.
+        pub fn remove_member(&self, identity_commitment: Field) {
+            self.member_changes
+                .lock()
+                .unwrap()
+                .push((identity_commitment, true));
+        }
+
+        /// The block ranges events were fetched for.
+        pub fn fetches(&self) -> Vec<(u64, Option<u64>)> {
+            self.fetches.lock().unwrap().clone()
+        }
+
+        /// The number of calls to `assert_valid_root`.
+        pub fn root_checks(&self) -> usize {
+            self.root_checks.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl IdentityManager for MockIdentityManager {
+        fn tree_depth(&self) -> usize {
+            10
+        }
+
+        fn initial_leaf_value(&self) -> Field {
+            Field::ZERO
+        }
+
+        fn group_id(&self) -> U256 {
+            1.into()
+        }
+
+        async fn confirmed_block_number(&self) -> Result<u64, EventError> {
+            self.block_number_calls.lock().unwrap().push(Instant::now());
+            let unreachable = self
+                .unreachable_calls
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |calls| {
+                    calls.checked_sub(1)
+                })
+                .is_ok();
+            if unreachable {
+                return Err(EventError::EmptyBlockIndex);
+            }
+            Ok(self.confirmed_block.load(Ordering::SeqCst))
+        }
+
+        async fn block_hash(&self, block: u64) -> Result<Option<H256>, EventError> {
+            if block > self.confirmed_block.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            // Each reorg at or before the block moves it to another fork.
+            let fork = self
+                .reorgs
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|from| **from <= block)
+                .count() as u64;
+            Ok(Some(H256::from_low_u64_be((fork << 32) | block)))
+        }
+
+        async fn is_owner(&self) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+
+        async fn register_identities(
+            &self,
+            identity_commitments: Vec<Field>,
+        ) -> Result<SentTransaction, TxError> {
+            if self.gas_price_too_high.load(Ordering::SeqCst) {
+                return Err(TxError::GasPriceTooHigh {
+                    required: 200.into(),
+                    max:      100.into(),
+                });
+            }
+            let reverts = identity_commitments
+                .iter()
+                .any(|commitment| self.reverting.contains(commitment));
+            if !reverts {
+                self.member_changes.lock().unwrap().extend(
+                    identity_commitments
+                        .iter()
+                        .map(|identity_commitment| (*identity_commitment, false)),
+                );
+            }
+            let mut registered = self.registered.lock().unwrap();
+            registered.push(identity_commitments);
+            let nonce = registered.len() as u64;
+            Ok(SentTransaction {
+                hash: H256::from_low_u64_be(nonce),
+                nonce,
+                gas_limit: None,
+            })
+        }
+
+        fn max_batch_size(&self) -> usize {
+            usize::MAX
+        }
+
+        fn submits_proofs(&self) -> bool {
+            self.submits_proofs
+        }
+
+        async fn await_transaction(
+            &self,
+            transaction: SentTransaction,
+        ) -> Result<TransactionReceipt, TxError> {
+            if let Some(gate) = &self.transaction_gate {
+                // Fails once the gate is closed.
+                let _ = gate.acquire().await;
+            }
+            let block = self
+                .mined_in_block
+                .ok_or(TxError::Dropped(transaction.hash))?;
+            // Transactions are numbered by their nonce.
+            let registered = self.registered.lock().unwrap();
+            let reverts = usize::try_from(transaction.nonce)
+                .ok()
+                .and_then(|nonce| registered.get(nonce.checked_sub(1)?))
+                .map_or(false, |commitments| {
+                    commitments
+                        .iter()
+                        .any(|commitment| self.reverting.contains(commitment))
+                });
+            drop(registered);
+            let receipt = TransactionReceipt {
+                transaction_hash: transaction.hash,
+                block_number: Some(U64::from(block)),
+                status: Some(U64::from(u64::from(!reverts))),
+                ..TransactionReceipt::default()
+            };
+            if reverts {
+                return Err(TxError::Failed(Box::new(receipt)));
+            }
+            Ok(receipt)
+        }
+
+        async fn assert_latest_root(&self, _: Field) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn latest_root(&self) -> anyhow::Result<Field> {
+            Ok(*self.roots().last().unwrap())
+        }
+
+        async fn leaf_count(&self) -> anyhow::Result<usize> {
+            Ok(self
+                .member_changes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, removed)| !removed)
+                .count())
+        }
+
+        // Confirmed changes are all mined in `mined_in_block`, so any
+        // confirmed block has them all.
+        async fn root_at(&self, _block: u64) -> anyhow::Result<Field> {
+            Ok(*self.roots_after(self.confirmed_changes()).last().unwrap())
+        }
+
+        async fn leaf_count_at(&self, _block: u64) -> anyhow::Result<usize> {
+            let confirmed = self.confirmed_changes();
+            Ok(self.member_changes.lock().unwrap()[..confirmed]
+                .iter()
+                .filter(|(_, removed)| !removed)
+                .count())
+        }
+
+        async fn unconfirmed_members(&self, _from_block: u64) -> anyhow::Result<HashSet<Field>> {
+            // Changes not yet emitted as events are the unprocessed ones.
+            let changes = self.member_changes.lock().unwrap();
+            let emitted = self.emitted_registrations.load(Ordering::SeqCst);
+            let mut members = HashSet::new();
+            for (member, removed) in changes.iter().skip(emitted) {
+                if *removed {
+                    members.remove(member);
+                } else {
+                    members.insert(*member);
+                }
+            }
+            Ok(members)
+        }
+
+        async fn assert_valid_root(&self, root: Field) -> anyhow::Result<()> {
+            self.root_checks.fetch_add(1, Ordering::SeqCst);
+            // Take a while, like an RPC would.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if self.failing_root_checks.load(Ordering::SeqCst) {
+                return Err(ProviderError::CustomError("Provider unreachable".into()).into());
+            }
+            if self.checks_roots && !self.roots().contains(&root) {
+                return Err(InvalidRoot("Root does not exist").into());
+            }
+            Ok(())
+        }
+
+        fn fetch_events(&self, starting_block: u64, end_block: Option<u64>) -> Option<EventStream> {
+            self.fetches
+                .lock()
+                .unwrap()
+                .push((starting_block, end_block));
+            if !self.emits_registrations {
+                return Some(Box::pin(futures::stream::empty()));
+            }
+
+            let mut changes = self.member_changes.lock().unwrap().clone();
+            changes.truncate(self.confirmed_changes());
+            let already_emitted = self
+                .emitted_registrations
+                .swap(changes.len(), Ordering::SeqCst);
+            let mut tree = PoseidonTree::new(self.poseidon_tree_depth(), self.initial_leaf_value());
+            let mut next_leaf = 0;
+            let mut events = Vec::new();
+            for (position, (leaf, removed)) in changes.iter().enumerate() {
+                if *removed {
+                    let index = tree.leaves()[..next_leaf]
+                        .iter()
+                        .position(|member| member == leaf)
+                        .expect("Removed member is in the group");
+                    tree.set(index, self.initial_leaf_value());
+                } else {
+                    tree.set(next_leaf, *leaf);
+                    next_leaf += 1;
+                }
+                if position < already_emitted {
+                    continue;
+                }
+
+                let group_id = self.group_id();
+                let identity_commitment = U256::from(leaf.to_be_bytes());
+                let root = U256::from(tree.root().to_be_bytes());
+                events.push(Ok(Log {
+                    block_index:       U64::from(self.mined_in_block.unwrap_or_default()),
+                    transaction_index: U64::from(position),
+                    transaction_hash:  H256::from_low_u64_be(position as u64),
+                    log_index:         U256::zero(),
+                    raw_log:           String::new(),
+                    event:             if *removed {
+                        MemberEvent::MemberRemovedFilter(MemberRemovedEvent {
+                            group_id,
+                            identity_commitment,
+                            root,
+                        })
+                    } else {
+                        MemberEvent::MemberAddedFilter(MemberAddedEvent {
+                            group_id,
+                            identity_commitment,
+                            root,
+                        })
+                    },
+                }));
+            }
+            let interrupted = self
+                .interrupted_fetches
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |fetches| {
+                    fetches.checked_sub(1)
+                })
+                .is_ok();
+            if interrupted {
+                // The failed range is fetched again in full.
+                self.emitted_registrations
+                    .store(already_emitted, Ordering::SeqCst);
+                events.truncate(1);
+                events.push(Err(EventError::FetchingBlock(ProviderError::CustomError(
+                    "Connection reset".into(),
+                ))));
+            }
+            let skipping = self
+                .skipping_fetches
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |fetches| {
+                    fetches.checked_sub(1)
+                })
+                .is_ok();
+            if skipping && events.len() > 1 {
+                // The range is fetched again in full.
+                self.emitted_registrations
+                    .store(already_emitted, Ordering::SeqCst);
+                events.remove(1);
+            }
+            if let Some(order) = &self.event_order {
+                let mut events = events.into_iter().map(Some).collect::<Vec<_>>();
+                let reordered = order
+                    .iter()
+                    .filter_map(|&position| events[position].take())
+                    .collect::<Vec<_>>();
+                return Some(Box::pin(futures::stream::iter(reordered)));
+            }
+            Some(Box::pin(futures::stream::iter(events)))
+        }
+    }
+}
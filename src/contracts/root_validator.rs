@@ -0,0 +1,87 @@
+use super::SharedIdentityManager;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use semaphore::Field;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+type Validation = Shared<BoxFuture<'static, Result<(), Arc<anyhow::Error>>>>;
+
+/// Checks roots with [`super::IdentityManager::assert_valid_root`], sharing a
+/// single in-flight RPC between concurrent checks of the same root.
+pub struct RootValidator {
+    identity_manager: SharedIdentityManager,
+    in_flight:        Mutex<HashMap<Field, Validation>>,
+}
+
+impl RootValidator {
+    pub fn new(identity_manager: SharedIdentityManager) -> Self {
+        Self {
+            identity_manager,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Asserts that `root` is valid on chain, joining a check of the same root
+    /// that is already in flight.
+    pub async fn assert_valid_root(&self, root: Field) -> Result<(), Arc<anyhow::Error>> {
+        let validation = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(root)
+            .or_insert_with(|| {
+                let identity_manager = self.identity_manager.clone();
+                async move {
+                    identity_manager
+                        .assert_valid_root(root)
+                        .await
+                        .map_err(Arc::new)
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+
+        let result = validation.clone().await;
+
+        // Completed checks are forgotten, so a root expiring on chain is noticed.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(&root)
+            .map_or(false, |current| Shared::ptr_eq(current, &validation))
+        {
+            in_flight.remove(&root);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contracts::mock::MockIdentityManager;
+    use futures::future::join_all;
+    use ruint::uint;
+
+    #[tokio::test]
+    async fn concurrent_checks_of_a_root_share_one_rpc() {
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(1)));
+        let validator = RootValidator::new(identity_manager.clone());
+        let root = uint!(0x1234_U256);
+
+        let results = join_all((0..20).map(|_| validator.assert_valid_root(root))).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(identity_manager.root_checks(), 1);
+        assert!(validator.in_flight.lock().unwrap().is_empty());
+
+        // Later checks are not served from the completed one.
+        validator.assert_valid_root(root).await.unwrap();
+        assert_eq!(identity_manager.root_checks(), 2);
+    }
+}
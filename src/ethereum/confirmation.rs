@@ -0,0 +1,302 @@
+use ethers::{
+    providers::{JsonRpcClient, Middleware, Provider, ProviderError},
+    types::{BlockNumber, U64},
+};
+use serde::Serialize;
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use thiserror::Error;
+
+/// When a block is deep enough for its events to be ingested and the
+/// committer's transactions in it to be considered final. Parsed from
+/// `blocks(n)`, `seconds(t)` or `finalized`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ConfirmationStrategy {
+    /// At least this many blocks were mined on top of it.
+    Blocks(u64),
+    /// Mined at least this many seconds before the latest block.
+    Seconds(u64),
+    /// At or below the block tagged `finalized` by the node.
+    Finalized,
+}
+
+#[derive(Debug, Error)]
+#[error("Expected blocks(n), seconds(t) or finalized, got {0:?}")]
+pub struct ParseError(String);
+
+impl FromStr for ConfirmationStrategy {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseError(s.to_owned());
+        if s == "finalized" {
+            return Ok(Self::Finalized);
+        }
+        let (kind, value) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(error)?;
+        let value = value.trim().parse().map_err(|_| error())?;
+        match kind {
+            "blocks" => Ok(Self::Blocks(value)),
+            "seconds" => Ok(Self::Seconds(value)),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl ConfirmationStrategy {
+    /// Returns the number of the latest block that is final under this
+    /// strategy.
+    pub async fn confirmed_block<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<U64, ProviderError> {
+        match *self {
+            Self::Blocks(count) => {
+                let latest = provider.get_block_number().await?;
+                Ok(latest.saturating_sub(U64::from(count)))
+            }
+            Self::Seconds(seconds) => Self::mined_before(provider, seconds, U64::zero()).await,
+            Self::Finalized => provider
+                .get_block(BlockNumber::Finalized)
+                .await?
+                .and_then(|block| block.number)
+                .ok_or_else(|| {
+                    ProviderError::CustomError("Node did not return a finalized block".to_owned())
+                }),
+        }
+    }
+
+    /// Searches for the latest block mined at least `seconds` before the
+    /// latest block, relying on timestamps increasing with block numbers. The
+    /// search starts from `known`, a block found before, and widens forward
+    /// from it, so it takes few requests if the answer moved little.
+    async fn mined_before<P: JsonRpcClient>(
+        provider: &Provider<P>,
+        seconds: u64,
+        known: U64,
+    ) -> Result<U64, ProviderError> {
+        let timestamp = |number: U64| async move {
+            provider
+                .get_block(number)
+                .await?
+                .map(|block| block.timestamp)
+                .ok_or_else(|| ProviderError::CustomError(format!("Block {number} not found")))
+        };
+        let latest = provider.get_block_number().await?;
+        let Some(cutoff) = timestamp(latest).await?.checked_sub(seconds.into()) else {
+            return Ok(U64::zero());
+        };
+
+        // The genesis block is always taken as final, so the search never
+        // ends up without a block. A reorg may have made the known block too
+        // recent, then the search starts over.
+        let mut low = known.min(latest);
+        if !low.is_zero() && timestamp(low).await? > cutoff {
+            low = U64::zero();
+        }
+        let mut high = latest;
+        let mut step = U64::one();
+        while !low.is_zero() && low + step < latest {
+            let probe = low + step;
+            if timestamp(probe).await? > cutoff {
+                high = probe - 1;
+                break;
+            }
+            low = probe;
+            step *= 2;
+        }
+        while low < high {
+            let middle = low + (high - low + 1) / 2;
+            if timestamp(middle).await? <= cutoff {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Ok(low)
+    }
+}
+
+/// The latest final block under a strategy, remembering the previous answer.
+/// Final blocks only move forward, so the `seconds(t)` strategy searches
+/// forward from there instead of across the whole chain on every poll.
+#[derive(Clone, Debug)]
+pub struct FinalBlock {
+    strategy: ConfirmationStrategy,
+    previous: Arc<AtomicU64>,
+}
+
+impl FinalBlock {
+    #[must_use]
+    pub fn new(strategy: ConfirmationStrategy) -> Self {
+        Self {
+            strategy,
+            previous: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn strategy(&self) -> ConfirmationStrategy {
+        self.strategy
+    }
+
+    /// Returns the number of the latest block that is final under the
+    /// strategy.
+    pub async fn latest<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<U64, ProviderError> {
+        let block = match self.strategy {
+            ConfirmationStrategy::Seconds(seconds) => {
+                let previous = self.previous.load(Ordering::Relaxed);
+                ConfirmationStrategy::mined_before(provider, seconds, previous.into()).await?
+            }
+            strategy => strategy.confirmed_block(provider).await?,
+        };
+        self.previous.store(block.as_u64(), Ordering::Relaxed);
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use ethers::types::{Block, H256, U256};
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+    use std::fmt::Debug;
+
+    /// A chain with a block every `block_time` seconds, answering the block
+    /// number and block requests the strategies make.
+    #[derive(Debug)]
+    struct MockChain {
+        latest:     AtomicU64,
+        block_time: u64,
+        finalized:  Option<u64>,
+        requests:   AtomicU64,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for MockChain {
+        type Error = ProviderError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned,
+        {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let response = match method {
+                "eth_blockNumber" => {
+                    serde_json::to_value(U64::from(self.latest.load(Ordering::SeqCst)))?
+                }
+                "eth_getBlockByNumber" => {
+                    let params = serde_json::to_value(params)?;
+                    let number = match params[0].as_str() {
+                        Some("finalized") => self.finalized,
+                        Some(number) => u64::from_str_radix(&number[2..], 16).ok(),
+                        None => None,
+                    };
+                    match number {
+                        Some(number) => serde_json::to_value(Block::<H256> {
+                            number: Some(number.into()),
+                            timestamp: U256::from(number * self.block_time),
+                            ..Block::default()
+                        })?,
+                        None => Value::Null,
+                    }
+                }
+                _ => return Err(ProviderError::CustomError(format!("Unsupported {method}"))),
+            };
+            Ok(serde_json::from_value(response)?)
+        }
+    }
+
+    async fn confirmed(
+        strategy: ConfirmationStrategy,
+        finalized: Option<u64>,
+    ) -> Result<u64, ProviderError> {
+        let provider = Provider::new(MockChain {
+            latest: AtomicU64::new(100),
+            block_time: 12,
+            finalized,
+            requests: AtomicU64::new(0),
+        });
+        let block = strategy.confirmed_block(&provider).await?;
+        Ok(block.as_u64())
+    }
+
+    #[test]
+    fn strategies_are_parsed() {
+        let parse = |s: &str| s.parse::<ConfirmationStrategy>().ok();
+        assert_eq!(parse("blocks(35)"), Some(ConfirmationStrategy::Blocks(35)));
+        assert_eq!(
+            parse("seconds(600)"),
+            Some(ConfirmationStrategy::Seconds(600))
+        );
+        assert_eq!(parse("finalized"), Some(ConfirmationStrategy::Finalized));
+        for invalid in ["blocks", "blocks()", "blocks(x)", "minutes(5)", "safe"] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn block_count_is_subtracted_from_the_latest_block() {
+        let strategy = ConfirmationStrategy::Blocks;
+        assert_eq!(confirmed(strategy(35), None).await.unwrap(), 65);
+        assert_eq!(confirmed(strategy(500), None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn blocks_old_enough_are_confirmed() {
+        // The latest block is at 1200 seconds, one block every 12 seconds.
+        let strategy = ConfirmationStrategy::Seconds;
+        assert_eq!(confirmed(strategy(600), None).await.unwrap(), 50);
+        assert_eq!(confirmed(strategy(605), None).await.unwrap(), 49);
+        assert_eq!(confirmed(strategy(0), None).await.unwrap(), 100);
+        assert_eq!(confirmed(strategy(5000), None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn finalized_tag_is_followed_where_supported() {
+        let strategy = ConfirmationStrategy::Finalized;
+        assert_eq!(confirmed(strategy, Some(68)).await.unwrap(), 68);
+        assert!(confirmed(strategy, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn old_enough_blocks_are_searched_from_the_previous_answer() {
+        let provider = Provider::new(MockChain {
+            latest:     AtomicU64::new(1_000_000),
+            block_time: 12,
+            finalized:  None,
+            requests:   AtomicU64::new(0),
+        });
+        let final_block = FinalBlock::new(ConfirmationStrategy::Seconds(600));
+        assert_eq!(
+            final_block.latest(&provider).await.unwrap().as_u64(),
+            999_950
+        );
+        let full_search = provider.as_ref().requests.swap(0, Ordering::SeqCst);
+
+        provider.as_ref().latest.store(1_000_003, Ordering::SeqCst);
+        assert_eq!(
+            final_block.latest(&provider).await.unwrap().as_u64(),
+            999_953
+        );
+        let requests = provider.as_ref().requests.load(Ordering::SeqCst);
+        assert!(requests < full_search / 2, "{requests} of {full_search}");
+
+        // A chain that went back is searched again from the start.
+        provider.as_ref().latest.store(1_000, Ordering::SeqCst);
+        assert_eq!(final_block.latest(&provider).await.unwrap().as_u64(), 950);
+    }
+}
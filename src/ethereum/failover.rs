@@ -0,0 +1,244 @@
+use super::transport::TransportError;
+use async_trait::async_trait;
+use ethers::providers::{HttpClientError, IpcError, JsonRpcClient, ProviderError, WsClientError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Errors that tell an unreachable or misbehaving endpoint apart from a
+/// well-formed error response, which any endpoint would have returned.
+pub trait EndpointError {
+    fn is_endpoint_failure(&self) -> bool;
+}
+
+impl EndpointError for TransportError {
+    fn is_endpoint_failure(&self) -> bool {
+        !matches!(
+            self,
+            Self::Http(HttpClientError::JsonRpcError(_))
+                | Self::Ws(WsClientError::JsonRpcError(_))
+                | Self::Ipc(IpcError::JsonRpcError(_))
+        )
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FailoverError<Inner> {
+    #[error(transparent)]
+    Endpoint(Inner),
+
+    #[error("Failed to serialize request parameters: {0}")]
+    Params(serde_json::Error),
+}
+
+impl<Inner: Into<ProviderError>> From<FailoverError<Inner>> for ProviderError {
+    fn from(error: FailoverError<Inner>) -> Self {
+        match error {
+            FailoverError::Endpoint(error) => error.into(),
+            FailoverError::Params(error) => Self::SerdeJson(error),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint<Inner> {
+    name:     String,
+    inner:    Inner,
+    /// Consecutive endpoint failures, reset on the first success.
+    failures: AtomicUsize,
+}
+
+/// Spreads requests over several endpoints, using one at a time. After
+/// `threshold` consecutive failures of the current endpoint the next one takes
+/// over. Read requests that fail on an endpoint are retried on the others
+/// before giving up; transactions are only sent once.
+#[derive(Debug, Clone)]
+pub struct Failover<Inner> {
+    endpoints: Arc<[Endpoint<Inner>]>,
+    current:   Arc<AtomicUsize>,
+    threshold: usize,
+}
+
+impl<Inner> Failover<Inner> {
+    /// Creates a failover over named endpoints, starting with the first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn new(endpoints: Vec<(String, Inner)>, threshold: usize) -> Self {
+        assert!(!endpoints.is_empty(), "At least one endpoint is required");
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(name, inner)| Endpoint {
+                name,
+                inner,
+                failures: AtomicUsize::new(0),
+            })
+            .collect();
+        Self {
+            endpoints,
+            current: Arc::new(AtomicUsize::new(0)),
+            threshold: threshold.max(1),
+        }
+    }
+
+    /// Index of the endpoint requests are sent to first.
+    #[must_use]
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    fn record_failure(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        let failures = endpoint.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures < self.threshold || self.endpoints.len() == 1 {
+            return;
+        }
+        let next = (index + 1) % self.endpoints.len();
+        if self
+            .current
+            .compare_exchange(index, next, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            endpoint.failures.store(0, Ordering::SeqCst);
+            warn!(
+                from = %endpoint.name,
+                to = %self.endpoints[next].name,
+                failures,
+                "Ethereum provider failing, switching to the next one."
+            );
+        }
+    }
+
+    fn record_success(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        if endpoint.failures.swap(0, Ordering::SeqCst) > 0 {
+            info!(provider = %endpoint.name, "Ethereum provider recovered.");
+        }
+    }
+}
+
+/// Sending a transaction twice is harmless for signed transactions, but would
+/// double the load on the network for no benefit, so only reads are retried.
+fn is_retryable(method: &str) -> bool {
+    !method.starts_with("eth_send")
+}
+
+#[async_trait]
+impl<Inner> JsonRpcClient for Failover<Inner>
+where
+    Inner: JsonRpcClient + 'static,
+    <Inner as JsonRpcClient>::Error: EndpointError + Sync + Send + 'static,
+{
+    type Error = FailoverError<Inner::Error>;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        // Serialized once, so the request can be repeated on other endpoints.
+        let params = serde_json::to_value(params).map_err(FailoverError::Params)?;
+        let attempts = if is_retryable(method) {
+            self.endpoints.len()
+        } else {
+            1
+        };
+
+        let start = self.current();
+        let mut last_error = None;
+        for attempt in 0..attempts {
+            let index = (start + attempt) % self.endpoints.len();
+            let endpoint = &self.endpoints[index];
+            match endpoint.inner.request(method, &params).await {
+                Ok(result) => {
+                    self.record_success(index);
+                    return Ok(result);
+                }
+                Err(error) if error.is_endpoint_failure() => {
+                    warn!(
+                        provider = %endpoint.name,
+                        method,
+                        %error,
+                        "Ethereum provider request failed."
+                    );
+                    self.record_failure(index);
+                    last_error = Some(error);
+                }
+                Err(error) => {
+                    self.record_success(index);
+                    return Err(FailoverError::Endpoint(error));
+                }
+            }
+        }
+        Err(FailoverError::Endpoint(
+            last_error.expect("At least one attempt is made"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::providers::{MockError, MockProvider};
+
+    impl EndpointError for MockError {
+        fn is_endpoint_failure(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_endpoint_is_skipped_and_rotated_out() {
+        // Without queued responses, the first mock fails every request.
+        let failing = MockProvider::new();
+        let healthy = MockProvider::new();
+        let failover = Failover::new(
+            vec![
+                ("failing".to_owned(), failing),
+                ("healthy".to_owned(), healthy.clone()),
+            ],
+            2,
+        );
+
+        healthy.push(1_u64).unwrap();
+        let block: u64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block, 1);
+        assert_eq!(failover.current(), 0);
+
+        healthy.push(2_u64).unwrap();
+        let block: u64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block, 2);
+        assert_eq!(failover.current(), 1);
+
+        // Now requests go to the healthy endpoint first.
+        healthy.push(3_u64).unwrap();
+        let block: u64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block, 3);
+        assert_eq!(failover.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn transactions_are_not_retried() {
+        let failing = MockProvider::new();
+        let healthy = MockProvider::new();
+        let failover = Failover::new(
+            vec![
+                ("failing".to_owned(), failing),
+                ("healthy".to_owned(), healthy.clone()),
+            ],
+            2,
+        );
+
+        healthy.push(1_u64).unwrap();
+        let result: Result<u64, _> = failover.request("eth_sendRawTransaction", ()).await;
+        assert!(result.is_err());
+    }
+}
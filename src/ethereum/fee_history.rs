@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use ethers::{
+    middleware::gas_oracle::{GasOracle, GasOracleError},
+    providers::Middleware,
+    types::{BlockNumber, FeeHistory, U256},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Time fees fetched from `eth_feeHistory` are reused for, so the legacy and
+/// EIP1559 estimates of one transaction take one request.
+const FEES_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// Estimates fees from `eth_feeHistory`: the priority fee is the median over
+/// recent blocks of the fee paid at `percentile` in each block, on top of the
+/// base fee of the next block. The max fee leaves room for the base fee to
+/// double before the transaction is mined.
+#[derive(Debug, Clone)]
+pub struct FeeHistoryOracle<M> {
+    provider:   M,
+    blocks:     u64,
+    percentile: f64,
+    cache:      Arc<Mutex<Option<(Instant, (U256, U256))>>>,
+}
+
+impl<M> FeeHistoryOracle<M> {
+    pub fn new(provider: M, blocks: u64, percentile: f64) -> Self {
+        Self {
+            provider,
+            blocks,
+            percentile,
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl<M: Middleware> FeeHistoryOracle<M> {
+    /// The next block's base fee and the priority fee to offer. Fetches are
+    /// cached for [`FEES_CACHE_TTL`].
+    async fn fees(&self) -> Result<(U256, U256), GasOracleError> {
+        if let Some((fetched_at, fees)) = *self.cache.lock().unwrap() {
+            if fetched_at.elapsed() < FEES_CACHE_TTL {
+                return Ok(fees);
+            }
+        }
+
+        let history = self
+            .provider
+            .fee_history(self.blocks, BlockNumber::Latest, &[self.percentile])
+            .await
+            .map_err(|error| GasOracleError::ProviderError(Box::new(error)))?;
+        let fees = fees_from_history(&history)
+            .ok_or_else(|| GasOracleError::ProviderError("Empty fee history".into()))?;
+        *self.cache.lock().unwrap() = Some((Instant::now(), fees));
+        Ok(fees)
+    }
+}
+
+fn fees_from_history(history: &FeeHistory) -> Option<(U256, U256)> {
+    // Includes the base fee of the block after the newest one.
+    let base_fee = *history.base_fee_per_gas.last()?;
+    let mut rewards = history
+        .reward
+        .iter()
+        .filter_map(|block| block.first().copied())
+        .collect::<Vec<_>>();
+    rewards.sort_unstable();
+    let priority_fee = *rewards.get(rewards.len() / 2)?;
+    Some((base_fee, priority_fee))
+}
+
+#[async_trait]
+impl<M: Middleware> GasOracle for FeeHistoryOracle<M> {
+    async fn fetch(&self) -> Result<U256, GasOracleError> {
+        let (base_fee, priority_fee) = self.fees().await?;
+        Ok(base_fee + priority_fee)
+    }
+
+    async fn estimate_eip1559_fees(&self) -> Result<(U256, U256), GasOracleError> {
+        let (base_fee, priority_fee) = self.fees().await?;
+        Ok((base_fee * 2 + priority_fee, priority_fee))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::providers::Provider;
+
+    #[tokio::test]
+    async fn fees_follow_the_percentile_of_recent_blocks() {
+        let (provider, mock) = Provider::mocked();
+        let oracle = FeeHistoryOracle::new(provider.clone(), 3, 25.0);
+        let history = FeeHistory {
+            base_fee_per_gas: vec![90.into(), 95.into(), 100.into(), 110.into()],
+            gas_used_ratio:   vec![0.5, 0.9, 0.8],
+            oldest_block:     1.into(),
+            reward:           vec![vec![7.into()], vec![3.into()], vec![5.into()]],
+        };
+
+        mock.push(history).unwrap();
+        assert_eq!(
+            oracle.estimate_eip1559_fees().await.unwrap(),
+            (225.into(), 5.into())
+        );
+        // Served from the cache, the mock has no second response.
+        assert_eq!(oracle.fetch().await.unwrap(), 115.into());
+
+        let empty = FeeHistory {
+            base_fee_per_gas: vec![100.into()],
+            gas_used_ratio:   vec![],
+            oldest_block:     1.into(),
+            reward:           vec![],
+        };
+        mock.push(empty).unwrap();
+        let oracle = FeeHistoryOracle::new(provider, 3, 25.0);
+        assert!(oracle.fetch().await.is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use core::cmp::max;
+use ethers::{
+    middleware::gas_oracle::{GasOracle, GasOracleError},
+    types::U256,
+};
+
+/// Raises gas prices to a floor. There is no ceiling, as a clamped price
+/// produces transactions that are never mined. [`super::Ethereum`] defers
+/// transactions priced above the maximum instead.
+#[derive(Debug, Clone)]
+pub struct GasPriceFloor<Inner> {
+    inner: Inner,
+    min:   Option<U256>,
+}
+
+impl<Inner> GasPriceFloor<Inner> {
+    pub const fn new(inner: Inner, min: Option<U256>) -> Self {
+        Self { inner, min }
+    }
+
+    fn raise(&self, price: U256) -> U256 {
+        self.min.map_or(price, |floor| max(price, floor))
+    }
+}
+
+#[async_trait]
+impl<Inner: GasOracle> GasOracle for GasPriceFloor<Inner> {
+    async fn fetch(&self) -> Result<U256, GasOracleError> {
+        Ok(self.raise(self.inner.fetch().await?))
+    }
+
+    async fn estimate_eip1559_fees(&self) -> Result<(U256, U256), GasOracleError> {
+        let (max_fee, priority_fee) = self.inner.estimate_eip1559_fees().await?;
+        Ok((self.raise(max_fee), priority_fee))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct FixedOracle(U256, U256);
+
+    #[async_trait]
+    impl GasOracle for FixedOracle {
+        async fn fetch(&self) -> Result<U256, GasOracleError> {
+            Ok(self.0)
+        }
+
+        async fn estimate_eip1559_fees(&self) -> Result<(U256, U256), GasOracleError> {
+            Ok((self.0, self.1))
+        }
+    }
+
+    #[tokio::test]
+    async fn prices_are_raised_to_the_floor() {
+        let floor = |inner| GasPriceFloor::new(inner, Some(10.into()));
+
+        let above = floor(FixedOracle(500.into(), 200.into()));
+        assert_eq!(above.fetch().await.unwrap(), 500.into());
+        assert_eq!(
+            above.estimate_eip1559_fees().await.unwrap(),
+            (500.into(), 200.into())
+        );
+
+        let below = floor(FixedOracle(1.into(), 1.into()));
+        assert_eq!(below.fetch().await.unwrap(), 10.into());
+        assert_eq!(
+            below.estimate_eip1559_fees().await.unwrap(),
+            (10.into(), 1.into())
+        );
+
+        let unbounded = GasPriceFloor::new(FixedOracle(1.into(), 1.into()), None);
+        assert_eq!(unbounded.fetch().await.unwrap(), 1.into());
+    }
+}
@@ -1,21 +1,37 @@
 /// TODO: Upstream most of these to ethers-rs
+mod confirmation;
 mod estimator;
+mod failover;
+mod fee_history;
 mod gas_oracle_logger;
+mod gas_price_floor;
 mod min_gas_fees;
+mod nonce;
 mod rpc_logger;
+mod signer;
+mod timeout;
 mod transport;
 
+pub use self::{
+    confirmation::{ConfirmationStrategy, FinalBlock},
+    signer::{KmsSigner, SignerType, TxSigner},
+    timeout::RpcTimeout,
+};
 use self::{
-    estimator::Estimator, gas_oracle_logger::GasOracleLogger, min_gas_fees::MinGasFees,
-    rpc_logger::RpcLogger, transport::Transport,
+    estimator::Estimator, failover::Failover, fee_history::FeeHistoryOracle,
+    gas_oracle_logger::GasOracleLogger, gas_price_floor::GasPriceFloor, min_gas_fees::MinGasFees,
+    nonce::NonceManager, rpc_logger::RpcLogger, timeout::Timeout, transport::Transport,
+};
+use crate::{
+    contracts::confirmed_log_query::{ConfirmedLogQuery, Error as CachingLogQueryError},
+    utils::{serialize_provider_urls_redacted, serialize_redacted},
 };
-use crate::contracts::confirmed_log_query::{ConfirmedLogQuery, Error as CachingLogQueryError};
-use anyhow::{anyhow, Result as AnyhowResult};
+use anyhow::{anyhow, ensure, Result as AnyhowResult};
 use chrono::{Duration as ChronoDuration, Utc};
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use ethers::{
     abi::{Error as AbiError, RawLog},
-    contract::EthEvent,
+    contract::EthLogDecode,
     core::k256::ecdsa::SigningKey,
     middleware::{
         gas_oracle::{
@@ -24,8 +40,8 @@ use ethers::{
         },
         SignerMiddleware,
     },
-    providers::{Middleware, Provider, ProviderError},
-    signers::{LocalWallet, Signer, Wallet},
+    providers::{JsonRpcClient, Middleware, PendingTransaction, Provider, ProviderError},
+    signers::{LocalWallet, Signer},
     types::{
         transaction::eip2718::TypedTransaction, u256_from_f64_saturating, Address, BlockId,
         BlockNumber, Chain, Filter, Log as EthLog, TransactionReceipt, H160, H256, U256, U64,
@@ -38,11 +54,24 @@ use prometheus::{
     register_int_counter_vec, Counter, Gauge, Histogram, IntCounterVec,
 };
 use reqwest::Client as ReqwestClient;
-use std::{error::Error, num::ParseIntError, str::FromStr, sync::Arc, time::Duration};
+use rusoto_core::Region;
+use rusoto_kms::KmsClient;
+use serde::Serialize;
+use std::{
+    env,
+    error::Error,
+    fs, iter,
+    num::ParseIntError,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
-use tokio::time::timeout;
+use tokio::time::{sleep, timeout};
 use tracing::{debug_span, error, info, info_span, instrument, warn, Instrument};
 use url::Url;
+use zeroize::{Zeroize, Zeroizing};
 
 const PENDING: Option<BlockId> = Some(BlockId::Number(BlockNumber::Pending));
 
@@ -83,18 +112,65 @@ static TX_GAS_USED: Lazy<Counter> = Lazy::new(|| {
 static TX_WEI_USED: Lazy<Counter> = Lazy::new(|| {
     register_counter!("eth_tx_wei_used", "Cumulative wei used for transactions.").unwrap()
 });
+static WALLET_BALANCE: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "eth_wallet_balance_wei",
+        "Balance of the transaction signing wallet in wei."
+    )
+    .unwrap()
+});
+
+/// How long a looked up wallet balance is reused.
+const BALANCE_CACHE_TTL: Duration = Duration::from_secs(10);
+
+#[allow(clippy::cast_precision_loss)]
+fn record_balance(balance: U256) {
+    WALLET_BALANCE.set(balance.as_u128() as f64);
+}
 
 fn duration_from_str(value: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_secs(u64::from_str(value)?))
 }
 
+fn duration_from_millis_str(value: &str) -> Result<Duration, ParseIntError> {
+    Ok(Duration::from_millis(u64::from_str(value)?))
+}
+
+/// Overrides the provider's poll interval if one is configured.
+fn with_poll_interval<P: JsonRpcClient>(
+    provider: Provider<P>,
+    interval: Option<Duration>,
+) -> Provider<P> {
+    match interval {
+        Some(interval) => provider.interval(interval),
+        None => provider,
+    }
+}
+
 // TODO: Log and metrics for signer / nonces.
-#[derive(Clone, Debug, PartialEq, Parser)]
+#[derive(Clone, Debug, PartialEq, Parser, Serialize)]
 #[group(skip)]
 pub struct Options {
-    /// Ethereum API Provider
-    #[clap(long, env, default_value = "http://localhost:8545")]
-    pub ethereum_provider: Url,
+    /// Ethereum API Providers, separated by commas. Requests go to the first
+    /// one until it fails repeatedly, then to the next.
+    #[clap(
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "http://localhost:8545"
+    )]
+    #[serde(serialize_with = "serialize_provider_urls_redacted")]
+    pub ethereum_provider: Vec<Url>,
+
+    /// Number of consecutive failures after which the next Ethereum provider
+    /// takes over.
+    #[clap(long, env, default_value = "3")]
+    pub provider_failover_threshold: usize,
+
+    /// Chain id the Ethereum provider must report. Startup fails if it reports
+    /// a different one, which guards against pointing at the wrong network.
+    #[clap(long, env)]
+    pub expected_chain_id: Option<u64>,
 
     /// Private key used for transaction signing
     #[clap(
@@ -103,8 +179,37 @@ pub struct Options {
         default_value = "ee79b5f6e221356af78cf4c36f4f7885a11b67dfcc81c34d80249947330c0f82"
     )]
     // NOTE: We abuse `Hash` here because it has the right `FromStr` implementation.
+    #[serde(serialize_with = "serialize_redacted")]
     pub signing_key: H256,
 
+    /// File containing the hex encoded private key used for transaction
+    /// signing. Use this instead of `signing_key` to keep the key out of
+    /// process listings and shell history.
+    #[clap(long, env, conflicts_with = "signing_key")]
+    pub signing_key_file: Option<PathBuf>,
+
+    /// Name of an environment variable holding the hex encoded private key
+    /// used for transaction signing, such as one a secret store populates.
+    /// Only the reference is passed, so the key stays out of process
+    /// listings and configuration dumps.
+    #[clap(long, env, conflicts_with_all = ["signing_key", "signing_key_file"])]
+    pub signing_key_secret: Option<String>,
+
+    /// Where the transaction signing key is held, either `local` for
+    /// `signing_key`, `signing_key_file` or `signing_key_secret`, or `kms` for
+    /// an AWS KMS key.
+    #[clap(long, env, value_enum, default_value = "local")]
+    pub signer: SignerType,
+
+    /// AWS KMS key id used for transaction signing with the `kms` signer. The
+    /// credentials are read from the usual AWS environment variables.
+    #[clap(long, env, required_if_eq("signer", "kms"))]
+    pub kms_key_id: Option<String>,
+
+    /// AWS region of the `kms_key_id` key, such as `eu-central-1`.
+    #[clap(long, env, required_if_eq("signer", "kms"))]
+    pub kms_region: Option<String>,
+
     /// Maximum number of blocks to pull events from in one request.
     #[clap(long, env, default_value = "100000")]
     pub max_log_blocks: usize,
@@ -117,19 +222,70 @@ pub struct Options {
     #[clap(long, env, value_parser=duration_from_str, default_value="32")]
     pub max_backoff_time: Duration,
 
-    /// Minimum number of blocks before events are considered confirmed.
+    /// Minimum number of blocks before events are ingested into the tree.
     #[clap(long, env, default_value = "35")]
-    pub confirmation_blocks_delay: usize,
+    pub ingest_confirmations: usize,
+
+    /// Deprecated name of `ingest_confirmations`, still accepted from the
+    /// command line and the environment.
+    #[clap(long, env, hide = true, conflicts_with = "ingest_confirmations")]
+    pub confirmation_blocks_delay: Option<usize>,
+
+    /// Number of confirmations before a transaction sent by the committer is
+    /// considered final.
+    #[clap(long, env, default_value = "1")]
+    pub commit_tx_confirmations: usize,
+
+    /// When blocks are final, for both ingesting events and the committer's
+    /// transactions: `blocks(n)` deep, mined `seconds(t)` before the latest
+    /// block, or `finalized` as tagged by the node. Overrides
+    /// `ingest_confirmations` and `commit_tx_confirmations`.
+    #[clap(long, env)]
+    pub confirmation_strategy: Option<ConfirmationStrategy>,
 
-    /// The number of most recent blocks to be removed from cache on root
-    /// mismatch
+    /// The number of most recent blocks to be removed from cache on the first
+    /// root mismatch. Each following mismatch removes twice as many.
     #[clap(long, env, default_value = "1000")]
     pub cache_recovery_step_size: usize,
 
+    /// The number of times recent blocks are removed from cache on root
+    /// mismatch before the entire cache is wiped.
+    #[clap(long, env, default_value = "5")]
+    pub cache_recovery_max_attempts: usize,
+
+    /// Wipe the entire cache once removing recent blocks did not resolve a
+    /// root mismatch. When disabled, startup fails instead, leaving the cache
+    /// for an operator to inspect.
+    #[clap(long, env, default_value = "true", action = ArgAction::Set)]
+    pub cache_recovery_wipe: bool,
+
     /// Frequency of event fetching from Ethereum (seconds)
     #[clap(long, env, value_parser=duration_from_str, default_value="60")]
     pub refresh_rate: Duration,
 
+    /// Interval at which the provider polls for new blocks and pending
+    /// transactions (milliseconds). Defaults to the ethers-rs default.
+    #[clap(long, env, value_parser=duration_from_millis_str)]
+    pub provider_poll_interval: Option<Duration>,
+
+    /// Maximum delay between retries while the Ethereum provider is
+    /// unreachable (seconds). Retries back off exponentially from
+    /// `refresh_rate`, with jitter.
+    #[clap(long, env, value_parser=duration_from_str, default_value="600")]
+    pub max_retry_interval: Duration,
+
+    /// Deepest reorg of already ingested blocks that is handled by rolling
+    /// the tree back and syncing the replaced blocks again. Deeper reorgs halt
+    /// syncing and mark the instance unhealthy until an operator intervenes.
+    #[clap(long, env, default_value = "64")]
+    pub max_auto_reorg_depth: u64,
+
+    /// Maximum time to wait for a response to a single Ethereum provider
+    /// request (seconds). Requests that take longer fail and count against the
+    /// provider for failover. Sends are bounded by `send_timeout` instead.
+    #[clap(long, env, value_parser=duration_from_str, default_value="30")]
+    pub rpc_timeout: Duration,
+
     /// Minimum `max_fee_per_gas` to use in GWei. The default is for Polygon
     /// mainnet.
     #[clap(long, env, default_value = "1250.0")]
@@ -140,6 +296,24 @@ pub struct Options {
     #[clap(long, env, default_value = "31.0")]
     pub min_priority_fee: f64,
 
+    /// Minimum gas price, or EIP1559 `max_fee_per_gas`, to use in GWei.
+    #[clap(long, env)]
+    pub min_gas_price: Option<f64>,
+
+    /// Maximum gas price, or EIP1559 `max_fee_per_gas`, to use in GWei.
+    /// Transactions are deferred while the gas oracle asks for more.
+    #[clap(long, env)]
+    pub max_gas_price: Option<f64>,
+
+    /// Percentile of the priority fees paid in recent blocks to offer, as
+    /// reported by `eth_feeHistory`. Only used on EIP1559 chains.
+    #[clap(long, env, default_value = "50")]
+    pub fee_history_percentile: f64,
+
+    /// Number of recent blocks whose priority fees are considered.
+    #[clap(long, env, default_value = "10")]
+    pub fee_history_blocks: u64,
+
     /// Multiplier on `priority_fee_per_gas`.
     #[clap(long, env, default_value = "100")]
     pub priority_fee_multiplier_percentage: u64,
@@ -151,15 +325,113 @@ pub struct Options {
     /// Timeout for mining transaction (seconds).
     #[clap(long, env, default_value = "300")]
     pub mine_timeout: u64,
+
+    /// Timeout for a mined transaction to become final under the `seconds`
+    /// or `finalized` confirmation strategy (seconds). Counted from when it
+    /// was mined, separately from `mine_timeout`.
+    #[clap(long, env, default_value = "1800")]
+    pub finality_timeout: u64,
+
+    /// Wallet balance in ETH below which a warning is logged on every balance
+    /// lookup.
+    #[clap(long, env)]
+    pub low_balance_warning: Option<f64>,
+}
+
+impl Options {
+    /// When events are ingested, `ingest_confirmations` blocks deep unless a
+    /// confirmation strategy is configured.
+    #[must_use]
+    pub fn ingest_confirmation(&self) -> ConfirmationStrategy {
+        let blocks = self
+            .confirmation_blocks_delay
+            .unwrap_or(self.ingest_confirmations);
+        self.confirmation_strategy
+            .unwrap_or(ConfirmationStrategy::Blocks(blocks as u64))
+    }
+
+    /// When the committer's transactions are final, after
+    /// `commit_tx_confirmations` unless a confirmation strategy is configured.
+    #[must_use]
+    pub fn commit_confirmation(&self) -> ConfirmationStrategy {
+        self.confirmation_strategy
+            .unwrap_or(ConfirmationStrategy::Blocks(
+                self.commit_tx_confirmations as u64,
+            ))
+    }
+
+    /// Loads the transaction signing key, from `signing_key_file` or the
+    /// variable named by `signing_key_secret` if given, and from
+    /// `signing_key` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the key file or variable can not be read or does
+    /// not contain a valid private key.
+    pub fn load_signing_key(&self) -> AnyhowResult<SigningKey> {
+        if let Some(path) = &self.signing_key_file {
+            let contents = Zeroizing::new(
+                fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?,
+            );
+            return parse_signing_key(&contents)
+                .map_err(|e| anyhow!("Invalid signing key in {}: {}", path.display(), e));
+        }
+        if let Some(name) = &self.signing_key_secret {
+            let contents = Zeroizing::new(
+                env::var(name).map_err(|e| anyhow!("Failed to read ${name}: {e}"))?,
+            );
+            return parse_signing_key(&contents)
+                .map_err(|e| anyhow!("Invalid signing key in ${name}: {e}"));
+        }
+        Ok(SigningKey::from_bytes(self.signing_key.as_bytes())?)
+    }
+
+    /// Creates the configured transaction signer for `chain_id`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the signing key can not be loaded.
+    pub async fn load_signer(&self, chain_id: u64) -> AnyhowResult<TxSigner> {
+        match self.signer {
+            SignerType::Local => {
+                let signer = LocalWallet::from(self.load_signing_key()?);
+                Ok(TxSigner::Local(signer.with_chain_id(chain_id)))
+            }
+            SignerType::Kms => {
+                let key_id = self
+                    .kms_key_id
+                    .clone()
+                    .ok_or_else(|| anyhow!("The kms signer requires a kms_key_id"))?;
+                let region = self
+                    .kms_region
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("The kms signer requires a kms_region"))?;
+                let region = Region::from_str(region)
+                    .map_err(|e| anyhow!("Invalid kms_region {region}: {e}"))?;
+                let signer = KmsSigner::new(KmsClient::new(region), key_id, chain_id).await?;
+                Ok(TxSigner::Kms(signer))
+            }
+        }
+    }
+}
+
+/// Parses a hex encoded private key, zeroizing the intermediate copy.
+fn parse_signing_key(contents: &str) -> AnyhowResult<SigningKey> {
+    let mut key = H256::from_str(contents.trim())?;
+    let signing_key = SigningKey::from_bytes(key.as_bytes());
+    key.0.zeroize();
+    Ok(signing_key?)
 }
 
 // Code out the provider stack in types
 // Needed because of <https://github.com/gakonst/ethers-rs/issues/592>
-type Provider0 = Provider<RpcLogger<Transport>>;
+type Provider0 = Provider<RpcLogger<Failover<Timeout<Transport>>>>;
 type Provider1 = Estimator<Provider0>;
 type Provider2 = GasOracleMiddleware<Arc<Provider1>, Box<dyn GasOracle>>;
-type Provider3 = SignerMiddleware<Provider2, Wallet<SigningKey>>;
-// type Provider4 = NonceManagerMiddleware<Provider3>;
+type Provider3 = SignerMiddleware<Provider2, TxSigner>;
+// Nonces are handed out by `Ethereum::nonces` rather than a
+// `NonceManagerMiddleware`, so failed broadcasts can give theirs back.
 pub type ProviderStack = Provider3;
 
 #[derive(Debug, Error)]
@@ -184,6 +456,34 @@ pub enum TxError {
 
     #[error("Transaction failed.")]
     Failed(Box<TransactionReceipt>),
+
+    #[error("Gas price of {required} wei exceeds the maximum of {max} wei.")]
+    GasPriceTooHigh { required: U256, max: U256 },
+}
+
+impl TxError {
+    /// Whether the transaction may have reached the mempool despite the error.
+    /// Only failures before sending, and the node rejecting the transaction,
+    /// prove that it did not.
+    #[must_use]
+    pub fn may_have_been_broadcast(&self) -> bool {
+        match self {
+            Self::Fill(_) | Self::GasPriceTooHigh { .. } => false,
+            Self::Send(error) => !is_error_response(error.as_ref()),
+            _ => true,
+        }
+    }
+}
+
+/// How ethers displays a JSON-RPC error response.
+const ERROR_RESPONSE: &str = "(code: ";
+
+/// Whether `error` is an error response of the node, rather than a failure to
+/// get a response. Middleware errors only carry the response as text, so the
+/// message is matched.
+fn is_error_response(error: &(dyn Error + 'static)) -> bool {
+    iter::successors(Some(error), |error| error.source())
+        .any(|cause| cause.to_string().contains(ERROR_RESPONSE))
 }
 
 #[derive(Debug, Error)]
@@ -198,41 +498,76 @@ pub enum EventError {
     EmptyBlockIndex,
     #[error("empty transaction index")]
     EmptyTransactionIndex,
+    #[error("empty transaction hash")]
+    EmptyTransactionHash,
     #[error("empty log index")]
     EmptyLogIndex,
+    #[error("Error fetching block: {0}")]
+    FetchingBlock(#[source] ProviderError),
+}
+
+impl EventError {
+    /// Whether the provider could not be asked for events, rather than
+    /// returning ones that can not be used, so that asking again may succeed.
+    #[must_use]
+    pub const fn is_transient(&self) -> bool {
+        matches!(self, Self::Fetching(_) | Self::FetchingBlock(_))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Ethereum {
-    provider:                  Arc<ProviderStack>,
-    address:                   H160,
-    legacy:                    bool,
-    max_log_blocks:            usize,
-    min_log_blocks:            usize,
-    max_backoff_time:          Duration,
-    confirmation_blocks_delay: usize,
-    send_timeout:              Duration,
-    mine_timeout:              Duration,
+    provider:            Arc<ProviderStack>,
+    address:             H160,
+    read_only:           bool,
+    legacy:              bool,
+    max_log_blocks:      usize,
+    min_log_blocks:      usize,
+    max_backoff_time:    Duration,
+    ingest_confirmation: FinalBlock,
+    commit_confirmation: FinalBlock,
+    send_timeout:        Duration,
+    mine_timeout:        Duration,
+    finality_timeout:    Duration,
+    max_gas_price:       Option<U256>,
+    low_balance_warning: Option<U256>,
+    nonces:              Arc<NonceManager>,
+    /// The latest balance lookup and when it was made.
+    balance_cache:       Arc<Mutex<Option<(Instant, U256)>>>,
 }
 
 impl Ethereum {
+    /// Connects to the Ethereum providers. Read-only instances have no wallet
+    /// and can not send transactions, so no signing key is loaded.
     #[instrument(name = "Ethereum::new", level = "debug", skip_all)]
-    pub async fn new(options: Options) -> AnyhowResult<Self> {
-        // Connect to the Ethereum provider
-        // TODO: Allow multiple providers with failover / broadcast.
+    pub async fn new(options: Options, read_only: bool) -> AnyhowResult<Self> {
+        // Connect to the Ethereum providers
         // TODO: Requests don't seem to process in parallel. Check if this is
         // a limitation client side or server side.
         // TODO: Does the WebSocket impl handle dropped connections by
         // reconnecting? What is the timeout on stalled connections? What is
         // the retry policy?
+        ensure!(
+            (0.0..=100.0).contains(&options.fee_history_percentile),
+            "Fee history percentile {} is not between 0 and 100",
+            options.fee_history_percentile
+        );
         let (provider, chain_id, eip1559) = {
-            info!(
-                provider = %&options.ethereum_provider,
-                "Connecting to Ethereum"
+            ensure!(
+                !options.ethereum_provider.is_empty(),
+                "No Ethereum provider given"
             );
-            let transport = Transport::new(options.ethereum_provider).await?;
-            let logger = RpcLogger::new(transport);
-            let provider = Provider::new(logger);
+            let mut endpoints = Vec::with_capacity(options.ethereum_provider.len());
+            for url in options.ethereum_provider {
+                info!(provider = %url, "Connecting to Ethereum");
+                let name = url.to_string();
+                let transport = Transport::new(url).await?;
+                endpoints.push((name, Timeout::new(transport, options.rpc_timeout)));
+            }
+            let failover = Failover::new(endpoints, options.provider_failover_threshold);
+            let logger = RpcLogger::new(failover);
+            let provider =
+                with_poll_interval(Provider::new(logger), options.provider_poll_interval);
 
             // Fetch state of the chain.
             let (version, chain_id, latest_block, eip1559) = try_join!(
@@ -247,6 +582,14 @@ impl Ethereum {
             // Identify chain.
             let chain = Chain::try_from(chain_id)
                 .map_or_else(|_| "Unknown".to_string(), |chain| chain.to_string());
+            if let Some(expected) = options.expected_chain_id {
+                if chain_id != U256::from(expected) {
+                    return Err(anyhow!(
+                        "Ethereum provider is on chain id {chain_id} ({chain}), expected \
+                         {expected}"
+                    ));
+                }
+            }
 
             // Log chain state.
             let latest_block = latest_block
@@ -279,14 +622,33 @@ impl Ethereum {
         // TODO: Use local EVM evaluation?
         let provider = Estimator::new(provider, 1.10, 10e3);
 
+        let min_gas_price = options
+            .min_gas_price
+            .map(|gwei| u256_from_f64_saturating(gwei * 1e9));
+        let max_gas_price = options
+            .max_gas_price
+            .map(|gwei| u256_from_f64_saturating(gwei * 1e9));
+
         // Add a gas oracle.
         let provider = {
             // Start with a medianizer
             let mut median = Median::new();
 
-            // Construct a fallback oracle
+            // Construct a fallback oracle, based on the fees recently paid
+            // where the chain reports them.
             let provider = Arc::new(provider);
-            median.add_weighted(0.1, ProviderOracle::new(provider.clone()));
+            if eip1559 {
+                median.add_weighted(
+                    0.1,
+                    FeeHistoryOracle::new(
+                        provider.clone(),
+                        options.fee_history_blocks,
+                        options.fee_history_percentile,
+                    ),
+                );
+            } else {
+                median.add_weighted(0.1, ProviderOracle::new(provider.clone()));
+            }
 
             // Utility to get a Reqwest client with 30s timeout.
             let client = || -> AnyhowResult<ReqwestClient> {
@@ -321,6 +683,7 @@ impl Ethereum {
                 min_priority_fee,
                 options.priority_fee_multiplier_percentage.into(),
             );
+            let oracle = GasPriceFloor::new(oracle, min_gas_price);
 
             // Add a logging, caching and abstract the type.
             let oracle = GasOracleLogger::new(oracle);
@@ -340,35 +703,27 @@ impl Ethereum {
             GasOracleMiddleware::new(provider, oracle)
         };
 
-        // Construct a local key signer
-        let (provider, address) = {
+        // Construct the signer
+        let chain_id: u64 = chain_id.try_into().map_err(|e| anyhow!("{}", e))?;
+        let (provider, address) = if read_only {
+            info!("Read-only mode, not loading a signer");
+            let signer = TxSigner::ReadOnly { chain_id };
+            (SignerMiddleware::new(provider, signer), Address::zero())
+        } else {
             // Create signer
-            let signing_key = SigningKey::from_bytes(options.signing_key.as_bytes())?;
-            let signer = LocalWallet::from(signing_key);
+            let signer = options.load_signer(chain_id).await?;
             let address = signer.address();
 
             // Create signer middleware for provider.
-            let chain_id: u64 = chain_id.try_into().map_err(|e| anyhow!("{}", e))?;
-            let signer = signer.with_chain_id(chain_id);
             let provider = SignerMiddleware::new(provider, signer);
 
-            // Create local nonce manager.
-            // TODO: This is state full. There may be unsettled TXs in the mempool.
-            // let provider = { NonceManagerMiddleware::new(provider, address) };
-            //
-            // // Log wallet info.
-            // let (next_nonce, balance) = try_join!(
-            //     provider.initialize_nonce(PENDING),
-            //     provider.get_balance(address, PENDING)
-            // )?;
-            // info!(?address, %next_nonce, %balance, "Constructed wallet");
-
             // Log wallet info.
             let (next_nonce, balance) = try_join!(
                 provider.get_transaction_count(address, PENDING),
                 provider.get_balance(address, PENDING)
             )?;
             info!(?address, %next_nonce, %balance, "Constructed wallet");
+            record_balance(balance);
 
             // Sanity check the balance
             if balance.is_zero() {
@@ -377,19 +732,35 @@ impl Ethereum {
             }
             (provider, address)
         };
-        // TODO: Check signer balance regularly and keep the metric as a gauge.
+        // TODO: Check signer balance regularly, not only on lookups.
+
+        if let Some(blocks) = options.confirmation_blocks_delay {
+            warn!(
+                blocks,
+                "CONFIRMATION_BLOCKS_DELAY is deprecated, set INGEST_CONFIRMATIONS instead."
+            );
+        }
 
         let provider = Arc::new(provider);
         Ok(Self {
             provider,
             address,
+            read_only,
             legacy: !eip1559,
             max_log_blocks: options.max_log_blocks,
             min_log_blocks: options.min_log_blocks,
             max_backoff_time: options.max_backoff_time,
-            confirmation_blocks_delay: options.confirmation_blocks_delay,
+            ingest_confirmation: FinalBlock::new(options.ingest_confirmation()),
+            commit_confirmation: FinalBlock::new(options.commit_confirmation()),
             send_timeout: Duration::from_secs(options.send_timeout),
             mine_timeout: Duration::from_secs(options.mine_timeout),
+            finality_timeout: Duration::from_secs(options.finality_timeout),
+            max_gas_price,
+            low_balance_warning: options
+                .low_balance_warning
+                .map(|eth| u256_from_f64_saturating(eth * 1e18)),
+            nonces: Arc::new(NonceManager::new(address)),
+            balance_cache: Arc::new(Mutex::new(None)),
         })
     }
 
@@ -403,24 +774,79 @@ impl Ethereum {
         self.address
     }
 
+    /// Whether this instance has no wallet and can not send transactions.
+    #[must_use]
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the balance of the signing wallet in wei. Lookups are cached
+    /// for a few seconds.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the balance can not be fetched, or on read-only
+    /// instances.
+    pub async fn balance(&self) -> AnyhowResult<U256> {
+        ensure!(!self.read_only, "Read-only instances have no wallet");
+        if let Some((fetched_at, balance)) = *self.balance_cache.lock().unwrap() {
+            if fetched_at.elapsed() < BALANCE_CACHE_TTL {
+                return Ok(balance);
+            }
+        }
+
+        let balance = self.provider.get_balance(self.address, None).await?;
+        *self.balance_cache.lock().unwrap() = Some((Instant::now(), balance));
+        record_balance(balance);
+        if self
+            .low_balance_warning
+            .map_or(false, |threshold| balance < threshold)
+        {
+            warn!(address = ?self.address, %balance, "Wallet balance is running low.");
+        }
+        Ok(balance)
+    }
+
     #[instrument(level = "debug", skip_all)]
     pub async fn send_transaction(
         &self,
         tx: TypedTransaction,
     ) -> Result<TransactionReceipt, TxError> {
-        self.send_transaction_unlogged(tx).await.map_err(|e| {
+        let sent = self.broadcast_transaction(tx).await?;
+        self.wait_for_transaction(sent).await
+    }
+
+    /// Fills in, signs and sends `tx` to the mempool without waiting for it
+    /// to be mined.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn broadcast_transaction(
+        &self,
+        tx: TypedTransaction,
+    ) -> Result<SentTransaction, TxError> {
+        self.broadcast_transaction_unlogged(tx).await.map_err(|e| {
+            error!(?e, "Transaction failed");
+            e
+        })
+    }
+
+    /// Waits for a transaction previously sent by
+    /// [`Self::broadcast_transaction`] to be mined.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn wait_for_transaction(
+        &self,
+        sent: SentTransaction,
+    ) -> Result<TransactionReceipt, TxError> {
+        self.wait_for_transaction_unlogged(sent).await.map_err(|e| {
             error!(?e, "Transaction failed");
             e
         })
     }
 
     #[instrument(level = "info", skip(self))]
-    #[allow(clippy::option_if_let_else)] // Less readable
-    #[allow(clippy::cast_precision_loss)]
-    async fn send_transaction_unlogged(
+    async fn broadcast_transaction_unlogged(
         &self,
         tx: TypedTransaction,
-    ) -> Result<TransactionReceipt, TxError> {
+    ) -> Result<SentTransaction, TxError> {
         // Convert to legacy transaction if required
         let mut tx = if self.legacy {
             TypedTransaction::Legacy(match tx {
@@ -432,6 +858,79 @@ impl Ethereum {
             tx
         };
 
+        // Don't send transactions that would pay more than the maximum gas
+        // price. The fees are set before checking them, so the transaction
+        // pays the ones checked.
+        if let Some(max) = self.max_gas_price {
+            let required = self.fill_gas_price(&mut tx).await?;
+            if required > max {
+                warn!(%required, %max, "Gas price too high, deferring transaction.");
+                return Err(TxError::GasPriceTooHigh { required, max });
+            }
+        }
+
+        // Take the nonce from the local manager, and give it back if the
+        // transaction does not make it to the mempool, so later transactions
+        // don't stall behind the gap. The nonce stays reserved until then, so
+        // concurrent broadcasts wait instead of reusing it.
+        if tx.nonce().is_some() {
+            return self.fill_and_send(tx).await;
+        }
+        let reserved = self
+            .nonces
+            .reserve(self.provider.as_ref())
+            .await
+            .map_err(|error| TxError::Fill(Box::new(error)))?;
+        let nonce = reserved.nonce();
+        tx.set_nonce(nonce);
+        let result = self.fill_and_send(tx).await;
+        match &result {
+            Ok(_) => reserved.commit(),
+            // Reusing the nonce could replace a transaction that did reach the
+            // mempool. If it did not, the next transaction is eventually found
+            // dropped, which gives the gap back.
+            Err(error) if error.may_have_been_broadcast() => {
+                warn!(
+                    nonce,
+                    ?error,
+                    "Transaction may have been broadcast, keeping its nonce."
+                );
+                reserved.commit();
+            }
+            Err(_) => reserved.release(self.provider.as_ref()).await,
+        }
+        result
+    }
+
+    /// Sets the fees suggested by the gas oracle, unless already set, and
+    /// returns the most the transaction may pay per gas.
+    async fn fill_gas_price(&self, tx: &mut TypedTransaction) -> Result<U256, TxError> {
+        if let TypedTransaction::Eip1559(tx) = tx {
+            if tx.max_fee_per_gas.is_none() || tx.max_priority_fee_per_gas.is_none() {
+                let (max_fee, priority_fee) = self
+                    .provider
+                    .estimate_eip1559_fees(None)
+                    .await
+                    .map_err(|error| TxError::Fill(Box::new(error)))?;
+                tx.max_fee_per_gas.get_or_insert(max_fee);
+                tx.max_priority_fee_per_gas.get_or_insert(priority_fee);
+            }
+            return Ok(tx.max_fee_per_gas.unwrap_or_default());
+        }
+        if let Some(price) = tx.gas_price() {
+            return Ok(price);
+        }
+        let price = self
+            .provider
+            .get_gas_price()
+            .await
+            .map_err(|error| TxError::Fill(Box::new(error)))?;
+        tx.set_gas_price(price);
+        Ok(price)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    async fn fill_and_send(&self, mut tx: TypedTransaction) -> Result<SentTransaction, TxError> {
         // Fill in transaction
         self.provider
             .fill_transaction(&mut tx, None)
@@ -473,24 +972,135 @@ impl Ethereum {
         let tx_hash: H256 = *pending;
         info!(?nonce, ?tx_hash, "Transaction in mempool");
 
-        // Wait for TX to be mined
-        let timer = TX_LATENCY.start_timer();
-        let receipt = timeout(self.mine_timeout, pending)
+        Ok(SentTransaction {
+            hash: tx_hash,
+            nonce,
+            gas_limit: tx.gas().copied(),
+        })
+    }
+
+    /// Waits until `block` is final under the commit confirmation strategy.
+    async fn wait_until_final(&self, block: U64) -> Result<(), TxError> {
+        let provider = self.provider.provider();
+        loop {
+            let confirmed = self
+                .commit_confirmation
+                .latest(provider)
+                .await
+                .map_err(TxError::Confirmation)?;
+            if confirmed >= block {
+                return Ok(());
+            }
+            sleep(provider.get_interval()).await;
+        }
+    }
+
+    /// Waits for `tx_hash` to be mined and final under the commit
+    /// confirmation strategy. A reorg
+    /// can orphan the block the transaction was mined in while confirmations
+    /// are counted, so the receipt is fetched again once they are. The
+    /// transaction is then waited for again, to be mined anew or reported as
+    /// dropped if it left the mempool.
+    ///
+    /// Mining, including block confirmations, is bounded by `mine_timeout`,
+    /// and waiting for a mined block to become final by `finality_timeout`.
+    async fn confirmed_receipt(
+        &self,
+        tx_hash: H256,
+        nonce: u64,
+    ) -> Result<TransactionReceipt, TxError> {
+        let provider = self.provider.provider();
+        // Block counts are left to the pending transaction, other strategies
+        // are waited for once it is mined.
+        let (confirmations, wait_until_final) = match self.commit_confirmation.strategy() {
+            ConfirmationStrategy::Blocks(count) => {
+                (usize::try_from(count).unwrap_or(usize::MAX), false)
+            }
+            ConfirmationStrategy::Seconds(_) | ConfirmationStrategy::Finalized => (1, true),
+        };
+        loop {
+            let mined = timeout(
+                self.mine_timeout,
+                PendingTransaction::new(tx_hash, provider).confirmations(confirmations),
+            )
             .instrument(info_span!("Wait for TX to be mined"))
             .await
             .map_err(|elapsed| {
-                error!(?elapsed, "Waiting for transaction confirmation timed out");
+                error!(
+                    ?nonce,
+                    ?tx_hash,
+                    ?elapsed,
+                    "Waiting for transaction to be mined timed out"
+                );
                 TxError::ConfirmationTimeout
-            })?
-            .map_err(|err| {
-                error!(?nonce, ?tx_hash, ?err, "Transaction failed to confirm");
-
-                TxError::Confirmation(err)
-            })?
-            .ok_or_else(|| {
-                error!(?nonce, ?tx_hash, "Transaction dropped");
-                TxError::Dropped(tx_hash)
             })?;
+            let receipt = mined
+                .map_err(|err| {
+                    error!(?nonce, ?tx_hash, ?err, "Transaction failed to confirm");
+                    TxError::Confirmation(err)
+                })?
+                .ok_or_else(|| {
+                    error!(?nonce, ?tx_hash, "Transaction dropped");
+                    TxError::Dropped(tx_hash)
+                })?;
+            if let Some(block) = receipt.block_number.filter(|_| wait_until_final) {
+                timeout(self.finality_timeout, self.wait_until_final(block))
+                    .instrument(info_span!("Wait for TX to be final"))
+                    .await
+                    .map_err(|elapsed| {
+                        error!(?nonce, ?tx_hash, ?elapsed, "Waiting for finality timed out");
+                        TxError::ConfirmationTimeout
+                    })??;
+            }
+
+            let current = provider
+                .get_transaction_receipt(tx_hash)
+                .await
+                .map_err(TxError::Confirmation)?;
+            match current {
+                Some(current) if current.block_hash == receipt.block_hash => return Ok(receipt),
+                Some(current) => warn!(
+                    ?nonce,
+                    ?tx_hash,
+                    block = ?receipt.block_number,
+                    new_block = ?current.block_number,
+                    "Transaction moved to another block by a reorg, awaiting confirmations again."
+                ),
+                None => warn!(
+                    ?nonce,
+                    ?tx_hash,
+                    block = ?receipt.block_number,
+                    "Transaction orphaned by a reorg, waiting for it to be mined again."
+                ),
+            }
+        }
+    }
+
+    #[instrument(level = "info", skip(self))]
+    #[allow(clippy::option_if_let_else)] // Less readable
+    #[allow(clippy::cast_precision_loss)]
+    async fn wait_for_transaction_unlogged(
+        &self,
+        sent: SentTransaction,
+    ) -> Result<TransactionReceipt, TxError> {
+        let SentTransaction {
+            hash: tx_hash,
+            nonce,
+            gas_limit,
+        } = sent;
+
+        // Wait for TX to be mined
+        let timer = TX_LATENCY.start_timer();
+        let receipt = match self.confirmed_receipt(tx_hash, nonce).await {
+            // Later transactions queue behind the gap it left until it is filled.
+            Err(TxError::Dropped(tx_hash)) => {
+                self.nonces
+                    .give_back_dropped(self.provider.as_ref(), nonce)
+                    .await;
+                return Err(TxError::Dropped(tx_hash));
+            }
+            result => result?,
+        };
         timer.observe_duration();
         info!(?nonce, ?tx_hash, ?receipt, "Transaction mined");
 
@@ -500,14 +1110,14 @@ impl Ethereum {
         } else {
             error!(
                 ?nonce,
-                ?tx,
+                ?tx_hash,
                 ?receipt,
                 "Receipt did not include effective gas price."
             );
         }
         if let Some(gas_used) = receipt.gas_used {
             TX_GAS_USED.inc_by(gas_used.as_u128() as f64);
-            if let Some(gas_limit) = tx.gas() {
+            if let Some(gas_limit) = gas_limit {
                 let gas_fraction = gas_used.as_u128() as f64 / gas_limit.as_u128() as f64;
                 TX_GAS_FRACTION.observe(gas_fraction);
                 if gas_fraction > 0.9 {
@@ -525,7 +1135,12 @@ impl Ethereum {
                 TX_WEI_USED.inc_by(cost_wei.as_u128() as f64);
             }
         } else {
-            error!(?nonce, ?tx, ?receipt, "Receipt did not include gas used.");
+            error!(
+                ?nonce,
+                ?tx_hash,
+                ?receipt,
+                "Receipt did not include gas used."
+            );
         }
 
         // Check receipt status for success
@@ -536,12 +1151,21 @@ impl Ethereum {
     }
 
     pub async fn confirmed_block_number(&self) -> Result<U64, EventError> {
+        self.ingest_confirmation
+            .latest(self.provider.provider())
+            .await
+            .map_err(|e| EventError::Fetching(CachingLogQueryError::LoadLastBlock(e)))
+    }
+
+    /// Returns the hash of block number `block`, or `None` if the chain has no
+    /// such block.
+    pub async fn block_hash(&self, block: u64) -> Result<Option<H256>, EventError> {
         self.provider
             .provider()
-            .get_block_number()
+            .get_block(block)
             .await
-            .map(|num| num.saturating_sub(U64::from(self.confirmation_blocks_delay)))
-            .map_err(|e| EventError::Fetching(CachingLogQueryError::LoadLastBlock(e)))
+            .map(|block| block.and_then(|block| block.hash))
+            .map_err(EventError::FetchingBlock)
     }
 
     pub fn fetch_events_raw(
@@ -552,18 +1176,32 @@ impl Ethereum {
             .with_start_page_size(self.max_log_blocks as u64)
             .with_min_page_size(self.min_log_blocks as u64)
             .with_max_backoff_time(self.max_backoff_time)
-            .with_blocks_delay(self.confirmation_blocks_delay as u64)
+            .with_confirmation(self.ingest_confirmation.clone())
             .into_stream()
             .map_err(Into::into)
     }
 
-    pub fn fetch_events<T: EthEvent>(
+    pub fn fetch_events<T: EthLogDecode>(
         &self,
         filter: &Filter,
     ) -> impl Stream<Item = Result<Log<T>, EventError>> + '_ {
-        self.fetch_events_raw(filter).map(|res| {
+        self.fetch_events_with(filter, T::decode_log)
+    }
+
+    /// Like [`Self::fetch_events`], but decodes events with `decode` rather
+    /// than the event's bindings.
+    pub fn fetch_events_with<'a, T, F>(
+        &'a self,
+        filter: &Filter,
+        decode: F,
+    ) -> impl Stream<Item = Result<Log<T>, EventError>> + 'a
+    where
+        T: EthLogDecode,
+        F: Fn(&RawLog) -> Result<T, AbiError> + 'a,
+    {
+        self.fetch_events_raw(filter).map(move |res| {
             res.and_then(|log| {
-                let event = T::decode_log(&RawLog {
+                let event = decode(&RawLog {
                     topics: log.topics.clone(),
                     data:   log.data.to_vec(),
                 })
@@ -574,6 +1212,9 @@ impl Ethereum {
                     transaction_index: log
                         .transaction_index
                         .ok_or(EventError::EmptyTransactionIndex)?,
+                    transaction_hash: log
+                        .transaction_hash
+                        .ok_or(EventError::EmptyTransactionHash)?,
                     log_index: log.log_index.ok_or(EventError::EmptyLogIndex)?,
                     raw_log: serde_json::to_string(&log).map_err(EventError::Serialize)?,
                     event,
@@ -582,10 +1223,173 @@ impl Ethereum {
         })
     }
 }
-pub struct Log<Event: EthEvent> {
+
+/// A transaction that has been accepted into the mempool but may not have
+/// been mined yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SentTransaction {
+    pub hash:      H256,
+    pub nonce:     u64,
+    /// The gas limit of the transaction, if known. Only used for metrics.
+    pub gas_limit: Option<U256>,
+}
+
+pub struct Log<Event: EthLogDecode> {
     pub block_index:       U64,
     pub transaction_index: U64,
+    pub transaction_hash:  H256,
     pub log_index:         U256,
     pub raw_log:           String,
     pub event:             Event,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    const KEY: &str = "ee79b5f6e221356af78cf4c36f4f7885a11b67dfcc81c34d80249947330c0f82";
+
+    fn key_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("signup-sequencer-{name}-{}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn only_sends_known_to_have_failed_give_back_their_nonce() {
+        // The node may still have received a send that timed out or lost its
+        // connection.
+        assert!(TxError::SendTimeout.may_have_been_broadcast());
+        let lost = ProviderError::CustomError("connection reset by peer".into());
+        assert!(TxError::Send(Box::new(lost)).may_have_been_broadcast());
+
+        let rejected =
+            ProviderError::CustomError("(code: -32000, message: nonce too low, data: None)".into());
+        assert!(!TxError::Send(Box::new(rejected)).may_have_been_broadcast());
+        let unsent = ProviderError::CustomError("gas estimation failed".into());
+        assert!(!TxError::Fill(Box::new(unsent)).may_have_been_broadcast());
+    }
+
+    #[test]
+    fn configured_poll_interval_is_applied() {
+        let options = Options::try_parse_from(["", "--provider-poll-interval", "500"]).unwrap();
+        assert_eq!(
+            options.provider_poll_interval,
+            Some(Duration::from_millis(500))
+        );
+
+        let (provider, _mock) = Provider::mocked();
+        let default = provider.get_interval();
+        let provider = with_poll_interval(provider, options.provider_poll_interval);
+        assert_eq!(provider.get_interval(), Duration::from_millis(500));
+
+        let (provider, _mock) = Provider::mocked();
+        assert_eq!(with_poll_interval(provider, None).get_interval(), default);
+    }
+
+    #[test]
+    fn confirmation_options_are_independent() {
+        let options = Options::try_parse_from([""]).unwrap();
+        assert_eq!(options.ingest_confirmations, 35);
+        assert_eq!(options.commit_tx_confirmations, 1);
+
+        let options = Options::try_parse_from(["", "--confirmation-blocks-delay", "7"]).unwrap();
+        assert_eq!(
+            options.ingest_confirmation(),
+            ConfirmationStrategy::Blocks(7)
+        );
+        assert_eq!(options.commit_tx_confirmations, 1);
+        assert!(Options::try_parse_from([
+            "",
+            "--confirmation-blocks-delay",
+            "7",
+            "--ingest-confirmations",
+            "3"
+        ])
+        .is_err());
+
+        // Deployments still set the old environment variable.
+        let command = Options::command();
+        let deprecated = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "confirmation_blocks_delay")
+            .unwrap();
+        assert_eq!(
+            deprecated.get_env(),
+            Some(std::ffi::OsStr::new("CONFIRMATION_BLOCKS_DELAY"))
+        );
+
+        let options = Options::try_parse_from(["", "--commit-tx-confirmations", "3"]).unwrap();
+        assert_eq!(options.ingest_confirmations, 35);
+        assert_eq!(options.commit_tx_confirmations, 3);
+        assert_eq!(
+            options.ingest_confirmation(),
+            ConfirmationStrategy::Blocks(35)
+        );
+        assert_eq!(
+            options.commit_confirmation(),
+            ConfirmationStrategy::Blocks(3)
+        );
+
+        let options =
+            Options::try_parse_from(["", "--confirmation-strategy", "finalized"]).unwrap();
+        assert_eq!(
+            options.ingest_confirmation(),
+            ConfirmationStrategy::Finalized
+        );
+        assert_eq!(
+            options.commit_confirmation(),
+            ConfirmationStrategy::Finalized
+        );
+    }
+
+    #[test]
+    fn signing_key_is_read_from_file() {
+        let path = key_file("key-file", &format!("0x{KEY}\n"));
+        let options =
+            Options::try_parse_from(["", "--signing-key-file", path.to_str().unwrap()]).unwrap();
+        let signing_key = options.load_signing_key();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            signing_key.unwrap().to_bytes().as_slice(),
+            hex::decode(KEY).unwrap()
+        );
+    }
+
+    #[test]
+    fn signing_key_is_read_from_secret_reference() {
+        let name = format!("SEQUENCER_TEST_SIGNING_KEY_{}", std::process::id());
+        env::set_var(&name, format!("0x{KEY}"));
+        let options = Options::try_parse_from(["", "--signing-key-secret", &name]).unwrap();
+        let signing_key = options.load_signing_key();
+        env::remove_var(&name);
+
+        assert_eq!(
+            signing_key.unwrap().to_bytes().as_slice(),
+            hex::decode(KEY).unwrap()
+        );
+        assert!(options.load_signing_key().is_err());
+        assert!(
+            Options::try_parse_from(["", "--signing-key", KEY, "--signing-key-secret", &name])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn inline_signing_key_conflicts_with_file() {
+        let path = key_file("conflicting-key-file", KEY);
+        let result = Options::try_parse_from([
+            "",
+            "--signing-key",
+            KEY,
+            "--signing-key-file",
+            path.to_str().unwrap(),
+        ]);
+        fs::remove_file(path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockId, BlockNumber},
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+
+static NONCE_GAPS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "eth_nonce_gaps",
+        "Number of locally handed out nonces given back after a failed broadcast or dropped \
+         transaction."
+    )
+    .unwrap()
+});
+
+const PENDING: Option<BlockId> = Some(BlockId::Number(BlockNumber::Pending));
+
+/// Hands out the nonces of the signing wallet locally, so transactions sent
+/// in quick succession don't reuse one. Unlike ethers'
+/// `NonceManagerMiddleware`, nonces of transactions that never reached the
+/// mempool, or left it without being mined, are given back: the local nonce
+/// is reconciled with the chain's pending transaction count, so the next
+/// transaction fills the gap instead of queueing behind it forever.
+///
+/// A nonce stays reserved until its broadcast has succeeded or failed, and no
+/// other nonce is handed out meanwhile. Reconciling can therefore never hand
+/// out a nonce that is still in flight.
+#[derive(Debug)]
+pub struct NonceManager {
+    address: Address,
+    /// The next nonce to hand out, read from the chain on first use.
+    next:    Mutex<Option<u64>>,
+}
+
+/// A nonce handed out by [`NonceManager::reserve`], to be resolved with
+/// [`Self::commit`] or [`Self::release`] once the broadcast using it is done.
+/// If it is dropped unresolved, the next nonce is read from the chain again.
+#[derive(Debug)]
+pub struct ReservedNonce<'a> {
+    manager: &'a NonceManager,
+    next:    MutexGuard<'a, Option<u64>>,
+    nonce:   u64,
+}
+
+impl NonceManager {
+    pub const fn new(address: Address) -> Self {
+        Self {
+            address,
+            next: Mutex::const_new(None),
+        }
+    }
+
+    /// Reserves the next nonce, reading the pending transaction count from
+    /// the chain if none is known. Waits until earlier reservations are
+    /// resolved.
+    pub async fn reserve<M: Middleware>(
+        &self,
+        provider: &M,
+    ) -> Result<ReservedNonce<'_>, M::Error> {
+        let mut next = self.next.lock().await;
+        let nonce = match next.take() {
+            Some(nonce) => nonce,
+            None => self.pending_count(provider).await?,
+        };
+        Ok(ReservedNonce {
+            manager: self,
+            next,
+            nonce,
+        })
+    }
+
+    /// Gives back the nonce of a transaction that left the mempool without
+    /// being mined, so that the next transaction fills the gap it left. Later
+    /// transactions only become executable once it is filled. If the chain
+    /// already used the nonce, such as for a replacement, the local nonce is
+    /// only caught up with the chain.
+    pub async fn give_back_dropped<M: Middleware>(&self, provider: &M, nonce: u64) {
+        let mut next = self.next.lock().await;
+        match self.pending_count(provider).await {
+            Ok(pending) if pending <= nonce => {
+                NONCE_GAPS.inc();
+                warn!(nonce, pending, "Transaction dropped, reissuing its nonce.");
+                *next = Some(pending);
+            }
+            Ok(pending) => {
+                info!(nonce, pending, "Nonce of the dropped transaction is used.");
+                *next = (*next).max(Some(pending));
+            }
+            Err(error) => {
+                warn!(?error, "Failed to read the pending nonce, resetting it.");
+                *next = None;
+            }
+        }
+    }
+
+    async fn pending_count<M: Middleware>(&self, provider: &M) -> Result<u64, M::Error> {
+        let count = provider
+            .get_transaction_count(self.address, PENDING)
+            .await?;
+        Ok(count.as_u64())
+    }
+}
+
+impl ReservedNonce<'_> {
+    pub const fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Marks the nonce as used after its transaction reached the mempool.
+    pub fn commit(mut self) {
+        *self.next = Some(self.nonce + 1);
+    }
+
+    /// Gives the nonce back once its transaction is known not to have been
+    /// broadcast, by resetting the next nonce to the chain's pending
+    /// transaction count. If the count can not be read, it is read again on the
+    /// next reservation instead. A transaction that may still reach the
+    /// mempool, such as after a send timed out, must be committed instead.
+    pub async fn release<M: Middleware>(mut self, provider: &M) {
+        let nonce = self.nonce;
+        *self.next = match self.manager.pending_count(provider).await {
+            Ok(pending) => Some(pending),
+            Err(error) => {
+                warn!(?error, "Failed to read the pending nonce, resetting it.");
+                None
+            }
+        };
+        match *self.next {
+            Some(pending) if pending <= nonce => {
+                NONCE_GAPS.inc_by(nonce + 1 - pending);
+                warn!(nonce, pending, "Nonce gap, giving back unused nonces.");
+            }
+            pending => {
+                info!(nonce, ?pending, "Reconciled nonce with the chain.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use ethers::{
+        providers::{JsonRpcClient, Provider, ProviderError},
+        types::U256,
+    };
+    use futures::FutureExt;
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::{
+        fmt::Debug,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    };
+
+    /// A wallet's account on a chain that only mines transactions with the
+    /// account's next nonce, like nodes do for gapped nonces.
+    #[derive(Clone, Debug, Default)]
+    struct MockAccount {
+        nonce: Arc<AtomicU64>,
+    }
+
+    impl MockAccount {
+        fn mine(&self, nonce: u64) -> bool {
+            self.nonce
+                .compare_exchange(nonce, nonce + 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        }
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for MockAccount {
+        type Error = ProviderError;
+
+        async fn request<T, R>(&self, method: &str, _params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned,
+        {
+            match method {
+                "eth_getTransactionCount" => {
+                    let count = U256::from(self.nonce.load(Ordering::SeqCst));
+                    Ok(serde_json::from_value(serde_json::to_value(count)?)?)
+                }
+                _ => Err(ProviderError::CustomError(format!("Unsupported {method}"))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_broadcasts_do_not_stall_later_transactions() {
+        let account = MockAccount::default();
+        account.nonce.store(5, Ordering::SeqCst);
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+
+        let reserved = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(reserved.nonce(), 5);
+        assert!(account.mine(reserved.nonce()));
+        reserved.commit();
+
+        // The broadcast fails after the nonce was handed out.
+        let failed = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(failed.nonce(), 6);
+        failed.release(&provider).await;
+
+        for expected in 6..10 {
+            let reserved = nonces.reserve(&provider).await.unwrap();
+            let nonce = reserved.nonce();
+            assert_eq!(nonce, expected);
+            assert!(account.mine(nonce), "nonce {nonce} stalled");
+            reserved.commit();
+        }
+    }
+
+    #[tokio::test]
+    async fn nonces_in_flight_are_not_handed_out_again() {
+        let account = MockAccount::default();
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+
+        // While the first broadcast is in flight the chain does not count it,
+        // so a failing concurrent broadcast would reconcile back to nonce 0.
+        let first = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(first.nonce(), 0);
+        assert!(nonces.reserve(&provider).now_or_never().is_none());
+        first.commit();
+
+        let second = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(second.nonce(), 1);
+        assert!(account.mine(0));
+        second.release(&provider).await;
+        assert_eq!(nonces.reserve(&provider).await.unwrap().nonce(), 1);
+    }
+
+    #[tokio::test]
+    async fn transactions_sent_elsewhere_are_skipped() {
+        let account = MockAccount::default();
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+        nonces.reserve(&provider).await.unwrap().commit();
+
+        // Another process used the wallet, so the local nonce is too low.
+        account.nonce.store(3, Ordering::SeqCst);
+        let failed = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(failed.nonce(), 1);
+        failed.release(&provider).await;
+        assert_eq!(nonces.reserve(&provider).await.unwrap().nonce(), 3);
+    }
+
+    #[tokio::test]
+    async fn nonces_of_dropped_transactions_are_reissued() {
+        let account = MockAccount::default();
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+
+        // Both reach the mempool, but the first is dropped before it is
+        // mined, so the second can not be mined either.
+        nonces.reserve(&provider).await.unwrap().commit();
+        nonces.reserve(&provider).await.unwrap().commit();
+        assert!(!account.mine(1));
+        nonces.give_back_dropped(&provider, 0).await;
+
+        let reserved = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(reserved.nonce(), 0);
+        assert!(account.mine(reserved.nonce()));
+        reserved.commit();
+        assert!(account.mine(1));
+
+        // A nonce the chain used meanwhile is not handed out again.
+        nonces.give_back_dropped(&provider, 1).await;
+        assert_eq!(nonces.reserve(&provider).await.unwrap().nonce(), 2);
+    }
+
+    #[tokio::test]
+    async fn nonces_of_timed_out_sends_are_not_reused() {
+        let account = MockAccount::default();
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+
+        // The send timed out, so the transaction may still arrive and its
+        // nonce is kept, although the chain does not count it yet.
+        nonces.reserve(&provider).await.unwrap().commit();
+        let next = nonces.reserve(&provider).await.unwrap();
+        assert_eq!(next.nonce(), 1);
+        next.commit();
+
+        // It never arrived, so the next transaction is found dropped, which
+        // gives the gap back.
+        assert!(!account.mine(1));
+        nonces.give_back_dropped(&provider, 1).await;
+        assert_eq!(nonces.reserve(&provider).await.unwrap().nonce(), 0);
+    }
+
+    #[tokio::test]
+    async fn unresolved_reservations_are_read_from_the_chain() {
+        let account = MockAccount::default();
+        account.nonce.store(2, Ordering::SeqCst);
+        let provider = Provider::new(account.clone());
+        let nonces = NonceManager::new(Address::zero());
+        drop(nonces.reserve(&provider).await.unwrap());
+        assert_eq!(nonces.reserve(&provider).await.unwrap().nonce(), 2);
+    }
+}
@@ -1,4 +1,4 @@
-use ::prometheus::{register_histogram, register_int_counter_vec, Histogram, IntCounterVec};
+use ::prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
 use async_trait::async_trait;
 use ethers::providers::JsonRpcClient;
 use once_cell::sync::Lazy;
@@ -14,10 +14,11 @@ static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
     )
     .unwrap()
 });
-static LATENCY: Lazy<Histogram> = Lazy::new(|| {
-    register_histogram!(
+static LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
         "eth_rpc_latency_seconds",
-        "The Ethereum provider latency in seconds."
+        "The Ethereum provider latency in seconds by method.",
+        &["method"]
     )
     .unwrap()
 });
@@ -48,9 +49,50 @@ where
         R: DeserializeOwned,
     {
         REQUESTS.with_label_values(&[method]).inc();
-        let timer = LATENCY.start_timer();
+        // Failed requests are timed as well, a slow provider may also be failing.
+        let timer = LATENCY.with_label_values(&[method]).start_timer();
         let result = self.inner.request(method, params).await;
         timer.observe_duration();
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::providers::MockProvider;
+
+    fn latency_samples(method: &str) -> u64 {
+        ::prometheus::gather()
+            .iter()
+            .filter(|family| family.get_name() == "eth_rpc_latency_seconds")
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "method" && label.get_value() == method)
+            })
+            .map(|metric| metric.get_histogram().get_sample_count())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn requests_are_timed_by_method() {
+        let mock = MockProvider::new();
+        let logger = RpcLogger::new(mock.clone());
+        let block_numbers = latency_samples("eth_blockNumber");
+        let logs = latency_samples("eth_getLogs");
+
+        mock.push(1_u64).unwrap();
+        let _: u64 = logger.request("eth_blockNumber", ()).await.unwrap();
+        mock.push(2_u64).unwrap();
+        let _: u64 = logger.request("eth_blockNumber", ()).await.unwrap();
+        // Without a queued response the request fails.
+        let result: Result<Vec<u64>, _> = logger.request("eth_getLogs", ()).await;
+        assert!(result.is_err());
+
+        assert_eq!(latency_samples("eth_blockNumber"), block_numbers + 2);
+        assert_eq!(latency_samples("eth_getLogs"), logs + 1);
+    }
+}
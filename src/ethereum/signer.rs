@@ -0,0 +1,320 @@
+use async_trait::async_trait;
+use clap::ValueEnum;
+use ethers::{
+    core::types::transaction::eip712::Eip712,
+    signers::{AwsSigner, AwsSignerError, LocalWallet, Signer, WalletError},
+    types::{transaction::eip2718::TypedTransaction, Address, Signature},
+};
+use rusoto_kms::KmsClient;
+use serde::Serialize;
+use std::{fmt, sync::Arc};
+use thiserror::Error;
+
+/// Where the transaction signing key is held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignerType {
+    /// A private key passed through `signing_key`, `signing_key_file` or
+    /// `signing_key_secret`.
+    Local,
+    /// A key held in AWS KMS, referenced by `kms_key_id`.
+    Kms,
+}
+
+/// Signs transactions with the configured key, so the rest of the provider
+/// stack does not depend on where the key is held.
+#[derive(Clone, Debug)]
+pub enum TxSigner {
+    Local(LocalWallet),
+    Kms(KmsSigner),
+    /// Stands in for a signer on read-only instances, refusing to sign.
+    ReadOnly {
+        chain_id: u64,
+    },
+}
+
+/// Signs with a key held in AWS KMS through a signer created once, as
+/// creating one looks up the public key.
+///
+/// The chain id is kept apart from the inner signer, which can not be
+/// rebuilt for [`Signer::with_chain_id`], and set on transactions lacking one.
+pub struct KmsSigner<K = AwsSigner<'static>> {
+    signer:   Arc<K>,
+    chain_id: u64,
+}
+
+impl KmsSigner {
+    /// Looks up the address of the KMS key `key_id`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the public key can not be read from KMS.
+    pub async fn new(
+        client: KmsClient,
+        key_id: String,
+        chain_id: u64,
+    ) -> Result<Self, AwsSignerError> {
+        // [`AwsSigner`] borrows its client. The signer is kept until the
+        // process exits, and so is the client.
+        let client: &'static KmsClient = Box::leak(Box::new(client));
+        let signer = AwsSigner::new(client, &key_id, chain_id).await?;
+        Ok(Self::with_signer(signer, chain_id))
+    }
+}
+
+impl<K> KmsSigner<K> {
+    fn with_signer(signer: K, chain_id: u64) -> Self {
+        Self {
+            signer: Arc::new(signer),
+            chain_id,
+        }
+    }
+}
+
+// Not derived, which would require the inner signer to be `Clone`.
+impl<K> Clone for KmsSigner<K> {
+    fn clone(&self) -> Self {
+        Self {
+            signer:   self.signer.clone(),
+            chain_id: self.chain_id,
+        }
+    }
+}
+
+impl<K: Signer> fmt::Debug for KmsSigner<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KmsSigner")
+            .field("address", &self.signer.address())
+            .field("chain_id", &self.chain_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<K: Signer> Signer for KmsSigner<K> {
+    type Error = K::Error;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        self.signer.sign_message(message).await
+    }
+
+    async fn sign_transaction(&self, message: &TypedTransaction) -> Result<Signature, Self::Error> {
+        if message.chain_id().is_some() {
+            return self.signer.sign_transaction(message).await;
+        }
+        let mut message = message.clone();
+        message.set_chain_id(self.chain_id);
+        self.signer.sign_transaction(&message).await
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        self.signer.sign_typed_data(payload).await
+    }
+
+    fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        Self {
+            chain_id: chain_id.into(),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[allow(clippy::module_name_repetitions)]
+pub enum TxSignerError {
+    #[error("Local signer error: {0}")]
+    Local(#[from] WalletError),
+
+    #[error("KMS signer error: {0}")]
+    Kms(#[from] AwsSignerError),
+
+    #[error("Read-only instances do not sign")]
+    ReadOnly,
+}
+
+#[async_trait]
+impl Signer for TxSigner {
+    type Error = TxSignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(inner) => Ok(inner.sign_message(message).await?),
+            Self::Kms(inner) => Ok(inner.sign_message(message).await?),
+            Self::ReadOnly { .. } => Err(TxSignerError::ReadOnly),
+        }
+    }
+
+    async fn sign_transaction(&self, message: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(inner) => Ok(inner.sign_transaction(message).await?),
+            Self::Kms(inner) => Ok(inner.sign_transaction(message).await?),
+            Self::ReadOnly { .. } => Err(TxSignerError::ReadOnly),
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(inner) => Ok(inner.sign_typed_data(payload).await?),
+            Self::Kms(inner) => Ok(inner.sign_typed_data(payload).await?),
+            Self::ReadOnly { .. } => Err(TxSignerError::ReadOnly),
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            Self::Local(inner) => inner.address(),
+            Self::Kms(inner) => inner.address(),
+            Self::ReadOnly { .. } => Address::zero(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            Self::Local(inner) => inner.chain_id(),
+            Self::Kms(inner) => inner.chain_id(),
+            Self::ReadOnly { chain_id } => *chain_id,
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            Self::Local(inner) => Self::Local(inner.with_chain_id(chain_id)),
+            Self::Kms(inner) => Self::Kms(inner.with_chain_id(chain_id)),
+            Self::ReadOnly { .. } => Self::ReadOnly {
+                chain_id: chain_id.into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::{TransactionRequest, U256};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const KEY: &str = "ee79b5f6e221356af78cf4c36f4f7885a11b67dfcc81c34d80249947330c0f82";
+
+    /// Stands in for [`AwsSigner`]: created for a fixed chain id, and counting
+    /// the signatures it makes.
+    #[derive(Debug)]
+    struct MockKms {
+        wallet: LocalWallet,
+        signed: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Signer for MockKms {
+        type Error = WalletError;
+
+        async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+            &self,
+            message: S,
+        ) -> Result<Signature, Self::Error> {
+            self.signed.fetch_add(1, Ordering::SeqCst);
+            self.wallet.sign_message(message).await
+        }
+
+        async fn sign_transaction(
+            &self,
+            message: &TypedTransaction,
+        ) -> Result<Signature, Self::Error> {
+            self.signed.fetch_add(1, Ordering::SeqCst);
+            self.wallet.sign_transaction(message).await
+        }
+
+        async fn sign_typed_data<T: Eip712 + Send + Sync>(
+            &self,
+            payload: &T,
+        ) -> Result<Signature, Self::Error> {
+            self.signed.fetch_add(1, Ordering::SeqCst);
+            self.wallet.sign_typed_data(payload).await
+        }
+
+        fn address(&self) -> Address {
+            self.wallet.address()
+        }
+
+        fn chain_id(&self) -> u64 {
+            self.wallet.chain_id()
+        }
+
+        fn with_chain_id<T: Into<u64>>(self, _: T) -> Self {
+            unimplemented!("KMS signers are created for a chain id")
+        }
+    }
+
+    #[tokio::test]
+    async fn kms_signer_is_reused_across_signatures() {
+        let wallet = KEY.parse::<LocalWallet>().unwrap().with_chain_id(1_u64);
+        let expected = wallet.address();
+        let signer = KmsSigner::with_signer(
+            MockKms {
+                wallet,
+                signed: AtomicUsize::new(0),
+            },
+            1,
+        )
+        .with_chain_id(1337_u64);
+
+        // The chain id is left to the signer.
+        let transaction: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .value(U256::from(1000))
+            .nonce(7)
+            .gas(21000)
+            .gas_price(1)
+            .into();
+        for _ in 0..2 {
+            let signature = signer.sign_transaction(&transaction).await.unwrap();
+            assert!(matches!(signature.v, 2709 | 2710), "v = {}", signature.v);
+            let mut signed = transaction.clone();
+            signed.set_chain_id(1337_u64);
+            assert_eq!(signature.recover(signed.sighash()).unwrap(), expected);
+        }
+
+        assert_eq!(signer.address(), expected);
+        assert_eq!(signer.chain_id(), 1337);
+        assert_eq!(signer.signer.signed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn transaction_is_signed_by_signer_address() {
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let expected = wallet.address();
+        let signer = TxSigner::Local(wallet).with_chain_id(1337_u64);
+
+        let transaction: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .value(U256::from(1000))
+            .nonce(7)
+            .gas(21000)
+            .gas_price(1)
+            .chain_id(1337_u64)
+            .into();
+        let signature = signer.sign_transaction(&transaction).await.unwrap();
+
+        assert_eq!(signer.address(), expected);
+        assert_eq!(signature.recover(transaction.sighash()).unwrap(), expected);
+    }
+}
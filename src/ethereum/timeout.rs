@@ -0,0 +1,184 @@
+use super::failover::EndpointError;
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, ProviderError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, time::Duration};
+use thiserror::Error;
+
+/// A provider request that did not complete in time.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("Ethereum provider did not respond within {0:?}")]
+pub struct RpcTimeout(pub Duration);
+
+impl RpcTimeout {
+    const MESSAGE: &'static str = "Ethereum provider did not respond within";
+
+    /// Whether `error` was caused by a timed out provider request. Contract
+    /// errors only carry the provider error as text, so the message is matched
+    /// as well.
+    #[must_use]
+    pub fn is_cause_of(error: &anyhow::Error) -> bool {
+        error
+            .chain()
+            .any(|cause| cause.is::<Self>() || cause.to_string().contains(Self::MESSAGE))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TimeoutError<Inner> {
+    #[error(transparent)]
+    RpcTimeout(RpcTimeout),
+
+    #[error(transparent)]
+    Inner(Inner),
+}
+
+impl<Inner: EndpointError> EndpointError for TimeoutError<Inner> {
+    fn is_endpoint_failure(&self) -> bool {
+        match self {
+            Self::RpcTimeout(_) => true,
+            Self::Inner(error) => error.is_endpoint_failure(),
+        }
+    }
+}
+
+impl<Inner: Into<ProviderError>> From<TimeoutError<Inner>> for ProviderError {
+    fn from(error: TimeoutError<Inner>) -> Self {
+        match error {
+            TimeoutError::RpcTimeout(error) => Self::JsonRpcClientError(Box::new(error)),
+            TimeoutError::Inner(error) => error.into(),
+        }
+    }
+}
+
+/// Fails requests the inner client does not answer within `timeout`, so a hung
+/// provider can not stall its callers indefinitely.
+///
+/// Sends are exempt: a send cut off here may still reach the mempool, and the
+/// caller could not tell it apart from one that never did. Their callers bound
+/// them instead, see `Ethereum::fill_and_send`.
+#[derive(Debug, Clone)]
+pub struct Timeout<Inner> {
+    inner:   Inner,
+    timeout: Duration,
+}
+
+impl<Inner> Timeout<Inner> {
+    pub const fn new(inner: Inner, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+#[async_trait]
+impl<Inner> JsonRpcClient for Timeout<Inner>
+where
+    Inner: JsonRpcClient + 'static,
+    <Inner as JsonRpcClient>::Error: Sync + Send + 'static,
+{
+    type Error = TimeoutError<Inner::Error>;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        if method.starts_with("eth_send") {
+            return self
+                .inner
+                .request(method, params)
+                .await
+                .map_err(TimeoutError::Inner);
+        }
+        tokio::time::timeout(self.timeout, self.inner.request(method, params))
+            .await
+            .map_err(|_| TimeoutError::RpcTimeout(RpcTimeout(self.timeout)))?
+            .map_err(TimeoutError::Inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::providers::{MockError, MockProvider};
+
+    /// Answers from a mock provider, but only after `delay`.
+    #[derive(Debug)]
+    struct Delayed {
+        inner: MockProvider,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for Delayed {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned,
+        {
+            tokio::time::sleep(self.delay).await;
+            self.inner.request(method, params).await
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_requests_time_out() {
+        let mock = MockProvider::new();
+        let client = Timeout::new(
+            Delayed {
+                inner: mock.clone(),
+                delay: Duration::from_secs(5),
+            },
+            Duration::from_millis(50),
+        );
+
+        mock.push(1_u64).unwrap();
+        let result: Result<u64, _> = client.request("eth_chainId", ()).await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, TimeoutError::RpcTimeout(_)));
+        assert_eq!(
+            error.to_string(),
+            "Ethereum provider did not respond within 50ms"
+        );
+
+        let error = anyhow::Error::new(ProviderError::from(error));
+        assert!(RpcTimeout::is_cause_of(&error));
+    }
+
+    #[tokio::test]
+    async fn timely_responses_are_returned() {
+        let mock = MockProvider::new();
+        let client = Timeout::new(
+            Delayed {
+                inner: mock.clone(),
+                delay: Duration::from_millis(10),
+            },
+            Duration::from_secs(5),
+        );
+
+        mock.push(1_u64).unwrap();
+        let chain_id: u64 = client.request("eth_chainId", ()).await.unwrap();
+        assert_eq!(chain_id, 1);
+    }
+
+    #[tokio::test]
+    async fn sends_are_not_cut_off() {
+        let mock = MockProvider::new();
+        let client = Timeout::new(
+            Delayed {
+                inner: mock.clone(),
+                delay: Duration::from_millis(100),
+            },
+            Duration::from_millis(10),
+        );
+
+        let hash = ethers::types::H256::repeat_byte(1);
+        mock.push(hash).unwrap();
+        let sent: ethers::types::H256 = client
+            .request("eth_sendRawTransaction", ["0x00"])
+            .await
+            .unwrap();
+        assert_eq!(sent, hash);
+    }
+}
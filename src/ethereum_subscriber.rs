@@ -1,18 +1,76 @@
 use crate::{
-    contracts::{legacy::MemberAddedEvent, SharedIdentityManager},
+    app::{HealthReport, SyncResponse},
+    contracts::{
+        legacy::{MemberAddedEvent, MemberEvent, MemberRemovedEvent},
+        InvalidRoot, SharedIdentityManager,
+    },
     database::{
-        ConfirmedIdentityEvent, Database, Error as DatabaseError, IdentityConfirmationResult,
+        CachedLog, ConfirmedIdentityEvent, Database, Error as DatabaseError,
+        IdentityConfirmationResult, LeafOrigin,
     },
     ethereum::{EventError, Log},
-    identity_committer::IdentityCommitter,
+    identity_committer::{IdentityCommitter, LIFECYCLE_TARGET},
     identity_tree::{SharedTreeState, TreeState},
+    timed_rw_lock::TimedRwLock,
 };
+use ethers::types::H256;
 use futures::TryStreamExt;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use rand::{thread_rng, Rng};
 use semaphore::Field;
-use std::{cmp::min, sync::Arc, time::Duration};
+use std::{
+    cmp::{max, min},
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::{sync::RwLock, task::JoinHandle, time::sleep};
-use tracing::{error, info, instrument, warn};
+use tracing::{debug, error, info, instrument, warn};
+
+/// How often progress is logged while processing a long range of events.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many events that do not continue the tree are held back, waiting for
+/// the events before them, until an event is considered missing.
+const MAX_HELD_BACK_EVENTS: usize = 64;
+
+/// Root mismatches by where the expected root came from: `cache`, `events` or
+/// `contract`.
+pub static ROOT_MISMATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "root_mismatches",
+        "Number of mismatches between the computed tree root and an expected root.",
+        &["source"]
+    )
+    .unwrap()
+});
+
+/// Counts a root mismatch and logs it at critical severity for alerting. The
+/// expected root is `None` if it could not be retrieved.
+pub fn report_root_mismatch(
+    source: &str,
+    computed_root: &Field,
+    expected_root: Option<&Field>,
+    block: Option<u64>,
+) {
+    ROOT_MISMATCHES.with_label_values(&[source]).inc();
+    error!(
+        severity = "critical",
+        source,
+        ?computed_root,
+        ?expected_root,
+        ?block,
+        "Root mismatch between computed tree and expected root."
+    );
+}
+
+/// Number of cached events loaded from the database at a time.
+const CACHED_EVENTS_PAGE_SIZE: usize = 10_000;
 
 struct RunningInstance {
     #[allow(dead_code)]
@@ -26,6 +84,264 @@ impl RunningInstance {
     }
 }
 
+/// Tracks whether the Ethereum provider could be reached on the latest
+/// subscriber update.
+#[derive(Debug, Default)]
+pub struct ChainHealth {
+    unreachable_since: Mutex<Option<Instant>>,
+    /// Depth of the reorg that halted syncing, if any.
+    halting_reorg:     Mutex<Option<u64>>,
+}
+
+impl ChainHealth {
+    fn report_reachable(&self) {
+        *self.unreachable_since.lock().unwrap() = None;
+    }
+
+    fn report_unreachable(&self) {
+        self.unreachable_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    fn report_halting_reorg(&self, depth: u64) {
+        *self.halting_reorg.lock().unwrap() = Some(depth);
+    }
+
+    /// Returns the depth of a reorg too deep to handle automatically, which
+    /// halted syncing until an operator intervenes.
+    pub fn halting_reorg(&self) -> Option<u64> {
+        *self.halting_reorg.lock().unwrap()
+    }
+
+    /// Returns how long the provider has been unreachable, or `None` if the
+    /// latest update reached it.
+    pub fn unreachable_for(&self) -> Option<Duration> {
+        self.unreachable_since
+            .lock()
+            .unwrap()
+            .map(|since| since.elapsed())
+    }
+}
+
+/// Progress of the initial sync, so it can be monitored before the server is
+/// ready.
+#[derive(Debug, Default)]
+pub struct SyncProgress {
+    state: Mutex<SyncState>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SyncState {
+    start_block:     u64,
+    target_block:    Option<u64>,
+    processed_block: Option<u64>,
+}
+
+impl SyncProgress {
+    /// Starts tracking a sync from `start_block` up to `target_block`.
+    pub fn report_target(&self, start_block: u64, target_block: u64) {
+        *self.state.lock().unwrap() = SyncState {
+            start_block,
+            target_block: Some(target_block),
+            processed_block: None,
+        };
+    }
+
+    /// Records that all blocks up to `block` are processed.
+    pub fn report_processed(&self, block: u64) {
+        self.state.lock().unwrap().processed_block = Some(block);
+    }
+
+    #[must_use]
+    pub fn response(&self) -> SyncResponse {
+        let state = *self.state.lock().unwrap();
+        let blocks = |end: u64| (end + 1).saturating_sub(state.start_block);
+        let blocks_total = state.target_block.map_or(0, blocks);
+        let blocks_processed = state.processed_block.map_or(0, blocks).min(blocks_total);
+        let synced = state.target_block.is_some() && state.processed_block >= state.target_block;
+        SyncResponse {
+            synced,
+            target_block: state.target_block,
+            blocks_processed,
+            blocks_total,
+        }
+    }
+}
+
+/// Exponential backoff with jitter between retries of failed updates, so
+/// sequencers sharing a provider do not retry in lockstep.
+#[derive(Debug)]
+struct Backoff {
+    initial:  Duration,
+    max:      Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    const fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// Returns the delay before the next retry: a random duration between
+    /// half and all of `initial` doubled for every consecutive failure, capped
+    /// at `max`.
+    fn next_delay(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        let base = self
+            .initial
+            .saturating_mul(2_u32.saturating_pow(self.failures))
+            .min(self.max);
+        thread_rng().gen_range(base / 2..=base)
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// A reorg that replaced blocks whose events are already in the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Reorg {
+    /// Latest remembered block still on the chain, or `None` if every
+    /// remembered block was replaced.
+    ancestor: Option<u64>,
+    /// Number of processed blocks replaced. If no remembered block is left,
+    /// the reorg is at least this deep.
+    depth:    u64,
+}
+
+/// Hashes of recently processed blocks, to notice reorgs deeper than the
+/// confirmation depth and find where they forked. Every block processed since
+/// the first update is remembered, while only the last block of the first
+/// update is. Only one block `max_depth` or more behind the latest one is
+/// kept, as a reorg replacing it is not handled anyway.
+#[derive(Debug)]
+struct Checkpoints {
+    max_depth: u64,
+    blocks:    VecDeque<(u64, H256)>,
+}
+
+impl Checkpoints {
+    fn new(max_depth: u64) -> Self {
+        Self {
+            max_depth,
+            blocks: VecDeque::new(),
+        }
+    }
+
+    /// Remembers the hashes of the blocks processed up to `block`, the last
+    /// processed one. The first time, only `block` and the block `max_depth`
+    /// before it are remembered, so a reorg replacing every remembered block
+    /// is always too deep.
+    async fn record(
+        &mut self,
+        identity_manager: &SharedIdentityManager,
+        block: u64,
+    ) -> Result<(), Error> {
+        match self.blocks.back() {
+            Some(&(latest, _)) => {
+                let first = (latest + 1).max(block.saturating_sub(self.max_depth));
+                for processed in first..=block {
+                    self.push(identity_manager, processed).await?;
+                }
+            }
+            None if block > 0 => {
+                self.push(identity_manager, block.saturating_sub(self.max_depth))
+                    .await?;
+                self.push(identity_manager, block).await?;
+            }
+            None => self.push(identity_manager, block).await?,
+        }
+        while self
+            .blocks
+            .get(1)
+            .map_or(false, |(second, _)| second + self.max_depth <= block)
+        {
+            self.blocks.pop_front();
+        }
+        Ok(())
+    }
+
+    async fn push(
+        &mut self,
+        identity_manager: &SharedIdentityManager,
+        block: u64,
+    ) -> Result<(), Error> {
+        if self
+            .blocks
+            .back()
+            .map_or(false, |(latest, _)| *latest >= block)
+        {
+            return Ok(());
+        }
+        let hash = identity_manager
+            .block_hash(block)
+            .await
+            .map_err(Error::Unreachable)?;
+        if let Some(hash) = hash {
+            self.blocks.push_back((block, hash));
+        }
+        Ok(())
+    }
+
+    /// Compares the remembered hashes with the chain, forgetting the blocks a
+    /// reorg replaced.
+    async fn find_reorg(
+        &mut self,
+        identity_manager: &SharedIdentityManager,
+    ) -> Result<Option<Reorg>, Error> {
+        let Some(&(latest, hash)) = self.blocks.back() else {
+            return Ok(None);
+        };
+        if Self::on_chain(identity_manager, latest, hash).await? {
+            return Ok(None);
+        }
+
+        // A reorg replaces every block from where it forked, so the remembered
+        // blocks still on the chain all precede the replaced ones.
+        let (mut low, mut high) = (0, self.blocks.len() - 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (block, hash) = self.blocks[middle];
+            if Self::on_chain(identity_manager, block, hash).await? {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let first_replaced = self.blocks[low].0;
+        self.blocks.truncate(low);
+        Ok(Some(match self.blocks.back() {
+            Some(&(ancestor, _)) => Reorg {
+                ancestor: Some(ancestor),
+                depth:    latest - ancestor,
+            },
+            None => Reorg {
+                ancestor: None,
+                depth:    latest - first_replaced + 1,
+            },
+        }))
+    }
+
+    async fn on_chain(
+        identity_manager: &SharedIdentityManager,
+        block: u64,
+        hash: H256,
+    ) -> Result<bool, Error> {
+        let on_chain = identity_manager
+            .block_hash(block)
+            .await
+            .map_err(Error::Unreachable)?;
+        Ok(on_chain == Some(hash))
+    }
+}
+
 pub struct EthereumSubscriber {
     instance:           RwLock<Option<RunningInstance>>,
     starting_block:     u64,
@@ -33,6 +349,13 @@ pub struct EthereumSubscriber {
     identity_manager:   SharedIdentityManager,
     tree_state:         SharedTreeState,
     identity_committer: Arc<IdentityCommitter>,
+    chain_health:       Arc<ChainHealth>,
+    sync_progress:      Arc<SyncProgress>,
+    /// First block whose events are not yet in the tree, shared with the
+    /// running instance.
+    next_block:         Arc<AtomicU64>,
+    /// Whether the tree is built without writing to the database.
+    dry_run:            bool,
 }
 
 impl EthereumSubscriber {
@@ -50,11 +373,56 @@ impl EthereumSubscriber {
             identity_manager,
             tree_state,
             identity_committer,
+            chain_health: Arc::default(),
+            sync_progress: Arc::default(),
+            next_block: Arc::new(AtomicU64::new(starting_block)),
+            dry_run: false,
         }
     }
 
+    /// Builds the tree without writing to the database: events fetched from
+    /// chain are not cached, the cache is not trimmed and queued identities
+    /// are not confirmed. For checks that must not disturb a running instance.
+    #[must_use]
+    pub const fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Reports the progress of [`Self::process_initial_events`] to
+    /// `sync_progress`.
+    #[must_use]
+    pub fn with_sync_progress(mut self, sync_progress: Arc<SyncProgress>) -> Self {
+        self.sync_progress = sync_progress;
+        self
+    }
+
+    pub fn chain_health(&self) -> &ChainHealth {
+        &self.chain_health
+    }
+
+    /// Returns the first block whose events are not yet in the tree.
+    pub fn next_block(&self) -> u64 {
+        self.next_block.load(Ordering::SeqCst)
+    }
+
+    fn set_starting_block(&mut self, block: u64) {
+        self.starting_block = block;
+        self.next_block.store(block, Ordering::SeqCst);
+    }
+
+    /// Processes new events every `refresh_rate`. While the provider is
+    /// unreachable or its events skip a leaf, retries back off up to
+    /// `max_retry_interval`. Reorgs of
+    /// processed blocks up to `max_auto_reorg_depth` deep are rolled back and
+    /// synced again, deeper ones halt syncing.
     #[instrument(level = "debug", skip_all)]
-    pub async fn start(&self, refresh_rate: Duration) {
+    pub async fn start(
+        &self,
+        refresh_rate: Duration,
+        max_retry_interval: Duration,
+        max_auto_reorg_depth: u64,
+    ) {
         let mut instance = self.instance.write().await;
         if instance.is_some() {
             info!("Chain Subscriber already running");
@@ -66,21 +434,45 @@ impl EthereumSubscriber {
         let tree_state = self.tree_state.clone();
         let identity_manager = self.identity_manager.clone();
         let identity_committer = self.identity_committer.clone();
+        let chain_health = self.chain_health.clone();
+        let next_block = self.next_block.clone();
 
         let handle = tokio::spawn(async move {
+            let mut backoff = Backoff::new(refresh_rate, max_retry_interval);
+            let mut checkpoints = Checkpoints::new(max_auto_reorg_depth);
+            let mut delay = refresh_rate;
             loop {
-                sleep(refresh_rate).await;
+                sleep(delay).await;
 
-                let processed_block = Self::process_events_internal(
-                    starting_block,
+                let update = Self::process_update(
+                    &mut checkpoints,
+                    &mut starting_block,
                     tree_state.clone(),
                     identity_manager.clone(),
                     database.clone(),
                     identity_committer.clone(),
                 )
                 .await;
-                match processed_block {
-                    Ok(block_number) => starting_block = block_number + 1,
+                next_block.store(starting_block, Ordering::SeqCst);
+                match update {
+                    Ok(()) => {
+                        chain_health.report_reachable();
+                        backoff.reset();
+                        delay = refresh_rate;
+                    }
+                    Err(Error::Unreachable(error)) => {
+                        delay = backoff.next_delay();
+                        warn!(?error, ?delay, "Ethereum provider unreachable, retrying.");
+                        chain_health.report_unreachable();
+                    }
+                    Err(error @ Error::LeafGap(_)) => {
+                        delay = backoff.next_delay();
+                        warn!(%error, ?delay, "Events skipped a leaf, syncing them again.");
+                    }
+                    Err(Error::ReorgTooDeep(depth)) => {
+                        chain_health.report_halting_reorg(depth);
+                        return Ok(());
+                    }
                     Err(error) => {
                         panic!("Couldn't process events update: {error:?}");
                     }
@@ -90,34 +482,300 @@ impl EthereumSubscriber {
         *instance = Some(RunningInstance { handle });
     }
 
+    /// Rebuilds the tree from cached and on-chain events. The tree is built on
+    /// the side and only swapped in once every root matched, so readers see
+    /// either the previous or the rebuilt tree, never one in between.
     #[instrument(level = "info", skip_all)]
     pub async fn process_initial_events(&mut self) -> Result<(), Error> {
-        let end_block = self
-            .identity_manager
-            .confirmed_block_number()
-            .await
-            .map_err(Error::Event)?;
+        let side_tree = Self::empty_side_tree(&self.tree_state).await;
+        let end_block = self.confirmed_cache_end().await?;
+        self.sync_progress
+            .report_target(self.starting_block, end_block);
 
         let last_db_block = Self::process_cached_events(
             self.starting_block,
             end_block,
-            self.tree_state.clone(),
+            side_tree.clone(),
             self.database.clone(),
+            CACHED_EVENTS_PAGE_SIZE,
+            !self.dry_run,
         )
         .await?;
+        self.sync_progress.report_processed(last_db_block);
         let processed_block = Self::process_blockchain_events(
             last_db_block + 1,
             end_block,
-            self.tree_state.clone(),
+            side_tree.clone(),
             self.identity_manager.clone(),
             self.database.clone(),
             self.identity_committer.clone(),
+            Some(&self.sync_progress),
+            !self.dry_run,
         )
         .await?;
-        self.starting_block = processed_block + 1;
+        self.sync_progress.report_processed(processed_block);
+        self.set_starting_block(processed_block + 1);
+        Self::swap_in(&self.tree_state, &side_tree).await;
         Ok(())
     }
 
+    /// Builds the tree from cached events alone and swaps it in if its root is
+    /// valid on chain, leaving the blocks after the cache to live syncing.
+    /// Returns `false`, with the tree untouched, if the cache is empty or can
+    /// not be trusted.
+    #[instrument(level = "info", skip_all)]
+    pub async fn process_trusted_cache(&mut self) -> Result<bool, Error> {
+        let side_tree = Self::empty_side_tree(&self.tree_state).await;
+        let end_block = self.confirmed_cache_end().await?;
+
+        let last_db_block = match Self::process_cached_events(
+            self.starting_block,
+            end_block,
+            side_tree.clone(),
+            self.database.clone(),
+            CACHED_EVENTS_PAGE_SIZE,
+            !self.dry_run,
+        )
+        .await
+        {
+            Ok(block) => block,
+            Err(error @ Error::RootMismatch) => {
+                warn!(%error, "Cache is inconsistent, not trusting it.");
+                return Ok(false);
+            }
+            Err(error) => return Err(error),
+        };
+
+        let root = {
+            let tree = side_tree.read().await.unwrap_or_else(|e| {
+                error!(?e, "Failed to obtain tree lock in process_trusted_cache.");
+                panic!("Sequencer potentially deadlocked, terminating.");
+            });
+            if tree.next_leaf == 0 {
+                info!("Cache is empty, nothing to trust.");
+                return Ok(false);
+            }
+            tree.merkle_tree.root()
+        };
+        if let Err(error) = self.identity_manager.assert_valid_root(root).await {
+            warn!(
+                ?root,
+                ?error,
+                "Cached root not valid on chain, not trusting the cache."
+            );
+            return Ok(false);
+        }
+
+        info!(
+            ?root,
+            last_db_block, "Trusting the cache, syncing on from its last block."
+        );
+        self.sync_progress
+            .report_target(self.starting_block, last_db_block);
+        self.sync_progress.report_processed(last_db_block);
+        self.set_starting_block(last_db_block + 1);
+        Self::swap_in(&self.tree_state, &side_tree).await;
+        Ok(true)
+    }
+
+    /// An empty tree like the one in `tree_state`, to rebuild on the side.
+    async fn empty_side_tree(tree_state: &SharedTreeState) -> SharedTreeState {
+        let tree = tree_state.read_retrying().await;
+        Arc::new(
+            TimedRwLock::new(tree_state.write_timeout(), tree.empty_like())
+                .with_read_timeout(tree_state.read_timeout()),
+        )
+    }
+
+    /// Returns the latest confirmed block, after dropping cached events past
+    /// it.
+    async fn confirmed_cache_end(&self) -> Result<u64, Error> {
+        let end_block = self
+            .identity_manager
+            .confirmed_block_number()
+            .await
+            .map_err(Error::Event)?;
+
+        // Cached events above the confirmation depth may have been re-orged out.
+        // Cached events are only read up to `end_block`, so a dry run can leave
+        // them.
+        if !self.dry_run {
+            self.database
+                .delete_cached_events_after(end_block)
+                .await
+                .map_err(Error::Database)?;
+        }
+        Ok(end_block)
+    }
+
+    /// Replaces the tree in `tree_state` with the one rebuilt in `side_tree`.
+    async fn swap_in(tree_state: &SharedTreeState, side_tree: &SharedTreeState) {
+        let mut tree = tree_state.write().await.unwrap_or_else(|e| {
+            error!(?e, "Failed to obtain tree lock in process_initial_events.");
+            panic!("Sequencer potentially deadlocked, terminating.");
+        });
+        let mut rebuilt = side_tree.write().await.unwrap_or_else(|e| {
+            error!(?e, "Failed to obtain tree lock in process_initial_events.");
+            panic!("Sequencer potentially deadlocked, terminating.");
+        });
+        std::mem::swap(&mut *tree, &mut *rebuilt);
+    }
+
+    /// Rolls back blocks replaced by a reorg, then processes the blocks from
+    /// `starting_block` on, advancing it past them.
+    async fn process_update(
+        checkpoints: &mut Checkpoints,
+        starting_block: &mut u64,
+        tree_state: SharedTreeState,
+        identity_manager: SharedIdentityManager,
+        database: Arc<Database>,
+        identity_committer: Arc<IdentityCommitter>,
+    ) -> Result<(), Error> {
+        match checkpoints.find_reorg(&identity_manager).await? {
+            None => {}
+            Some(Reorg {
+                ancestor: Some(ancestor),
+                depth,
+            }) if depth <= checkpoints.max_depth => {
+                warn!(
+                    depth,
+                    ancestor, "Reorg replaced processed blocks, rolling back."
+                );
+                let replaced = Self::roll_back(&tree_state, &database, ancestor).await?;
+                // Replaced insertions are no longer on chain, so they are
+                // queued again. If the new blocks include them after all, they
+                // are confirmed as usual.
+                let requeued = replaced
+                    .iter()
+                    .filter(|event| !event.removed)
+                    .map(|event| event.leaf)
+                    .collect::<Vec<_>>();
+                if !requeued.is_empty() {
+                    database
+                        .requeue_identities(identity_manager.group_id().as_usize(), &requeued)
+                        .await
+                        .map_err(Error::Database)?;
+                    info!(count = requeued.len(), "Queued replaced identities again.");
+                    identity_committer.notify_queued().await;
+                }
+                *starting_block = ancestor + 1;
+            }
+            Some(Reorg { depth, .. }) => {
+                error!(
+                    severity = "critical",
+                    depth,
+                    max_auto_reorg_depth = checkpoints.max_depth,
+                    "Reorg too deep to handle automatically, halting sync."
+                );
+                return Err(Error::ReorgTooDeep(depth));
+            }
+        }
+
+        let processed_block = match Self::process_events_internal(
+            *starting_block,
+            tree_state.clone(),
+            identity_manager.clone(),
+            database.clone(),
+            identity_committer,
+        )
+        .await
+        {
+            // Forget the events applied before the interruption, so the retry
+            // starts from a tree without them.
+            Err(Error::Interrupted(error)) => {
+                Self::roll_back(&tree_state, &database, starting_block.saturating_sub(1)).await?;
+                return Err(Error::Unreachable(error));
+            }
+            // The provider left out an event, which it may return when the
+            // range is fetched again.
+            Err(error @ Error::LeafGap(_)) => {
+                Self::roll_back(&tree_state, &database, starting_block.saturating_sub(1)).await?;
+                return Err(error);
+            }
+            result => result?,
+        };
+        *starting_block = processed_block + 1;
+        checkpoints.record(&identity_manager, processed_block).await
+    }
+
+    /// Forgets the events after `ancestor`, the latest processed block a reorg
+    /// left in place, and returns them. Replaced insertions are undone in the
+    /// tree. Only if that is not possible, such as when a removal was replaced
+    /// too, is the tree rebuilt from the cached events before `ancestor`.
+    async fn roll_back(
+        tree_state: &SharedTreeState,
+        database: &Arc<Database>,
+        ancestor: u64,
+    ) -> Result<Vec<CachedLog>, Error> {
+        let replaced = Self::cached_events_after(database, ancestor).await?;
+        database
+            .delete_cached_events_after(ancestor)
+            .await
+            .map_err(Error::Database)?;
+        {
+            let mut tree = tree_state.write().await.unwrap_or_else(|e| {
+                error!(?e, "Failed to obtain tree lock in roll_back.");
+                panic!("Sequencer potentially deadlocked, terminating.");
+            });
+            // The replaced events must be the latest insertions into the tree.
+            let leaf_count = tree
+                .next_leaf
+                .checked_sub(replaced.len())
+                .filter(|&leaf_count| {
+                    replaced
+                        .iter()
+                        .zip(&tree.merkle_tree.leaves()[leaf_count..tree.next_leaf])
+                        .all(|(event, leaf)| !event.removed && event.leaf == *leaf)
+                });
+            if let Some(leaf_count) = leaf_count {
+                debug!(
+                    undone = replaced.len(),
+                    leaf_count, "Undid replaced insertions."
+                );
+                tree.truncate(leaf_count);
+                return Ok(replaced);
+            }
+        }
+
+        warn!(
+            ancestor,
+            "Replaced events can not be undone, rebuilding the tree from the cache."
+        );
+        let side_tree = Self::empty_side_tree(tree_state).await;
+        Self::process_cached_events(
+            0,
+            ancestor,
+            side_tree.clone(),
+            database.clone(),
+            CACHED_EVENTS_PAGE_SIZE,
+            true,
+        )
+        .await?;
+        Self::swap_in(tree_state, &side_tree).await;
+        Ok(replaced)
+    }
+
+    /// Loads the cached events after block `block`, in chain order.
+    async fn cached_events_after(database: &Database, block: u64) -> Result<Vec<CachedLog>, Error> {
+        let mut events: Vec<CachedLog> = Vec::new();
+        loop {
+            let page = database
+                .load_logs(
+                    i64::try_from(block + 1).unwrap(),
+                    None,
+                    events.last().map(|event| event.position),
+                    CACHED_EVENTS_PAGE_SIZE,
+                )
+                .await
+                .map_err(Error::Database)?;
+            let done = page.len() < CACHED_EVENTS_PAGE_SIZE;
+            events.extend(page);
+            if done {
+                return Ok(events);
+            }
+        }
+    }
+
     async fn process_events_internal(
         start_block: u64,
         tree_state: SharedTreeState,
@@ -125,10 +783,11 @@ impl EthereumSubscriber {
         database: Arc<Database>,
         identity_committer: Arc<IdentityCommitter>,
     ) -> Result<u64, Error> {
+        // Nothing is processed yet, so this is safe to retry.
         let end_block = identity_manager
             .confirmed_block_number()
             .await
-            .map_err(Error::Event)?;
+            .map_err(Error::Unreachable)?;
 
         Self::process_blockchain_events(
             start_block,
@@ -137,52 +796,83 @@ impl EthereumSubscriber {
             identity_manager,
             database,
             identity_committer,
+            None,
+            true,
         )
         .await
     }
 
+    /// Replays the cached events in `start_block..=end_block` on the tree,
+    /// recording the origins of the inserted leaves if `persist` is set.
     async fn process_cached_events(
         start_block: u64,
         end_block: u64,
         tree_state: SharedTreeState,
         database: Arc<Database>,
+        page_size: usize,
+        persist: bool,
     ) -> Result<u64, Error> {
         if start_block > end_block {
             return Ok(end_block);
         }
 
-        let last_cached_block = database.get_block_number().await.unwrap();
+        // Blocks up to the last cached event are cached, as well as any
+        // recorded ranges without events.
+        let last_event_block = database.get_block_number().await.map_err(Error::Database)?;
+        let cached_range_end = database
+            .get_cached_range_end(start_block)
+            .await
+            .map_err(Error::Database)?;
+        let last_cached_block = max(last_event_block, cached_range_end.unwrap_or_default());
 
         info!(
             start_block,
             end_block, last_cached_block, "processing cached events in ethereum subscriber"
         );
 
-        let events = database
-            .load_logs(
-                i64::try_from(start_block).unwrap(),
-                Some(i64::try_from(end_block).unwrap()),
-            )
-            .await
-            .map_err(Error::Database)?;
-        let root = events.last().map(|event| event.1);
-        let leaves = events.iter().map(|event| event.0);
-        let count = leaves.len();
-
         let mut tree = tree_state.write().await.unwrap_or_else(|e| {
             error!(?e, "Failed to obtain tree lock in process_events.");
             panic!("Sequencer potentially deadlocked, terminating.");
         });
 
-        // Insert
-        let index = tree.next_leaf;
-        tree.merkle_tree.set_range(index, leaves);
-        tree.next_leaf += count;
+        let mut root = None;
+        let mut root_block = None;
+        let mut after = None;
+        loop {
+            let events = database
+                .load_logs(
+                    i64::try_from(start_block).unwrap(),
+                    Some(i64::try_from(end_block).unwrap()),
+                    after,
+                    page_size,
+                )
+                .await
+                .map_err(Error::Database)?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            root = Some(last.root);
+            root_block = u64::try_from(last.position.block_index).ok();
+            after = Some(last.position);
+            debug!(count = events.len(), "Replaying cached events.");
+            let origins = Self::replay_cached_events(&mut tree, &events)?;
+            if persist {
+                for (index, origin) in &origins {
+                    database
+                        .save_leaf_origin(*index, origin)
+                        .await
+                        .map_err(Error::Database)?;
+                }
+            }
+            if events.len() < page_size {
+                break;
+            }
+        }
 
         // Check root
         if let Some(root) = root {
             if root != tree.merkle_tree.root() {
-                error!(computed_root = ?tree.merkle_tree.root(), event_root = ?root, "Root mismatch between event and computed tree.");
+                report_root_mismatch("cache", &tree.merkle_tree.root(), Some(&root), root_block);
                 return Err(Error::RootMismatch);
             }
         }
@@ -190,6 +880,46 @@ impl EthereumSubscriber {
         Ok(min(end_block, last_cached_block))
     }
 
+    /// Applies `events` to the tree and returns the origins of the inserted
+    /// leaves whose transaction is known.
+    fn replay_cached_events(
+        tree: &mut TreeState,
+        events: &[CachedLog],
+    ) -> Result<Vec<(usize, LeafOrigin)>, Error> {
+        let mut origins = Vec::new();
+        // Insert in bulk up to every removal
+        for run in events.split_inclusive(|event| event.removed) {
+            let (removal, insertions) = match run.split_last() {
+                Some((last, rest)) if last.removed => (Some(last), rest),
+                _ => (None, run),
+            };
+
+            let index = tree.next_leaf;
+            tree.merkle_tree
+                .set_range(index, insertions.iter().map(|event| event.leaf));
+            tree.next_leaf += insertions.len();
+            for (offset, event) in insertions.iter().enumerate() {
+                tree.record_root(event.root, index + offset + 1);
+                if let Some(transaction_hash) = &event.transaction_hash {
+                    let origin = LeafOrigin {
+                        commitment:       event.leaf,
+                        block_number:     u64::try_from(event.position.block_index)
+                            .unwrap_or_default(),
+                        transaction_hash: transaction_hash.clone(),
+                    };
+                    origins.push((index + offset, origin));
+                }
+            }
+
+            if let Some(removal) = removal {
+                Self::remove_leaf(tree, &removal.leaf)?;
+                let leaf_count = tree.next_leaf;
+                tree.record_root(removal.root, leaf_count);
+            }
+        }
+        Ok(origins)
+    }
+
     async fn process_blockchain_events(
         start_block: u64,
         end_block: u64,
@@ -197,6 +927,8 @@ impl EthereumSubscriber {
         identity_manager: SharedIdentityManager,
         database: Arc<Database>,
         identity_committer: Arc<IdentityCommitter>,
+        sync_progress: Option<&SyncProgress>,
+        persist: bool,
     ) -> Result<u64, Error> {
         if start_block > end_block {
             return Ok(end_block);
@@ -216,51 +948,84 @@ impl EthereumSubscriber {
             panic!("Sequencer potentially deadlocked, terminating.");
         });
 
+        let initial_leaf = identity_manager.initial_leaf_value();
+        let group_id = identity_manager.group_id().as_usize();
         let mut wake_up_committer = false;
+        let mut last_progress = Instant::now();
+        // Events arriving before the ones they follow, in the order received.
+        let mut held_back = Vec::new();
 
         loop {
-            let event = match events.try_next().await.map_err(Error::Event)? {
+            let event = match events.try_next().await.map_err(Error::from_event_stream)? {
                 Some(a) => a,
                 None => break,
             };
 
             let identity = ConfirmedIdentityEvent::try_from(event)?;
 
-            Self::log_event_errors(
-                &tree,
-                &identity_manager.initial_leaf_value(),
-                tree.next_leaf,
-                &identity.leaf,
-            )?;
+            // Earlier blocks are complete once an event of a later one arrives.
+            let block = u64::try_from(identity.block_index).unwrap_or_default();
+            if let Some(sync_progress) = sync_progress {
+                sync_progress.report_processed(block.saturating_sub(1));
+            }
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                info!(
+                    blocks_processed = block.saturating_sub(start_block),
+                    blocks_total = end_block - start_block + 1,
+                    "Processing blockchain events."
+                );
+                last_progress = Instant::now();
+            }
 
-            // Insert
-            let index = tree.next_leaf;
-            tree.merkle_tree.set(index, identity.leaf);
-            tree.next_leaf += 1;
+            let Some(index) = Self::apply_event(&mut tree, &initial_leaf, &identity)? else {
+                warn!(
+                    commitment = ?identity.leaf,
+                    block,
+                    next_leaf = tree.next_leaf,
+                    "Event does not continue the tree, holding it back."
+                );
+                held_back.push(identity);
+                if held_back.len() > MAX_HELD_BACK_EVENTS {
+                    return Err(Self::leaf_gap(&tree, &held_back));
+                }
+                continue;
+            };
 
-            // Check root
-            if identity.root != tree.merkle_tree.root() {
-                error!(computed_root = ?tree.merkle_tree.root(), event_root = ?identity.root, "Root mismatch between event and computed tree.");
-                return Err(Error::RootMismatch);
+            let mut applied = Some((index, identity));
+            while let Some((index, identity)) = applied.take() {
+                if persist {
+                    wake_up_committer |= Self::save_event(
+                        &database,
+                        &identity_committer,
+                        group_id,
+                        &identity,
+                        index,
+                    )
+                    .await?;
+                }
+
+                // A held back event may continue the tree now.
+                for position in 0..held_back.len() {
+                    if let Some(index) =
+                        Self::apply_event(&mut tree, &initial_leaf, &held_back[position])?
+                    {
+                        applied = Some((index, held_back.remove(position)));
+                        break;
+                    }
+                }
             }
+        }
 
-            // Cache event
-            database
-                .save_log(&identity)
-                .await
-                .map_err(Error::Database)?;
+        if !held_back.is_empty() {
+            return Err(Self::leaf_gap(&tree, &held_back));
+        }
 
-            // Remove from pending identities
-            let queue_status = database
-                .confirm_identity_and_retrigger_stale_recods(&identity.leaf)
+        // Remember that this range is cached, so it isn't fetched again.
+        if persist {
+            database
+                .save_cached_range(start_block, end_block)
                 .await
                 .map_err(Error::Database)?;
-            if matches!(
-                queue_status,
-                IdentityConfirmationResult::RetriggerProcessing
-            ) {
-                wake_up_committer = true;
-            }
         }
 
         if wake_up_committer {
@@ -274,6 +1039,114 @@ impl EthereumSubscriber {
         Ok(end_block)
     }
 
+    /// Applies an event if it continues the tree, returning the index of the
+    /// inserted or removed leaf. Events that do not result in their root leave
+    /// the tree unchanged, as an event before them may not have arrived yet.
+    fn apply_event(
+        tree: &mut TreeState,
+        initial_leaf: &Field,
+        identity: &ConfirmedIdentityEvent,
+    ) -> Result<Option<usize>, Error> {
+        let index = if identity.removed {
+            let Some(index) = tree.merkle_tree.leaves()[..tree.next_leaf]
+                .iter()
+                .position(|leaf| leaf == &identity.leaf)
+            else {
+                return Ok(None);
+            };
+            tree.merkle_tree.set(index, *initial_leaf);
+            let continues = identity.root == tree.merkle_tree.root();
+            tree.merkle_tree.set(index, identity.leaf);
+            if !continues {
+                return Ok(None);
+            }
+            Self::remove_leaf(tree, &identity.leaf)?
+        } else {
+            Self::log_event_errors(tree, initial_leaf, tree.next_leaf, &identity.leaf)?;
+            let index = tree.append(identity.leaf);
+            if identity.root != tree.merkle_tree.root() {
+                tree.merkle_tree.set(index, *initial_leaf);
+                tree.next_leaf = index;
+                return Ok(None);
+            }
+            index
+        };
+        let leaf_count = tree.next_leaf;
+        tree.record_root(identity.root, leaf_count);
+        Ok(Some(index))
+    }
+
+    /// Caches an applied event and confirms inserted identities, returning
+    /// whether the committer needs to be woken up.
+    async fn save_event(
+        database: &Database,
+        identity_committer: &IdentityCommitter,
+        group_id: usize,
+        identity: &ConfirmedIdentityEvent,
+        index: usize,
+    ) -> Result<bool, Error> {
+        if identity.removed {
+            info!(commitment = ?identity.leaf, index, "Identity removed.");
+            database.save_log(identity).await.map_err(Error::Database)?;
+            return Ok(false);
+        }
+
+        info!(
+            target: LIFECYCLE_TARGET,
+            transition = "confirmed",
+            group_id,
+            commitment = ?identity.leaf,
+            index,
+            block = identity.block_index,
+            "Identity confirmed."
+        );
+
+        // Cache event
+        database.save_log(identity).await.map_err(Error::Database)?;
+        let origin = LeafOrigin {
+            commitment:       identity.leaf,
+            block_number:     u64::try_from(identity.block_index).unwrap_or_default(),
+            transaction_hash: identity.transaction_hash.clone(),
+        };
+        database
+            .save_leaf_origin(index, &origin)
+            .await
+            .map_err(Error::Database)?;
+
+        // Remove from pending identities
+        let queue_status = database
+            .confirm_identity_and_retrigger_stale_recods(group_id, &identity.leaf)
+            .await
+            .map_err(Error::Database)?;
+        identity_committer.confirmed(&identity.leaf);
+        Ok(matches!(
+            queue_status,
+            IdentityConfirmationResult::RetriggerProcessing
+        ))
+    }
+
+    /// Reports held back events that no event before them made continue the
+    /// tree, as the first of them does not follow the tree.
+    fn leaf_gap(tree: &TreeState, held_back: &[ConfirmedIdentityEvent]) -> Error {
+        let first = &held_back[0];
+        let block = u64::try_from(first.block_index).ok();
+        report_root_mismatch("events", &tree.merkle_tree.root(), Some(&first.root), block);
+        error!(
+            next_leaf = tree.next_leaf,
+            held_back = held_back.len(),
+            "Events do not continue the tree, an event before them is missing."
+        );
+        Error::LeafGap(tree.next_leaf)
+    }
+
+    /// Resets a removed leaf to the initial value, returning its index.
+    fn remove_leaf(tree: &mut TreeState, leaf: &Field) -> Result<usize, Error> {
+        tree.remove_leaf(leaf).ok_or_else(|| {
+            error!(?leaf, "Received removal of a leaf not in the tree.");
+            Error::UnknownLeafRemoved
+        })
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn log_event_errors(
         tree: &TreeState,
@@ -309,37 +1182,23 @@ impl EthereumSubscriber {
         Ok(())
     }
 
+    /// Checks the tree against the contract and for skipped or duplicate
+    /// leaves, logging the findings.
     #[instrument(level = "debug", skip_all)]
-    pub async fn check_health(&self) {
-        let tree = self.tree_state.read().await.unwrap_or_else(|e| {
-            error!(?e, "Failed to obtain tree lock in check_leaves.");
-            panic!("Sequencer potentially deadlocked, terminating.");
-        });
+    pub async fn check_health(&self) -> HealthReport {
+        let tree = self.tree_state.read_retrying().await;
         let initial_leaf = self.identity_manager.initial_leaf_value();
-
-        if tree.next_leaf > 0 {
-            if let Err(error) = self
-                .identity_manager
-                .assert_valid_root(tree.merkle_tree.root())
-                .await
-            {
-                error!(root = ?tree.merkle_tree.root(), %error, "Root not valid on-chain.");
-            } else {
-                info!(root = ?tree.merkle_tree.root(), "Root matches on-chain root.");
-            }
-        } else {
-            // TODO: This should still be checkable.
-            info!(root = ?tree.merkle_tree.root(), "Empty tree, not checking root.");
-        }
+        let root = tree.merkle_tree.root();
+        let next_leaf = tree.next_leaf;
 
         // Check tree health
-        let next_leaf = tree
+        let used = tree
             .merkle_tree
             .leaves()
             .iter()
             .rposition(|&l| l != initial_leaf)
             .map_or(0, |i| i + 1);
-        let used_leaves = &tree.merkle_tree.leaves()[..next_leaf];
+        let used_leaves = &tree.merkle_tree.leaves()[..used];
         let skipped = used_leaves.iter().filter(|&&l| l == initial_leaf).count();
         let mut dedup = used_leaves
             .iter()
@@ -350,9 +1209,11 @@ impl EthereumSubscriber {
         let unique = dedup.len();
         let duplicates = used_leaves.len() - skipped - unique;
         let total = tree.merkle_tree.num_leaves();
-        let available = total - next_leaf;
+        let available = total - used;
+        drop(tree);
+
         #[allow(clippy::cast_precision_loss)]
-        let fill = (next_leaf as f64) / (total as f64);
+        let fill = (used as f64) / (total as f64);
         if skipped == 0 && duplicates == 0 {
             info!(
                 healthy = %unique,
@@ -366,29 +1227,65 @@ impl EthereumSubscriber {
                 healthy = %unique,
                 %duplicates,
                 %skipped,
-                used = %next_leaf,
+                %used,
                 %available,
                 %total,
                 %fill,
                 "Merkle tree has duplicate or skipped leaves."
             );
         }
-        if next_leaf > available * 3 {
-            if next_leaf > available * 19 {
-                error!(
-                    used = %next_leaf,
-                    available = %available,
-                    total = %total,
-                    "Merkle tree is over 95% full."
-                );
+        if used > available * 3 {
+            if used > available * 19 {
+                error!(%used, %available, %total, "Merkle tree is over 95% full.");
             } else {
-                warn!(
-                    used = %next_leaf,
-                    available = %available,
-                    total = %total,
-                    "Merkle tree is over 75% full."
-                );
+                warn!(%used, %available, %total, "Merkle tree is over 75% full.");
+            }
+        }
+
+        let root_matches = if next_leaf > 0 {
+            match self.identity_manager.assert_valid_root(root).await {
+                Ok(()) => {
+                    info!(?root, "Root matches on-chain root.");
+                    Some(true)
+                }
+                Err(error) if InvalidRoot::is_cause_of(&error) => {
+                    error!(?root, %error, "Root not valid on-chain.");
+                    Some(false)
+                }
+                Err(error) => {
+                    warn!(?root, %error, "Failed to check the root on-chain.");
+                    None
+                }
             }
+        } else {
+            // TODO: This should still be checkable.
+            info!(?root, "Empty tree, not checking root.");
+            Some(true)
+        };
+        let onchain_root = self
+            .identity_manager
+            .latest_root()
+            .await
+            .map_err(|error| warn!(%error, "Failed to read the on-chain root."))
+            .ok();
+        let onchain_leaf_count = self
+            .identity_manager
+            .leaf_count()
+            .await
+            .map_err(|error| warn!(%error, "Failed to read the on-chain leaf count."))
+            .ok();
+
+        HealthReport {
+            root_matches,
+            expected_root: root,
+            onchain_root,
+            next_leaf,
+            onchain_leaf_count,
+            skipped_leaves: skipped,
+            duplicate_leaves: duplicates,
+            fill,
+            degraded: false,
+            halting_reorg: self.chain_health.halting_reorg(),
         }
     }
 
@@ -411,12 +1308,34 @@ pub enum Error {
     RootMismatch,
     #[error("Received event out of range")]
     EventOutOfRange,
+    #[error("Received removal of a leaf not in the tree")]
+    UnknownLeafRemoved,
+    #[error("Missing event for leaf {0}, later events do not continue the tree")]
+    LeafGap(usize),
     #[error("Event error: {0}")]
     Event(#[source] EventError),
+    #[error("Ethereum provider unreachable: {0}")]
+    Unreachable(#[source] EventError),
+    #[error("Ethereum provider unreachable while processing events: {0}")]
+    Interrupted(#[source] EventError),
     #[error("Database error: {0}")]
     Database(#[source] DatabaseError),
     #[error("Integer conversion error: {0}")]
     Conversion(String),
+    #[error("Reorg of {0} processed blocks is too deep to handle automatically")]
+    ReorgTooDeep(u64),
+}
+
+impl Error {
+    /// Failing to fetch events part way interrupts processing, while events
+    /// that can not be used are a data integrity error.
+    fn from_event_stream(error: EventError) -> Self {
+        if error.is_transient() {
+            Self::Interrupted(error)
+        } else {
+            Self::Event(error)
+        }
+    }
 }
 
 struct IdentityCommitment {
@@ -433,11 +1352,23 @@ impl From<MemberAddedEvent> for IdentityCommitment {
     }
 }
 
-impl TryFrom<Log<MemberAddedEvent>> for ConfirmedIdentityEvent {
+impl From<MemberRemovedEvent> for IdentityCommitment {
+    fn from(value: MemberRemovedEvent) -> Self {
+        Self {
+            leaf: value.identity_commitment.into(),
+            root: value.root.into(),
+        }
+    }
+}
+
+impl TryFrom<Log<MemberEvent>> for ConfirmedIdentityEvent {
     type Error = Error;
 
-    fn try_from(value: Log<MemberAddedEvent>) -> Result<Self, Self::Error> {
-        let commitment = IdentityCommitment::from(value.event);
+    fn try_from(value: Log<MemberEvent>) -> Result<Self, Self::Error> {
+        let (commitment, removed) = match value.event {
+            MemberEvent::MemberAddedFilter(event) => (IdentityCommitment::from(event), false),
+            MemberEvent::MemberRemovedFilter(event) => (IdentityCommitment::from(event), true),
+        };
 
         let block_index: i64 = value
             .block_index
@@ -459,8 +1390,729 @@ impl TryFrom<Log<MemberAddedEvent>> for ConfirmedIdentityEvent {
             transaction_index,
             log_index,
             raw_log: value.raw_log,
+            transaction_hash: format!("{:?}", value.transaction_hash),
             leaf: commitment.leaf,
             root: commitment.root,
+            removed,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        contracts::{mock::MockIdentityManager, IdentityManager},
+        database,
+    };
+    use clap::Parser;
+    use semaphore::poseidon_tree::PoseidonTree;
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tokio::sync::oneshot;
+    use tracing_test::traced_test;
+
+    async fn test_database() -> Arc<Database> {
+        Arc::new(
+            Database::new(database::Options::try_parse_from([""]).unwrap())
+                .await
+                .unwrap(),
+        )
+    }
+
+    async fn subscriber(
+        database: &Arc<Database>,
+        identity_manager: &Arc<MockIdentityManager>,
+    ) -> EthereumSubscriber {
+        let tree_state = Arc::new(TimedRwLock::new(
+            Duration::from_secs(10),
+            TreeState::new(
+                identity_manager.poseidon_tree_depth(),
+                identity_manager.initial_leaf_value(),
+            ),
+        ));
+        let identity_committer = Arc::new(IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        ));
+        EthereumSubscriber::new(
+            1,
+            database.clone(),
+            identity_manager.clone(),
+            tree_state,
+            identity_committer,
+        )
+    }
+
+    #[tokio::test]
+    async fn resync_of_cached_range_makes_no_rpc_calls() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+
+        subscriber(&database, &identity_manager)
+            .await
+            .process_initial_events()
+            .await
+            .unwrap();
+        assert_eq!(identity_manager.fetches(), vec![(1, Some(100))]);
+
+        subscriber(&database, &identity_manager)
+            .await
+            .process_initial_events()
+            .await
+            .unwrap();
+        assert_eq!(identity_manager.fetches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn removed_leaf_reads_as_initial_value_after_resync() {
+        let database = test_database().await;
+        let identity_manager =
+            Arc::new(MockIdentityManager::mining_at(Some(100)).emitting_registrations());
+        let (removed, kept) = (Field::from(1234), Field::from(5678));
+        identity_manager
+            .register_identities(vec![removed, kept])
+            .await
+            .unwrap();
+        identity_manager.remove_member(removed);
+
+        let mut from_chain = subscriber(&database, &identity_manager).await;
+        from_chain.process_initial_events().await.unwrap();
+        let mut from_cache = subscriber(&database, &identity_manager).await;
+        from_cache.process_initial_events().await.unwrap();
+        assert_eq!(identity_manager.fetches().len(), 1);
+
+        for subscriber in [from_chain, from_cache] {
+            let tree = subscriber.tree_state.read().await.unwrap();
+            assert_eq!(tree.next_leaf, 2);
+            assert_eq!(
+                tree.merkle_tree.leaves()[0],
+                identity_manager.initial_leaf_value()
+            );
+            assert_eq!(tree.merkle_tree.leaves()[1], kept);
+        }
+    }
+
+    #[tokio::test]
+    async fn events_arriving_out_of_order_are_applied_in_order() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(
+            MockIdentityManager::mining_at(Some(100))
+                .emitting_registrations()
+                .emitting_in_order(vec![1, 0, 3, 4, 2]),
+        );
+        let leaves = (0..4)
+            .map(|index| Field::from(1000 + index))
+            .collect::<Vec<_>>();
+        identity_manager
+            .register_identities(leaves.clone())
+            .await
+            .unwrap();
+        identity_manager.remove_member(leaves[1]);
+        let mut expected = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        for (index, leaf) in leaves.iter().enumerate() {
+            expected.set(index, *leaf);
+        }
+        expected.set(1, identity_manager.initial_leaf_value());
+
+        let mut subscriber = subscriber(&database, &identity_manager).await;
+        subscriber.process_initial_events().await.unwrap();
+
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 4);
+        assert_eq!(tree.merkle_tree.root(), expected.root());
+    }
+
+    #[tokio::test]
+    async fn missing_event_is_flagged_as_a_gap() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(
+            MockIdentityManager::mining_at(Some(100))
+                .emitting_registrations()
+                .emitting_in_order(vec![0, 2, 3]),
+        );
+        identity_manager
+            .register_identities((0..4).map(|index| Field::from(1000 + index)).collect())
+            .await
+            .unwrap();
+
+        let mut subscriber = subscriber(&database, &identity_manager).await;
+        let result = subscriber.process_initial_events().await;
+
+        assert!(matches!(result, Err(Error::LeafGap(1))));
+    }
+
+    #[tokio::test]
+    async fn health_report_compares_the_tree_with_the_contract() {
+        let database = test_database().await;
+        let identity_manager =
+            Arc::new(MockIdentityManager::mining_at(Some(100)).emitting_registrations());
+        identity_manager
+            .register_identities(vec![Field::from(1), Field::from(2)])
+            .await
+            .unwrap();
+        let onchain_root = identity_manager.latest_root().await.unwrap();
+
+        let mut subscriber = subscriber(&database, &identity_manager).await;
+        subscriber.process_initial_events().await.unwrap();
+        let report = subscriber.check_health().await;
+        assert_eq!(report, HealthReport {
+            root_matches:       Some(true),
+            expected_root:      onchain_root,
+            onchain_root:       Some(onchain_root),
+            next_leaf:          2,
+            onchain_leaf_count: Some(2),
+            skipped_leaves:     0,
+            duplicate_leaves:   0,
+            fill:               report.fill,
+            degraded:           false,
+            halting_reorg:      None,
+        });
+
+        // A leaf the contract does not know of, past a skipped one.
+        let expected_root = {
+            let mut tree = subscriber.tree_state.write().await.unwrap();
+            tree.merkle_tree.set(3, Field::from(1));
+            tree.next_leaf = 4;
+            tree.merkle_tree.root()
+        };
+        let report = subscriber.check_health().await;
+        assert_ne!(report.expected_root, onchain_root);
+        assert_eq!(report.expected_root, expected_root);
+        assert_eq!(report.onchain_root, Some(onchain_root));
+        assert_eq!((report.next_leaf, report.onchain_leaf_count), (4, Some(2)));
+        assert_eq!((report.skipped_leaves, report.duplicate_leaves), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn unchecked_root_is_not_reported_as_mismatch() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(
+            MockIdentityManager::mining_at(Some(100))
+                .emitting_registrations()
+                .checking_roots(),
+        );
+        identity_manager
+            .register_identities(vec![Field::from(1), Field::from(2)])
+            .await
+            .unwrap();
+        let mut subscriber = subscriber(&database, &identity_manager).await;
+        subscriber.process_initial_events().await.unwrap();
+
+        identity_manager.set_root_checks_failing(true);
+        let report = subscriber.check_health().await;
+        assert_eq!(report.root_matches, None);
+        assert!(!report.is_critical());
+
+        identity_manager.set_root_checks_failing(false);
+        subscriber
+            .tree_state
+            .write()
+            .await
+            .unwrap()
+            .merkle_tree
+            .set(0, Field::from(3));
+        let report = subscriber.check_health().await;
+        assert_eq!(report.root_matches, Some(false));
+        assert!(report.is_critical());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn readers_never_observe_a_partially_rebuilt_tree() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+        let mut rebuilt = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        for index in 0..200 {
+            let leaf = Field::from(1000 + index);
+            rebuilt.set(index, leaf);
+            database
+                .save_log(&ConfirmedIdentityEvent {
+                    block_index: 1 + i64::try_from(index / 10).unwrap(),
+                    transaction_index: 0,
+                    log_index: i32::try_from(index % 10).unwrap(),
+                    raw_log: String::new(),
+                    transaction_hash: String::new(),
+                    leaf,
+                    root: rebuilt.root(),
+                    removed: false,
+                })
+                .await
+                .unwrap();
+        }
+
+        // The tree served before the rebuild.
+        let mut subscriber = subscriber(&database, &identity_manager).await;
+        let previous_root = {
+            let mut tree = subscriber.tree_state.write().await.unwrap();
+            tree.merkle_tree.set(0, Field::from(1));
+            tree.next_leaf = 1;
+            tree.merkle_tree.root()
+        };
+
+        let rebuilding = Arc::new(AtomicBool::new(true));
+        let (started, reading) = oneshot::channel();
+        let reader = tokio::spawn({
+            let (tree_state, rebuilding) = (subscriber.tree_state.clone(), rebuilding.clone());
+            async move {
+                let mut roots = HashSet::new();
+                let mut started = Some(started);
+                while rebuilding.load(Ordering::SeqCst) {
+                    let tree = tree_state.read().await.unwrap();
+                    let (root, leaf) = (tree.merkle_tree.root(), tree.merkle_tree.leaves()[0]);
+                    let proof = tree.merkle_tree.proof(0).unwrap();
+                    assert_eq!(proof.root(leaf), root);
+                    roots.insert(root);
+                    drop(tree);
+                    if let Some(started) = started.take() {
+                        started.send(()).unwrap();
+                    }
+                    tokio::task::yield_now().await;
+                }
+                roots
+            }
+        });
+
+        reading.await.unwrap();
+        subscriber.process_initial_events().await.unwrap();
+        rebuilding.store(false, Ordering::SeqCst);
+        let roots = reader.await.unwrap();
+
+        assert!(roots.contains(&previous_root));
+        assert!(roots.is_subset(&HashSet::from([previous_root, rebuilt.root()])));
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(
+            (tree.next_leaf, tree.merkle_tree.root()),
+            (200, rebuilt.root())
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::disallowed_methods)] // False positive from macro
+    #[traced_test]
+    async fn cached_events_are_replayed_in_bounded_pages() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+        let mut expected = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        // Two events per block, so pages also end within blocks.
+        let positions = [(10, 0), (10, 1), (11, 0), (11, 1), (12, 0)];
+        for (index, (block_index, transaction_index)) in positions.into_iter().enumerate() {
+            let leaf = Field::from(1000 + index);
+            expected.set(index, leaf);
+            database
+                .save_log(&ConfirmedIdentityEvent {
+                    block_index,
+                    transaction_index,
+                    log_index: 0,
+                    raw_log: String::new(),
+                    transaction_hash: String::new(),
+                    leaf,
+                    root: expected.root(),
+                    removed: false,
+                })
+                .await
+                .unwrap();
+        }
+
+        let subscriber = subscriber(&database, &identity_manager).await;
+        EthereumSubscriber::process_cached_events(
+            1,
+            100,
+            subscriber.tree_state.clone(),
+            database.clone(),
+            2,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 5);
+        assert_eq!(tree.merkle_tree.root(), expected.root());
+        logs_assert(|lines: &[&str]| {
+            let pages = lines
+                .iter()
+                .filter(|line| line.contains("Replaying cached events."))
+                .filter_map(|line| line.split("count=").nth(1)?.split_whitespace().next())
+                .collect::<Vec<_>>();
+            if pages == ["2", "2", "1"] {
+                Ok(())
+            } else {
+                Err(format!("Unexpected pages: {pages:?}"))
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn leaf_origins_are_recorded_on_replay() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+        let mut expected = PoseidonTree::new(
+            identity_manager.poseidon_tree_depth(),
+            identity_manager.initial_leaf_value(),
+        );
+        for index in 0..3 {
+            let leaf = Field::from(1000 + index);
+            expected.set(index, leaf);
+            database
+                .save_log(&ConfirmedIdentityEvent {
+                    block_index:       10 + i64::try_from(index).unwrap(),
+                    transaction_index: 0,
+                    log_index:         0,
+                    raw_log:           String::new(),
+                    transaction_hash:  format!("0x{index:064x}"),
+                    leaf,
+                    root:              expected.root(),
+                    removed:           false,
+                })
+                .await
+                .unwrap();
+        }
+
+        let subscriber = subscriber(&database, &identity_manager).await;
+        EthereumSubscriber::process_cached_events(
+            1,
+            100,
+            subscriber.tree_state.clone(),
+            database.clone(),
+            2,
+            true,
+        )
+        .await
+        .unwrap();
+
+        for index in 0..3 {
+            let origin = database.get_leaf_origin(index).await.unwrap().unwrap();
+            assert_eq!(origin.commitment, Field::from(1000 + index));
+            assert_eq!(origin.block_number, 10 + u64::try_from(index).unwrap());
+            assert_eq!(origin.transaction_hash, format!("0x{index:064x}"));
+        }
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter_up_to_max() {
+        let (initial, max) = (Duration::from_millis(100), Duration::from_millis(1000));
+        let runs = (0..100)
+            .map(|_| {
+                let mut backoff = Backoff::new(initial, max);
+                (0..6).map(|_| backoff.next_delay()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let bases = [200, 400, 800, 1000, 1000, 1000].map(Duration::from_millis);
+        for run in &runs {
+            for (delay, base) in run.iter().zip(bases) {
+                assert!(
+                    *delay >= base / 2 && *delay <= base,
+                    "{delay:?} is not within the jitter of {base:?}"
+                );
+            }
+        }
+        // Jittered, so runs do not all wait the same.
+        assert!(runs.iter().any(|run| run != &runs[0]));
+
+        let mut backoff = Backoff::new(initial, max);
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn unreachable_provider_is_retried_with_growing_delays() {
+        let database = test_database().await;
+        let identity_manager =
+            Arc::new(MockIdentityManager::mining_at(Some(100)).unreachable_for(3));
+        let subscriber = subscriber(&database, &identity_manager).await;
+
+        subscriber
+            .start(Duration::from_millis(20), Duration::from_secs(1), 64)
+            .await;
+        for _ in 0..100 {
+            if identity_manager.block_number_calls().len() >= 5 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let calls = identity_manager.block_number_calls();
+        assert!(calls.len() >= 5);
+        let intervals = calls
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        // Retries after the first three failures wait 20-40, 40-80 and 80-160ms.
+        for (interval, base) in intervals
+            .iter()
+            .zip([40, 80, 160].map(Duration::from_millis))
+        {
+            assert!(*interval >= base / 2, "Retried after only {interval:?}");
+        }
+        assert!(intervals[2] > intervals[0]);
+        assert!(subscriber.chain_health().unreachable_for().is_none());
+    }
+
+    #[tokio::test]
+    async fn events_interrupted_by_the_provider_are_fetched_again() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(
+            MockIdentityManager::mining_at(Some(100))
+                .emitting_registrations()
+                .interrupting_fetches(1),
+        );
+        identity_manager
+            .register_identities((0..3).map(|index| Field::from(1000 + index)).collect())
+            .await
+            .unwrap();
+        let subscriber = subscriber(&database, &identity_manager).await;
+
+        subscriber
+            .start(Duration::from_millis(20), Duration::from_secs(1), 64)
+            .await;
+        for _ in 0..100 {
+            if subscriber.tree_state.read().await.unwrap().next_leaf == 3 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(identity_manager.fetches(), vec![
+            (1, Some(100)),
+            (1, Some(100))
+        ]);
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 3);
+        assert_eq!(
+            tree.merkle_tree.root(),
+            identity_manager.latest_root().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn events_skipping_a_leaf_are_fetched_again() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(
+            MockIdentityManager::mining_at(Some(100))
+                .emitting_registrations()
+                .skipping_in_fetches(1),
+        );
+        identity_manager
+            .register_identities((0..3).map(|index| Field::from(1000 + index)).collect())
+            .await
+            .unwrap();
+        let subscriber = subscriber(&database, &identity_manager).await;
+
+        subscriber
+            .start(Duration::from_millis(20), Duration::from_secs(1), 64)
+            .await;
+        for _ in 0..100 {
+            if subscriber.tree_state.read().await.unwrap().next_leaf == 3 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(identity_manager.fetches(), vec![
+            (1, Some(100)),
+            (1, Some(100))
+        ]);
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 3);
+        assert_eq!(
+            tree.merkle_tree.root(),
+            identity_manager.latest_root().await.unwrap()
+        );
+    }
+
+    /// Starts a subscriber on a chain confirmed up to block 100 and lets it
+    /// process up to block 110, then replaces the blocks from 105 on.
+    async fn reorg_after_sync(
+        max_auto_reorg_depth: u64,
+    ) -> (Arc<MockIdentityManager>, EthereumSubscriber) {
+        let database = test_database().await;
+        let identity_manager =
+            Arc::new(MockIdentityManager::mining_at(Some(100)).emitting_registrations());
+        identity_manager
+            .register_identities(vec![Field::from(1234)])
+            .await
+            .unwrap();
+        let subscriber = subscriber(&database, &identity_manager).await;
+        subscriber
+            .start(
+                Duration::from_millis(20),
+                Duration::from_secs(1),
+                max_auto_reorg_depth,
+            )
+            .await;
+
+        let fetched = |count: usize| {
+            let identity_manager = identity_manager.clone();
+            async move {
+                for _ in 0..100 {
+                    if identity_manager.fetches().len() >= count {
+                        return;
+                    }
+                    sleep(Duration::from_millis(10)).await;
+                }
+                panic!(
+                    "Expected {count} fetches, got {:?}",
+                    identity_manager.fetches()
+                );
+            }
+        };
+        fetched(1).await;
+        identity_manager.confirm_until(110);
+        fetched(2).await;
+        // Let the update finish, so block 110 is remembered before the reorg.
+        sleep(Duration::from_millis(100)).await;
+        identity_manager.reorg_from(105);
+        (identity_manager, subscriber)
+    }
+
+    #[tokio::test]
+    async fn shallow_reorg_is_synced_again() {
+        let (identity_manager, subscriber) = reorg_after_sync(20).await;
+        for _ in 0..100 {
+            if identity_manager.fetches().len() >= 3 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // Blocks from where the chain forked are fetched again.
+        assert_eq!(identity_manager.fetches(), vec![
+            (1, Some(100)),
+            (101, Some(110)),
+            (105, Some(110))
+        ]);
+        assert_eq!(subscriber.chain_health().halting_reorg(), None);
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 1);
+        assert_eq!(
+            tree.merkle_tree.root(),
+            identity_manager.latest_root().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn reorg_beyond_max_depth_halts_sync() {
+        let (identity_manager, subscriber) = reorg_after_sync(5).await;
+        for _ in 0..100 {
+            if subscriber.chain_health().halting_reorg().is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(subscriber.chain_health().halting_reorg(), Some(6));
+        identity_manager.confirm_until(120);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(identity_manager.fetches().len(), 2);
+        let tree = subscriber.tree_state.read().await.unwrap();
+        assert_eq!(tree.next_leaf, 1);
+    }
+
+    #[tokio::test]
+    async fn reorg_depth_is_measured_from_the_fork() {
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+        let shared_identity_manager: SharedIdentityManager = identity_manager.clone();
+        let mut checkpoints = Checkpoints::new(20);
+        checkpoints
+            .record(&shared_identity_manager, 100)
+            .await
+            .unwrap();
+        identity_manager.confirm_until(110);
+        checkpoints
+            .record(&shared_identity_manager, 110)
+            .await
+            .unwrap();
+
+        identity_manager.reorg_from(105);
+
+        assert_eq!(
+            checkpoints
+                .find_reorg(&shared_identity_manager)
+                .await
+                .unwrap(),
+            Some(Reorg {
+                ancestor: Some(104),
+                depth:    6,
+            })
+        );
+        assert_eq!(
+            checkpoints
+                .find_reorg(&shared_identity_manager)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn replaced_insertions_are_queued_again() {
+        let database = test_database().await;
+        let identity_manager = Arc::new(MockIdentityManager::mining_at(Some(100)));
+        let subscriber = subscriber(&database, &identity_manager).await;
+        let root = {
+            let mut tree = subscriber.tree_state.write().await.unwrap();
+            tree.append(Field::from(1234));
+            tree.merkle_tree.root()
+        };
+        database
+            .save_log(&ConfirmedIdentityEvent {
+                block_index: 107,
+                transaction_index: 0,
+                log_index: 0,
+                raw_log: String::new(),
+                transaction_hash: String::new(),
+                leaf: Field::from(1234),
+                root,
+                removed: false,
+            })
+            .await
+            .unwrap();
+        let shared_identity_manager: SharedIdentityManager = identity_manager.clone();
+        let mut checkpoints = Checkpoints::new(20);
+        checkpoints
+            .record(&shared_identity_manager, 100)
+            .await
+            .unwrap();
+        identity_manager.confirm_until(110);
+        checkpoints
+            .record(&shared_identity_manager, 110)
+            .await
+            .unwrap();
+
+        identity_manager.reorg_from(105);
+        let mut starting_block = 111;
+        EthereumSubscriber::process_update(
+            &mut checkpoints,
+            &mut starting_block,
+            subscriber.tree_state.clone(),
+            shared_identity_manager,
+            database.clone(),
+            subscriber.identity_committer.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(identity_manager.fetches(), vec![(105, Some(110))]);
+        assert_eq!(subscriber.tree_state.read().await.unwrap().next_leaf, 0);
+        assert!(database
+            .get_pending_identity_state(1, &Field::from(1234))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
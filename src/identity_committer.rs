@@ -1,22 +1,148 @@
 use crate::{
     contracts::{IdentityManager, SharedIdentityManager},
-    database::Database,
+    database::{Database, Error as DatabaseError, Transaction},
+    ethereum::{SentTransaction, TxError},
     identity_tree::{Hash, SharedTreeState},
     utils::spawn_or_abort,
 };
 use anyhow::{anyhow, Result as AnyhowResult};
-use std::sync::Arc;
+use ethers::types::{TransactionReceipt, H256};
+use futures::future::BoxFuture;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use thiserror::Error;
 use tokio::{
     select,
-    sync::{mpsc, mpsc::error::TrySendError, RwLock},
+    sync::{mpsc, mpsc::error::TrySendError, oneshot, watch, RwLock},
     task::JoinHandle,
+    time::sleep,
 };
 use tracing::{debug, error, info, instrument, warn};
 
+/// Target of the events logged on every state transition of an identity, from
+/// being enqueued to being confirmed on chain. The `transition` field of each
+/// event is one of `enqueued`, `batch_selected`, `tx_broadcast`, `mined` and
+/// `confirmed`. The `index` field holds the leaf index, which is only expected
+/// until `confirmed`, assuming identities are committed in order. It is left
+/// out where not even that is known.
+pub const LIFECYCLE_TARGET: &str = "signup_sequencer::identity_lifecycle";
+
+/// How long to wait before retrying identities deferred because gas was too
+/// expensive, or because their transaction was dropped.
+const DEFERRED_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the writer lease stays valid without being renewed, after which
+/// another instance may take over.
+const WRITER_LEASE_TTL: Duration = Duration::from_secs(60);
+
+/// How many times a reverting batch is split in half to isolate the
+/// commitments making it revert. Commitments in a reverting batch that is not
+/// split any further are marked as failed.
+const MAX_BISECTION_DEPTH: u32 = 8;
+
+/// How often the writer lease is renewed, and how often an instance standing
+/// by tries to take it.
+const WRITER_LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long before the writer lease expires its holder gives up on renewing
+/// it, to allow for clock skew between instances.
+const WRITER_LEASE_MARGIN: Duration = Duration::from_secs(10);
+
+/// The identities committed in one pass over the queue.
+#[derive(Default)]
+struct Committed {
+    transactions: Vec<H256>,
+    /// Whether identities were left queued to be retried later, because gas
+    /// is too expensive or their transaction was dropped.
+    deferred:     bool,
+}
+
+/// The outcome of submitting a batch of identities.
+enum Submission {
+    /// Sent in a transaction that is mined.
+    Mined(TransactionReceipt),
+    /// Not sent, because gas is too expensive for it to be mined.
+    Deferred,
+    /// Sent in the given transaction, which reverted.
+    Reverted(H256),
+    /// Sent in the given transaction, which left the mempool without being
+    /// mined, or was orphaned by a reorg and not mined again.
+    Dropped(H256),
+}
+
+/// Why a batch could not be committed. A rejected batch proof is kept apart
+/// from other transaction failures, as it points at the batch itself rather
+/// than at the chain.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("proof rejected on chain in transaction {0:?}")]
+    ProofRejectedOnChain(H256),
+
+    #[error(transparent)]
+    Transaction(#[from] TxError),
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+impl Error {
+    /// Classifies the failure of a transaction carrying a batch proof, where a
+    /// revert means the contract rejected the proof.
+    fn from_proof_transaction(error: TxError) -> Self {
+        match error {
+            TxError::Failed(receipt) => Self::ProofRejectedOnChain(receipt.transaction_hash),
+            error => Self::Transaction(error),
+        }
+    }
+
+    fn log(&self) {
+        match self {
+            Self::ProofRejectedOnChain(tx_hash) => {
+                error!(?tx_hash, "Batch proof rejected on chain.");
+            }
+            Self::Transaction(error) => {
+                error!(?error, "Failed to insert identity to contract.");
+            }
+            Self::Database(error) => {
+                error!(?error, "Failed to record identity submission.");
+            }
+        }
+    }
+}
+
+/// What a running committer works with, shared with its [`IdentityCommitter`].
+#[derive(Clone)]
+struct Worker {
+    database:         Arc<Database>,
+    identity_manager: SharedIdentityManager,
+    tree_state:       SharedTreeState,
+    pending:          Arc<PendingCounts>,
+    paused:           Arc<AtomicBool>,
+    in_flight:        Arc<Mutex<HashSet<Hash>>>,
+    batch_size:       usize,
+}
+
+/// Answers a flush request with the hashes of the sent transactions.
+type FlushReply = oneshot::Sender<AnyhowResult<Vec<H256>>>;
+
+/// Why committing under the writer lease stopped.
+enum Leased {
+    Lost,
+    Shutdown,
+}
+
 struct RunningInstance {
     #[allow(dead_code)]
     handle:          JoinHandle<()>,
     wake_up_sender:  mpsc::Sender<()>,
+    flush_sender:    mpsc::Sender<FlushReply>,
     shutdown_sender: mpsc::Sender<()>,
 }
 
@@ -40,6 +166,17 @@ impl RunningInstance {
         }
     }
 
+    async fn flush(&self) -> AnyhowResult<Vec<H256>> {
+        let (sender, receiver) = oneshot::channel();
+        self.flush_sender
+            .send(sender)
+            .await
+            .map_err(|_| anyhow!("Committer thread terminated unexpectedly."))?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("Committer stopped before completing the flush."))?
+    }
+
     async fn shutdown(self) -> AnyhowResult<()> {
         info!("Sending a shutdown signal to the committer.");
         // Ignoring errors here, since we have two options: either the channel is full,
@@ -53,17 +190,208 @@ impl RunningInstance {
     }
 }
 
+/// Makes sure only one instance sharing the database commits identities of a
+/// group at a time, through a lease in the database. Other instances stand by
+/// until the lease is released or expires.
+#[derive(Clone, Debug)]
+struct WriterLease {
+    group_id:       usize,
+    holder:         Arc<str>,
+    ttl:            Duration,
+    renew_interval: Duration,
+    margin:         Duration,
+}
+
+impl WriterLease {
+    fn new(group_id: usize) -> Self {
+        let holder = format!("{}-{:016x}", std::process::id(), rand::random::<u64>());
+        Self {
+            group_id,
+            holder: holder.into(),
+            ttl: WRITER_LEASE_TTL,
+            renew_interval: WRITER_LEASE_RENEW_INTERVAL,
+            margin: WRITER_LEASE_MARGIN,
+        }
+    }
+
+    #[cfg(test)]
+    const fn with_timing(
+        mut self,
+        ttl: Duration,
+        renew_interval: Duration,
+        margin: Duration,
+    ) -> Self {
+        self.ttl = ttl;
+        self.renew_interval = renew_interval;
+        self.margin = margin;
+        self
+    }
+
+    async fn acquire(&self, database: &Database) -> Result<bool, DatabaseError> {
+        database
+            .acquire_writer_lease(self.group_id, &self.holder, SystemTime::now(), self.ttl)
+            .await
+    }
+
+    async fn release(&self, database: &Database) -> Result<(), DatabaseError> {
+        database
+            .release_writer_lease(self.group_id, &self.holder)
+            .await
+    }
+
+    /// Renews the lease until it is lost, either to another instance or by
+    /// failing to renew it. Failing that, it is given up once the next attempt
+    /// could come too close to its expiry, so it is never still held once
+    /// another instance may take it.
+    async fn keep_renewed(&self, database: &Database) {
+        let give_up_after = self
+            .ttl
+            .saturating_sub(self.renew_interval)
+            .saturating_sub(self.margin);
+        let mut renewed_at = Instant::now();
+        loop {
+            sleep(self.renew_interval).await;
+            let attempted_at = Instant::now();
+            match self.acquire(database).await {
+                Ok(true) => renewed_at = attempted_at,
+                Ok(false) => return,
+                Err(error) => {
+                    warn!(?error, "Failed to renew the writer lease.");
+                    if renewed_at.elapsed() >= give_up_after {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps the lease renewed in the background until the returned handle is
+    /// aborted. The returned receiver turns `true` once the lease is lost.
+    fn hold(&self, database: Arc<Database>) -> (watch::Receiver<bool>, JoinHandle<()>) {
+        let (lost_sender, lost) = watch::channel(false);
+        let lease = self.clone();
+        let renewal = tokio::spawn(async move {
+            lease.keep_renewed(&database).await;
+            let _ = lost_sender.send(true);
+        });
+        (lost, renewal)
+    }
+}
+
+/// Why identities could not be queued.
+#[derive(Debug, Error)]
+pub enum EnqueueError {
+    #[error("pending identity queue is full")]
+    QueueFull,
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// A flush was requested from an instance standing by for the writer lease.
+#[derive(Debug, Error)]
+#[error("another instance holds the writer lease, nothing was committed")]
+pub struct StandingBy;
+
+/// Bounds the number of identities waiting to be committed in each group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueLimit {
+    /// Number of waiting identities at which inserts are refused.
+    pub max:       usize,
+    /// Number of waiting identities below which inserts are accepted again.
+    pub low_water: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct GroupQueue {
+    waiting: usize,
+    full:    bool,
+}
+
+/// Number of identities waiting to be committed per group. Kept in memory so
+/// inserts can be refused without counting the queue in the database.
+#[derive(Debug, Default)]
+struct PendingCounts(Mutex<HashMap<usize, GroupQueue>>);
+
+impl PendingCounts {
+    /// Replaces the counts with ones read from the database, which also
+    /// accounts for identities requeued outside of the committer.
+    fn reset(&self, counts: Vec<(usize, usize)>) {
+        let mut groups = self.0.lock().unwrap();
+        for queue in groups.values_mut() {
+            queue.waiting = 0;
+        }
+        for (group_id, waiting) in counts {
+            groups.entry(group_id).or_default().waiting = waiting;
+        }
+    }
+
+    fn remove(&self, group_id: usize, count: usize) {
+        let mut groups = self.0.lock().unwrap();
+        let queue = groups.entry(group_id).or_default();
+        queue.waiting = queue.waiting.saturating_sub(count);
+    }
+
+    fn remove_one(&self, group_id: usize) {
+        self.remove(group_id, 1);
+    }
+
+    /// Once full, a queue stays full until it drains below the low-water mark.
+    fn has_room(&self, group_id: usize, count: usize, limit: QueueLimit) -> bool {
+        let mut groups = self.0.lock().unwrap();
+        Self::update_full(groups.entry(group_id).or_default(), count, limit)
+    }
+
+    /// Counts `count` identities as waiting if there is room for them under
+    /// `limit`, checking and counting under one lock so that concurrent
+    /// inserts can not overfill the queue together.
+    fn reserve(&self, group_id: usize, count: usize, limit: Option<QueueLimit>) -> bool {
+        let mut groups = self.0.lock().unwrap();
+        let queue = groups.entry(group_id).or_default();
+        if let Some(limit) = limit {
+            if !Self::update_full(queue, count, limit) {
+                return false;
+            }
+        }
+        queue.waiting += count;
+        true
+    }
+
+    fn update_full(queue: &mut GroupQueue, count: usize, limit: QueueLimit) -> bool {
+        if queue.full && queue.waiting < limit.low_water {
+            queue.full = false;
+        }
+        if !queue.full && queue.waiting + count > limit.max {
+            queue.full = true;
+        }
+        !queue.full
+    }
+}
+
 /// A worker that commits identities to the blockchain.
 ///
 /// This uses the database to keep track of identities that need to be
-/// committed. It assumes that there's only one such worker spawned at
-/// a time. Spawning multiple worker threads will result in undefined behavior,
-/// including data duplication.
+/// committed. Only the worker holding the group's writer lease commits, so
+/// workers of several instances sharing a database never submit the same
+/// identities. The others stand by until the lease becomes free.
+///
+/// The hash of every broadcast transaction is recorded before waiting for it to
+/// be mined. On start, recorded transactions without a known outcome are looked
+/// up on chain and only resubmitted if they were dropped or reverted. This
+/// makes submission exactly-once, except for a crash between the broadcast and
+/// recording its hash, in which case the identity may be submitted twice.
 pub struct IdentityCommitter {
     instance:         RwLock<Option<RunningInstance>>,
     database:         Arc<Database>,
     identity_manager: SharedIdentityManager,
     tree_state:       SharedTreeState,
+    queue_limit:      Option<QueueLimit>,
+    pending:          Arc<PendingCounts>,
+    paused:           Arc<AtomicBool>,
+    /// Commitments selected for a transaction but not yet confirmed on chain.
+    in_flight:        Arc<Mutex<HashSet<Hash>>>,
+    batch_size:       usize,
+    lease:            WriterLease,
 }
 
 impl IdentityCommitter {
@@ -72,14 +400,38 @@ impl IdentityCommitter {
         contracts: SharedIdentityManager,
         tree_state: SharedTreeState,
     ) -> Self {
+        let lease = WriterLease::new(contracts.group_id().as_usize());
         Self {
             instance: RwLock::new(None),
             database,
             identity_manager: contracts,
             tree_state,
+            queue_limit: None,
+            pending: Arc::new(PendingCounts::default()),
+            paused: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            batch_size: 1,
+            lease,
         }
     }
 
+    /// Registers up to `batch_size` identities per transaction instead of one.
+    /// A batch that reverts is bisected, so the commitments making it revert
+    /// are marked as failed and the others are still committed.
+    #[must_use]
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Refuses inserts into groups with too many identities waiting to be
+    /// committed. See [`Self::queue_has_room`].
+    #[must_use]
+    pub fn with_queue_limit(mut self, limit: QueueLimit) -> Self {
+        self.queue_limit = Some(limit);
+        self
+    }
+
     #[instrument(level = "debug", skip_all)]
     pub async fn start(&self) {
         let mut instance = self.instance.write().await;
@@ -89,36 +441,55 @@ impl IdentityCommitter {
         }
         let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
         let (wake_up_sender, mut wake_up_receiver) = mpsc::channel(1);
+        let (flush_sender, mut flush_receiver) = mpsc::channel::<FlushReply>(1);
+        let worker = Worker {
+            database:         self.database.clone(),
+            identity_manager: self.identity_manager.clone(),
+            tree_state:       self.tree_state.clone(),
+            pending:          self.pending.clone(),
+            paused:           self.paused.clone(),
+            in_flight:        self.in_flight.clone(),
+            batch_size:       self.batch_size,
+        };
         let database = self.database.clone();
-        let identity_manager = self.identity_manager.clone();
-        let tree_state = self.tree_state.clone();
+        let lease = self.lease.clone();
         let handle = spawn_or_abort(async move {
             loop {
-                while let Some((group_id, commitment)) =
-                    database.get_oldest_unprocessed_identity().await?
+                if !Self::await_writer_lease(
+                    &database,
+                    &lease,
+                    &mut wake_up_receiver,
+                    &mut flush_receiver,
+                    &mut shutdown_receiver,
+                )
+                .await?
                 {
-                    if (shutdown_receiver.try_recv()).is_ok() {
-                        info!("Shutdown signal received, not processing remaining items.");
-                        return Ok(());
-                    }
-
-                    Self::commit_identity(
-                        &database,
-                        &*identity_manager,
-                        &tree_state,
-                        group_id,
-                        commitment,
-                    )
-                    .await?;
+                    return Ok(());
                 }
 
-                select! {
-                    _ = wake_up_receiver.recv() => {
-                        debug!("Woke up by a request.");
+                // Losing the lease is checked between batches, so a batch is
+                // never abandoned between broadcasting and recording its
+                // transaction. Transactions still in flight once it is lost
+                // are reconciled by the next holder.
+                let (mut lease_lost, renewal) = lease.hold(database.clone());
+                let result = Self::commit_while_leased(
+                    &worker,
+                    &mut lease_lost,
+                    &mut wake_up_receiver,
+                    &mut flush_receiver,
+                    &mut shutdown_receiver,
+                )
+                .await;
+                renewal.abort();
+                match result {
+                    Ok(Leased::Lost) => {
+                        error!("Lost the writer lease, standing by.");
+                        worker.in_flight.lock().unwrap().clear();
                     }
-                    _ = shutdown_receiver.recv() => {
-                        info!("Woke up by shutdown signal, exiting.");
-                        return Ok(());
+                    result => {
+                        lease.release(&database).await?;
+                        info!("Released the writer lease.");
+                        return result.map(|_| ());
                     }
                 }
             }
@@ -126,71 +497,676 @@ impl IdentityCommitter {
         *instance = Some(RunningInstance {
             handle,
             wake_up_sender,
+            flush_sender,
             shutdown_sender,
         });
     }
 
-    #[instrument(level = "info", skip_all)]
-    async fn commit_identity(
+    /// Commits queued identities whenever woken up, until the writer lease is
+    /// lost or a shutdown signal is received.
+    async fn commit_while_leased(
+        worker: &Worker,
+        lease_lost: &mut watch::Receiver<bool>,
+        wake_up_receiver: &mut mpsc::Receiver<()>,
+        flush_receiver: &mut mpsc::Receiver<FlushReply>,
+        shutdown_receiver: &mut mpsc::Receiver<()>,
+    ) -> AnyhowResult<Leased> {
+        Self::reconcile_submitted_identities(&worker.database, &*worker.identity_manager).await?;
+
+        loop {
+            let Some(committed) =
+                Self::commit_queued_identities(worker, lease_lost, shutdown_receiver).await?
+            else {
+                return Ok(Leased::Shutdown);
+            };
+            if *lease_lost.borrow() {
+                return Ok(Leased::Lost);
+            }
+
+            select! {
+                _ = wake_up_receiver.recv() => {
+                    debug!("Woke up by a request.");
+                }
+                Some(reply) = flush_receiver.recv() => {
+                    info!("Woke up by a flush request.");
+                    let Some(committed) =
+                        Self::commit_queued_identities(worker, lease_lost, shutdown_receiver)
+                            .await? else {
+                        return Ok(Leased::Shutdown);
+                    };
+                    // The requester may have stopped waiting, which is fine.
+                    let _ = reply.send(Ok(committed.transactions));
+                }
+                _ = sleep(DEFERRED_RETRY_INTERVAL), if committed.deferred => {
+                    info!("Retrying deferred identities.");
+                }
+                _ = lease_lost.changed() => {}
+                _ = shutdown_receiver.recv() => {
+                    info!("Woke up by shutdown signal, exiting.");
+                    return Ok(Leased::Shutdown);
+                }
+            }
+        }
+    }
+
+    /// Waits until the writer lease is acquired. Flush requests made meanwhile
+    /// fail with [`StandingBy`]. Returns `false` if interrupted by a shutdown
+    /// signal.
+    async fn await_writer_lease(
         database: &Database,
-        identity_manager: &(dyn IdentityManager + Send + Sync),
-        tree_state: &SharedTreeState,
-        group_id: usize,
-        commitment: Hash,
-    ) -> AnyhowResult<()> {
+        lease: &WriterLease,
+        wake_up_receiver: &mut mpsc::Receiver<()>,
+        flush_receiver: &mut mpsc::Receiver<FlushReply>,
+        shutdown_receiver: &mut mpsc::Receiver<()>,
+    ) -> AnyhowResult<bool> {
+        let mut standing_by = false;
+        loop {
+            if lease.acquire(database).await? {
+                info!(holder = %lease.holder, "Acquired the writer lease.");
+                return Ok(true);
+            }
+            if !standing_by {
+                warn!("Another instance holds the writer lease, standing by.");
+                standing_by = true;
+            }
+
+            let retry = sleep(lease.renew_interval);
+            tokio::pin!(retry);
+            loop {
+                select! {
+                    () = &mut retry => break,
+                    _ = wake_up_receiver.recv() => {}
+                    Some(reply) = flush_receiver.recv() => {
+                        let _ = reply.send(Err(StandingBy.into()));
+                    }
+                    _ = shutdown_receiver.recv() => return Ok(false),
+                }
+            }
+        }
+    }
+
+    /// Commits all queued identities in batches, until paused, gas is too
+    /// expensive or the writer lease is lost. Returns `None` if interrupted by
+    /// a shutdown signal.
+    async fn commit_queued_identities(
+        worker: &Worker,
+        lease_lost: &watch::Receiver<bool>,
+        shutdown_receiver: &mut mpsc::Receiver<()>,
+    ) -> AnyhowResult<Option<Committed>> {
+        let database = &*worker.database;
+        worker
+            .pending
+            .reset(database.count_unprocessed_identities().await?);
+
+        let mut committed = Committed::default();
+        while let Some((group_id, batch)) = database
+            .get_oldest_unprocessed_identities(worker.batch_size)
+            .await?
         {
-            let tree = tree_state.read().await.unwrap_or_else(|e| {
-                error!(?e, "Failed to obtain tree lock in check_leaves.");
-                panic!("Sequencer potentially deadlocked, terminating.");
-            });
-            let is_duplicate = tree.merkle_tree.leaves()[..tree.next_leaf].contains(&commitment);
-            if is_duplicate {
-                warn!(
+            if (shutdown_receiver.try_recv()).is_ok() {
+                info!("Shutdown signal received, not processing remaining items.");
+                return Ok(None);
+            }
+            if worker.paused.load(Ordering::SeqCst) {
+                info!("Committer paused, leaving remaining items queued.");
+                break;
+            }
+            if *lease_lost.borrow() {
+                info!("Writer lease lost, leaving remaining items queued.");
+                break;
+            }
+
+            let batch = Self::drop_duplicates(worker, group_id, batch).await?;
+            let batch = Self::drop_overflow(worker, group_id, batch).await?;
+            if batch.is_empty() {
+                continue;
+            }
+            let batch_committed = Self::commit_batch(worker, group_id, batch).await?;
+            committed.transactions.extend(batch_committed.transactions);
+            if batch_committed.deferred {
+                info!("Leaving remaining items queued to retry later.");
+                committed.deferred = true;
+                break;
+            }
+        }
+        Ok(Some(committed))
+    }
+
+    /// Removes the identities of `batch` that are already in the tree from the
+    /// queue, returning the others.
+    async fn drop_duplicates(
+        worker: &Worker,
+        group_id: usize,
+        batch: Vec<Hash>,
+    ) -> AnyhowResult<Vec<Hash>> {
+        let duplicates = {
+            let tree = worker.tree_state.read_retrying().await;
+            let leaves = &tree.merkle_tree.leaves()[..tree.next_leaf];
+            batch
+                .iter()
+                .filter(|commitment| leaves.contains(commitment))
+                .copied()
+                .collect::<HashSet<_>>()
+        };
+        for commitment in &duplicates {
+            warn!(
+                ?commitment,
+                "Attempted to insert duplicate identity, skipping"
+            );
+            worker
+                .database
+                .delete_pending_identity(group_id, commitment)
+                .await?;
+            worker.pending.remove_one(group_id);
+        }
+        Ok(batch
+            .into_iter()
+            .filter(|commitment| !duplicates.contains(commitment))
+            .collect())
+    }
+
+    /// Marks the identities of `batch` that no longer fit in the tree as
+    /// failed, returning the others. Identities submitted but not yet in the
+    /// tree count as taking up a leaf.
+    async fn drop_overflow(
+        worker: &Worker,
+        group_id: usize,
+        mut batch: Vec<Hash>,
+    ) -> AnyhowResult<Vec<Hash>> {
+        let room = {
+            let tree = worker.tree_state.read_retrying().await;
+            let in_flight = worker.in_flight.lock().unwrap().len();
+            tree.free_leaves()
+                .saturating_sub(in_flight)
+                .min(batch.len())
+        };
+        if room == batch.len() {
+            return Ok(batch);
+        }
+
+        let overflow = batch.split_off(room);
+        error!(?overflow, "Tree is full, giving up on identities.");
+        let count = overflow.len();
+        worker
+            .database
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    for commitment in &overflow {
+                        tx.mark_identity_failed(group_id, commitment, "Tree is full")
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+        for _ in 0..count {
+            worker.pending.remove_one(group_id);
+        }
+        Ok(batch)
+    }
+
+    /// Commits `batch` in one transaction. If it reverts, each half is
+    /// committed on its own, until the commitments making it revert are
+    /// isolated or [`MAX_BISECTION_DEPTH`] is reached. Commitments in reverting
+    /// batches that are not split further are marked as failed.
+    ///
+    /// Transactions carrying a batch proof are not bisected, as the proof
+    /// covers the whole batch. A revert fails with
+    /// [`Error::ProofRejectedOnChain`] instead.
+    #[instrument(level = "info", skip(worker, batch), fields(size = batch.len()))]
+    async fn commit_batch(
+        worker: &Worker,
+        group_id: usize,
+        batch: Vec<Hash>,
+    ) -> AnyhowResult<Committed> {
+        let database = &*worker.database;
+        let mut committed = Committed::default();
+        // Batches still to be submitted, the next one last.
+        let mut remaining = vec![(batch, 0)];
+        while let Some((commitments, depth)) = remaining.pop() {
+            // Identities in flight are inserted before this batch.
+            let first_index = {
+                let tree = worker.tree_state.read_retrying().await;
+                tree.next_leaf + worker.in_flight.lock().unwrap().len()
+            };
+            for (offset, commitment) in commitments.iter().enumerate() {
+                info!(
+                    target: LIFECYCLE_TARGET,
+                    transition = "batch_selected",
+                    group_id,
                     ?commitment,
-                    "Attempted to insert duplicate identity, skipping"
+                    index = first_index + offset,
+                    "Identity selected for submission."
                 );
-                database
-                    .delete_pending_identity(group_id, &commitment)
+            }
+            worker
+                .in_flight
+                .lock()
+                .unwrap()
+                .extend(commitments.iter().copied());
+
+            let submission = Self::submit_identities(
+                database,
+                &*worker.identity_manager,
+                group_id,
+                &commitments,
+                first_index,
+            )
+            .await;
+            if !matches!(submission, Ok(Submission::Mined(_))) {
+                let mut in_flight = worker.in_flight.lock().unwrap();
+                for commitment in &commitments {
+                    in_flight.remove(commitment);
+                }
+            }
+
+            match submission? {
+                Submission::Mined(receipt) => {
+                    let count = commitments.len();
+                    Self::record_receipt(
+                        database,
+                        group_id,
+                        commitments,
+                        Some(first_index),
+                        &receipt,
+                    )
                     .await?;
-                return Ok(());
+                    for _ in 0..count {
+                        worker.pending.remove_one(group_id);
+                    }
+                    // ethereum_subscriber module takes over from now. Once identity is found
+                    // in a confirmed block, it'll update the merkle tree and remove job from
+                    // pending_identities queue.
+                    committed.transactions.push(receipt.transaction_hash);
+                }
+                Submission::Deferred => {
+                    committed.deferred = true;
+                    break;
+                }
+                Submission::Dropped(hash) => {
+                    warn!(
+                        ?hash,
+                        ?commitments,
+                        "Transaction dropped, requeueing identities."
+                    );
+                    Self::clear_submissions(database, group_id, commitments).await?;
+                    committed.deferred = true;
+                    break;
+                }
+                Submission::Reverted(hash)
+                    if commitments.len() == 1 || depth >= MAX_BISECTION_DEPTH =>
+                {
+                    error!(
+                        ?hash,
+                        ?commitments,
+                        "Transaction reverted, giving up on identities."
+                    );
+                    let failure = format!("Transaction {hash:?} reverted");
+                    let count = commitments.len();
+                    database
+                        .with_transaction(move |tx| {
+                            Box::pin(async move {
+                                for commitment in &commitments {
+                                    tx.mark_identity_failed(group_id, commitment, &failure)
+                                        .await?;
+                                }
+                                Ok(())
+                            })
+                        })
+                        .await?;
+                    for _ in 0..count {
+                        worker.pending.remove_one(group_id);
+                    }
+                }
+                Submission::Reverted(hash) => {
+                    warn!(
+                        ?hash,
+                        size = commitments.len(),
+                        depth,
+                        "Transaction reverted, bisecting."
+                    );
+                    Self::clear_submissions(database, group_id, commitments.clone()).await?;
+                    let mut first = commitments;
+                    let second = first.split_off(first.len() / 2);
+                    remaining.push((second, depth + 1));
+                    remaining.push((first, depth + 1));
+                }
             }
         }
+        Ok(committed)
+    }
 
+    /// Sends the transaction registering `commitments`, expected at leaves
+    /// from `first_index` on, and waits for it to be mined. Returns
+    /// [`Submission::Deferred`] without sending if gas is too expensive.
+    async fn submit_identities(
+        database: &Database,
+        identity_manager: &(dyn IdentityManager + Send + Sync),
+        group_id: usize,
+        commitments: &[Hash],
+        first_index: usize,
+    ) -> Result<Submission, Error> {
         // Send Semaphore transaction
-        let receipt = identity_manager
-            .register_identities(vec![commitment])
+        let transaction = match identity_manager
+            .register_identities(commitments.to_vec())
+            .await
+        {
+            Ok(transaction) => transaction,
+            Err(TxError::GasPriceTooHigh { required, max }) => {
+                warn!(?commitments, %required, %max, "Deferring identities, gas too expensive.");
+                return Ok(Submission::Deferred);
+            }
+            Err(e) => {
+                let error = Error::from(e);
+                error.log();
+                return Err(error);
+            }
+        };
+
+        // Record the transaction before waiting, so a restart can find it. The
+        // identities were sent together, so they are recorded together.
+        for (offset, commitment) in commitments.iter().enumerate() {
+            info!(
+                target: LIFECYCLE_TARGET,
+                transition = "tx_broadcast",
+                group_id,
+                ?commitment,
+                index = first_index + offset,
+                tx_hash = ?transaction.hash,
+                nonce = transaction.nonce,
+                "Identity transaction broadcast."
+            );
+        }
+        let submitted = commitments.to_vec();
+        let tx_hash = format!("{:?}", transaction.hash);
+        let nonce = transaction.nonce;
+        database
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    for commitment in &submitted {
+                        tx.mark_identity_submitted(group_id, commitment, &tx_hash, nonce)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+
+        match identity_manager.await_transaction(transaction).await {
+            Ok(receipt) => Ok(Submission::Mined(receipt)),
+            Err(TxError::Dropped(hash)) => Ok(Submission::Dropped(hash)),
+            Err(TxError::Failed(receipt)) if !identity_manager.submits_proofs() => {
+                Ok(Submission::Reverted(receipt.transaction_hash))
+            }
+            Err(e) => {
+                let error = Error::from_proof_transaction(e);
+                error.log();
+                Err(error)
+            }
+        }
+    }
+
+    /// Forgets the transaction `commitments` were sent in, all or none of
+    /// them, so they are submitted again.
+    async fn clear_submissions(
+        database: &Database,
+        group_id: usize,
+        commitments: Vec<Hash>,
+    ) -> Result<(), DatabaseError> {
+        database
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    for commitment in &commitments {
+                        tx.clear_identity_submission(group_id, commitment).await?;
+                    }
+                    Ok(())
+                })
+            })
             .await
-            .map_err(|e| {
-                error!(?e, "Failed to insert identity to contract.");
-                e
-            })?;
+    }
 
+    /// Records that `commitments` were mined with `receipt`, all or none of
+    /// them. They are expected at leaves from `first_index` on, if known.
+    async fn record_receipt(
+        database: &Database,
+        group_id: usize,
+        commitments: Vec<Hash>,
+        first_index: Option<usize>,
+        receipt: &TransactionReceipt,
+    ) -> Result<(), DatabaseError> {
         let block = receipt
             .block_number
             .expect("Transaction is mined, block number must be present.");
+        let tx_hash = format!("{:?}", receipt.transaction_hash);
 
-        info!("Identity submitted in block {}.", block);
+        for (offset, commitment) in commitments.iter().enumerate() {
+            info!(
+                target: LIFECYCLE_TARGET,
+                transition = "mined",
+                group_id,
+                ?commitment,
+                index = first_index.map(|index| index + offset),
+                tx_hash = ?receipt.transaction_hash,
+                block = block.as_u64(),
+                "Identity transaction mined."
+            );
+        }
         database
-            .mark_identity_inserted(group_id, &commitment, block.as_usize())
-            .await?;
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    for commitment in &commitments {
+                        tx.mark_identity_inserted(group_id, commitment, block.as_usize(), &tx_hash)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await
+    }
 
-        // ethereum_subscriber module takes over from now. Once identity is found in a
-        // confirmed block, it'll update the merkle tree and remove job from
-        // pending_identities queue.
+    /// Resolves transactions that were broadcast by a previous run but whose
+    /// outcome was never recorded. Mined transactions are recorded, dropped or
+    /// reverted ones are queued for submission again.
+    #[instrument(level = "info", skip_all)]
+    async fn reconcile_submitted_identities(
+        database: &Database,
+        identity_manager: &(dyn IdentityManager + Send + Sync),
+    ) -> AnyhowResult<()> {
+        for submitted in database.get_submitted_identities().await? {
+            let hash = H256::from_str(&submitted.transaction_hash)?;
+            info!(
+                ?hash,
+                nonce = ?submitted.nonce,
+                commitment = ?submitted.commitment,
+                "Reconciling in-flight transaction."
+            );
 
+            let transaction = SentTransaction {
+                hash,
+                nonce: submitted.nonce.unwrap_or_default(),
+                gas_limit: None,
+            };
+            match identity_manager.await_transaction(transaction).await {
+                Ok(receipt) => {
+                    // Other identities may have been inserted since, so where
+                    // this one lands is not known.
+                    Self::record_receipt(
+                        database,
+                        submitted.group_id,
+                        vec![submitted.commitment],
+                        None,
+                        &receipt,
+                    )
+                    .await?;
+                }
+                Err(TxError::Dropped(_) | TxError::Failed(_)) => {
+                    warn!(
+                        ?hash,
+                        "In-flight transaction did not succeed, resubmitting."
+                    );
+                    database
+                        .clear_identity_submission(submitted.group_id, &submitted.commitment)
+                        .await?;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
         Ok(())
     }
 
+    /// Queues `commitments` for submission, all or none of them, and wakes up
+    /// the committer.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::DuplicateCommitment`] if any of the
+    /// commitments is already pending or in flight, and
+    /// [`EnqueueError::QueueFull`] if they do not fit in the queue limit.
+    pub async fn enqueue(
+        &self,
+        group_id: usize,
+        commitments: &[Hash],
+    ) -> Result<(), EnqueueError> {
+        self.enqueue_and(group_id, commitments, |_| Box::pin(async { Ok(()) }))
+            .await
+    }
+
+    /// Like [`Self::enqueue`], but also runs `writes` in the transaction that
+    /// queues the commitments, after queueing them, and returns their result.
+    pub async fn enqueue_and<T, F>(
+        &self,
+        group_id: usize,
+        commitments: &[Hash],
+        writes: F,
+    ) -> Result<T, EnqueueError>
+    where
+        F: for<'t> FnOnce(&'t mut Transaction) -> BoxFuture<'t, Result<T, DatabaseError>>
+            + Send
+            + 'static,
+        T: Send + 'static,
+    {
+        if commitments
+            .iter()
+            .any(|commitment| self.is_in_flight(commitment))
+        {
+            return Err(DatabaseError::DuplicateCommitment.into());
+        }
+        if !self
+            .pending
+            .reserve(group_id, commitments.len(), self.queue_limit)
+        {
+            return Err(EnqueueError::QueueFull);
+        }
+        let next_leaf = self.tree_state.read_retrying().await.next_leaf;
+        let queued = commitments.to_vec();
+        let result = self
+            .database
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    tx.insert_pending_identities(group_id, &queued).await?;
+                    // Queue position of the first commitment, for the expected
+                    // index.
+                    let position = match queued.first() {
+                        Some(first) => tx.pending_commitments_up_to(group_id, first).await?.len(),
+                        None => 0,
+                    };
+                    Ok((position.saturating_sub(1), writes(tx).await?))
+                })
+            })
+            .await;
+        let (position, result) = match result {
+            Ok(result) => result,
+            Err(error) => {
+                self.pending.remove(group_id, commitments.len());
+                return Err(error.into());
+            }
+        };
+        for (offset, commitment) in commitments.iter().enumerate() {
+            info!(
+                target: LIFECYCLE_TARGET,
+                transition = "enqueued",
+                group_id,
+                ?commitment,
+                index = next_leaf + position + offset,
+                "Identity enqueued."
+            );
+        }
+        self.notify_queued().await;
+        Ok(result)
+    }
+
+    /// Returns whether `commitment` was selected for a transaction that is not
+    /// yet confirmed. Such commitments may already have left the queue.
+    #[must_use]
+    pub fn is_in_flight(&self, commitment: &Hash) -> bool {
+        self.in_flight.lock().unwrap().contains(commitment)
+    }
+
+    /// Forgets an in-flight commitment once its insertion is confirmed.
+    pub fn confirmed(&self, commitment: &Hash) {
+        self.in_flight.lock().unwrap().remove(commitment);
+    }
+
+    /// Returns whether `count` more identities can be queued in `group_id`
+    /// under the queue limit, if any.
+    #[must_use]
+    pub fn queue_has_room(&self, group_id: usize, count: usize) -> bool {
+        self.queue_limit
+            .map_or(true, |limit| self.pending.has_room(group_id, count, limit))
+    }
+
+    /// Wakes up the committer. A committer that is not running, such as on
+    /// read-only instances, finds the queue when it is started.
     pub async fn notify_queued(&self) {
+        let instance = self.instance.read().await;
+        let Some(instance) = instance.as_ref() else {
+            debug!("Committer not running, not waking it up.");
+            return;
+        };
         // Escalate all errors to panics. In the future could perform some
         // restart procedure here.
+        instance.wake_up().unwrap();
+    }
+
+    /// Stops broadcasting transactions. Identities are still accepted and
+    /// queued, and are committed after [`Self::resume`].
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            warn!("Identity committer paused.");
+        }
+    }
+
+    /// Resumes broadcasting transactions and commits everything queued while
+    /// paused.
+    pub async fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!("Identity committer resumed.");
+            self.notify_queued().await;
+        }
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Commits all currently queued identities without waiting for a wake-up,
+    /// returning the hashes of the sent transactions. Runs on the committer
+    /// thread, so it never races the regular processing. Commits nothing while
+    /// paused.
+    ///
+    /// # Errors
+    ///
+    /// Will return an Error if the committer is not running or fails, or
+    /// [`StandingBy`] if another instance holds the writer lease.
+    pub async fn flush(&self) -> AnyhowResult<Vec<H256>> {
         self.instance
             .read()
             .await
             .as_ref()
-            .expect("Committer not running, terminating.")
-            .wake_up()
-            .unwrap();
+            .ok_or_else(|| anyhow!("Committer not running."))?
+            .flush()
+            .await
     }
 
     /// # Errors
@@ -207,3 +1183,653 @@ impl IdentityCommitter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        contracts::mock::MockIdentityManager,
+        database,
+        ethereum_subscriber::EthereumSubscriber,
+        identity_tree::{poseidon_tree_depth, TreeState},
+        timed_rw_lock::TimedRwLock,
+    };
+    use clap::Parser;
+    use ruint::uint;
+    use tracing_test::traced_test;
+
+    async fn test_database() -> Arc<Database> {
+        Arc::new(
+            Database::new(database::Options::try_parse_from([""]).unwrap())
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn test_tree_state(identity_manager: &MockIdentityManager, depth: usize) -> SharedTreeState {
+        Arc::new(TimedRwLock::new(
+            Duration::from_secs(10),
+            TreeState::new(depth, identity_manager.initial_leaf_value()),
+        ))
+    }
+
+    /// A database, `identity_manager` and an empty tree of its depth.
+    async fn harness(
+        identity_manager: MockIdentityManager,
+    ) -> (Arc<Database>, Arc<MockIdentityManager>, SharedTreeState) {
+        let identity_manager = Arc::new(identity_manager);
+        let tree_state = test_tree_state(&identity_manager, identity_manager.poseidon_tree_depth());
+        (test_database().await, identity_manager, tree_state)
+    }
+
+    fn worker(
+        database: &Arc<Database>,
+        identity_manager: &Arc<MockIdentityManager>,
+        tree_state: SharedTreeState,
+        batch_size: usize,
+    ) -> Worker {
+        Worker {
+            database:         database.clone(),
+            identity_manager: identity_manager.clone(),
+            tree_state,
+            pending:          Arc::new(PendingCounts::default()),
+            paused:           Arc::new(AtomicBool::new(false)),
+            in_flight:        Arc::new(Mutex::new(HashSet::new())),
+            batch_size,
+        }
+    }
+
+    async fn submitted_identity(database: &Database, commitment: &Hash) {
+        database
+            .insert_pending_identity(1, commitment)
+            .await
+            .unwrap();
+        let transaction_hash = format!("{:?}", H256::repeat_byte(1));
+        database
+            .mark_identity_submitted(1, commitment, &transaction_hash, 7)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn mined_in_flight_transaction_is_not_resubmitted() {
+        let database = test_database().await;
+        let commitment = uint!(0x1234_U256);
+        submitted_identity(&database, &commitment).await;
+        let identity_manager = MockIdentityManager::mining_at(Some(42));
+
+        IdentityCommitter::reconcile_submitted_identities(&database, &identity_manager)
+            .await
+            .unwrap();
+
+        assert_eq!(identity_manager.registered().len(), 0);
+        let state = database
+            .get_pending_identity_state(1, &commitment)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.mined_in_block, Some(42));
+        assert!(database
+            .get_oldest_unprocessed_identity()
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn dropped_in_flight_transaction_is_requeued() {
+        let database = test_database().await;
+        let commitment = uint!(0x1234_U256);
+        submitted_identity(&database, &commitment).await;
+        let identity_manager = MockIdentityManager::mining_at(None);
+
+        IdentityCommitter::reconcile_submitted_identities(&database, &identity_manager)
+            .await
+            .unwrap();
+
+        let state = database
+            .get_pending_identity_state(1, &commitment)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.transaction_hash, None);
+        assert_eq!(
+            database.get_oldest_unprocessed_identity().await.unwrap(),
+            Some((1, commitment))
+        );
+    }
+
+    #[tokio::test]
+    async fn flush_commits_queued_identities() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state);
+        committer.start().await;
+        // Make sure the committer is idle before queueing.
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+
+        // Queue without waking up the committer.
+        let commitments = [uint!(0x1234_U256), uint!(0x5678_U256)];
+        for commitment in &commitments {
+            database
+                .insert_pending_identity(1, commitment)
+                .await
+                .unwrap();
+        }
+
+        let transactions = committer.flush().await.unwrap();
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(identity_manager.registered().len(), 2);
+        for commitment in &commitments {
+            let state = database
+                .get_pending_identity_state(1, commitment)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(state.mined_in_block, Some(42));
+        }
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_writer_lease_holder_commits() {
+        let database = test_database().await;
+        let committer = |identity_manager: &Arc<MockIdentityManager>| {
+            let tree_state =
+                test_tree_state(identity_manager, identity_manager.poseidon_tree_depth());
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state)
+        };
+        let writer_manager = Arc::new(MockIdentityManager::mining_at(Some(42)));
+        let writer = committer(&writer_manager);
+        writer.start().await;
+        // The flush is answered once the lease is held.
+        assert_eq!(writer.flush().await.unwrap(), vec![]);
+
+        let standby_manager = Arc::new(MockIdentityManager::mining_at(Some(42)));
+        let standby = committer(&standby_manager);
+        standby.start().await;
+        let commitment = uint!(0x1234_U256);
+        standby.enqueue(1, &[commitment]).await.unwrap();
+        assert!(standby.flush().await.unwrap_err().is::<StandingBy>());
+        assert!(standby_manager.registered().is_empty());
+
+        assert_eq!(writer.flush().await.unwrap().len(), 1);
+        assert_eq!(writer_manager.registered().len(), 1);
+        assert!(standby_manager.registered().is_empty());
+
+        writer.shutdown().await.unwrap();
+        standby.shutdown().await.unwrap();
+        // The lease is released on shutdown.
+        assert!(database
+            .acquire_writer_lease(1, "next", SystemTime::now(), WRITER_LEASE_TTL)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn losing_the_writer_lease_stops_before_the_next_batch() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        let worker = worker(&database, &identity_manager, tree_state, 1);
+        let commitment = uint!(0x1234_U256);
+        database
+            .insert_pending_identity(1, &commitment)
+            .await
+            .unwrap();
+        let (_shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+        let (_lost_sender, lease_lost) = watch::channel(true);
+
+        let committed = IdentityCommitter::commit_queued_identities(
+            &worker,
+            &lease_lost,
+            &mut shutdown_receiver,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert!(committed.transactions.is_empty());
+        assert!(identity_manager.registered().is_empty());
+        assert!(worker.in_flight.lock().unwrap().is_empty());
+        assert!(database
+            .get_oldest_unprocessed_identity()
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn unrenewed_writer_lease_is_given_up_before_it_expires() {
+        let path =
+            std::env::temp_dir().join(format!("signup-sequencer-lease-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let open = || async {
+            Database::new(
+                database::Options::try_parse_from(["", "--database", url.as_str()]).unwrap(),
+            )
+            .await
+            .unwrap()
+        };
+        let writer_database = Arc::new(open().await);
+        let standby_database = open().await;
+        let timing = |lease: WriterLease| {
+            lease.with_timing(
+                Duration::from_secs(4),
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+            )
+        };
+        let writer = timing(WriterLease::new(1));
+        let standby = timing(WriterLease::new(1));
+        assert!(writer.acquire(&writer_database).await.unwrap());
+        let (lost, renewal) = writer.hold(writer_database.clone());
+
+        // The writer can no longer renew the lease.
+        writer_database.close().await;
+        tokio::time::timeout(Duration::from_secs(4), renewal)
+            .await
+            .expect("Writer lease was not given up")
+            .unwrap();
+        assert!(*lost.borrow());
+
+        // It was given up while the other instance could not take it yet.
+        assert!(!standby.acquire(&standby_database).await.unwrap());
+        let mut acquired = false;
+        for _ in 0..50 {
+            if standby.acquire(&standby_database).await.unwrap() {
+                acquired = true;
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(acquired);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn reverting_commitment_is_isolated_from_its_batch() {
+        let poisoned = uint!(0x3333_U256);
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42)).reverting(poisoned)).await;
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state)
+                .with_batch_size(4);
+        committer.start().await;
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+
+        let commitments = [
+            uint!(0x1111_U256),
+            uint!(0x2222_U256),
+            poisoned,
+            uint!(0x4444_U256),
+        ];
+        committer.enqueue(1, &commitments).await.unwrap();
+        committer.flush().await.unwrap();
+
+        // The whole batch, both halves and both quarters of the second half.
+        let registered = identity_manager.registered();
+        assert_eq!(registered.len(), 5);
+        assert_eq!(registered[0], commitments.to_vec());
+        assert_eq!(registered[1], commitments[..2].to_vec());
+        assert_eq!(registered[4], commitments[3..].to_vec());
+        for commitment in commitments
+            .iter()
+            .filter(|commitment| **commitment != poisoned)
+        {
+            let state = database
+                .get_pending_identity_state(1, commitment)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(state.mined_in_block, Some(42));
+            assert_eq!(state.failure, None);
+        }
+        let state = database
+            .get_pending_identity_state(1, &poisoned)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(state.failure.is_some());
+        assert_eq!(state.transaction_hash, None);
+        // Failed identities are not submitted again.
+        assert!(database
+            .get_oldest_unprocessed_identity()
+            .await
+            .unwrap()
+            .is_none());
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reverted_proof_transaction_is_rejected_on_chain() {
+        let poisoned = uint!(0x3333_U256);
+        let (database, identity_manager, tree_state) = harness(
+            MockIdentityManager::mining_at(Some(42))
+                .reverting(poisoned)
+                .submitting_proofs(),
+        )
+        .await;
+        let worker = worker(&database, &identity_manager, tree_state, 2);
+        let commitments = vec![uint!(0x1111_U256), poisoned];
+        database
+            .insert_pending_identities(1, &commitments)
+            .await
+            .unwrap();
+
+        let error = IdentityCommitter::commit_batch(&worker, 1, commitments.clone())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::ProofRejectedOnChain(hash)) if *hash == H256::from_low_u64_be(1)
+        ));
+        // The proof covers the whole batch, so it is not bisected.
+        assert_eq!(identity_manager.registered(), vec![commitments]);
+        assert!(worker.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn paused_committer_keeps_identities_queued_until_resumed() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state);
+        committer.start().await;
+        committer.pause();
+        assert!(committer.is_paused());
+
+        let commitments = [uint!(0x1234_U256), uint!(0x5678_U256)];
+        committer.enqueue(1, &commitments).await.unwrap();
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+        assert!(identity_manager.registered().is_empty());
+        assert_eq!(
+            database.get_oldest_unprocessed_identity().await.unwrap(),
+            Some((1, commitments[0]))
+        );
+
+        committer.resume().await;
+        assert!(!committer.is_paused());
+        // The resume wakes the committer up; the flush waits for it to finish.
+        committer.flush().await.unwrap();
+        assert_eq!(identity_manager.registered().len(), 2);
+        assert!(database
+            .get_oldest_unprocessed_identity()
+            .await
+            .unwrap()
+            .is_none());
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn in_flight_commitment_is_not_queued_twice() {
+        let (database, identity_manager, tree_state) = harness(
+            MockIdentityManager::mining_at(Some(42))
+                .emitting_registrations()
+                .holding_transactions(),
+        )
+        .await;
+        let committer = Arc::new(IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        ));
+        committer.start().await;
+
+        let commitment = uint!(0x1234_U256);
+        committer.enqueue(1, &[commitment]).await.unwrap();
+        while identity_manager.registered().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(committer.is_in_flight(&commitment));
+
+        // Even without the pending row, the twin is refused while in flight.
+        database
+            .delete_pending_identity(1, &commitment)
+            .await
+            .unwrap();
+        assert!(matches!(
+            committer.enqueue(1, &[commitment]).await,
+            Err(EnqueueError::Database(DatabaseError::DuplicateCommitment))
+        ));
+
+        identity_manager.release_transactions();
+        committer.flush().await.unwrap();
+        assert!(committer.is_in_flight(&commitment));
+        EthereumSubscriber::new(
+            1,
+            database.clone(),
+            identity_manager.clone(),
+            tree_state,
+            committer.clone(),
+        )
+        .process_initial_events()
+        .await
+        .unwrap();
+        assert!(!committer.is_in_flight(&commitment));
+        assert_eq!(identity_manager.registered().len(), 1);
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn identities_are_deferred_while_gas_is_too_expensive() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state);
+        committer.start().await;
+
+        identity_manager.set_gas_price_too_high(true);
+        let commitment = uint!(0x1234_U256);
+        committer.enqueue(1, &[commitment]).await.unwrap();
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+        assert!(identity_manager.registered().is_empty());
+        assert!(!committer.is_in_flight(&commitment));
+        assert_eq!(
+            database.get_oldest_unprocessed_identity().await.unwrap(),
+            Some((1, commitment))
+        );
+
+        identity_manager.set_gas_price_too_high(false);
+        assert_eq!(committer.flush().await.unwrap().len(), 1);
+        assert_eq!(identity_manager.registered().len(), 1);
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn identities_of_dropped_transaction_are_requeued() {
+        // Transactions are never mined, like one orphaned by a reorg that
+        // leaves the mempool.
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(None)).await;
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state);
+        committer.start().await;
+
+        let commitment = uint!(0x1234_U256);
+        committer.enqueue(1, &[commitment]).await.unwrap();
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+        assert_eq!(identity_manager.registered().len(), 1);
+        assert!(!committer.is_in_flight(&commitment));
+        let state = database
+            .get_pending_identity_state(1, &commitment)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.transaction_hash, None);
+        assert_eq!(
+            database.get_oldest_unprocessed_identity().await.unwrap(),
+            Some((1, commitment))
+        );
+
+        // The committer keeps running and submits the identity again.
+        assert_eq!(committer.flush().await.unwrap(), vec![]);
+        assert_eq!(identity_manager.registered().len(), 2);
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_queue_refuses_inserts_until_drained() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        let committer = IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        )
+        .with_queue_limit(QueueLimit {
+            max:       2,
+            low_water: 1,
+        });
+        committer.start().await;
+
+        // Holding the tree keeps the committer from draining the queue.
+        let tree = tree_state.write().await.unwrap();
+        assert!(committer.queue_has_room(1, 2));
+        let (first, second) = tokio::join!(
+            committer.enqueue(1, &[uint!(0x1234_U256), uint!(0x5678_U256)]),
+            committer.enqueue(1, &[uint!(0x4321_U256), uint!(0x8765_U256)]),
+        );
+        // Room is taken when it is checked, so only one of them fits.
+        assert_eq!(usize::from(first.is_ok()) + usize::from(second.is_ok()), 1);
+        assert!(matches!(first.and(second), Err(EnqueueError::QueueFull)));
+        assert!(!committer.queue_has_room(1, 1));
+        drop(tree);
+
+        committer.flush().await.unwrap();
+        assert_eq!(identity_manager.registered().len(), 2);
+        assert!(committer.queue_has_room(1, 1));
+        committer.enqueue(1, &[uint!(0x9abc_U256)]).await.unwrap();
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn identities_beyond_tree_capacity_fail() {
+        let (database, identity_manager, _) =
+            harness(MockIdentityManager::mining_at(Some(42))).await;
+        // A tree of two leaves.
+        let tree_state = test_tree_state(&identity_manager, poseidon_tree_depth(1));
+        let committer =
+            IdentityCommitter::new(database.clone(), identity_manager.clone(), tree_state);
+        committer.start().await;
+
+        let commitments = [uint!(0x1111_U256), uint!(0x2222_U256), uint!(0x3333_U256)];
+        committer.enqueue(1, &commitments).await.unwrap();
+        committer.flush().await.unwrap();
+        assert_eq!(identity_manager.registered(), vec![
+            commitments[..2].to_vec()
+        ]);
+        let state = database
+            .get_pending_identity_state(1, &commitments[2])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.failure.as_deref(), Some("Tree is full"));
+
+        // Confirming an identity queued later does not queue it again.
+        let later = uint!(0x5555_U256);
+        database.insert_pending_identity(1, &later).await.unwrap();
+        database
+            .set_queued_at(&commitments[2], "2000-01-01 00:00:00")
+            .await;
+        database.set_queued_at(&later, "2000-01-01 00:00:01").await;
+        database
+            .confirm_identity_and_retrigger_stale_recods(1, &later)
+            .await
+            .unwrap();
+        let state = database
+            .get_pending_identity_state(1, &commitments[2])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.failure.as_deref(), Some("Tree is full"));
+
+        // Mined identities take up the remaining leaves until they are in the
+        // tree.
+        committer.enqueue(1, &[uint!(0x4444_U256)]).await.unwrap();
+        committer.flush().await.unwrap();
+        assert_eq!(identity_manager.registered().len(), 1);
+        assert!(database
+            .get_oldest_unprocessed_identity()
+            .await
+            .unwrap()
+            .is_none());
+        committer.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[allow(clippy::disallowed_methods)] // False positive from macro
+    #[traced_test]
+    async fn identity_lifecycle_is_logged_in_order() {
+        let (database, identity_manager, tree_state) =
+            harness(MockIdentityManager::mining_at(Some(42)).emitting_registrations()).await;
+        let committer = Arc::new(IdentityCommitter::new(
+            database.clone(),
+            identity_manager.clone(),
+            tree_state.clone(),
+        ));
+        committer.start().await;
+
+        committer.enqueue(1, &[uint!(0x1234_U256)]).await.unwrap();
+        // Returns once the woken up committer is done.
+        committer.flush().await.unwrap();
+        EthereumSubscriber::new(
+            1,
+            database.clone(),
+            identity_manager.clone(),
+            tree_state,
+            committer.clone(),
+        )
+        .process_initial_events()
+        .await
+        .unwrap();
+        committer.shutdown().await.unwrap();
+
+        logs_assert(|lines: &[&str]| {
+            let transitions = lines
+                .iter()
+                .filter_map(|line| line.split("transition=").nth(1)?.split_whitespace().next())
+                .collect::<Vec<_>>();
+            let expected = [
+                "\"enqueued\"",
+                "\"batch_selected\"",
+                "\"tx_broadcast\"",
+                "\"mined\"",
+                "\"confirmed\"",
+            ];
+            if transitions != expected {
+                return Err(format!("Unexpected transitions: {transitions:?}"));
+            }
+            // The identity is the first leaf, expected there from the start.
+            let indices = lines
+                .iter()
+                .filter(|line| line.contains("transition="))
+                .filter(|line| line.contains(" index=0"))
+                .count();
+            if indices == expected.len() {
+                Ok(())
+            } else {
+                Err(format!("Index missing from transitions: {lines:?}"))
+            }
+        });
+    }
+
+    #[test]
+    fn rejected_proofs_are_distinct_from_chain_failures() {
+        let receipt = TransactionReceipt {
+            transaction_hash: H256::repeat_byte(1),
+            ..TransactionReceipt::default()
+        };
+        assert!(matches!(
+            Error::from_proof_transaction(TxError::Failed(Box::new(receipt))),
+            Error::ProofRejectedOnChain(hash) if hash == H256::repeat_byte(1)
+        ));
+        assert!(matches!(
+            Error::from_proof_transaction(TxError::SendTimeout),
+            Error::Transaction(TxError::SendTimeout)
+        ));
+    }
+}
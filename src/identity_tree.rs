@@ -1,26 +1,415 @@
 use crate::timed_rw_lock::TimedRwLock;
 use semaphore::{
-    merkle_tree::Hasher,
-    poseidon_tree::{PoseidonHash, PoseidonTree},
+    merkle_tree::{self, Branch, Hasher},
+    poseidon_tree::{PoseidonHash, PoseidonTree, Proof},
     Field,
 };
-use std::sync::Arc;
+use std::{collections::VecDeque, iter, sync::Arc};
 
 pub type Hash = <PoseidonHash as Hasher>::Hash;
 
 pub struct TreeState {
-    pub next_leaf:   usize,
-    pub merkle_tree: PoseidonTree,
+    pub next_leaf:        usize,
+    pub merkle_tree:      PoseidonTree,
+    initial_leaf:         Field,
+    /// Hash of a subtree holding only initial leaves, by height. Historical
+    /// and prospective proofs use these for subtrees past their leaf count,
+    /// which [`PoseidonTree`] does not hold.
+    empty_subtree_hashes: Vec<Field>,
+    /// Recent roots with the number of leaves the tree held at the time,
+    /// oldest first.
+    root_history:         VecDeque<(Field, usize)>,
+    max_root_history:     usize,
 }
 
 pub type SharedTreeState = Arc<TimedRwLock<TreeState>>;
 
+/// Returns the depth of the [`PoseidonTree`] mirroring a contract tree of depth
+/// `contract_depth`.
+///
+/// Contracts count the levels of hashing above the leaves, while
+/// [`PoseidonTree`] also counts the leaf level. A contract tree of depth `d`
+/// holds `2^d` leaves, as does a [`PoseidonTree`] of depth `d + 1`.
+#[must_use]
+pub const fn poseidon_tree_depth(contract_depth: usize) -> usize {
+    contract_depth + 1
+}
+
 impl TreeState {
     #[must_use]
     pub fn new(tree_depth: usize, initial_leaf: Field) -> Self {
+        let empty_subtree_hashes = iter::successors(Some(initial_leaf), |hash| {
+            Some(PoseidonHash::hash_node(hash, hash))
+        })
+        .take(tree_depth)
+        .collect();
         Self {
-            next_leaf:   0,
+            next_leaf: 0,
             merkle_tree: PoseidonTree::new(tree_depth, initial_leaf),
+            initial_leaf,
+            empty_subtree_hashes,
+            root_history: VecDeque::new(),
+            max_root_history: 0,
+        }
+    }
+
+    /// An empty tree of the same depth, initial leaf and root history size.
+    #[must_use]
+    pub fn empty_like(&self) -> Self {
+        Self::new(self.empty_subtree_hashes.len(), self.initial_leaf)
+            .with_root_history(self.max_root_history)
+    }
+
+    /// Hash of a subtree of `2^height` leaves that were never set, or `None`
+    /// if the tree has no subtrees that high.
+    #[must_use]
+    pub fn empty_subtree_hash(&self, height: usize) -> Option<Field> {
+        self.empty_subtree_hashes.get(height).copied()
+    }
+
+    /// Retains the `size` most recent roots, so proofs can be served against
+    /// them.
+    #[must_use]
+    pub fn with_root_history(mut self, size: usize) -> Self {
+        self.max_root_history = size;
+        self
+    }
+
+    /// Records that the tree had `root` when it held `leaf_count` leaves.
+    pub fn record_root(&mut self, root: Field, leaf_count: usize) {
+        if self.max_root_history == 0 {
+            return;
+        }
+        if self.root_history.len() == self.max_root_history {
+            self.root_history.pop_front();
+        }
+        self.root_history.push_back((root, leaf_count));
+    }
+
+    /// Number of leaves left after the last inserted one.
+    #[must_use]
+    pub fn free_leaves(&self) -> usize {
+        self.merkle_tree.num_leaves() - self.next_leaf
+    }
+
+    /// Whether `count` more leaves fit after the last inserted one.
+    #[must_use]
+    pub fn has_room(&self, count: usize) -> bool {
+        count <= self.free_leaves()
+    }
+
+    /// Percentage of the tree's leaves up to the last inserted one.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn capacity_used(&self) -> f64 {
+        100.0 * self.next_leaf as f64 / self.merkle_tree.num_leaves() as f64
+    }
+
+    /// Returns whether `root` is one of the retained prior roots.
+    #[must_use]
+    pub fn retains_root(&self, root: &Field) -> bool {
+        self.root_history
+            .iter()
+            .any(|(historical_root, _)| historical_root == root)
+    }
+
+    /// Appends `leaf` after the current leaves, returning its index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree is full.
+    pub fn append(&mut self, leaf: Hash) -> usize {
+        let index = self.next_leaf;
+        assert!(index < self.merkle_tree.num_leaves(), "Tree is full");
+        self.merkle_tree.set(index, leaf);
+        self.next_leaf += 1;
+        index
+    }
+
+    /// Undoes the appends made since the tree held `leaf_count` leaves,
+    /// forgetting the roots recorded since.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree holds fewer than `leaf_count` leaves.
+    pub fn truncate(&mut self, leaf_count: usize) {
+        assert!(
+            leaf_count <= self.next_leaf,
+            "Tree holds fewer than {leaf_count} leaves"
+        );
+        for index in leaf_count..self.next_leaf {
+            self.merkle_tree.set(index, self.initial_leaf);
+        }
+        self.next_leaf = leaf_count;
+        self.root_history
+            .retain(|(_, historical_count)| *historical_count <= leaf_count);
+    }
+
+    /// Resets `leaf` to the initial value, returning its index, or `None` if it
+    /// is not in the tree.
+    ///
+    /// Historical proofs assume leaves are only ever appended, so roots
+    /// preceding the removal are forgotten.
+    pub fn remove_leaf(&mut self, leaf: &Field) -> Option<usize> {
+        let index = self.merkle_tree.leaves()[..self.next_leaf]
+            .iter()
+            .position(|existing| existing == leaf)?;
+        self.merkle_tree.set(index, self.initial_leaf);
+        self.root_history.clear();
+        Some(index)
+    }
+
+    /// Returns the proof of the leaf at `leaf_index` against `root`, or `None`
+    /// if `root` is not retained or the leaf was not yet inserted then.
+    #[must_use]
+    pub fn historical_proof(&self, root: &Field, leaf_index: usize) -> Option<Proof> {
+        let leaf_count = self
+            .root_history
+            .iter()
+            .rev()
+            .find(|(historical_root, _)| historical_root == root)?
+            .1;
+        if leaf_index >= leaf_count || leaf_count > self.next_leaf {
+            return None;
+        }
+
+        // Leaves are only ever appended, so every sibling on the path is either
+        // unchanged since, was still empty, or straddles the leaf count.
+        let proof = self.merkle_tree.proof(leaf_index)?;
+        let branches = proof
+            .0
+            .iter()
+            .enumerate()
+            .map(|(height, branch)| {
+                let start = ((leaf_index >> height) ^ 1) << height;
+                let hash = self.subtree_hash_at(height, start, leaf_count, &[]);
+                match branch {
+                    Branch::Left(_) => Branch::Left(hash),
+                    Branch::Right(_) => Branch::Right(hash),
+                }
+            })
+            .collect();
+        Some(merkle_tree::Proof(branches))
+    }
+
+    /// Returns the proof of the leaf at `leaf_index` in the tree that results
+    /// from appending `appended` to the current leaves, or `None` if there is
+    /// no such leaf.
+    #[must_use]
+    pub fn prospective_proof(&self, appended: &[Field], leaf_index: usize) -> Option<Proof> {
+        let leaf_count = self.next_leaf + appended.len();
+        if leaf_index >= leaf_count || leaf_count > self.merkle_tree.num_leaves() {
+            return None;
+        }
+
+        let depth = self.merkle_tree.num_leaves().trailing_zeros() as usize;
+        let branches = (0..depth)
+            .map(|height| {
+                let start = ((leaf_index >> height) ^ 1) << height;
+                let hash = self.subtree_hash_at(height, start, self.next_leaf, appended);
+                if (leaf_index >> height) & 1 == 0 {
+                    Branch::Left(hash)
+                } else {
+                    Branch::Right(hash)
+                }
+            })
+            .collect();
+        Some(merkle_tree::Proof(branches))
+    }
+
+    /// Hash of the subtree of `2^height` leaves starting at `start`, in the
+    /// tree holding the first `leaf_count` current leaves followed by
+    /// `appended`.
+    fn subtree_hash_at(
+        &self,
+        height: usize,
+        start: usize,
+        leaf_count: usize,
+        appended: &[Field],
+    ) -> Field {
+        if start >= leaf_count + appended.len() {
+            return self.empty_subtree_hashes[height];
+        }
+        if start + (1 << height) <= leaf_count {
+            return self.subtree_hash(height, start);
+        }
+        if height == 0 {
+            return appended[start - leaf_count];
+        }
+        let half = 1 << (height - 1);
+        PoseidonHash::hash_node(
+            &self.subtree_hash_at(height - 1, start, leaf_count, appended),
+            &self.subtree_hash_at(height - 1, start + half, leaf_count, appended),
+        )
+    }
+
+    /// Current hash of the subtree of `2^height` leaves starting at `start`.
+    fn subtree_hash(&self, height: usize, start: usize) -> Field {
+        if height == 0 {
+            return self.merkle_tree.leaves()[start];
+        }
+        // The subtree is the sibling at `height` of every leaf in its neighbour.
+        let neighbour = start ^ (1 << height);
+        let proof = self
+            .merkle_tree
+            .proof(neighbour)
+            .expect("Neighbouring leaf is in the tree");
+        match &proof.0[height] {
+            Branch::Left(hash) | Branch::Right(hash) => *hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn poseidon_tree_holds_as_many_leaves_as_contract_tree() {
+        let contract_depth = 21;
+        let depth = poseidon_tree_depth(contract_depth);
+        assert_eq!(depth, 22);
+
+        let tree = TreeState::new(depth, Field::from(0));
+        assert_eq!(tree.merkle_tree.num_leaves(), 1 << contract_depth);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // Quarters are exact
+    fn full_tree_has_no_room() {
+        let mut tree = TreeState::new(poseidon_tree_depth(2), Field::from(0));
+        assert!(tree.has_room(4));
+        assert!(!tree.has_room(5));
+
+        for leaf in 1..=4_u32 {
+            let index = tree.next_leaf;
+            tree.merkle_tree.set(index, Field::from(leaf));
+            tree.next_leaf += 1;
+            assert_eq!(tree.capacity_used(), f64::from(leaf) * 25.0);
+        }
+        assert_eq!(tree.free_leaves(), 0);
+        assert!(tree.has_room(0));
+        assert!(!tree.has_room(1));
+    }
+
+    #[test]
+    fn empty_subtree_hashes_match_naive_computation() {
+        let initial_leaf = Field::from(7);
+        let mut tree = TreeState::new(5, initial_leaf);
+        let naive = |height| {
+            (0..height).fold(initial_leaf, |hash, _| {
+                PoseidonHash::hash_node(&hash, &hash)
+            })
+        };
+        for height in 0..5 {
+            assert_eq!(tree.empty_subtree_hash(height), Some(naive(height)));
+        }
+        assert_eq!(tree.empty_subtree_hash(4), Some(tree.merkle_tree.root()));
+        assert_eq!(tree.empty_subtree_hash(5), None);
+
+        for leaf in 1..=3_u32 {
+            tree.append(Field::from(leaf));
+        }
+
+        // Unset leaves have empty siblings above them, and still verify.
+        let proof = tree.merkle_tree.proof(12).unwrap();
+        assert!(matches!(proof.0[2], Branch::Right(hash) if hash == naive(2)));
+        assert_eq!(proof.root(initial_leaf), tree.merkle_tree.root());
+
+        // Prospective proofs fill the empty subtrees from the cache.
+        let appended = [Field::from(4)];
+        let proof = tree.prospective_proof(&appended, 2).unwrap();
+        assert!(matches!(proof.0[2], Branch::Left(hash) if hash == naive(2)));
+        tree.append(appended[0]);
+        assert!(proof == tree.merkle_tree.proof(2).unwrap());
+        assert_eq!(proof.root(Field::from(3)), tree.merkle_tree.root());
+    }
+
+    #[test]
+    fn historical_proof_verifies_against_prior_root() {
+        let mut tree = TreeState::new(5, Field::from(0)).with_root_history(10);
+        let leaves = (1..=11_u32).map(Field::from).collect::<Vec<_>>();
+        let mut roots = Vec::new();
+        for leaf in &leaves {
+            let index = tree.next_leaf;
+            tree.merkle_tree.set(index, *leaf);
+            tree.next_leaf += 1;
+            tree.record_root(tree.merkle_tree.root(), tree.next_leaf);
+            roots.push(tree.merkle_tree.root());
+        }
+
+        // After six leaves, the proof of the third one.
+        let proof = tree.historical_proof(&roots[5], 2).unwrap();
+        assert_eq!(proof.root(leaves[2]), roots[5]);
+        assert_ne!(roots[5], tree.merkle_tree.root());
+
+        // Leaves inserted after the root, and roots no longer retained.
+        assert!(tree.historical_proof(&roots[5], 6).is_none());
+        assert!(tree.historical_proof(&roots[0], 0).is_none());
+    }
+
+    #[test]
+    fn appended_root_matches_recomputed_root() {
+        let depth = 5;
+        let mut tree = TreeState::new(depth, Field::from(0));
+        let leaves = (1..=13_u32).map(Field::from).collect::<Vec<_>>();
+        for (index, leaf) in leaves.iter().enumerate() {
+            assert_eq!(tree.append(*leaf), index);
+        }
+
+        let mut level = leaves;
+        level.resize(1 << (depth - 1), Field::from(0));
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| PoseidonHash::hash_node(&pair[0], &pair[1]))
+                .collect();
+        }
+        assert_eq!(tree.merkle_tree.root(), level[0]);
+        assert_eq!(tree.next_leaf, 13);
+    }
+
+    #[test]
+    fn truncated_tree_matches_tree_before_appends() {
+        let mut tree = TreeState::new(5, Field::from(0)).with_root_history(10);
+        for leaf in 1..=4_u32 {
+            tree.append(Field::from(leaf));
+            tree.record_root(tree.merkle_tree.root(), tree.next_leaf);
+        }
+        let root = tree.merkle_tree.root();
+        let mut appended_roots = Vec::new();
+        for leaf in 5..=7_u32 {
+            tree.append(Field::from(leaf));
+            tree.record_root(tree.merkle_tree.root(), tree.next_leaf);
+            appended_roots.push(tree.merkle_tree.root());
+        }
+
+        tree.truncate(4);
+
+        assert_eq!(tree.next_leaf, 4);
+        assert_eq!(tree.merkle_tree.root(), root);
+        assert!(tree.retains_root(&root));
+        assert!(!appended_roots.iter().any(|root| tree.retains_root(root)));
+    }
+
+    #[test]
+    fn prospective_proof_matches_tree_with_appended_leaves() {
+        let mut tree = TreeState::new(5, Field::from(0));
+        for leaf in 1..=5_u32 {
+            let index = tree.next_leaf;
+            tree.merkle_tree.set(index, Field::from(leaf));
+            tree.next_leaf += 1;
+        }
+        let appended = (6..=9_u32).map(Field::from).collect::<Vec<_>>();
+
+        let proof = tree.prospective_proof(&appended, 7).unwrap();
+
+        let mut expected = PoseidonTree::new(5, Field::from(0));
+        for (index, leaf) in (1..=9_u32).map(Field::from).enumerate() {
+            expected.set(index, leaf);
         }
+        assert!(proof == expected.proof(7).unwrap());
+        assert_eq!(proof.root(appended[2]), expected.root());
+        assert!(tree.prospective_proof(&appended, 9).is_none());
     }
 }
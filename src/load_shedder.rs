@@ -0,0 +1,170 @@
+use ::prometheus::{register_gauge_vec, register_int_counter_vec, GaugeVec, IntCounterVec};
+use once_cell::sync::Lazy;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use std::{collections::HashMap, str::FromStr, sync::Mutex, time::Duration};
+use thiserror::Error;
+use tracing::warn;
+
+static SHED_FRACTION: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "api_shed_fraction",
+        "Fraction of requests to an endpoint being shed.",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+static SHED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "api_requests_shed",
+        "Number of requests shed for exceeding the latency budget.",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+/// Weight of the latest request in the moving average latency.
+const AVERAGE_WEIGHT: f64 = 0.2;
+
+/// Upper bound on the fraction of requests shed, so the average latency keeps
+/// being measured and shedding stops once it recovers.
+const MAX_SHED_FRACTION: f64 = 0.9;
+
+/// The moving average latency an endpoint is kept under, parsed from
+/// `path=milliseconds`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LatencyBudget {
+    pub path:   String,
+    pub budget: Duration,
+}
+
+#[derive(Debug, Error)]
+#[error("Expected a latency budget as path=milliseconds, got {0:?}")]
+pub struct ParseError(String);
+
+impl FromStr for LatencyBudget {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, millis) = s.split_once('=').ok_or_else(|| ParseError(s.to_owned()))?;
+        let millis = millis.parse().map_err(|_| ParseError(s.to_owned()))?;
+        Ok(Self {
+            path:   path.to_owned(),
+            budget: Duration::from_millis(millis),
+        })
+    }
+}
+
+struct Endpoint {
+    budget:  Duration,
+    /// Moving average latency in seconds.
+    average: Mutex<f64>,
+}
+
+impl Endpoint {
+    fn shed_fraction(&self) -> f64 {
+        let average = *self.average.lock().unwrap();
+        let budget = self.budget.as_secs_f64();
+        if average <= budget {
+            0.0
+        } else {
+            (1.0 - budget / average).min(MAX_SHED_FRACTION)
+        }
+    }
+}
+
+/// Refuses a share of the requests to endpoints whose moving average latency
+/// exceeds their budget, growing with the excess, until latency recovers.
+/// Endpoints without a budget are never shed.
+pub struct LoadShedder {
+    endpoints: HashMap<String, Endpoint>,
+}
+
+impl LoadShedder {
+    #[must_use]
+    pub fn new(budgets: &[LatencyBudget]) -> Self {
+        let endpoints = budgets
+            .iter()
+            .map(|LatencyBudget { path, budget }| {
+                let endpoint = Endpoint {
+                    budget:  *budget,
+                    average: Mutex::new(0.0),
+                };
+                (path.clone(), endpoint)
+            })
+            .collect();
+        Self { endpoints }
+    }
+
+    /// Whether to refuse a new request to `path`.
+    pub fn should_shed(&self, path: &str) -> bool {
+        let Some(endpoint) = self.endpoints.get(path) else {
+            return false;
+        };
+        let fraction = endpoint.shed_fraction();
+        let shed = fraction > 0.0 && thread_rng().gen_bool(fraction);
+        if shed {
+            warn!(path, fraction, "Latency budget exceeded, shedding request.");
+            SHED.with_label_values(&[path]).inc();
+        }
+        shed
+    }
+
+    /// Records the time a request to `path` took to handle.
+    pub fn observe(&self, path: &str, latency: Duration) {
+        let Some(endpoint) = self.endpoints.get(path) else {
+            return;
+        };
+        {
+            let mut average = endpoint.average.lock().unwrap();
+            *average += AVERAGE_WEIGHT * (latency.as_secs_f64() - *average);
+        }
+        SHED_FRACTION
+            .with_label_values(&[path])
+            .set(endpoint.shed_fraction());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn shed_count(shedder: &LoadShedder, path: &str) -> usize {
+        (0..1000).filter(|_| shedder.should_shed(path)).count()
+    }
+
+    #[test]
+    fn budgets_are_parsed() {
+        assert_eq!(
+            "/inclusionProof=250".parse::<LatencyBudget>().unwrap(),
+            LatencyBudget {
+                path:   "/inclusionProof".to_owned(),
+                budget: Duration::from_millis(250),
+            }
+        );
+        assert!("/inclusionProof".parse::<LatencyBudget>().is_err());
+        assert!("/inclusionProof=fast".parse::<LatencyBudget>().is_err());
+    }
+
+    #[test]
+    fn requests_are_shed_while_latency_exceeds_budget() {
+        let shedder = LoadShedder::new(&[LatencyBudget {
+            path:   "/inclusionProof".to_owned(),
+            budget: Duration::from_millis(10),
+        }]);
+        assert_eq!(shed_count(&shedder, "/inclusionProof"), 0);
+
+        for _ in 0..20 {
+            shedder.observe("/inclusionProof", Duration::from_millis(100));
+            shedder.observe("/insertIdentity", Duration::from_millis(100));
+        }
+        let shed = shed_count(&shedder, "/inclusionProof");
+        assert!(shed > 0 && shed < 1000, "shed {shed} requests");
+        assert_eq!(shed_count(&shedder, "/insertIdentity"), 0);
+
+        for _ in 0..50 {
+            shedder.observe("/inclusionProof", Duration::from_millis(1));
+        }
+        assert_eq!(shed_count(&shedder, "/inclusionProof"), 0);
+    }
+}
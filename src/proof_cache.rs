@@ -0,0 +1,123 @@
+use crate::{app::InclusionProofResponse, identity_tree::Hash};
+use ::prometheus::{register_int_counter_vec, IntCounterVec};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use semaphore::Field;
+use std::sync::Mutex;
+
+static LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("proof_cache_lookups", "Proof cache lookups by result.", &[
+        "result"
+    ])
+    .unwrap()
+});
+
+/// Group, commitment and root a proof was served for.
+type Key = (usize, Hash, Field);
+
+/// The most recently served proofs against the latest root. All entries are
+/// dropped once the root changes, so a proof against a root that has since
+/// been replaced is never served from the cache.
+pub struct ProofCache {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The latest root the entries were added at.
+    root:    Option<Field>,
+    entries: LruCache<Key, InclusionProofResponse>,
+}
+
+impl State {
+    fn invalidate_if_changed(&mut self, latest_root: &Field) {
+        if self.root.as_ref() != Some(latest_root) {
+            self.entries.clear();
+            self.root = Some(*latest_root);
+        }
+    }
+}
+
+impl ProofCache {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                root:    None,
+                entries: LruCache::new(capacity),
+            }),
+        }
+    }
+
+    /// Returns the cached proof of `commitment` against `root`, if the tree's
+    /// root is still `latest_root`.
+    pub fn get(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+        root: &Field,
+        latest_root: &Field,
+    ) -> Option<InclusionProofResponse> {
+        let mut state = self.state.lock().expect("Proof cache lock poisoned");
+        state.invalidate_if_changed(latest_root);
+        let cached = state.entries.get(&(group_id, *commitment, *root)).cloned();
+        let result = if cached.is_some() { "hit" } else { "miss" };
+        LOOKUPS.with_label_values(&[result]).inc();
+        cached
+    }
+
+    /// Caches the proof of `commitment` against `latest_root`.
+    pub fn insert(
+        &self,
+        group_id: usize,
+        commitment: &Hash,
+        latest_root: &Field,
+        response: InclusionProofResponse,
+    ) {
+        let mut state = self.state.lock().expect("Proof cache lock poisoned");
+        state.invalidate_if_changed(latest_root);
+        state
+            .entries
+            .put((group_id, *commitment, *latest_root), response);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use semaphore::poseidon_tree::PoseidonTree;
+
+    fn lookups(result: &str) -> u64 {
+        LOOKUPS.with_label_values(&[result]).get()
+    }
+
+    #[test]
+    fn entries_are_dropped_when_the_root_changes() {
+        let mut tree = PoseidonTree::new(4, Field::from(0));
+        let commitment = Field::from(1);
+        tree.set(0, commitment);
+        let root = tree.root();
+        let response = InclusionProofResponse::Proof {
+            group_id: Some(1),
+            root,
+            proof: tree.proof(0).unwrap(),
+            index: Some(0),
+            signature: None,
+            unconfirmed: false,
+        };
+
+        let cache = ProofCache::new(2);
+        let (hits, misses) = (lookups("hit"), lookups("miss"));
+        assert!(cache.get(1, &commitment, &root, &root).is_none());
+        cache.insert(1, &commitment, &root, response);
+        assert!(cache.get(1, &commitment, &root, &root).is_some());
+        assert_eq!(lookups("hit"), hits + 1);
+        assert_eq!(lookups("miss"), misses + 1);
+
+        // Another insertion changes the root.
+        tree.set(1, Field::from(2));
+        let latest_root = tree.root();
+        assert!(cache.get(1, &commitment, &root, &latest_root).is_none());
+        assert!(cache.get(1, &commitment, &root, &root).is_none());
+        assert_eq!(lookups("hit"), hits + 1);
+    }
+}
@@ -0,0 +1,100 @@
+use ::prometheus::{register_int_gauge, IntGauge};
+use once_cell::sync::Lazy;
+use std::time::Duration;
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::timeout,
+};
+
+static IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "inclusion_proofs_in_flight",
+        "Number of inclusion proofs being computed."
+    )
+    .unwrap()
+});
+
+/// Limits the number of inclusion proofs computed at once, as each may take
+/// the tree lock and an RPC. Requests beyond the limit wait for a slot for up
+/// to `queue_timeout`.
+pub struct ProofLimiter {
+    slots:         Semaphore,
+    queue_timeout: Duration,
+}
+
+/// A slot held while a proof is computed, released on drop.
+pub struct ProofSlot<'a> {
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Drop for ProofSlot<'_> {
+    fn drop(&mut self) {
+        IN_FLIGHT.dec();
+    }
+}
+
+impl ProofLimiter {
+    #[must_use]
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> Self {
+        Self {
+            slots: Semaphore::new(max_concurrent),
+            queue_timeout,
+        }
+    }
+
+    /// Waits for a free slot, or returns `None` if none frees up within the
+    /// queue timeout.
+    pub async fn acquire(&self) -> Option<ProofSlot<'_>> {
+        let permit = timeout(self.queue_timeout, self.slots.acquire())
+            .await
+            .ok()?
+            .expect("The semaphore is never closed");
+        IN_FLIGHT.inc();
+        Some(ProofSlot { _permit: permit })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::join_all;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn concurrent_proofs_are_limited() {
+        let limiter = Arc::new(ProofLimiter::new(3, Duration::from_secs(10)));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let requests = (0..20).map(|_| {
+            let (limiter, in_flight, max_in_flight) =
+                (limiter.clone(), in_flight.clone(), max_in_flight.clone());
+            tokio::spawn(async move {
+                let _slot = limiter.acquire().await.unwrap();
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+        for result in join_all(requests).await {
+            result.unwrap();
+        }
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn queued_proofs_time_out() {
+        let limiter = ProofLimiter::new(1, Duration::from_millis(50));
+        let slot = limiter.acquire().await.unwrap();
+        assert!(limiter.acquire().await.is_none());
+
+        drop(slot);
+        assert!(limiter.acquire().await.is_some());
+    }
+}
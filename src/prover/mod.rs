@@ -2,16 +2,16 @@
 mod identity;
 mod proof;
 
-use crate::prover::{identity::Identity, proof::Proof};
+use crate::{
+    prover::{identity::Identity, proof::Proof},
+    utils::serialize_redacted,
+};
 use clap::Parser;
 use ethers::{types::U256, utils::keccak256};
 use reqwest;
 use serde::{Deserialize, Serialize};
-use std::{
-    fmt::{Display, Formatter},
-    mem::size_of,
-    time::Duration,
-};
+use std::{mem::size_of, time::Duration};
+use thiserror::Error;
 use url::Url;
 
 /// The endpoint used for proving operations.
@@ -19,12 +19,13 @@ const MTB_PROVE_ENDPOINT: &str = "prove";
 
 /// Configuration options for the component responsible for interacting with the
 /// prover service.
-#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[derive(Clone, Debug, PartialEq, Eq, Parser, Serialize)]
 #[group(skip)]
 pub struct Options {
     /// The URL at which to contact the semaphore prover service for proof
     /// generation.
     #[clap(long, env, default_value = "http://localhost:3001")]
+    #[serde(serialize_with = "serialize_redacted")]
     pub mtb_prover_url: String,
 
     /// The number of seconds to wait before timing out the transaction.
@@ -38,6 +39,24 @@ pub struct Options {
     pub batch_size: usize,
 }
 
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Provided batch does not match prover batch size.")]
+    BatchSizeMismatch,
+
+    #[error("Invalid prover URL: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("Prover unreachable: {0}")]
+    Unreachable(#[from] reqwest::Error),
+
+    #[error("PROVER FAILURE: Code = {code}, Message = {message}")]
+    Failure { code: String, message: String },
+
+    #[error("Invalid prover response: {0}")]
+    InvalidResponse(#[source] serde_json::Error),
+}
+
 /// A representation of the connection to the MTB prover service.
 #[derive(Clone, Debug)]
 pub struct Prover {
@@ -86,11 +105,9 @@ impl Prover {
         pre_root: U256,
         post_root: U256,
         identities: Vec<Identity>,
-    ) -> anyhow::Result<Proof> {
+    ) -> Result<Proof, Error> {
         if identities.len() != self.batch_size {
-            return Err(anyhow::Error::msg(
-                "Provided batch does not match prover batch size.",
-            ));
+            return Err(Error::BatchSizeMismatch);
         }
 
         let identity_commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
@@ -120,8 +137,11 @@ impl Prover {
         let json = proof_term.text().await?;
 
         let Ok(proof) = serde_json::from_str::<Proof>(&json) else {
-            let error: ProverError = serde_json::from_str(&json)?;
-            return Err(anyhow::Error::msg(format!("{error}")))
+            let error: ProverError = serde_json::from_str(&json).map_err(Error::InvalidResponse)?;
+            return Err(Error::Failure {
+                code:    error.code,
+                message: error.message,
+            });
         };
 
         Ok(proof)
@@ -181,16 +201,6 @@ struct ProverError {
     pub message: String,
 }
 
-impl Display for ProverError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "PROVER FAILURE: Code = {}, Message = {}",
-            self.code, self.message
-        )
-    }
-}
-
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ProofInput {
@@ -262,7 +272,7 @@ mod test {
             .await;
 
         mock_service.stop();
-        assert!(prover_result.is_err());
+        assert!(matches!(prover_result, Err(Error::Failure { .. })));
 
         Ok(())
     }
@@ -296,6 +306,33 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn prover_should_be_unreachable_if_not_running() -> anyhow::Result<()> {
+        // A port that was free a moment ago, which nothing listens on anymore.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let options = Options {
+            mtb_prover_url:          format!("http://127.0.0.1:{port}"),
+            mtb_prover_timeout_secs: 30,
+            batch_size:              3,
+        };
+        let mtb = Prover::new(&options).unwrap();
+        let input_data = get_default_proof_input();
+        let identities = extract_identities_from(&input_data);
+
+        let prover_result = mtb
+            .generate_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                identities,
+            )
+            .await;
+
+        assert!(matches!(prover_result, Err(Error::Unreachable(_))));
+
+        Ok(())
+    }
+
     #[test]
     fn compute_input_hash_should_succeed() {
         let input = get_default_proof_input();
@@ -1,40 +1,212 @@
-use crate::{app::App, database, identity_tree::Hash};
+use self::compression::{compress, Encoding};
+use crate::{
+    app::{encode_binary_proof, App, BranchRepr, FlatInclusionProof, InclusionProofResponse},
+    database,
+    ethereum_subscriber::SyncProgress,
+    identity_tree::Hash,
+    load_shedder::LoadShedder,
+};
 use ::prometheus::{opts, register_counter, register_histogram, Counter, Histogram};
 use anyhow::{bail, ensure, Context, Error as EyreError, Result as AnyhowResult};
 use clap::Parser;
 use cli_batteries::{await_shutdown, trace_from_headers};
+use ethers::types::Signature;
 use futures::Future;
 use hyper::{
-    body::Buf,
+    body::HttpBody,
     header,
+    server::{
+        accept::{self, Accept},
+        conn::AddrIncoming,
+    },
     service::{make_service_fn, service_fn},
-    Body, Method, Request, Response, Server, StatusCode,
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
 };
 use once_cell::sync::Lazy;
 use prometheus::{register_int_counter_vec, IntCounterVec};
+use rand::{thread_rng, Rng};
+use schemars::JsonSchema;
+use semaphore::poseidon_tree::Proof;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    fs, io,
     net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
-    sync::Arc,
-    time::Duration,
+    os::unix::{fs::FileTypeExt, net::UnixStream as StdUnixStream},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 use thiserror::Error;
-use tokio::time::timeout;
-use tracing::{error, info, instrument, trace};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    join,
+    net::UnixListener,
+    select,
+    sync::oneshot,
+    time::{sleep, timeout},
+};
+use tokio_io_timeout::TimeoutStream;
+use tracing::{error, info, info_span, trace, warn, Instrument, Span};
 use url::{Host, Url};
 
-#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+mod compression;
+mod json_rpc;
+mod openapi;
+mod tls;
+
+pub use self::tls::TlsFiles;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser, Serialize)]
 #[group(skip)]
 pub struct Options {
-    /// API Server url
+    /// API Server url. Use `unix:/path/to/socket` to serve over a Unix domain
+    /// socket instead.
     #[clap(long, env, default_value = "http://127.0.0.1:8080/")]
     pub server: Url,
 
-    /// Request handling timeout (seconds)
+    /// Time after which a request handler is considered stalled, which
+    /// terminates the sequencer (seconds)
     #[clap(long, env, default_value = "300")]
     pub serve_timeout: u64,
+
+    /// Requests taking longer are aborted with 408 Request Timeout (seconds).
+    /// Without it, requests may take up to `serve_timeout`. Inserts and admin
+    /// actions are not aborted, they may take up to `serve_timeout`.
+    #[clap(long, env)]
+    pub request_timeout: Option<u64>,
+
+    /// Connections on which the client sends nothing for this long are closed
+    /// (seconds). Must exceed `request_timeout`, as a client waiting for its
+    /// response sends nothing either. Connections are kept open indefinitely
+    /// if not set.
+    #[clap(long, env)]
+    pub idle_timeout: Option<u64>,
+
+    /// Time in-flight requests are given to complete on shutdown before their
+    /// connections are closed (seconds)
+    #[clap(long, env, default_value = "30")]
+    pub shutdown_timeout: u64,
+
+    /// JSON responses larger than this are compressed with gzip or deflate
+    /// if the client accepts it (bytes). Set to a large value to disable.
+    #[clap(long, env, default_value = "1024")]
+    pub compression_threshold: usize,
+
+    /// Requests with larger bodies are refused (bytes).
+    #[clap(long, env, default_value = "1048576")]
+    pub max_request_body_bytes: usize,
+
+    /// PEM file with the TLS certificate chain. When set together with
+    /// `tls_key`, the API is served over HTTPS. Send SIGHUP to reload both
+    /// files without downtime.
+    #[clap(long, env, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for `tls_cert`.
+    #[clap(long, env, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Path prefix all routes are served under, e.g. `/sequencer/` to serve
+    /// `/sequencer/inclusionProof`. Routes are served at the root if empty.
+    #[clap(long, env, default_value = "")]
+    pub base_path: String,
+
+    /// Print the OpenAPI document describing the HTTP API and exit.
+    #[clap(long)]
+    pub dump_openapi: bool,
 }
 
+impl Options {
+    /// Checks the options for combinations that can not be served.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `idle_timeout` is set without a shorter
+    /// `request_timeout`, which would close connections of clients waiting
+    /// for their response.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if let Some(idle_timeout) = self.idle_timeout {
+            ensure!(
+                self.request_timeout
+                    .map_or(false, |request_timeout| request_timeout < idle_timeout),
+                "--idle-timeout requires a shorter --request-timeout, clients waiting for a \
+                 response send nothing"
+            );
+        }
+        Ok(())
+    }
+
+    /// The settings requests are served with.
+    #[must_use]
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig {
+            timeouts:               Timeouts {
+                serve:    Duration::from_secs(self.serve_timeout),
+                request:  self.request_timeout.map(Duration::from_secs),
+                idle:     self.idle_timeout.map(Duration::from_secs),
+                shutdown: Duration::from_secs(self.shutdown_timeout),
+            },
+            compression_threshold:  self.compression_threshold,
+            max_request_body_bytes: self.max_request_body_bytes,
+            base_path:              self.base_path.clone(),
+        }
+    }
+
+    /// The certificate files to serve, if TLS is configured.
+    #[must_use]
+    pub fn tls_files(&self) -> Option<TlsFiles> {
+        Some(TlsFiles {
+            cert: self.tls_cert.clone()?,
+            key:  self.tls_key.clone()?,
+        })
+    }
+}
+
+/// Settings requests are served with, independent of where the server
+/// listens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub timeouts:               Timeouts,
+    /// JSON responses larger than this are compressed if the client accepts
+    /// it (bytes).
+    pub compression_threshold:  usize,
+    /// Requests with larger bodies are refused (bytes).
+    pub max_request_body_bytes: usize,
+    /// Path prefix all routes are served under.
+    pub base_path:              String,
+}
+
+/// Limits on how long requests and connections may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Handlers taking longer are considered stalled, which terminates the
+    /// sequencer.
+    pub serve:    Duration,
+    /// Handlers taking longer are aborted with 408 Request Timeout, except
+    /// for inserts and admin actions.
+    pub request:  Option<Duration>,
+    /// Connections on which the client sends nothing for this long are closed.
+    pub idle:     Option<Duration>,
+    /// Time in-flight requests are given to complete on shutdown.
+    pub shutdown: Duration,
+}
+
+/// Routes changing state. Their handlers are not aborted by the request
+/// timeout, as an insert dropped between queueing an identity and storing its
+/// idempotent response would be neither done nor safely repeatable.
+const MUTATING_ROUTES: &[&str] = &[
+    "/insertIdentity",
+    "/insertIdentities",
+    "/rpc",
+    "/admin/flush",
+    "/admin/pause",
+    "/admin/resume",
+];
+
 static REQUESTS: Lazy<Counter> =
     Lazy::new(|| register_counter!(opts!("api_requests", "Number of requests received.")).unwrap());
 static STATUS: Lazy<IntCounterVec> = Lazy::new(|| {
@@ -50,24 +222,215 @@ static LATENCY: Lazy<Histogram> = Lazy::new(|| {
 });
 const CONTENT_JSON: &str = "application/json";
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Media type of proofs encoded with [`encode_binary_proof`], served to
+/// clients listing it in their `Accept` header.
+pub const CONTENT_BINARY_PROOF: &str = "application/octet-stream";
+
+/// Header selecting the shape of JSON proofs. `flat` sends a
+/// [`FlatInclusionProof`] instead of a list of branch objects.
+pub const PROOF_FORMAT_HEADER: &str = "X-Proof-Format";
+
+/// Header carrying the signature of a signed response.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Header carrying the ID that the log lines of a request are tagged with. An
+/// ID is generated for requests without one.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Header identifying an insert, so a repeated request returns the original
+/// response.
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// Upper bound on the number of entries returned by paginated endpoints.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// The names a request's group id may be given under.
+const GROUP_ID_KEYS: [&str; 2] = ["groupId", "group_id"];
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InsertCommitmentRequest {
-    group_id:            usize,
-    identity_commitment: Hash,
+    #[serde(alias = "group_id")]
+    pub group_id:            usize,
+    #[serde(alias = "identity_commitment")]
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct InsertCommitmentsRequest {
+    #[serde(alias = "group_id")]
+    pub group_id:             usize,
+    #[serde(alias = "identity_commitments")]
+    #[schemars(with = "Vec<String>")]
+    pub identity_commitments: Vec<Hash>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FlushRequest {}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PauseRequest {}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StatusRequest {}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct LogsRequest {
+    #[serde(alias = "from_block")]
+    pub from_block: u64,
+    #[serde(alias = "to_block")]
+    pub to_block:   u64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct InclusionProofRequest {
+    #[serde(alias = "group_id")]
     pub group_id:            usize,
+    #[serde(alias = "identity_commitment")]
+    #[schemars(with = "String")]
     pub identity_commitment: Hash,
+    /// Root to prove inclusion against. Defaults to the latest root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub root:                Option<Hash>,
+    /// Return a proof flagged as unconfirmed instead of failing if its root
+    /// is not yet valid on chain, including optimistic proofs of queued
+    /// identities.
+    #[serde(
+        default,
+        alias = "allow_unconfirmed",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub allow_unconfirmed:   bool,
+}
+
+/// One entry of a `/inclusionProofsMultiGroup` request, which takes a list of
+/// these.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct GroupInclusionProofRequest {
+    #[serde(alias = "group_id")]
+    pub group_id:            usize,
+    #[serde(alias = "identity_commitment")]
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct VerifyProofRequest {
+    #[serde(alias = "group_id")]
+    pub group_id:            usize,
+    #[serde(alias = "identity_commitment")]
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+    #[schemars(with = "String")]
+    pub root:                Hash,
+    #[schemars(with = "Vec<BranchRepr>")]
+    pub proof:               Proof,
+    /// Also check whether the root is valid on chain.
+    #[serde(
+        default,
+        alias = "check_on_chain",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub check_on_chain:      bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct IdentityStatusRequest {
+    #[serde(alias = "group_id")]
+    pub group_id:            usize,
+    #[serde(alias = "identity_commitment")]
+    #[schemars(with = "String")]
+    pub identity_commitment: Hash,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct NextEmptyProofRequest {
+    #[serde(alias = "group_id")]
+    pub group_id: usize,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct GroupInfoRequest {
+    #[serde(alias = "group_id")]
+    pub group_id: usize,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct ListPendingRequest {
+    #[serde(alias = "group_id")]
+    pub group_id: usize,
+    #[serde(default = "default_page_size")]
+    pub limit:    usize,
+    #[serde(default)]
+    pub offset:   usize,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct LeafOriginRequest {
+    #[serde(alias = "group_id")]
+    pub group_id: usize,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct TreeLeavesRequest {
+    #[serde(alias = "group_id")]
+    pub group_id:      usize,
+    /// Index of the first leaf.
+    #[serde(default)]
+    pub from:          usize,
+    #[serde(default = "default_page_size")]
+    pub count:         usize,
+    /// Also list leaves holding the initial value, such as removed ones.
+    #[serde(default, alias = "include_empty")]
+    pub include_empty: bool,
+}
+
+const fn default_page_size() -> usize {
+    100
 }
 
 pub trait ToResponseCode {
     fn to_response_code(&self) -> StatusCode;
+
+    /// A signature authenticating the response, sent in the `X-Signature`
+    /// header.
+    fn signature(&self) -> Option<Signature> {
+        None
+    }
+
+    /// A suggested delay before the client polls again, sent in the
+    /// `Retry-After` header.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
 }
 
 impl ToResponseCode for () {
@@ -76,6 +439,13 @@ impl ToResponseCode for () {
     }
 }
 
+impl<T: ToResponseCode> ToResponseCode for Option<T> {
+    fn to_response_code(&self) -> StatusCode {
+        self.as_ref()
+            .map_or(StatusCode::OK, ToResponseCode::to_response_code)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("invalid http method")]
@@ -86,8 +456,12 @@ pub enum Error {
     InvalidContentType,
     #[error("invalid group id")]
     InvalidGroupId,
+    #[error("invalid group id {0}, expected an integer from 0 to {max}", max = usize::MAX)]
+    MalformedGroupId(String),
     #[error("provided identity index out of bounds")]
     IndexOutOfBounds,
+    #[error("tree is full, no empty leaf left")]
+    TreeFull,
     #[error("provided identity commitment not found")]
     IdentityCommitmentNotFound,
     #[error("provided identity commitment is invalid")]
@@ -96,10 +470,48 @@ pub enum Error {
     DuplicateCommitment,
     #[error("provided identity commitment is not reduced into SNARK_SCALAR_FIELD")]
     UnreducedCommitment,
+    #[error("provided identity commitment is not permitted")]
+    ForbiddenCommitment,
+    #[error("batch exceeds the maximum of {0} commitments")]
+    BatchTooLarge(usize),
+    #[error("request body exceeds the maximum of {0} bytes")]
+    BodyTooLarge(usize),
+    #[error("request took too long to handle")]
+    RequestTimeout,
+    #[error("initial sync in progress, only /health and /sync are served")]
+    Starting,
+    #[error("Ethereum provider unreachable, not accepting new identities")]
+    ChainUnavailable,
+    #[error("syncing halted by a deep reorg, not accepting new identities")]
+    SyncHalted,
+    #[error("Ethereum provider did not respond in time, try again later")]
+    RpcTimeout,
+    #[error("read-only instance, not accepting new identities")]
+    ReadOnly,
+    #[error("tree does not match the chain, not serving it until repaired")]
+    Degraded,
+    #[error("too many identities waiting to be committed, try again later")]
+    QueueFull,
+    #[error("too many inclusion proofs in flight, try again later")]
+    TooManyProofs,
+    #[error("endpoint exceeds its latency budget, try again later")]
+    Overloaded,
+    #[error("missing or invalid admin token")]
+    Unauthorized,
+    #[error("block range must not be reversed or span more than {0} blocks")]
+    InvalidBlockRange(u64),
+    #[error("no insertion of the leaf was recorded")]
+    LeafOriginUnknown,
+    #[error("requested root is not retained or predates the commitment")]
+    RootNotRetained,
     #[error("Root mismatch between tree and contract.")]
     RootMismatch,
+    #[error("could not check the root on chain, try again later")]
+    RootUnchecked,
     #[error("invalid JSON request: {0}")]
     InvalidSerialization(#[from] serde_json::Error),
+    #[error("invalid query string: {0}")]
+    InvalidQuery(#[from] serde_urlencoded::de::Error),
     #[error(transparent)]
     Database(#[from] database::Error),
     #[error(transparent)]
@@ -117,22 +529,40 @@ pub enum Error {
 }
 
 impl Error {
-    fn to_response(&self) -> hyper::Response<Body> {
+    fn status_code(&self) -> StatusCode {
         #[allow(clippy::enum_glob_use)]
         use Error::*;
-        let status_code = match self {
+        match self {
             InvalidMethod => StatusCode::METHOD_NOT_ALLOWED,
             InvalidPath => StatusCode::NOT_FOUND,
             InvalidContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BatchTooLarge(_) | BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ChainUnavailable | SyncHalted | RpcTimeout | TooManyProofs | Overloaded | Starting
+            | Degraded | LockTimeout(_) | RootUnchecked => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            QueueFull => StatusCode::TOO_MANY_REQUESTS,
+            RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            Unauthorized => StatusCode::UNAUTHORIZED,
+            ForbiddenCommitment | ReadOnly => StatusCode::FORBIDDEN,
             IndexOutOfBounds
+            | TreeFull
             | IdentityCommitmentNotFound
+            | LeafOriginUnknown
+            | RootNotRetained
             | InvalidCommitment
             | DuplicateCommitment
-            | InvalidSerialization(_) => StatusCode::BAD_REQUEST,
+            | InvalidBlockRange(_)
+            | MalformedGroupId(_)
+            | InvalidSerialization(_)
+            | InvalidQuery(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+        }
+    }
+
+    fn to_response(&self) -> hyper::Response<Body> {
         hyper::Response::builder()
-            .status(status_code)
+            .status(self.status_code())
             .body(hyper::Body::from(self.to_string()))
             .expect("Failed to convert error string into hyper::Body")
     }
@@ -142,6 +572,7 @@ impl Error {
 /// method.
 async fn json_middleware<F, T, S, U>(
     request: Request<Body>,
+    max_body_bytes: usize,
     mut next: F,
 ) -> Result<Response<Body>, Error>
 where
@@ -150,28 +581,321 @@ where
     S: Future<Output = Result<U, Error>> + Send,
     U: Serialize + ToResponseCode,
 {
-    let valid_content_type = request
-        .headers()
+    let request = read_json(request, max_body_bytes).await?;
+    let response = next(request).await?;
+    let json = serde_json::to_string_pretty(&response)?;
+    respond(&response, CONTENT_JSON, json)
+}
+
+/// Like [`json_middleware`], but proofs are sent in the compact binary format
+/// of [`encode_binary_proof`]. Pending responses are still JSON.
+async fn binary_proof_middleware<F, T, S>(
+    request: Request<Body>,
+    max_body_bytes: usize,
+    mut next: F,
+) -> Result<Response<Body>, Error>
+where
+    T: DeserializeOwned + Send,
+    F: FnMut(T) -> S + Send,
+    S: Future<Output = Result<InclusionProofResponse, Error>> + Send,
+{
+    let request = read_json(request, max_body_bytes).await?;
+    let response = next(request).await?;
+    match &response {
+        InclusionProofResponse::Proof {
+            root,
+            proof,
+            unconfirmed,
+            ..
+        } => {
+            let bytes = encode_binary_proof(root, proof, *unconfirmed);
+            respond(&response, CONTENT_BINARY_PROOF, bytes)
+        }
+        InclusionProofResponse::Pending { .. } => {
+            let json = serde_json::to_string_pretty(&response)?;
+            respond(&response, CONTENT_JSON, json)
+        }
+    }
+}
+
+/// Like [`json_middleware`], but proofs are sent as a [`FlatInclusionProof`].
+/// Pending responses are unchanged.
+async fn flat_proof_middleware<F, T, S>(
+    request: Request<Body>,
+    max_body_bytes: usize,
+    mut next: F,
+) -> Result<Response<Body>, Error>
+where
+    T: DeserializeOwned + Send,
+    F: FnMut(T) -> S + Send,
+    S: Future<Output = Result<InclusionProofResponse, Error>> + Send,
+{
+    let request = read_json(request, max_body_bytes).await?;
+    let response = next(request).await?;
+    let json = match &response {
+        InclusionProofResponse::Proof {
+            group_id,
+            root,
+            proof,
+            index,
+            unconfirmed,
+            ..
+        } => {
+            let flat = FlatInclusionProof::new(*group_id, *root, proof, *index, *unconfirmed);
+            serde_json::to_string_pretty(&flat)?
+        }
+        InclusionProofResponse::Pending { .. } => serde_json::to_string_pretty(&response)?,
+    };
+    respond(&response, CONTENT_JSON, json)
+}
+
+/// Parses the JSON body of a request.
+async fn read_json<T: DeserializeOwned>(
+    request: Request<Body>,
+    max_body_bytes: usize,
+) -> Result<T, Error> {
+    check_json_content_type(request.headers())?;
+    let body = read_body(request.into_body(), max_body_bytes).await?;
+    let value = serde_json::from_slice(&body)?;
+    validate_json_group_ids(&value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Refuses requests whose `Content-Type` is not JSON.
+fn check_json_content_type(headers: &HeaderMap) -> Result<(), Error> {
+    let valid_content_type = headers
         .get(header::CONTENT_TYPE)
         .map_or(false, |content_type| content_type == CONTENT_JSON);
-    if !valid_content_type {
-        return Err(Error::InvalidContentType);
+    if valid_content_type {
+        Ok(())
+    } else {
+        Err(Error::InvalidContentType)
+    }
+}
+
+/// Refuses group ids that are not non-negative integers fitting a `usize`,
+/// in a request object or a list of them, which would otherwise fail to
+/// deserialize with a less helpful error.
+fn validate_json_group_ids(value: &serde_json::Value) -> Result<(), Error> {
+    match value {
+        serde_json::Value::Array(entries) => entries.iter().try_for_each(validate_json_group_ids),
+        serde_json::Value::Object(fields) => {
+            for key in GROUP_ID_KEYS {
+                if let Some(group_id) = fields.get(key) {
+                    let valid = group_id
+                        .as_u64()
+                        .map_or(false, |group_id| usize::try_from(group_id).is_ok());
+                    if !valid {
+                        return Err(Error::MalformedGroupId(group_id.to_string()));
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
     }
-    let body = hyper::body::aggregate(request).await?;
-    let request = serde_json::from_reader(body.reader())?;
+}
+
+/// Like [`validate_json_group_ids`], for the group id in a query string.
+fn validate_query_group_id(query: &str) -> Result<(), Error> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)?;
+    for (key, value) in pairs {
+        if GROUP_ID_KEYS.contains(&key.as_str()) && value.parse::<usize>().is_err() {
+            return Err(Error::MalformedGroupId(value));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the response to a POST request, with the signature and polling
+/// delay of `response` in their headers.
+fn respond<U: ToResponseCode>(
+    response: &U,
+    content_type: &'static str,
+    body: impl Into<Body>,
+) -> Result<Response<Body>, Error> {
+    let mut builder = Response::builder()
+        .status(response.to_response_code())
+        .header(header::CONTENT_TYPE, content_type);
+    // No need to include cache-control since POST is not cached by default.
+    if let Some(signature) = response.signature() {
+        builder = builder.header(SIGNATURE_HEADER, format!("0x{signature}"));
+    }
+    if let Some(retry_after) = response.retry_after() {
+        builder = builder.header(header::RETRY_AFTER, retry_after.as_secs().max(1));
+    }
+    builder.body(body.into()).map_err(Error::Http)
+}
+
+/// The `Idempotency-Key` header of a request, if set to a non-empty string.
+fn idempotency_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(IDEMPOTENCY_KEY)?
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+/// Whether the `Accept` header of a request lists the binary proof format.
+/// JSON stays the default for clients not asking for it.
+fn accepts_binary_proof(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let accepted = params.next() == Some(CONTENT_BINARY_PROOF);
+            // Media types with `q=0` are explicitly refused.
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map_or(false, |q| q <= 0.0)
+            });
+            accepted && !refused
+        })
+}
+
+/// Whether the request asks for proofs in the flat form with the
+/// `X-Proof-Format` header. The list of branch objects stays the default.
+fn requests_flat_proof(headers: &HeaderMap) -> bool {
+    headers
+        .get(PROOF_FORMAT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |format| format.trim().eq_ignore_ascii_case("flat"))
+}
+
+/// Reads a request body, refusing bodies larger than `limit` bytes without
+/// buffering them.
+async fn read_body(mut body: Body, limit: usize) -> Result<Vec<u8>, Error> {
+    // The lower bound is the `Content-Length`, if given.
+    if body.size_hint().lower() > limit as u64 {
+        return Err(Error::BodyTooLarge(limit));
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(Error::BodyTooLarge(limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Parse the query string of a [`Request<Body>`] using Serde and handle using
+/// the provided method. The response is serialized as JSON.
+async fn query_middleware<F, T, S, U>(
+    request: Request<Body>,
+    mut next: F,
+) -> Result<Response<Body>, Error>
+where
+    T: DeserializeOwned + Send,
+    F: FnMut(T) -> S + Send,
+    S: Future<Output = Result<U, Error>> + Send,
+    U: Serialize + ToResponseCode,
+{
+    let query = request.uri().query().unwrap_or_default();
+    validate_query_group_id(query)?;
+    let request = serde_urlencoded::from_str(query)?;
     let response = next(request).await?;
     let json = serde_json::to_string_pretty(&response)?;
     let response = Response::builder()
         .status(response.to_response_code())
         .header(header::CONTENT_TYPE, CONTENT_JSON)
-        // No need to include cache-control since POST is not cached by default.
+        .header(header::CACHE_CONTROL, "no-cache")
         .body(Body::from(json))
         .map_err(Error::Http)?;
     Ok(response)
 }
 
-#[instrument(level="info", name="api_request", skip(app), fields(http.uri=%request.uri(), http.method=%request.method()))]
-async fn route(request: Request<Body>, app: Arc<App>) -> Result<Response<Body>, hyper::Error> {
+/// Check the `Authorization: Bearer` token of a request to an admin endpoint,
+/// then handle it like [`query_middleware`].
+async fn admin_middleware<F, T, S, U>(
+    app: &App,
+    request: Request<Body>,
+    next: F,
+) -> Result<Response<Body>, Error>
+where
+    T: DeserializeOwned + Send,
+    F: FnMut(T) -> S + Send,
+    S: Future<Output = Result<U, Error>> + Send,
+    U: Serialize + ToResponseCode,
+{
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    app.authorize_admin(token)?;
+    query_middleware(request, next).await
+}
+
+fn openapi_response() -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, CONTENT_JSON)
+        .body(Body::from(openapi_json()?))
+        .map_err(Error::Http)
+}
+
+/// Returns the OpenAPI document describing the HTTP API.
+///
+/// # Errors
+///
+/// Will return `Err` if the document fails to serialize.
+pub fn openapi_json() -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&openapi::document())
+}
+
+/// Returns the route `path` addresses below `base_path`, or `None` if it is
+/// outside of it.
+fn route_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    let base_path = base_path.trim_matches('/');
+    if base_path.is_empty() {
+        return Some(path);
+    }
+    path.strip_prefix('/')?
+        .strip_prefix(base_path)
+        .filter(|route| route.starts_with('/'))
+}
+
+/// The leaf index of a `/leaf/{index}/origin` route.
+fn leaf_origin_index(route: &str) -> Option<usize> {
+    route
+        .strip_prefix("/leaf/")?
+        .strip_suffix("/origin")?
+        .parse()
+        .ok()
+}
+
+/// The span a request is handled in, tagged with the `X-Request-Id` of the
+/// request or a generated ID.
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map_or_else(
+            || format!("{:016x}", thread_rng().gen::<u64>()),
+            ToOwned::to_owned,
+        );
+    info_span!(
+        "api_request",
+        http.uri = %request.uri(),
+        http.method = %request.method(),
+        request_id = %request_id
+    )
+}
+
+async fn route(
+    request: Request<Body>,
+    app: Arc<App>,
+    config: &ServerConfig,
+) -> Result<Response<Body>, hyper::Error> {
     trace_from_headers(request.headers());
 
     // Measure and log request
@@ -180,28 +904,220 @@ async fn route(request: Request<Body>, app: Arc<App>) -> Result<Response<Body>,
     trace!(url = %request.uri(), "Receiving request");
 
     // Route requests
-    let result = match (request.method(), request.uri().path()) {
+    let max_request_body_bytes = config.max_request_body_bytes;
+    let path = route_path(request.uri().path(), &config.base_path).unwrap_or_default();
+    let result = match (request.method(), path) {
         (&Method::POST, "/inclusionProof") => {
-            json_middleware(request, |request: InclusionProofRequest| {
+            let binary = accepts_binary_proof(request.headers());
+            let flat = requests_flat_proof(request.headers());
+            let handler = |request: InclusionProofRequest| {
                 let app = app.clone();
                 async move {
-                    app.inclusion_proof(request.group_id, &request.identity_commitment)
+                    app.inclusion_proof(
+                        request.group_id,
+                        &request.identity_commitment,
+                        request.root.as_ref(),
+                        request.allow_unconfirmed,
+                    )
+                    .await
+                }
+            };
+            if binary {
+                binary_proof_middleware(request, max_request_body_bytes, handler).await
+            } else if flat {
+                flat_proof_middleware(request, max_request_body_bytes, handler).await
+            } else {
+                json_middleware(request, max_request_body_bytes, handler).await
+            }
+        }
+        (&Method::POST, "/inclusionProofsMultiGroup") => {
+            json_middleware(
+                request,
+                max_request_body_bytes,
+                |request: Vec<GroupInclusionProofRequest>| {
+                    let app = app.clone();
+                    let entries = request
+                        .into_iter()
+                        .map(|entry| (entry.group_id, entry.identity_commitment))
+                        .collect();
+                    async move { app.inclusion_proofs_multi_group(entries).await }
+                },
+            )
+            .await
+        }
+        (&Method::POST, "/insertIdentity") => {
+            let idempotency_key = idempotency_key(request.headers());
+            json_middleware(
+                request,
+                max_request_body_bytes,
+                |request: InsertCommitmentRequest| {
+                    let app = app.clone();
+                    let idempotency_key = idempotency_key.clone();
+                    async move {
+                        app.insert_identity(
+                            request.group_id,
+                            request.identity_commitment,
+                            idempotency_key.as_deref(),
+                        )
+                        .await
+                    }
+                },
+            )
+            .await
+        }
+        (&Method::POST, "/insertIdentities") => {
+            json_middleware(
+                request,
+                max_request_body_bytes,
+                |request: InsertCommitmentsRequest| {
+                    let app = app.clone();
+                    async move {
+                        app.insert_identities(request.group_id, request.identity_commitments)
+                            .await
+                    }
+                },
+            )
+            .await
+        }
+        (&Method::POST, "/proof/verify") => {
+            json_middleware(
+                request,
+                max_request_body_bytes,
+                |request: VerifyProofRequest| {
+                    let app = app.clone();
+                    async move {
+                        app.verify_proof(
+                            request.group_id,
+                            &request.identity_commitment,
+                            &request.root,
+                            &request.proof,
+                            request.check_on_chain,
+                        )
                         .await
+                    }
+                },
+            )
+            .await
+        }
+        (&Method::POST, "/identityStatus") => {
+            json_middleware(
+                request,
+                max_request_body_bytes,
+                |request: IdentityStatusRequest| {
+                    let app = app.clone();
+                    async move {
+                        app.identity_status(request.group_id, &request.identity_commitment)
+                            .await
+                    }
+                },
+            )
+            .await
+        }
+        (&Method::GET, "/nextEmptyProof") => {
+            query_middleware(request, |request: NextEmptyProofRequest| {
+                let app = app.clone();
+                async move { app.next_empty_proof(request.group_id).await }
+            })
+            .await
+        }
+        (&Method::GET, "/pending") => {
+            query_middleware(request, |request: ListPendingRequest| {
+                let app = app.clone();
+                async move {
+                    app.pending_identities(
+                        request.group_id,
+                        request.limit.min(MAX_PAGE_SIZE),
+                        request.offset,
+                    )
+                    .await
                 }
             })
             .await
         }
-        (&Method::POST, "/insertIdentity") => {
-            json_middleware(request, |request: InsertCommitmentRequest| {
+        (&Method::GET, "/tree/leaves") => {
+            query_middleware(request, |request: TreeLeavesRequest| {
                 let app = app.clone();
                 async move {
-                    app.insert_identity(request.group_id, request.identity_commitment)
-                        .await
+                    app.tree_leaves(
+                        request.group_id,
+                        request.from,
+                        request.count,
+                        request.include_empty,
+                    )
+                    .await
                 }
             })
             .await
         }
-        (&Method::POST, _) => Err(Error::InvalidPath),
+        (&Method::GET, path) if path.starts_with("/leaf/") => match leaf_origin_index(path) {
+            Some(index) => {
+                query_middleware(request, |request: LeafOriginRequest| {
+                    let app = app.clone();
+                    async move { app.leaf_origin(request.group_id, index).await }
+                })
+                .await
+            }
+            None => Err(Error::InvalidPath),
+        },
+        (&Method::POST, "/admin/flush") => {
+            admin_middleware(&app, request, |_: FlushRequest| {
+                let app = app.clone();
+                async move { app.flush_identities().await }
+            })
+            .await
+        }
+        (&Method::POST, "/admin/pause") => {
+            admin_middleware(&app, request, |_: PauseRequest| {
+                let app = app.clone();
+                async move { Ok(app.pause_committer().await) }
+            })
+            .await
+        }
+        (&Method::POST, "/admin/resume") => {
+            admin_middleware(&app, request, |_: PauseRequest| {
+                let app = app.clone();
+                async move { Ok(app.resume_committer().await) }
+            })
+            .await
+        }
+        (&Method::GET, "/admin/rootCheck") => {
+            admin_middleware(&app, request, |_: StatusRequest| {
+                let app = app.clone();
+                async move { app.root_check().await }
+            })
+            .await
+        }
+        (&Method::GET, "/admin/logs") => {
+            admin_middleware(&app, request, |request: LogsRequest| {
+                let app = app.clone();
+                async move { app.logs(request.from_block, request.to_block).await }
+            })
+            .await
+        }
+        (&Method::GET, "/status") => {
+            query_middleware(request, |_: StatusRequest| {
+                let app = app.clone();
+                async move { Ok(app.status().await) }
+            })
+            .await
+        }
+        (&Method::GET, "/sync") => {
+            query_middleware(request, |_: StatusRequest| async {
+                Ok(app.sync_progress())
+            })
+            .await
+        }
+        (&Method::GET, "/health") => {
+            query_middleware(request, |_: StatusRequest| async {
+                Ok(app.health_report())
+            })
+            .await
+        }
+        (&Method::POST, "/rpc") if app.json_rpc_enabled() => {
+            json_rpc::handle(&app, request, max_request_body_bytes).await
+        }
+        (&Method::GET, "/openapi.json") => openapi_response(),
+        (&Method::POST | &Method::GET, _) => Err(Error::InvalidPath),
         _ => Err(Error::InvalidMethod),
     };
     let response = result.unwrap_or_else(|err| {
@@ -216,15 +1132,52 @@ async fn route(request: Request<Body>, app: Arc<App>) -> Result<Response<Body>,
     Ok(response)
 }
 
+/// Routes requests while the initial sync runs. Only the health check and
+/// the sync progress are served.
+async fn route_during_startup(
+    request: Request<Body>,
+    sync_progress: Arc<SyncProgress>,
+    base_path: &str,
+) -> Result<Response<Body>, hyper::Error> {
+    let path = route_path(request.uri().path(), base_path).unwrap_or_default();
+    let result = match (request.method(), path) {
+        (&Method::GET, "/sync") => {
+            query_middleware(request, |_: StatusRequest| async {
+                Ok(sync_progress.response())
+            })
+            .await
+        }
+        (&Method::GET, "/health") => {
+            query_middleware(request, |_: StatusRequest| async { Ok(()) }).await
+        }
+        _ => Err(Error::Starting),
+    };
+    let response = result.unwrap_or_else(|err| err.to_response());
+    STATUS
+        .with_label_values(&[response.status().as_str()])
+        .inc();
+    Ok(response)
+}
+
 /// # Errors
 ///
-/// Will return `Err` if `options.server` URI is not http, incorrectly includes
-/// a path beyond `/`, or cannot be cast into an IP address. Also returns an
-/// `Err` if the server cannot bind to the given address.
+/// Will return `Err` if `options.server` URI is not http, https or unix, the
+/// scheme does not match the TLS options, the URI incorrectly
+/// includes a path beyond `/`, or cannot be cast into an IP address. Also
+/// returns an `Err` if the server cannot bind to the given address.
 pub async fn main(app: Arc<App>, options: Options) -> AnyhowResult<()> {
+    let config = options.server_config();
+
+    if options.server.scheme() == "unix" {
+        let path = Path::new(options.server.path());
+        return bind_from_uds(app, config, path).await;
+    }
+
+    let tls_files = options.tls_files();
+    let scheme = if tls_files.is_some() { "https" } else { "http" };
     ensure!(
-        options.server.scheme() == "http",
-        "Only http:// is supported in {}",
+        options.server.scheme() == scheme,
+        "Expected {scheme}:// or unix: in {}, https:// requires --tls-cert and --tls-key",
         options.server
     );
     ensure!(
@@ -232,6 +1185,18 @@ pub async fn main(app: Arc<App>, options: Options) -> AnyhowResult<()> {
         "Only / is supported in {}",
         options.server
     );
+    let listener = TcpListener::bind(socket_addr(&options)?)?;
+
+    if let Some(tls_files) = tls_files {
+        return bind_tls(app, config, listener, tls_files).await;
+    }
+
+    bind_from_listener(app, config, listener).await?;
+
+    Ok(())
+}
+
+fn socket_addr(options: &Options) -> AnyhowResult<SocketAddr> {
     let ip: IpAddr = match options.server.host() {
         Some(Host::Ipv4(ip)) => ip.into(),
         Some(Host::Ipv6(ip)) => ip.into(),
@@ -239,14 +1204,70 @@ pub async fn main(app: Arc<App>, options: Options) -> AnyhowResult<()> {
         None => Ipv4Addr::LOCALHOST.into(),
     };
     let port = options.server.port().unwrap_or(9998);
-    let addr = SocketAddr::new(ip, port);
+    Ok(SocketAddr::new(ip, port))
+}
 
-    let listener = TcpListener::bind(addr)?;
+/// Serves `/health` and `/sync` on the address in `options` until `startup`
+/// completes, so a long initial sync can be monitored. Other requests are
+/// refused with 503 Service Unavailable. Servers on Unix domain sockets or
+/// over TLS are not started early.
+///
+/// # Errors
+///
+/// Will return `Err` if the address cannot be bound or if the server fails.
+pub async fn serve_during_startup<F: Future>(
+    options: &Options,
+    sync_progress: Arc<SyncProgress>,
+    startup: F,
+) -> AnyhowResult<F::Output> {
+    if options.server.scheme() != "http" || options.tls_files().is_some() {
+        return Ok(startup.await);
+    }
+    let listener = TcpListener::bind(socket_addr(options)?)?;
+    bind_during_startup(listener, sync_progress, &options.base_path, startup).await
+}
 
-    let serve_timeout = Duration::from_secs(options.serve_timeout);
-    bind_from_listener(app, serve_timeout, listener).await?;
+/// Serves `/health` and `/sync` on `listener` until `startup` completes, then
+/// closes the listener.
+async fn bind_during_startup<F: Future>(
+    listener: TcpListener,
+    sync_progress: Arc<SyncProgress>,
+    base_path: &str,
+    startup: F,
+) -> AnyhowResult<F::Output> {
+    let local_addr = listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    let incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)
+        .context("Failed to bind address")?;
 
-    Ok(())
+    info!(url = %local_addr, "Serving sync progress during startup");
+
+    let base_path: Arc<str> = base_path.into();
+    let make_svc = make_service_fn(move |_| {
+        let sync_progress = sync_progress.clone();
+        let base_path = base_path.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                let sync_progress = sync_progress.clone();
+                let base_path = base_path.clone();
+                let span = request_span(&req);
+                async move { route_during_startup(req, sync_progress, &base_path).await }
+                    .instrument(span)
+            }))
+        }
+    });
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = Server::builder(incoming)
+        .serve(make_svc)
+        .with_graceful_shutdown(async { stopped.await.unwrap_or_default() });
+    let startup = async {
+        let output = startup.await;
+        drop(stop);
+        output
+    };
+    let (result, output) = join!(server, startup);
+    result?;
+    Ok(output)
 }
 
 /// # Errors
@@ -256,53 +1277,592 @@ pub async fn main(app: Arc<App>, options: Options) -> AnyhowResult<()> {
 ///
 /// # Panics
 ///
-/// Panics if the request handler exceeds the provided `serve_timeout`.
+/// Panics if the request handler exceeds `config.timeouts.serve`.
 pub async fn bind_from_listener(
     app: Arc<App>,
-    serve_timeout: Duration,
+    config: ServerConfig,
     listener: TcpListener,
 ) -> AnyhowResult<()> {
     let local_addr = listener.local_addr()?;
-    let make_svc = make_service_fn(move |_| {
-        // Clone here as `make_service_fn` is called for every connection
-        let app = app.clone();
-        let serve_timeout = serve_timeout;
-        async move {
-            Ok::<_, hyper::Error>(service_fn(move |req| {
-                // Clone here as `service_fn` is called for every request
-                let app = app.clone();
-                let serve_timeout = serve_timeout;
-                async move {
-                    timeout(serve_timeout, route(req, app))
-                        .await
-                        .unwrap_or_else(|err| {
-                            error!(?err, timeout = ?serve_timeout, "Timeout while handling request");
-                            panic!("Sequencer may be stalled, terminating.");
-                            #[allow(unreachable_code)]
-                            Ok(Error::Elapsed(err).to_response())
-                        })
-                }
-            }))
+    listener.set_nonblocking(true)?;
+    let incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)
+        .context("Failed to bind address")?;
+
+    info!(url = %local_addr, "Server listening");
+
+    serve(incoming, app, config).await?;
+    Ok(())
+}
+
+/// Serves the API over TLS on `listener`, offering HTTP/2 and HTTP/1.1. The
+/// certificate is read from `tls_files` again on every SIGHUP.
+///
+/// # Errors
+///
+/// Will return `Err` if the certificate cannot be loaded, the `listener`
+/// cannot be used or if the server fails.
+///
+/// # Panics
+///
+/// Panics if the request handler exceeds `config.timeouts.serve`.
+pub async fn bind_tls(
+    app: Arc<App>,
+    config: ServerConfig,
+    listener: TcpListener,
+    tls_files: TlsFiles,
+) -> AnyhowResult<()> {
+    let resolver = Arc::new(
+        tls::CertificateResolver::new(tls_files).context("Failed to load TLS certificate")?,
+    );
+    let reload = tokio::spawn(resolver.clone().reload_on_hangup());
+
+    let local_addr = listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let incoming = accept::from_stream(tls::incoming(listener, tls::server_config(resolver)));
+
+    info!(url = %format!("https://{local_addr}/"), "Server listening");
+
+    serve(incoming, app, config).await?;
+    reload.await??;
+    Ok(())
+}
+
+/// Removes the socket file at `path` if no server is listening on it anymore.
+/// Nothing is removed if `path` does not exist.
+fn remove_stale_socket(path: &Path) -> AnyhowResult<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(error).context(format!("Failed to inspect {}", path.display()));
+        }
+    };
+    ensure!(
+        metadata.file_type().is_socket(),
+        "Refusing to replace {}, it is not a socket",
+        path.display()
+    );
+    ensure!(
+        StdUnixStream::connect(path).is_err(),
+        "Socket {} is in use by a running server",
+        path.display()
+    );
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    warn!(path = %path.display(), "Removed stale socket file");
+    Ok(())
+}
+
+/// Serves the API over a Unix domain socket at `path`. A stale socket file left
+/// at `path` is replaced, and the socket file is removed once the server stops.
+///
+/// # Errors
+///
+/// Will return `Err` if the socket cannot be bound at `path`, `path` is taken
+/// by something other than a stale socket, or if the server fails.
+///
+/// # Panics
+///
+/// Panics if the request handler exceeds `config.timeouts.serve`.
+pub async fn bind_from_uds(
+    app: Arc<App>,
+    config: ServerConfig,
+    path: &Path,
+) -> AnyhowResult<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind socket {}", path.display()))?;
+    let incoming = accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|result| Some(result.map(|(stream, _)| stream)))
+    });
+
+    info!(path = %path.display(), "Server listening");
+
+    let result = serve(incoming, app, config).await;
+    if let Err(error) = fs::remove_file(path) {
+        warn!(path = %path.display(), %error, "Failed to remove socket file");
+    }
+    result?;
+    Ok(())
+}
+
+/// Serves requests until the shutdown signal. On shutdown no new connections
+/// are accepted, and in-flight requests get `config.timeouts.shutdown` to
+/// complete before the remaining connections are closed.
+async fn serve<I>(
+    incoming: I,
+    app: Arc<App>,
+    config: ServerConfig,
+) -> Result<(), hyper::Error>
+where
+    I: Accept + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    I::Conn: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let timeouts = config.timeouts;
+    let config = Arc::new(config);
+    let connections = Arc::new(AtomicUsize::new(0));
+    let make_svc = make_service_fn({
+        let connections = connections.clone();
+        move |_| {
+            // Clone here as `make_service_fn` is called for every connection
+            let app = app.clone();
+            let config = config.clone();
+            let guard = ConnectionGuard::new(&connections);
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    // The guard lives as long as the connection's service.
+                    let _guard = &guard;
+                    serve_request(app.clone(), config.clone(), req)
+                }))
+            }
         }
     });
 
-    let server = Server::from_tcp(listener)
-        .context("Failed to bind address")?
+    let server = Server::builder(with_idle_timeout(incoming, timeouts.idle))
         .serve(make_svc)
         .with_graceful_shutdown(await_shutdown());
+    let drain_deadline = async {
+        await_shutdown().await;
+        info!(
+            connections = connections.load(Ordering::Relaxed),
+            timeout = ?timeouts.shutdown,
+            "Draining connections"
+        );
+        sleep(timeouts.shutdown).await;
+    };
 
-    info!(url = %local_addr, "Server listening");
+    select! {
+        result = server => result,
+        () = drain_deadline => {
+            warn!(
+                force_closed = connections.load(Ordering::Relaxed),
+                "Drain timeout elapsed, closing remaining connections"
+            );
+            Ok(())
+        }
+    }
+}
 
-    server.await?;
-    Ok(())
+/// Closes connections once reading from them stalls for `idle_timeout`, so
+/// idle clients do not hold on to them.
+fn with_idle_timeout<I>(
+    incoming: I,
+    idle_timeout: Option<Duration>,
+) -> impl Accept<Conn = Pin<Box<TimeoutStream<I::Conn>>>, Error = I::Error> + Send
+where
+    I: Accept + Send + 'static,
+    I::Conn: AsyncRead + AsyncWrite,
+{
+    let mut incoming = Box::pin(incoming);
+    accept::poll_fn(move |cx| {
+        incoming.as_mut().poll_accept(cx).map_ok(|conn| {
+            let mut conn = TimeoutStream::new(conn);
+            conn.set_read_timeout(idle_timeout);
+            Box::pin(conn)
+        })
+    })
+}
+
+/// Tracks an open connection in a shared counter for as long as it lives.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    fn new(connections: &Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::Relaxed);
+        Self(connections.clone())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+async fn serve_request(
+    app: Arc<App>,
+    config: Arc<ServerConfig>,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let encoding = Encoding::negotiate(req.headers());
+    let span = request_span(&req);
+    let path = route_path(req.uri().path(), &config.base_path)
+        .unwrap_or_default()
+        .to_owned();
+    let serve_timeout = config.timeouts.serve;
+    let request_timeout = request_timeout(&config.timeouts, &path);
+    let handler = with_load_shedding(
+        app.load_shedder(),
+        &path,
+        with_request_timeout(request_timeout, route(req, app.clone(), &config).instrument(span)),
+    );
+    let response = timeout(serve_timeout, handler)
+        .await
+        .unwrap_or_else(|err| {
+            error!(?err, timeout = ?serve_timeout, "Timeout while handling request");
+            panic!("Sequencer may be stalled, terminating.");
+            #[allow(unreachable_code)]
+            Ok(Error::Elapsed(err).to_response())
+        })?;
+    match encoding {
+        Some(encoding) => compress(response, encoding, config.compression_threshold).await,
+        None => Ok(response),
+    }
+}
+
+/// Refuses requests to `path` with 503 Service Unavailable while it exceeds
+/// its latency budget. Otherwise the time `handler` takes is recorded, also
+/// when it is aborted by the request timeout.
+async fn with_load_shedding(
+    load_shedder: &LoadShedder,
+    path: &str,
+    handler: impl Future<Output = Result<Response<Body>, hyper::Error>>,
+) -> Result<Response<Body>, hyper::Error> {
+    if load_shedder.should_shed(path) {
+        let response = Error::Overloaded.to_response();
+        STATUS
+            .with_label_values(&[response.status().as_str()])
+            .inc();
+        return Ok(response);
+    }
+    let start = Instant::now();
+    let response = handler.await;
+    load_shedder.observe(path, start.elapsed());
+    response
+}
+
+/// The time after which a request to `path` is aborted, if any. Requests to
+/// [`MUTATING_ROUTES`] are left to complete.
+fn request_timeout(timeouts: &Timeouts, path: &str) -> Option<Duration> {
+    timeouts
+        .request
+        .filter(|_| !MUTATING_ROUTES.contains(&path))
+}
+
+/// Aborts `handler` with 408 Request Timeout if it takes longer than
+/// `request_timeout`. The handler is dropped wherever it is waiting, so it
+/// must not be one of the [`MUTATING_ROUTES`].
+async fn with_request_timeout(
+    request_timeout: Option<Duration>,
+    handler: impl Future<Output = Result<Response<Body>, hyper::Error>>,
+) -> Result<Response<Body>, hyper::Error> {
+    let Some(request_timeout) = request_timeout else {
+        return handler.await;
+    };
+    timeout(request_timeout, handler).await.unwrap_or_else(|_| {
+        warn!(timeout = ?request_timeout, "Request took too long, aborting.");
+        let response = Error::RequestTimeout.to_response();
+        STATUS
+            .with_label_values(&[response.status().as_str()])
+            .inc();
+        Ok(response)
+    })
 }
 
 #[cfg(test)]
 #[allow(unused_imports)]
 mod test {
     use super::*;
+    use crate::load_shedder::LatencyBudget;
     use hyper::{body::to_bytes, Request, StatusCode};
     use serde_json::json;
+    use std::os::unix::net::UnixListener as StdUnixListener;
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let path = std::env::temp_dir().join(format!("sequencer-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        remove_stale_socket(&path).unwrap();
+
+        fs::write(&path, "not a socket").unwrap();
+        let error = remove_stale_socket(&path).unwrap_err();
+        assert!(error.to_string().contains("not a socket"), "{error}");
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+
+        let listener = StdUnixListener::bind(&path).unwrap();
+        let error = remove_stale_socket(&path).unwrap_err();
+        assert!(error.to_string().contains("in use"), "{error}");
+        assert!(path.exists());
+
+        drop(listener);
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn malformed_group_ids_are_refused() {
+        async fn read<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+            let request = Request::builder()
+                .header(header::CONTENT_TYPE, CONTENT_JSON)
+                .body(Body::from(body.to_owned()))
+                .unwrap();
+            read_json(request, 1024).await
+        }
+
+        for group_id in ["-1", "\"one\"", "1.5", "18446744073709551616"] {
+            let body = format!(r#"{{ "groupId": {group_id}, "identityCommitment": "0x1" }}"#);
+            let error = read::<InsertCommitmentRequest>(&body).await.err().unwrap();
+            assert!(
+                matches!(error, Error::MalformedGroupId(_)),
+                "{group_id}: {error}"
+            );
+            assert_eq!(error.to_response().status(), StatusCode::BAD_REQUEST);
+            assert!(error.to_string().starts_with("invalid group id"));
+        }
+        let request =
+            read::<InsertCommitmentRequest>(r#"{ "group_id": 1, "identity_commitment": "0x1" }"#)
+                .await
+                .unwrap();
+        assert_eq!(request.group_id, 1);
+
+        // Every entry of a multi-group request is checked.
+        let error = read::<Vec<GroupInclusionProofRequest>>(
+            r#"[{ "groupId": 1, "identityCommitment": "0x1" },
+                { "groupId": -1, "identityCommitment": "0x2" }]"#,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(error, Error::MalformedGroupId(id) if id == "-1"));
+
+        for query in ["groupId=-1", "groupId=one", "group_id=18446744073709551616"] {
+            let error = validate_query_group_id(query).unwrap_err();
+            assert!(matches!(error, Error::MalformedGroupId(_)), "{query}");
+        }
+        validate_query_group_id("groupId=1").unwrap();
+    }
+
+    #[test]
+    fn requests_accept_camel_and_snake_case_fields() {
+        for body in [
+            json!({ "groupId": 1, "identityCommitment": "0x1", "allowUnconfirmed": true }),
+            json!({ "group_id": 1, "identity_commitment": "0x1", "allow_unconfirmed": true }),
+        ] {
+            let request: InclusionProofRequest = serde_json::from_value(body).unwrap();
+            assert_eq!(request.group_id, 1);
+            assert_eq!(request.identity_commitment, Hash::from(1));
+            assert!(request.allow_unconfirmed);
+        }
+
+        for body in [
+            json!({ "groupId": 1, "identityCommitments": ["0x1", "0x2"] }),
+            json!({ "group_id": 1, "identity_commitments": ["0x1", "0x2"] }),
+        ] {
+            let request: InsertCommitmentsRequest = serde_json::from_value(body).unwrap();
+            assert_eq!(request.identity_commitments, [Hash::from(1), Hash::from(2)]);
+        }
+
+        for query in ["fromBlock=1&toBlock=2", "from_block=1&to_block=2"] {
+            let request: LogsRequest = serde_urlencoded::from_str(query).unwrap();
+            assert_eq!((request.from_block, request.to_block), (1, 2));
+        }
+    }
+
+    #[test]
+    fn routes_are_resolved_below_the_base_path() {
+        assert_eq!(route_path("/status", ""), Some("/status"));
+        assert_eq!(route_path("/status", "/"), Some("/status"));
+        for base_path in ["/sequencer", "/sequencer/", "sequencer"] {
+            assert_eq!(route_path("/sequencer/status", base_path), Some("/status"));
+            assert_eq!(route_path("/status", base_path), None);
+            assert_eq!(route_path("/sequencerstatus", base_path), None);
+        }
+    }
+
+    #[test]
+    fn leaf_origin_routes_are_parsed() {
+        assert_eq!(leaf_origin_index("/leaf/12/origin"), Some(12));
+        assert_eq!(leaf_origin_index("/leaf/12"), None);
+        assert_eq!(leaf_origin_index("/leaf/twelve/origin"), None);
+        assert_eq!(leaf_origin_index("/leaf//origin"), None);
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_time_out() {
+        let slow = || async {
+            sleep(Duration::from_secs(1)).await;
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        };
+
+        let response = with_request_timeout(Some(Duration::from_millis(10)), slow())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let response = with_request_timeout(None, slow()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn inserts_are_not_aborted_by_the_request_timeout() {
+        let timeouts = Timeouts {
+            serve:    Duration::from_secs(10),
+            request:  Some(Duration::from_millis(10)),
+            idle:     None,
+            shutdown: Duration::from_secs(1),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let request_timeout = request_timeout(&timeouts, req.uri().path());
+                    with_request_timeout(request_timeout, async {
+                        sleep(Duration::from_millis(200)).await;
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    })
+                }))
+            }));
+        let server = tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let response = client.post(format!("{url}/status")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        for route in MUTATING_ROUTES {
+            let response = client.post(format!("{url}{route}")).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{route}");
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn timed_out_requests_count_towards_the_latency_budget() {
+        let load_shedder = Arc::new(LoadShedder::new(&[LatencyBudget {
+            path:   "/inclusionProof".to_owned(),
+            budget: Duration::from_millis(1),
+        }]));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/inclusionProof", listener.local_addr().unwrap());
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| {
+                let load_shedder = load_shedder.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                        let load_shedder = load_shedder.clone();
+                        async move {
+                            let slow = async {
+                                sleep(Duration::from_secs(1)).await;
+                                Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                            };
+                            let handler =
+                                with_request_timeout(Some(Duration::from_millis(20)), slow);
+                            with_load_shedding(&load_shedder, req.uri().path(), handler).await
+                        }
+                    }))
+                }
+            }));
+        let server = tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let mut statuses = Vec::new();
+        for _ in 0..40 {
+            statuses.push(client.post(&url).send().await.unwrap().status());
+        }
+        assert_eq!(statuses[0], StatusCode::REQUEST_TIMEOUT);
+        assert!(
+            statuses.contains(&StatusCode::SERVICE_UNAVAILABLE),
+            "{statuses:?}"
+        );
+        server.abort();
+    }
+
+    #[test]
+    fn idle_timeout_must_exceed_request_timeout() {
+        let validate = |args: &[&str]| Options::try_parse_from(args).unwrap().validate();
+        validate(&[""]).unwrap();
+        validate(&["", "--request-timeout", "10", "--idle-timeout", "60"]).unwrap();
+        validate(&["", "--idle-timeout", "60"]).unwrap_err();
+        validate(&["", "--request-timeout", "60", "--idle-timeout", "60"]).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn sync_progress_is_served_before_the_app_is_ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let sync_progress = Arc::new(SyncProgress::default());
+        sync_progress.report_target(10, 19);
+        sync_progress.report_processed(14);
+        let (ready, startup) = oneshot::channel::<()>();
+        let server = tokio::spawn(bind_during_startup(listener, sync_progress, "", startup));
+
+        let client = reqwest::Client::new();
+        let response = client.get(format!("{url}/sync")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let sync: crate::app::SyncResponse = response.json().await.unwrap();
+        assert!(!sync.synced);
+        assert_eq!(sync.target_block, Some(19));
+        assert_eq!((sync.blocks_processed, sync.blocks_total), (5, 10));
+        let response = client.get(format!("{url}/health")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client
+            .post(format!("{url}/insertIdentity"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // The listener is closed once the app is ready.
+        ready.send(()).unwrap();
+        server.await.unwrap().unwrap().unwrap();
+        assert!(client.get(format!("{url}/sync")).send().await.is_err());
+    }
+
+    /// Collects the output of a subscriber, like the log collector would.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn json_logs_are_tagged_with_the_request_id() {
+        let logs = LogBuffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let guard = tracing::subscriber::set_default(subscriber);
+
+        let request = Request::builder()
+            .uri("/health")
+            .header(REQUEST_ID_HEADER, "request-42")
+            .body(Body::empty())
+            .unwrap();
+        let span = request_span(&request);
+        route_during_startup(request, Arc::default(), "")
+            .instrument(span)
+            .await
+            .unwrap();
+        drop(guard);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!lines.is_empty());
+        assert!(lines
+            .iter()
+            .any(|line| line["span"]["request_id"] == "request-42"));
+    }
 
     // TODO: Fix test
     // #[tokio::test]
@@ -322,7 +1882,8 @@ mod test {
             .header("Content-Type", "application/json")
             .body(body)
             .unwrap();
-        let res = route(request, app).await.unwrap();
+        let config = Options::try_parse_from([""]).unwrap().server_config();
+        let res = route(request, app, &config).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
         // TODO deserialize proof and compare results
     }
@@ -0,0 +1,142 @@
+use super::CONTENT_JSON;
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use hyper::{
+    header::{self, HeaderValue},
+    Body, HeaderMap, Response,
+};
+use std::io::Write;
+
+/// A content coding for response bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Picks the encoding for a response from the request's `Accept-Encoding`
+    /// header, preferring gzip.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let accepted = headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|coding| {
+                let mut params = coding.split(';').map(str::trim);
+                let name = params.next()?;
+                // Codings with `q=0` are explicitly refused.
+                let refused = params.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map_or(false, |q| q <= 0.0)
+                });
+                (!refused).then_some(name)
+            })
+            .collect::<Vec<_>>();
+        if accepted
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case("gzip"))
+        {
+            Some(Self::Gzip)
+        } else if accepted
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case("deflate"))
+        {
+            Some(Self::Deflate)
+        } else {
+            None
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> Vec<u8> {
+        // Writing to a `Vec` does not fail.
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Failed to compress response");
+                encoder.finish().expect("Failed to compress response")
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Failed to compress response");
+                encoder.finish().expect("Failed to compress response")
+            }
+        }
+    }
+}
+
+/// Compresses JSON response bodies larger than `threshold` bytes. Smaller
+/// bodies are not worth the overhead.
+pub async fn compress(
+    response: Response<Body>,
+    encoding: Encoding,
+    threshold: usize,
+) -> Result<Response<Body>, hyper::Error> {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(false, |content_type| content_type == CONTENT_JSON);
+    if !is_json || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    parts
+        .headers
+        .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if body.len() <= threshold {
+        return Ok(Response::from_parts(parts, Body::from(body)));
+    }
+
+    parts.headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.name()),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Response::from_parts(
+        parts,
+        Body::from(encoding.encode(&body)),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn accepting(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiation_prefers_gzip_and_honors_refusals() {
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+        assert_eq!(
+            Encoding::negotiate(&accepting("deflate, gzip;q=0.5")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accepting("gzip;q=0, deflate")),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(Encoding::negotiate(&accepting("br, identity")), None);
+    }
+}
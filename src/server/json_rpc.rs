@@ -0,0 +1,270 @@
+use super::{
+    check_json_content_type, idempotency_key, read_body, validate_json_group_ids, Error,
+    GroupInfoRequest, InclusionProofRequest, InsertCommitmentRequest, ToResponseCode,
+    CONTENT_JSON,
+};
+use crate::app::App;
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Instant;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Code of errors of the sequencer that are not about the parameters, with
+/// the HTTP status the REST API would respond with in `data.status`.
+const SERVER_ERROR: i64 = -32000;
+
+/// The envelope of a call, parsed once it is known to be an object.
+#[derive(Deserialize)]
+struct Call {
+    jsonrpc: String,
+    method:  String,
+    #[serde(default)]
+    params:  Value,
+}
+
+/// The error object of a JSON-RPC response.
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code:    i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data:    Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(error: Error) -> Self {
+        let status = error.status_code();
+        let code = match error {
+            Error::InvalidGroupId
+            | Error::MalformedGroupId(_)
+            | Error::InvalidCommitment
+            | Error::UnreducedCommitment
+            | Error::InvalidSerialization(_) => INVALID_PARAMS,
+            _ if status == StatusCode::INTERNAL_SERVER_ERROR => INTERNAL_ERROR,
+            _ => SERVER_ERROR,
+        };
+        Self {
+            code,
+            message: error.to_string(),
+            data: Some(json!({ "status": status.as_u16() })),
+        }
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(error: serde_json::Error) -> Self {
+        Error::from(error).into()
+    }
+}
+
+/// Handles a JSON-RPC 2.0 request to `/rpc`, a single call or a batch of
+/// them. The methods take their parameters by name, as in the body of the
+/// REST endpoint of the same name. Errors of the sequencer are returned in
+/// the response with HTTP 200, like any other JSON-RPC error.
+///
+/// The calls of a batch are handled one after another, like as many REST
+/// requests, and batches of more than `max_json_rpc_batch_size` calls are
+/// refused. The `Idempotency-Key` header applies to every `insertIdentity`
+/// call of the request.
+pub async fn handle(
+    app: &App,
+    request: Request<Body>,
+    max_body_bytes: usize,
+) -> Result<Response<Body>, Error> {
+    check_json_content_type(request.headers())?;
+    let idempotency_key = idempotency_key(request.headers());
+    let idempotency_key = idempotency_key.as_deref();
+    let body = read_body(request.into_body(), max_body_bytes).await?;
+    let max_batch_size = app.max_json_rpc_batch_size();
+    let response = match serde_json::from_slice(&body) {
+        Ok(Value::Array(calls)) if calls.is_empty() => Some(error_response(
+            &Value::Null,
+            &RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Ok(Value::Array(calls)) if calls.len() > max_batch_size => Some(error_response(
+            &Value::Null,
+            &Error::BatchTooLarge(max_batch_size).into(),
+        )),
+        Ok(Value::Array(calls)) => {
+            let mut responses = Vec::with_capacity(calls.len());
+            for call in calls {
+                responses.extend(handle_call(app, call, idempotency_key).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Ok(call) => handle_call(app, call, idempotency_key).await,
+        Err(error) => Some(error_response(
+            &Value::Null,
+            &RpcError::new(PARSE_ERROR, error.to_string()),
+        )),
+    };
+
+    // Notifications are not answered.
+    let Some(response) = response else {
+        return Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .map_err(Error::Http);
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, CONTENT_JSON)
+        .body(Body::from(serde_json::to_string_pretty(&response)?))
+        .map_err(Error::Http)
+}
+
+/// Handles one call, returning its response, or `None` for a notification.
+/// Calls are shed under the latency budget of the REST endpoint of the same
+/// name, and count towards it.
+async fn handle_call(app: &App, call: Value, idempotency_key: Option<&str>) -> Option<Value> {
+    let id = call.get("id").cloned();
+    let call = match parse_call(call) {
+        Ok(call) => call,
+        // Invalid requests are answered even without an id.
+        Err(error) => return Some(error_response(&id.unwrap_or_default(), &error)),
+    };
+    let path = format!("/{}", call.method);
+    let result = if app.load_shedder().should_shed(&path) {
+        Err(Error::Overloaded.into())
+    } else {
+        let start = Instant::now();
+        let result = dispatch(app, &call.method, call.params, idempotency_key).await;
+        app.load_shedder().observe(&path, start.elapsed());
+        result
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(&id, &error),
+    })
+}
+
+fn parse_call(call: Value) -> Result<Call, RpcError> {
+    let invalid = |message: &str| RpcError::new(INVALID_REQUEST, message);
+    if !call.is_object() {
+        return Err(invalid("expected a request object"));
+    }
+    let call: Call = serde_json::from_value(call)
+        .map_err(|error| RpcError::new(INVALID_REQUEST, error.to_string()))?;
+    if call.jsonrpc != "2.0" {
+        return Err(invalid("unsupported jsonrpc version, expected 2.0"));
+    }
+    Ok(call)
+}
+
+async fn dispatch(
+    app: &App,
+    method: &str,
+    params: Value,
+    idempotency_key: Option<&str>,
+) -> Result<Value, RpcError> {
+    match method {
+        "insertIdentity" => {
+            let request: InsertCommitmentRequest = parse_params(params)?;
+            let response = app
+                .insert_identity(
+                    request.group_id,
+                    request.identity_commitment,
+                    idempotency_key,
+                )
+                .await?;
+            Ok(serde_json::to_value(response)?)
+        }
+        "inclusionProof" => {
+            let request: InclusionProofRequest = parse_params(params)?;
+            let response = app
+                .inclusion_proof(
+                    request.group_id,
+                    &request.identity_commitment,
+                    request.root.as_ref(),
+                    request.allow_unconfirmed,
+                )
+                .await?;
+            // Sent in the `X-Signature` header by the REST endpoint, which
+            // a batch has no room for.
+            let signature = response.signature();
+            let mut result = serde_json::to_value(response)?;
+            if let (Some(signature), Value::Object(fields)) = (signature, &mut result) {
+                fields.insert("signature".to_owned(), json!(format!("0x{signature}")));
+            }
+            Ok(result)
+        }
+        "groupInfo" => {
+            let request: GroupInfoRequest = parse_params(params)?;
+            let response = app.group_info(request.group_id).await?;
+            Ok(serde_json::to_value(response)?)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method {method:?} not found"),
+        )),
+    }
+}
+
+/// Parses parameters given by name, validating group ids like the REST API.
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    if !params.is_object() {
+        return Err(RpcError::new(INVALID_PARAMS, "expected parameters by name"));
+    }
+    validate_json_group_ids(&params)?;
+    Ok(serde_json::from_value(params)?)
+}
+
+fn error_response(id: &Value, error: &RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn server_errors_follow_json_rpc_codes() {
+        let error = RpcError::from(Error::MalformedGroupId("-1".to_owned()));
+        assert_eq!(error.code, INVALID_PARAMS);
+        assert_eq!(error.data, Some(json!({ "status": 400 })));
+
+        let error = RpcError::from(Error::QueueFull);
+        assert_eq!(error.code, SERVER_ERROR);
+        assert_eq!(error.message, Error::QueueFull.to_string());
+        assert_eq!(error.data, Some(json!({ "status": 429 })));
+
+        assert_eq!(RpcError::from(Error::NotManager).code, INTERNAL_ERROR);
+    }
+
+    #[test]
+    fn invalid_envelopes_are_refused() {
+        let code = |call: Value| parse_call(call).err().map(|error| error.code);
+        assert_eq!(
+            code(json!({ "jsonrpc": "2.0", "method": "groupInfo" })),
+            None
+        );
+        for invalid in [
+            json!([1]),
+            json!({ "jsonrpc": "1.0", "method": "groupInfo" }),
+            json!({ "jsonrpc": "2.0", "id": 1 }),
+        ] {
+            assert_eq!(code(invalid.clone()), Some(INVALID_REQUEST), "{invalid}");
+        }
+
+        let params = parse_params::<GroupInfoRequest>(json!([0]));
+        assert_eq!(params.err().map(|error| error.code), Some(INVALID_PARAMS));
+        let params = parse_params::<GroupInfoRequest>(json!({ "groupId": "one" }));
+        assert_eq!(params.err().map(|error| error.code), Some(INVALID_PARAMS));
+    }
+}
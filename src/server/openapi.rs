@@ -0,0 +1,506 @@
+//! OpenAPI description of the HTTP API, generated from the request and response
+//! types used by the handlers.
+use super::{
+    GroupInclusionProofRequest, IdentityStatusRequest, InclusionProofRequest,
+    InsertCommitmentRequest, InsertCommitmentsRequest, LeafOriginRequest, ListPendingRequest,
+    LogsRequest, NextEmptyProofRequest, TreeLeavesRequest, VerifyProofRequest,
+};
+use crate::app::{
+    FlatInclusionProof, FlushResponse, GroupInclusionProofsResponse, HealthReport,
+    IdentityStatusResponse, InclusionProofResponse, InsertIdentitiesResponse,
+    InsertIdentityResponse, LeafOriginResponse, LogsResponse, PendingIdentitiesResponse,
+    RootCheckResponse, StatusResponse, SyncResponse, TreeLeavesResponse, VerifyProofResponse,
+};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+
+/// Builds the OpenAPI 3.0 document for all routes served by
+/// [`super::route`].
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+
+    let mut leaf_origin_parameters = vec![json!({
+        "name": "index",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer", "minimum": 0 },
+    })];
+    leaf_origin_parameters.extend(
+        query_parameters::<LeafOriginRequest>(&mut gen)
+            .as_array()
+            .cloned()
+            .unwrap_or_default(),
+    );
+
+    let paths = json!({
+        "/insertIdentity": {
+            "post": {
+                "summary": "Queues an insertion of a new identity into the merkle tree",
+                "parameters": [{
+                    "name": "Idempotency-Key",
+                    "in": "header",
+                    "description": "Repeating a request for the same commitment with the same \
+                                    key returns the original response instead of inserting again",
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": json_body::<InsertCommitmentRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<InsertIdentityResponse>(
+                        &mut gen,
+                        "Identity insert was successfully queued",
+                    ),
+                    "400": error_response("Invalid request"),
+                    "403": error_response(
+                        "The commitment is not permitted, or the instance is read-only",
+                    ),
+                    "413": error_response("The request body is too large"),
+                    "429": error_response("Too many identities are waiting to be committed"),
+                    "503": error_response(
+                        "The Ethereum provider is unreachable, or a deep reorg halted syncing",
+                    ),
+                }
+            }
+        },
+        "/insertIdentities": {
+            "post": {
+                "summary": "Queues a batch of identities, either all or none of them",
+                "requestBody": json_body::<InsertCommitmentsRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<InsertIdentitiesResponse>(
+                        &mut gen,
+                        "All identities were queued",
+                    ),
+                    "400": json_response::<InsertIdentitiesResponse>(
+                        &mut gen,
+                        "Some identities were rejected and none were queued",
+                    ),
+                    "403": error_response("The instance is read-only"),
+                    "413": error_response("The batch or request body is too large"),
+                    "429": error_response("Too many identities are waiting to be committed"),
+                    "503": error_response(
+                        "The Ethereum provider is unreachable, or a deep reorg halted syncing",
+                    ),
+                }
+            }
+        },
+        "/inclusionProof": {
+            "post": {
+                "summary": "Get Merkle inclusion proof",
+                "parameters": [{
+                    "name": super::PROOF_FORMAT_HEADER,
+                    "in": "header",
+                    "description": "`flat` returns the sibling hashes and path indices as \
+                                    separate lists instead of a list of branch objects",
+                    "schema": { "type": "string", "enum": ["flat"] }
+                }],
+                "requestBody": json_body::<InclusionProofRequest>(&mut gen),
+                "responses": {
+                    "200": signed(binary_proof(flat_proof(
+                        json_response::<InclusionProofResponse>(
+                            &mut gen,
+                            "A Merkle inclusion proof for an already inserted commitment",
+                        ),
+                        &mut gen,
+                    ))),
+                    "202": retry_after(json_response::<InclusionProofResponse>(
+                        &mut gen,
+                        "The commitment is queued but not yet inserted",
+                    )),
+                    "400": error_response("Invalid request"),
+                    "413": error_response("The request body is too large"),
+                    "503": error_response("Too many proofs in flight, the tree is busy or degraded, or the root could not be checked on chain"),
+                }
+            }
+        },
+        "/inclusionProofsMultiGroup": {
+            "post": {
+                "summary": "Get Merkle inclusion proofs for commitments across groups",
+                "requestBody": json_body::<Vec<GroupInclusionProofRequest>>(&mut gen),
+                "responses": {
+                    "200": json_response::<GroupInclusionProofsResponse>(
+                        &mut gen,
+                        "One proof per entry, or why it could not be proven, in request order",
+                    ),
+                    "400": error_response("Invalid request"),
+                    "413": error_response("Too many entries, or the request body is too large"),
+                    "503": error_response("Too many proofs in flight, the tree is busy or degraded, or the root could not be checked on chain"),
+                }
+            }
+        },
+        "/nextEmptyProof": {
+            "get": {
+                "summary": "Get the Merkle proof of the next empty leaf",
+                "parameters": query_parameters::<NextEmptyProofRequest>(&mut gen),
+                "responses": {
+                    "200": signed(json_response::<InclusionProofResponse>(
+                        &mut gen,
+                        "A Merkle proof for the initial leaf value at the first unset leaf",
+                    )),
+                    "400": error_response("Invalid query string or the tree is full"),
+                    "503": error_response("The tree is busy or degraded"),
+                }
+            }
+        },
+        "/proof/verify": {
+            "post": {
+                "summary": "Verify a client-supplied Merkle inclusion proof",
+                "requestBody": json_body::<VerifyProofRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<VerifyProofResponse>(
+                        &mut gen,
+                        "Whether the proof is valid, and how its root relates to the tree",
+                    ),
+                    "400": error_response("Invalid request"),
+                    "413": error_response("The request body is too large"),
+                    "503": error_response(
+                        "The tree is busy or degraded, or the root could not be checked on chain",
+                    ),
+                }
+            }
+        },
+        "/identityStatus": {
+            "post": {
+                "summary": "Get the lifecycle stage of an identity commitment",
+                "requestBody": json_body::<IdentityStatusRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<IdentityStatusResponse>(
+                        &mut gen,
+                        "The current status of the commitment",
+                    ),
+                    "400": error_response("Invalid request or unknown commitment"),
+                    "413": error_response("The request body is too large"),
+                }
+            }
+        },
+        "/pending": {
+            "get": {
+                "summary": "List identities queued for insertion",
+                "parameters": query_parameters::<ListPendingRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<PendingIdentitiesResponse>(
+                        &mut gen,
+                        "Queued identities, oldest first",
+                    ),
+                    "400": error_response("Invalid query string"),
+                }
+            }
+        },
+        "/tree/leaves": {
+            "get": {
+                "summary": "List inserted leaves with their indices",
+                "parameters": query_parameters::<TreeLeavesRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<TreeLeavesResponse>(
+                        &mut gen,
+                        "Inserted leaves in the requested range, by index",
+                    ),
+                    "400": error_response("Invalid query string"),
+                    "503": error_response("The tree is busy or degraded"),
+                }
+            }
+        },
+        "/leaf/{index}/origin": {
+            "get": {
+                "summary": "Returns the block and transaction a leaf was inserted in",
+                "parameters": leaf_origin_parameters,
+                "responses": {
+                    "200": json_response::<LeafOriginResponse>(
+                        &mut gen,
+                        "The block and transaction of the leaf's insertion",
+                    ),
+                    "400": error_response("Invalid query string or no insertion recorded"),
+                }
+            }
+        },
+        "/admin/flush": {
+            "post": {
+                "summary": "Commits all queued identities immediately",
+                "security": [{ "adminToken": [] }],
+                "responses": {
+                    "200": json_response::<FlushResponse>(
+                        &mut gen,
+                        "The number of committed identities and their transactions",
+                    ),
+                    "401": error_response("Missing or invalid admin token"),
+                    "403": error_response("The instance is read-only"),
+                }
+            }
+        },
+        "/admin/pause": {
+            "post": {
+                "summary": "Stops broadcasting transactions while still queueing inserts",
+                "security": [{ "adminToken": [] }],
+                "responses": {
+                    "200": json_response::<StatusResponse>(&mut gen, "The updated status"),
+                    "401": error_response("Missing or invalid admin token"),
+                }
+            }
+        },
+        "/admin/resume": {
+            "post": {
+                "summary": "Resumes broadcasting transactions and commits the queue",
+                "security": [{ "adminToken": [] }],
+                "responses": {
+                    "200": json_response::<StatusResponse>(&mut gen, "The updated status"),
+                    "401": error_response("Missing or invalid admin token"),
+                }
+            }
+        },
+        "/admin/rootCheck": {
+            "get": {
+                "summary": "Checks that the root of the local tree is valid on chain",
+                "security": [{ "adminToken": [] }],
+                "responses": {
+                    "200": json_response::<RootCheckResponse>(
+                        &mut gen,
+                        "Both roots and whether the local one is valid on chain",
+                    ),
+                    "401": error_response("Missing or invalid admin token"),
+                    "503": error_response("The root could not be checked on chain"),
+                }
+            }
+        },
+        "/admin/logs": {
+            "get": {
+                "summary": "Membership events of the group in a block range, as fetched from chain",
+                "security": [{ "adminToken": [] }],
+                "parameters": query_parameters::<LogsRequest>(&mut gen),
+                "responses": {
+                    "200": json_response::<LogsResponse>(
+                        &mut gen,
+                        "The events in the range, with the index of each leaf in the tree",
+                    ),
+                    "400": error_response("Invalid query string or block range"),
+                    "401": error_response("Missing or invalid admin token"),
+                }
+            }
+        },
+        "/status": {
+            "get": {
+                "summary": "Operational state of the sequencer",
+                "responses": {
+                    "200": json_response::<StatusResponse>(&mut gen, "The current status"),
+                }
+            }
+        },
+        "/sync": {
+            "get": {
+                "summary": "Progress of the initial sync, also served while it runs",
+                "responses": {
+                    "200": json_response::<SyncResponse>(&mut gen, "The current progress"),
+                }
+            }
+        },
+        "/health": {
+            "get": {
+                "summary": "Whether the sequencer is running, also served during the initial sync",
+                "responses": {
+                    "200": json_response::<Option<HealthReport>>(
+                        &mut gen,
+                        "The sequencer is running, with the findings of the startup health \
+                         check or `null` during the initial sync",
+                    ),
+                    "503": json_response::<HealthReport>(
+                        &mut gen,
+                        "A reorg too deep to handle automatically halted syncing",
+                    ),
+                }
+            }
+        },
+        "/rpc": {
+            "post": {
+                "summary": "JSON-RPC 2.0 calls of insertIdentity, inclusionProof and groupInfo, \
+                            if enabled with --json-rpc",
+                "description": "Takes a call or a batch of calls with parameters by name, as \
+                                in the body of the REST endpoint of the same name. Errors are \
+                                returned in the JSON-RPC response, with the HTTP status of the \
+                                REST endpoint in `error.data.status`. The calls of a batch are \
+                                handled in order, up to --max-json-rpc-batch-size of them. \
+                                Signed proofs carry their signature in `result.signature`.",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": { "type": "object" } } }
+                },
+                "responses": {
+                    "200": {
+                        "description": "The response to the call, or the batch of responses",
+                        "content": { "application/json": { "schema": { "type": "object" } } }
+                    },
+                    "204": { "description": "All calls were notifications" },
+                    "404": error_response("The JSON-RPC endpoint is not enabled"),
+                    "413": error_response("The request body is too large"),
+                }
+            }
+        },
+        "/openapi.json": {
+            "get": {
+                "summary": "This document",
+                "responses": {
+                    "200": {
+                        "description": "OpenAPI document describing the HTTP API",
+                        "content": { "application/json": { "schema": { "type": "object" } } }
+                    }
+                }
+            }
+        },
+    });
+
+    let schemas = gen
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, to_value(&schema)))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "license": { "name": "MIT" },
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" }
+            },
+        },
+    })
+}
+
+fn json_body<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": to_value(&gen.subschema_for::<T>()) } }
+    })
+}
+
+fn json_response<T: JsonSchema>(gen: &mut SchemaGenerator, description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": to_value(&gen.subschema_for::<T>()) } }
+    })
+}
+
+/// Documents the signature header added when response signing is enabled.
+fn signed(mut response: Value) -> Value {
+    response["headers"] = json!({
+        (super::SIGNATURE_HEADER): {
+            "description": "EIP-191 signature over the group id, leaf index, confirmation status, root and proof, if response signing is enabled",
+            "schema": { "type": "string" }
+        }
+    });
+    response
+}
+
+/// Documents the flat form of proofs sent to clients asking for it.
+fn flat_proof(mut response: Value, gen: &mut SchemaGenerator) -> Value {
+    let content = &mut response["content"]["application/json"];
+    content["schema"] = json!({
+        "oneOf": [content["schema"].take(), to_value(&gen.subschema_for::<FlatInclusionProof>())]
+    });
+    response
+}
+
+/// Documents the compact binary encoding of proofs sent to clients accepting
+/// it.
+fn binary_proof(mut response: Value) -> Value {
+    response["content"][super::CONTENT_BINARY_PROOF] = json!({
+        "schema": {
+            "type": "string",
+            "format": "binary",
+            "description": "Branch count, flags, root, branch direction bitmap and sibling hashes"
+        }
+    });
+    response
+}
+
+/// Documents the suggested polling delay of pending responses.
+fn retry_after(mut response: Value) -> Value {
+    response["headers"] = json!({
+        "Retry-After": {
+            "description": "Suggested number of seconds to wait before polling again",
+            "schema": { "type": "integer" }
+        }
+    });
+    response
+}
+
+/// Errors are returned as a plain-text explanation of the error condition.
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } }
+    })
+}
+
+fn query_parameters<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    let schema = gen.root_schema_for::<T>().schema;
+    let object = schema.object.unwrap_or_default();
+    object
+        .properties
+        .iter()
+        .map(|(name, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(name),
+                "schema": to_value(schema),
+            })
+        })
+        .collect()
+}
+
+fn to_value(schema: &Schema) -> Value {
+    serde_json::to_value(schema).expect("Schemas are always serializable")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn document_lists_all_endpoints() {
+        let document: Value =
+            serde_json::from_str(&crate::server::openapi_json().unwrap()).unwrap();
+        let paths = document["paths"].as_object().unwrap();
+        for path in [
+            "/insertIdentity",
+            "/insertIdentities",
+            "/inclusionProof",
+            "/inclusionProofsMultiGroup",
+            "/nextEmptyProof",
+            "/proof/verify",
+            "/identityStatus",
+            "/pending",
+            "/tree/leaves",
+            "/leaf/{index}/origin",
+            "/admin/flush",
+            "/admin/pause",
+            "/admin/resume",
+            "/admin/rootCheck",
+            "/admin/logs",
+            "/status",
+            "/sync",
+            "/health",
+            "/rpc",
+            "/openapi.json",
+        ] {
+            assert!(paths.contains_key(path), "missing {path}");
+        }
+
+        // Every referenced schema must be defined.
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for name in [
+            "InclusionProofRequest",
+            "InclusionProofResponse",
+            "IdentityStatus",
+        ] {
+            assert!(schemas.contains_key(name), "missing schema {name}");
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::utils::reload_on_hangup;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use futures::{Stream, StreamExt};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{sleep, timeout},
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{error, warn};
+
+/// Clients that take longer to complete the handshake are disconnected.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshakes performed at the same time. Further connections wait in the
+/// listen backlog.
+const MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// PEM files holding the certificate chain and private key to serve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key:  PathBuf,
+}
+
+impl TlsFiles {
+    fn load(&self) -> AnyhowResult<CertifiedKey> {
+        let certs = read_pem(&self.cert)?
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::X509Certificate(der) => Some(Certificate(der)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if certs.is_empty() {
+            return Err(anyhow!("No certificate found in {}", self.cert.display()));
+        }
+        let key = read_pem(&self.key)?
+            .into_iter()
+            .find_map(|item| match item {
+                Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("No private key found in {}", self.key.display()))?;
+        let key = sign::any_supported_type(&key)
+            .with_context(|| format!("Unsupported private key in {}", self.key.display()))?;
+        Ok(CertifiedKey::new(certs, key))
+    }
+}
+
+fn read_pem(path: &Path) -> AnyhowResult<Vec<Item>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Serves the most recently loaded certificate to every client, so it can be
+/// replaced without restarting the server.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    files:   TlsFiles,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    /// # Errors
+    ///
+    /// Will return `Err` if the certificate or key can not be read or parsed.
+    pub fn new(files: TlsFiles) -> AnyhowResult<Self> {
+        let current = RwLock::new(Arc::new(files.load()?));
+        Ok(Self { files, current })
+    }
+
+    /// Reads the files again. On failure the previous certificate stays in
+    /// use.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the certificate or key can not be read or parsed.
+    pub fn reload(&self) -> AnyhowResult<()> {
+        let key = Arc::new(self.files.load()?);
+        *self.current.write().expect("Certificate lock poisoned") = key;
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        self.current
+            .read()
+            .expect("Certificate lock poisoned")
+            .clone()
+    }
+
+    /// Reloads the certificate on every SIGHUP until shutdown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the signal handler can not be installed.
+    pub async fn reload_on_hangup(self: Arc<Self>) -> AnyhowResult<()> {
+        reload_on_hangup("tls_certificate", || self.reload()).await
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+/// Server configuration offering HTTP/2 and HTTP/1.1 through ALPN.
+pub fn server_config(resolver: Arc<CertificateResolver>) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+/// Accepts connections from `listener` and completes their TLS handshake.
+/// Failed handshakes only close the connection in question.
+pub fn incoming(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> + Send {
+    let acceptor = TlsAcceptor::from(config);
+    let connections = async_stream::stream! {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => yield stream,
+                Err(error) => {
+                    // Usually out of file descriptors, so give others a chance
+                    // to close theirs.
+                    error!(%error, "Failed to accept connection");
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    };
+    connections
+        .map(move |stream| {
+            let peer = stream.peer_addr().ok();
+            let handshake = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+            async move {
+                match handshake.await {
+                    Ok(Ok(stream)) => Some(stream),
+                    Ok(Err(error)) => {
+                        warn!(?peer, %error, "TLS handshake failed");
+                        None
+                    }
+                    Err(_) => {
+                        warn!(?peer, "TLS handshake timed out");
+                        None
+                    }
+                }
+            }
+        })
+        .buffer_unordered(MAX_PENDING_HANDSHAKES)
+        .filter_map(|stream| async move { stream.map(Ok) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn write_self_signed(files: &TlsFiles) -> Vec<u8> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        fs::write(&files.cert, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&files.key, cert.serialize_private_key_pem()).unwrap();
+        cert.serialize_der().unwrap()
+    }
+
+    #[test]
+    fn reload_replaces_the_certificate() {
+        let dir = std::env::temp_dir();
+        let files = TlsFiles {
+            cert: dir.join(format!("signup-sequencer-cert-{}.pem", std::process::id())),
+            key:  dir.join(format!("signup-sequencer-key-{}.pem", std::process::id())),
+        };
+        let first = write_self_signed(&files);
+        let resolver = CertificateResolver::new(files.clone()).unwrap();
+        assert_eq!(resolver.current().cert[0].0, first);
+
+        let second = write_self_signed(&files);
+        resolver.reload().unwrap();
+        assert_eq!(resolver.current().cert[0].0, second);
+
+        // A broken file keeps the previous certificate in use.
+        fs::write(&files.key, "not a key").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.current().cert[0].0, second);
+
+        fs::remove_file(&files.cert).unwrap();
+        fs::remove_file(&files.key).unwrap();
+    }
+}
@@ -7,16 +7,19 @@ use tokio::{
     sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
     time::timeout,
 };
+use tracing::warn;
 
 // FEATURE: Add tracing spans to wait and the guard.
 
 /// A read-write lock with timeout.
 ///
-/// Wraps Tokio's [`RwLock`].
+/// Wraps Tokio's [`RwLock`]. Reads and writes may wait for different
+/// durations.
 #[derive(Debug)]
 pub struct TimedRwLock<T: Send + Sync> {
-    duration: Duration,
-    inner:    RwLock<T>,
+    read_duration:  Duration,
+    write_duration: Duration,
+    inner:          RwLock<T>,
 }
 
 /// Error for [`TimedRwLock`].
@@ -49,29 +52,105 @@ impl<T: Send + Sync> TimedRwLock<T> {
     }
 
     pub const fn from_lock(duration: Duration, inner: RwLock<T>) -> Self {
-        Self { duration, inner }
+        Self {
+            read_duration: duration,
+            write_duration: duration,
+            inner,
+        }
+    }
+
+    /// Waits at most `duration` for reads, instead of the duration given on
+    /// construction.
+    #[must_use]
+    pub fn with_read_timeout(mut self, duration: Duration) -> Self {
+        self.read_duration = duration;
+        self
+    }
+
+    pub const fn read_timeout(&self) -> Duration {
+        self.read_duration
     }
 
-    #[allow(dead_code)]
-    pub const fn timeout(&self) -> Duration {
-        self.duration
+    pub const fn write_timeout(&self) -> Duration {
+        self.write_duration
     }
 
     pub async fn read(&self) -> Result<RwLockReadGuard<'_, T>, Error> {
-        timeout(self.duration, self.inner.read())
+        timeout(self.read_duration, self.inner.read())
             .await
             .map_err(|_| Error {
                 operation: Operation::Read,
-                duration:  self.duration,
+                duration:  self.read_duration,
             })
     }
 
+    /// Waits for a read lock for as long as it takes, warning each time a read
+    /// times out. For background tasks, which can wait out a long write,
+    /// unlike requests.
+    pub async fn read_retrying(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            match self.read().await {
+                Ok(guard) => return guard,
+                Err(error) => warn!(%error, "Lock still held by a writer, waiting."),
+            }
+        }
+    }
+
     pub async fn write(&self) -> Result<RwLockWriteGuard<'_, T>, Error> {
-        timeout(self.duration, self.inner.write())
+        timeout(self.write_duration, self.inner.write())
             .await
             .map_err(|_| Error {
                 operation: Operation::Write,
-                duration:  self.duration,
+                duration:  self.write_duration,
             })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn read_times_out_before_write_under_contention() {
+        let lock = Arc::new(
+            TimedRwLock::new(Duration::from_secs(5), 0)
+                .with_read_timeout(Duration::from_millis(50)),
+        );
+        let guard = lock.write().await.unwrap();
+
+        let writer = tokio::spawn({
+            let lock = lock.clone();
+            async move {
+                *lock.write().await.unwrap() += 1;
+            }
+        });
+        let error = lock.read().await.unwrap_err();
+        assert_eq!(error.operation, Operation::Read);
+        assert_eq!(error.duration, Duration::from_millis(50));
+
+        // The writer is still waiting.
+        drop(guard);
+        writer.await.unwrap();
+        assert_eq!(*lock.read().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn retrying_read_outlasts_read_timeout() {
+        let lock = Arc::new(
+            TimedRwLock::new(Duration::from_secs(5), 0)
+                .with_read_timeout(Duration::from_millis(20)),
+        );
+        let guard = lock.write().await.unwrap();
+
+        let reader = tokio::spawn({
+            let lock = lock.clone();
+            async move { *lock.read_retrying().await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!reader.is_finished());
+
+        drop(guard);
+        assert_eq!(reader.await.unwrap(), 0);
+    }
+}
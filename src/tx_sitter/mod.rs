@@ -5,7 +5,7 @@
 /// This is a separate module because we may eventually pull it out into a
 /// separate crate and then into an independent service. A list of goals and
 /// features can be found [here](https://www.notion.so/worldcoin/tx-sitter-8ca70eec826e4491b500f55f03ec1b43).
-use crate::ethereum::{Ethereum, TxError};
+use crate::ethereum::{Ethereum, SentTransaction, TxError};
 use ethers::types::{transaction::eip2718::TypedTransaction, TransactionReceipt};
 
 pub struct Sitter {
@@ -21,4 +21,17 @@ impl Sitter {
     pub async fn send(&self, tx: TypedTransaction) -> Result<TransactionReceipt, TxError> {
         self.ethereum.send_transaction(tx).await
     }
+
+    /// Sends `tx` to the mempool. Callers should persist the returned
+    /// transaction before calling [`Self::wait`], so that its outcome can be
+    /// recovered after a crash.
+    pub async fn broadcast(&self, tx: TypedTransaction) -> Result<SentTransaction, TxError> {
+        self.ethereum.broadcast_transaction(tx).await
+    }
+
+    /// Waits for a broadcast transaction to be mined. This also works for
+    /// transactions sent by a previous run of the process.
+    pub async fn wait(&self, sent: SentTransaction) -> Result<TransactionReceipt, TxError> {
+        self.ethereum.wait_for_transaction(sent).await
+    }
 }
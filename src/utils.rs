@@ -1,9 +1,19 @@
-use anyhow::{Error as EyreError, Result as AnyhowResult};
+use anyhow::{Context, Error as EyreError, Result as AnyhowResult};
+use cli_batteries::await_shutdown;
 use ethers::types::U256;
 use futures::FutureExt;
+use serde::{Serialize, Serializer};
 use std::future::Future;
-use tokio::task::JoinHandle;
-use tracing::error;
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{error, info};
+use url::Url;
+
+/// Stands in for secrets in the printed configuration.
+const REDACTED: &str = "redacted";
 
 #[macro_export]
 macro_rules! require {
@@ -68,6 +78,76 @@ where
     })
 }
 
+/// Serializes a secret as a placeholder, so it never appears in output.
+pub fn serialize_redacted<T, S: Serializer>(_: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+/// Like [`serialize_redacted`], but keeps whether the secret is set.
+pub fn serialize_redacted_option<T, S: Serializer>(
+    secret: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| REDACTED).serialize(serializer)
+}
+
+fn without_password(url: &Url) -> Url {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        url.set_password(Some(REDACTED)).ok();
+    }
+    url
+}
+
+/// Serializes a URL with its password redacted.
+pub fn serialize_url_redacted<S: Serializer>(url: &Url, serializer: S) -> Result<S::Ok, S::Error> {
+    without_password(url).serialize(serializer)
+}
+
+/// Serializes Ethereum provider URLs with their password, path and query
+/// redacted, as providers commonly take an API key in either of them.
+pub fn serialize_provider_urls_redacted<S: Serializer>(
+    urls: &[Url],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let redacted = urls
+        .iter()
+        .map(|url| {
+            let mut url = without_password(url);
+            if url.path() != "/" {
+                url.set_path(REDACTED);
+            }
+            if url.query().is_some() {
+                url.set_query(Some(REDACTED));
+            }
+            url
+        })
+        .collect::<Vec<_>>();
+    redacted.serialize(serializer)
+}
+
 pub fn u256_to_f64(value: U256) -> f64 {
     value.to_string().parse::<f64>().unwrap()
 }
+
+/// Calls `reload` on every SIGHUP until shutdown. Failures are logged and leave
+/// the previously loaded state in use.
+///
+/// # Errors
+///
+/// Will return `Err` if the signal handler can not be installed.
+pub async fn reload_on_hangup<F>(name: &'static str, mut reload: F) -> AnyhowResult<()>
+where
+    F: FnMut() -> AnyhowResult<()> + Send,
+{
+    let mut hangups = signal(SignalKind::hangup()).context("Failed to install SIGHUP handler")?;
+    loop {
+        select! {
+            Some(()) = hangups.recv() => match reload() {
+                Ok(()) => info!(name, "Reloaded on SIGHUP"),
+                Err(error) => error!(name, ?error, "Failed to reload on SIGHUP"),
+            },
+            () = await_shutdown() => return Ok(()),
+        }
+    }
+}
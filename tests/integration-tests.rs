@@ -8,15 +8,19 @@ use ethers::{
         SignerMiddleware,
     },
     providers::Middleware,
-    types::{BlockNumber, Filter, Log, H160, H256, U256},
+    types::{BlockNumber, Filter, Log, Signature, H160, H256, U256},
     utils::{Anvil, AnvilInstance},
 };
 use eyre::{bail, Result as AnyhowResult};
-use hyper::{client::HttpConnector, Body, Client, Request, StatusCode};
+use hyper::{client::HttpConnector, header, Body, Client, Request, StatusCode};
 use semaphore::{merkle_tree::Branch, poseidon_tree::PoseidonTree};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use signup_sequencer::{app::App, identity_tree::Hash, server, Options};
+use signup_sequencer::{
+    app::{inclusion_proof_message, App, InclusionProofResponse},
+    identity_tree::{poseidon_tree_depth, Hash},
+    server, Options,
+};
 use std::{
     fs::File,
     io::BufReader,
@@ -24,11 +28,18 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::{spawn, task::JoinHandle};
+use tokio::{
+    net::{TcpStream, UnixStream},
+    spawn,
+    task::JoinHandle,
+};
 use tracing::{debug, error, info, instrument};
 use tracing_subscriber::fmt::{format::FmtSpan, time::Uptime};
 use url::{Host, Url};
 
+/// Depth of the Semaphore group created on the test chain.
+const TREE_DEPTH: usize = 21;
+
 const TEST_LEAVES: &[&str] = &[
     "0000F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0F0",
     "0000F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1F1",
@@ -50,18 +61,22 @@ async fn simulate_eth_reorg() {
         .expect("Failed to spawn ganache chain");
 
     options.app.ethereum.ethereum_provider =
-        Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint");
-    options.app.contracts.semaphore_address = semaphore_address;
+        vec![Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint")];
+    options.app.contracts.semaphore_address = semaphore_address.into();
     options.app.ethereum.signing_key = private_key;
-    options.app.ethereum.confirmation_blocks_delay = 5;
+    options.app.ethereum.ingest_confirmations = 5;
     options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.ethereum.provider_poll_interval = Some(Duration::from_millis(500));
 
     let (app, local_addr) = spawn_app(options.clone())
         .await
         .expect("Failed to spawn app.");
 
     let uri = "http://".to_owned() + &local_addr.to_string();
-    let mut ref_tree = PoseidonTree::new(22, options.app.contracts.initial_leaf_value);
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
     let client = Client::new();
 
     let provider = Provider::<Http>::try_from(chain.endpoint())
@@ -145,10 +160,10 @@ async fn insert_identity_and_proofs() {
         .expect("Failed to spawn ganache chain");
 
     options.app.ethereum.ethereum_provider =
-        Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint");
-    options.app.contracts.semaphore_address = semaphore_address;
+        vec![Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint")];
+    options.app.contracts.semaphore_address = semaphore_address.into();
     options.app.ethereum.signing_key = private_key;
-    options.app.ethereum.confirmation_blocks_delay = 2;
+    options.app.ethereum.ingest_confirmations = 2;
     options.app.ethereum.refresh_rate = Duration::from_secs(1);
 
     let (app, local_addr) = spawn_app(options.clone())
@@ -156,7 +171,10 @@ async fn insert_identity_and_proofs() {
         .expect("Failed to spawn app.");
 
     let uri = "http://".to_owned() + &local_addr.to_string();
-    let mut ref_tree = PoseidonTree::new(22, options.app.contracts.initial_leaf_value);
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
     let client = Client::new();
     test_inclusion_proof(
         &uri,
@@ -278,207 +296,2768 @@ async fn insert_identity_and_proofs() {
     reset_shutdown();
 }
 
-#[instrument(skip_all)]
-async fn wait_for_log_count(
-    provider: &Provider<Http>,
-    semaphore_address: H160,
-    expected_count: usize,
-) {
-    for i in 1..21 {
-        let filter = Filter::new()
-            .address(semaphore_address)
-            .from_block(BlockNumber::Earliest)
-            .to_block(BlockNumber::Latest);
-        let result: Vec<Log> = provider.request("eth_getLogs", [filter]).await.unwrap();
+#[tokio::test]
+#[serial_test::serial]
+async fn pending_identities_are_listed_until_committed() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting pending identities integration test");
 
-        if result.len() >= expected_count {
-            info!(
-                "Got {} logs (vs expected {}), done in iteration {}: {:?}",
-                result.len(),
-                expected_count,
-                i,
-                result
-            );
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.admin_token = Some("secret".to_owned());
 
-            // TODO: Figure out a better way to do this.
-            // Getting a log event is not enough. The app waits for 1 transaction
-            // confirmation. It will arrive only after the first poll interval.
-            // The DEFAULT_POLL_INTERVAL in ethers-providers is 7 seconds.
-            tokio::time::sleep(Duration::from_secs(8)).await;
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
 
-            return;
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let admin = |path: &str| {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("{uri}{path}"))
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .expect("Failed to create admin request");
+        let response = client.request(request);
+        async move {
+            let response = response.await.expect("Failed to execute request.");
+            assert_eq!(response.status(), StatusCode::OK);
         }
+    };
 
-        info!(
-            "Got {} logs (vs expected {}), waiting 1 second, iteration {}",
-            result.len(),
-            expected_count,
-            i
-        );
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
+    // Keep the committer from committing the identity while it is checked.
+    admin("/admin/pause").await;
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
 
-    panic!("Failed waiting for {expected_count} log events");
+    let (status, pending) = get_json(&uri, &client, "/pending?groupId=1").await;
+    assert_eq!(status, StatusCode::OK);
+    let pending = pending
+        .as_array()
+        .expect("Expected a list of pending identities");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["identityCommitment"], json!(leaf));
+    assert_eq!(pending[0]["prospectiveIndex"], json!(0));
+
+    let (_, page) = get_json(&uri, &client, "/pending?groupId=1&limit=1&offset=1").await;
+    assert_eq!(page, json!([]));
+
+    admin("/admin/resume").await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    let (status, pending) = get_json(&uri, &client, "/pending?groupId=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pending, json!([]));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
 }
 
-#[instrument(skip_all)]
-async fn test_inclusion_proof(
-    uri: &str,
-    client: &Client<HttpConnector>,
-    leaf_index: usize,
-    ref_tree: &mut PoseidonTree,
-    leaf: &Hash,
-    expect_failure: bool,
-) {
-    let mut success_response = None;
-    for i in 1..21 {
-        let body = construct_inclusion_proof_body(leaf);
-        info!(?uri, "Contacting");
-        let req = Request::builder()
-            .method("POST")
-            .uri(uri.to_owned() + "/inclusionProof")
-            .header("Content-Type", "application/json")
-            .body(body)
-            .expect("Failed to create inclusion proof hyper::Body");
-        let mut response = client
-            .request(req)
-            .await
-            .expect("Failed to execute request.");
-        if expect_failure {
-            assert!(!response.status().is_success());
-            return;
-        } else {
-            assert!(response.status().is_success());
-        }
+#[tokio::test]
+#[serial_test::serial]
+async fn tree_leaves_are_read_by_range() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting tree leaves integration test");
 
-        let bytes = hyper::body::to_bytes(response.body_mut())
-            .await
-            .expect("Failed to convert response body to bytes");
-        let result = String::from_utf8(bytes.into_iter().collect())
-            .expect("Could not parse response bytes to utf-8");
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.max_tree_leaves_count = 2;
 
-        if result == "\"pending\"" {
-            assert_eq!(response.status(), StatusCode::ACCEPTED);
-            info!("Got pending, waiting 1 second, iteration {}", i);
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        } else {
-            success_response = Some(result);
-            break;
-        }
-    }
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
 
-    let result = success_response.expect("Failed to get success response");
-    let result_json = serde_json::from_str::<serde_json::Value>(&result)
-        .expect("Failed to parse response as json");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
 
-    ref_tree.set(leaf_index, *leaf);
-    let proof = ref_tree.proof(leaf_index).expect("Ref tree malfunctioning");
+    let leaves = TEST_LEAVES[..3]
+        .iter()
+        .map(|leaf| Hash::from_str_radix(leaf, 16).expect("Failed to parse Hash from test leaf"))
+        .collect::<Vec<_>>();
+    for (index, leaf) in leaves.iter().enumerate() {
+        test_insert_identity(&uri, &client, TEST_LEAVES[index]).await;
+        test_inclusion_proof(&uri, &client, index, &mut ref_tree, leaf, false).await;
+    }
 
-    let proof_json = json!({
-        "root": ref_tree.root(),
-        "proof": proof.0.iter().map(|branch| match branch {
-            Branch::Left(hash) => json!({"Left": hash}),
-            Branch::Right(hash) => json!({"Right": hash}),
-        }).collect::<Vec<_>>(),
-    });
+    let (status, page) = get_json(&uri, &client, "/tree/leaves?groupId=1&from=1&count=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        page,
+        json!([
+            { "index": 1, "commitment": leaves[1] },
+            { "index": 2, "commitment": leaves[2] },
+        ])
+    );
 
-    assert_eq!(result_json, proof_json);
+    // The count is capped, and the range ends at the last inserted leaf.
+    let (_, page) = get_json(&uri, &client, "/tree/leaves?groupId=1&count=10").await;
+    assert_eq!(
+        page,
+        json!([
+            { "index": 0, "commitment": leaves[0] },
+            { "index": 1, "commitment": leaves[1] },
+        ])
+    );
+    let (_, page) = get_json(&uri, &client, "/tree/leaves?groupId=1&from=2&count=2").await;
+    assert_eq!(page, json!([{ "index": 2, "commitment": leaves[2] }]));
+    let (_, page) = get_json(&uri, &client, "/tree/leaves?groupId=1&from=3").await;
+    assert_eq!(page, json!([]));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
 }
 
-#[instrument(skip_all)]
-async fn test_insert_identity(
-    uri: &str,
-    client: &Client<HttpConnector>,
-    identity_commitment: &str,
-) {
-    let body = construct_insert_identity_body(identity_commitment);
-    let req = Request::builder()
-        .method("POST")
-        .uri(uri.to_owned() + "/insertIdentity")
-        .header("Content-Type", "application/json")
-        .body(body)
-        .expect("Failed to create insert identity hyper::Body");
+#[tokio::test]
+#[serial_test::serial]
+async fn leaf_origins_match_the_chain() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting leaf origin integration test");
 
-    let mut response = client
-        .request(req)
-        .await
-        .expect("Failed to execute request.");
-    let bytes = hyper::body::to_bytes(response.body_mut())
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
         .await
-        .expect("Failed to convert response body to bytes");
-    let result = String::from_utf8(bytes.into_iter().collect())
-        .expect("Could not parse response bytes to utf-8");
-    if !response.status().is_success() {
-        panic!("Failed to insert identity: {result}");
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let provider =
+        Provider::<Http>::try_from(chain.endpoint()).expect("Failed to initialize chain endpoint");
+
+    let leaves = TEST_LEAVES[..2]
+        .iter()
+        .map(|leaf| Hash::from_str_radix(leaf, 16).expect("Failed to parse Hash from test leaf"))
+        .collect::<Vec<_>>();
+    for (index, leaf) in leaves.iter().enumerate() {
+        test_insert_identity(&uri, &client, TEST_LEAVES[index]).await;
+        test_inclusion_proof(&uri, &client, index, &mut ref_tree, leaf, false).await;
     }
 
-    assert_eq!(result, "null");
-}
+    for (index, leaf) in leaves.iter().enumerate() {
+        let (status, origin) =
+            get_json(&uri, &client, &format!("/leaf/{index}/origin?groupId=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(origin["index"], json!(index));
+        assert_eq!(origin["commitment"], json!(leaf));
+
+        let transaction_hash: H256 = origin["transactionHash"]
+            .as_str()
+            .expect("Transaction hash must be a string")
+            .parse()
+            .expect("Failed to parse transaction hash");
+        let receipt = provider
+            .get_transaction_receipt(transaction_hash)
+            .await
+            .expect("Failed to fetch receipt")
+            .expect("Recorded transaction must be mined");
+        assert_eq!(
+            origin["blockNumber"],
+            json!(receipt
+                .block_number
+                .expect("Receipt must have a block")
+                .as_u64())
+        );
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct InsertIdentityResponse {
-    identity_index: usize,
+    let (status, _) = get_json(&uri, &client, "/leaf/2/origin?groupId=1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
 }
 
-fn construct_inclusion_proof_body(identity_commitment: &Hash) -> Body {
-    Body::from(
+#[tokio::test]
+#[serial_test::serial]
+async fn proofs_are_returned_per_group() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting multi-group inclusion proof integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.max_multi_group_proofs = 3;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+
+    let leaves = TEST_LEAVES[..2]
+        .iter()
+        .map(|leaf| Hash::from_str_radix(leaf, 16).expect("Failed to parse Hash from test leaf"))
+        .collect::<Vec<_>>();
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaves[0], false).await;
+
+    // Only group 1 is served, so entries of other groups are rejected on
+    // their own without failing the rest.
+    let body = json!([
+        { "groupId": 1, "identityCommitment": leaves[0] },
+        { "groupId": 2, "identityCommitment": leaves[0] },
+        { "groupId": 1, "identityCommitment": leaves[1] },
+    ]);
+    let (status, proofs) = post_json(&uri, &client, "/inclusionProofsMultiGroup", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(proofs[0]["groupId"], json!(1));
+    assert_eq!(proofs[0]["proof"]["root"], json!(ref_tree.root()));
+    assert_eq!(proofs[0]["proof"]["index"], json!(0));
+    assert_eq!(
+        proofs[1],
         json!({
-            "groupId": 1,
-            "identityCommitment": identity_commitment,
+            "groupId": 2,
+            "identityCommitment": leaves[0],
+            "error": "invalid group id",
         })
-        .to_string(),
-    )
-}
-
-fn construct_insert_identity_body(identity_commitment: &str) -> Body {
-    Body::from(
+    );
+    assert_eq!(
+        proofs[2],
         json!({
             "groupId": 1,
-            "identityCommitment": identity_commitment,
-
+            "identityCommitment": leaves[1],
+            "error": "provided identity commitment not found",
         })
-        .to_string(),
-    )
-}
+    );
 
-#[instrument(skip_all)]
-async fn spawn_app(options: Options) -> AnyhowResult<(JoinHandle<()>, SocketAddr)> {
-    let app = App::new(options.app).await.expect("Failed to create App");
+    // Requests are capped well below the batch insert size.
+    let entry = json!({ "groupId": 1, "identityCommitment": leaves[0] });
+    let body = json!(vec![entry; 4]);
+    let (status, _) = post_json(&uri, &client, "/inclusionProofsMultiGroup", &body).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
 
-    let ip: IpAddr = match options.server.server.host() {
-        Some(Host::Ipv4(ip)) => ip.into(),
-        Some(Host::Ipv6(ip)) => ip.into(),
-        Some(_) => bail!("Cannot bind {}", options.server.server),
-        None => Ipv4Addr::LOCALHOST.into(),
-    };
-    let port = options.server.server.port().unwrap_or(9998);
-    let addr = SocketAddr::new(ip, port);
-    let listener = TcpListener::bind(addr).expect("Failed to bind random port");
-    let local_addr = listener.local_addr()?;
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
 
-    let app = spawn({
-        async move {
-            info!("App thread starting");
-            server::bind_from_listener(Arc::new(app), Duration::from_secs(30), listener)
-                .await
-                .expect("Failed to bind address");
-            info!("App thread stopping");
-        }
-    });
+#[tokio::test]
+#[serial_test::serial]
+async fn contract_abi_is_loaded_from_a_file() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting contract ABI integration test");
 
-    Ok((app, local_addr))
-}
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct CompiledContract {
-    abi:      Abi,
-    bytecode: String,
-}
+    // An ABI lacking the functions the sequencer calls is refused at startup.
+    let incomplete = std::env::temp_dir().join("signup-sequencer-incomplete-abi.json");
+    std::fs::write(&incomplete, "[]").expect("Failed to write ABI");
+    options.app.contracts.contract_abi = Some(incomplete);
+    assert!(App::new(options.app.clone()).await.is_err());
 
-fn deserialize_to_bytes(input: String) -> AnyhowResult<Bytes> {
-    if input.len() >= 2 && &input[0..2] == "0x" {
-        let bytes: Vec<u8> = hex::decode(&input[2..])?;
+    // The build artifact the mock chain was deployed from.
+    options.app.contracts.contract_abi = Some("./sol/Semaphore.json".into());
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn missing_group_is_created_on_startup() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting group creation integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    // Only group 1 exists on the mock chain.
+    options.app.contracts.group_id = U256::from(2_u64);
+
+    assert!(App::new(options.app.clone()).await.is_err());
+
+    options.app.contracts.create_group_if_missing = true;
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+    let body = json!({ "groupId": 2, "identityCommitment": leaf });
+
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut response = None;
+    for _ in 0..20 {
+        let (status, proof) = post_json(&uri, &client, "/inclusionProof", &body).await;
+        if status == StatusCode::OK {
+            response = Some(proof);
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let response = response.expect("Identity was not inserted into the created group");
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    ref_tree.set(0, leaf);
+    assert_eq!(response["root"], json!(ref_tree.root()));
+    assert_eq!(response["groupId"], json!(2));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn unreduced_commitments_are_normalized_if_enabled() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting commitment normalization integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 2;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.normalize_commitments = true;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+
+    let snark_scalar_field = Hash::from_str_radix(
+        "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        10,
+    )
+    .expect("Failed to parse the SNARK scalar field");
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+    let unreduced = leaf + snark_scalar_field;
+
+    let body = json!({ "groupId": 1, "identityCommitment": unreduced });
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The reduced commitment is inserted, so the unreduced one is a duplicate.
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn identity_status_follows_lifecycle() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting identity status integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 5;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+
+    // Stop automatic mining so every block below is produced on demand.
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] });
+
+    let lifecycle = ["queued", "submitted", "mined", "confirmed"];
+    let mut observed: Vec<String> = Vec::new();
+    for _ in 0..60 {
+        let (status, response) = post_json(&uri, &client, "/identityStatus", &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let status = response["status"]
+            .as_str()
+            .expect("Status must be a string")
+            .to_owned();
+        if observed.last() != Some(&status) {
+            info!(%status, "Identity status changed");
+            observed.push(status.clone());
+        }
+
+        match status.as_str() {
+            // The committer records the transaction before waiting for it, so
+            // nothing is mined until it reports the identity as submitted.
+            "queued" => {}
+            "submitted" => {
+                // Mine a single block once the insertion transaction is in the mempool.
+                let pool = provider
+                    .txpool_status()
+                    .await
+                    .expect("Failed to read txpool status");
+                if !pool.pending.is_zero() {
+                    let _: serde_json::Value = provider
+                        .request("evm_mine", ())
+                        .await
+                        .expect("Failed to mine block");
+                }
+            }
+            "mined" => {
+                // Push the block past the confirmation delay.
+                let _: serde_json::Value = provider
+                    .request("anvil_mine", [U256::from(10)])
+                    .await
+                    .expect("Failed to mine blocks");
+            }
+            _ => break,
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let ranks = observed
+        .iter()
+        .map(|status| lifecycle.iter().position(|s| s == status).unwrap())
+        .collect::<Vec<_>>();
+    assert!(ranks.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(observed.contains(&"submitted".to_owned()));
+    assert!(observed.contains(&"mined".to_owned()));
+    assert_eq!(observed.last().map(String::as_str), Some("confirmed"));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn initial_load_skips_unconfirmed_leaves() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting unconfirmed initial load integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    // Restart with a fresh database and a confirmation window that covers the
+    // whole chain, so the leaf has to come from the live loop.
+    let ingest_confirmations = 50;
+    options.app.ethereum.ingest_confirmations = ingest_confirmations;
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, true).await;
+
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(ingest_confirmations)])
+        .await
+        .expect("Failed to mine blocks");
+
+    let body = json!({ "groupId": 1, "identityCommitment": leaf });
+    let mut loaded = false;
+    for _ in 0..20 {
+        let (status, _) = post_json(&uri, &client, "/inclusionProof", &body).await;
+        if status == StatusCode::OK {
+            loaded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(loaded, "Confirmed leaf was not picked up by the live loop");
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn committer_waits_for_commit_tx_confirmations() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting commit transaction confirmations integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    // Events are never ingested, so only the committer moves the status.
+    options.app.ethereum.ingest_confirmations = 50;
+    options.app.ethereum.commit_tx_confirmations = 3;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.ethereum.provider_poll_interval = Some(Duration::from_millis(500));
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    mine_pending_transaction(&provider).await;
+
+    // Mined once, but short of the required confirmations.
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        identity_status(&uri, &client, TEST_LEAVES[0]).await,
+        "submitted"
+    );
+
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(2)])
+        .await
+        .expect("Failed to mine blocks");
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "mined").await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn committer_recovers_from_orphaned_transaction() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting orphaned commit transaction integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 50;
+    options.app.ethereum.commit_tx_confirmations = 3;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.ethereum.provider_poll_interval = Some(Duration::from_millis(500));
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+    let snapshot_id: U256 = provider
+        .request("evm_snapshot", ())
+        .await
+        .expect("Failed to create EVM snapshot");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    mine_pending_transaction(&provider).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(
+        identity_status(&uri, &client, TEST_LEAVES[0]).await,
+        "submitted"
+    );
+
+    // Orphan the block with the transaction, then let the chain grow past the
+    // confirmations the committer waits for.
+    let _: bool = provider
+        .request("evm_revert", [snapshot_id])
+        .await
+        .expect("Failed to revert EVM snapshot");
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(3)])
+        .await
+        .expect("Failed to mine blocks");
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [1])
+        .await
+        .expect("Failed to enable interval mining");
+
+    // The identity is mined again, either as the same transaction or as a new
+    // one once the orphaned transaction is found dropped.
+    let mut mined = false;
+    for _ in 0..120 {
+        if identity_status(&uri, &client, TEST_LEAVES[0]).await == "mined" {
+            mined = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(
+        mined,
+        "Committer did not recover from the orphaned transaction"
+    );
+    // The group creation and the identity are on the canonical chain.
+    wait_for_log_count(&provider, semaphore_address, 2).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn subscriber_waits_for_ingest_confirmations() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting ingest confirmations integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 5;
+    options.app.ethereum.commit_tx_confirmations = 1;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.ethereum.provider_poll_interval = Some(Duration::from_millis(500));
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    mine_pending_transaction(&provider).await;
+
+    // The committer is done after a single block, the subscriber is not.
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "mined").await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        identity_status(&uri, &client, TEST_LEAVES[0]).await,
+        "mined"
+    );
+
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(5)])
+        .await
+        .expect("Failed to mine blocks");
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "confirmed").await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn unconfirmed_members_are_duplicates() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting unconfirmed member integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    // Far more blocks than the test takes, so nothing is ingested.
+    options.app.ethereum.ingest_confirmations = 50;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.check_unconfirmed_members = true;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "mined").await;
+
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    // Restart with a fresh database, so only the chain knows the commitment.
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+
+    let body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] });
+    let (status, _) = post_json(&uri, &client, "/identityStatus", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // A batch checks all of its commitments against the unconfirmed members.
+    let body = json!({
+        "groupId": 1,
+        "identityCommitments": [TEST_LEAVES[1], TEST_LEAVES[0]],
+    });
+    let (status, response) = post_json(&uri, &client, "/insertIdentities", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response["results"][0]["valid"], true);
+    assert_eq!(response["results"][1]["valid"], false);
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[1]).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+#[serial_test::serial]
+async fn client_drives_insertion_and_proofs() {
+    use signup_sequencer::{app::IdentityStatus, client::SequencerClient};
+
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting client integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let client = SequencerClient::new(
+        Url::parse(&format!("http://{local_addr}/")).expect("Failed to parse app URL"),
+    );
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    let response = client
+        .insert_identity(1, leaf)
+        .await
+        .expect("Failed to insert identity");
+    assert_eq!(response.identity_index, 0);
+    assert!(client.insert_identity(1, leaf).await.is_err());
+
+    let mut proof = None;
+    for _ in 0..20 {
+        match client
+            .inclusion_proof(1, leaf)
+            .await
+            .expect("Failed to fetch inclusion proof")
+        {
+            InclusionProofResponse::Proof {
+                root, proof: path, ..
+            } => {
+                proof = Some((root, path));
+                break;
+            }
+            InclusionProofResponse::Pending { retry_after, .. } => {
+                assert!(retry_after.is_some());
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    let (root, proof) = proof.expect("Identity was never included");
+
+    ref_tree.set(0, leaf);
+    assert_eq!(root, ref_tree.root());
+    assert!(proof == ref_tree.proof(0).expect("Ref tree malfunctioning"));
+
+    let (included_root, included_proof) = client
+        .inclusion_proof_by_commitment(1, leaf)
+        .await
+        .expect("Failed to fetch inclusion proof by commitment");
+    assert_eq!(included_root, root);
+    assert!(included_proof == proof);
+    let unknown =
+        Hash::from_str_radix(TEST_LEAVES[1], 16).expect("Failed to parse Hash from test leaf 1");
+    assert!(client
+        .inclusion_proof_by_commitment(1, unknown)
+        .await
+        .is_err());
+
+    let status = client
+        .identity_status(1, leaf)
+        .await
+        .expect("Failed to fetch identity status");
+    assert_eq!(status.status, IdentityStatus::Confirmed);
+    assert_eq!(status.index, Some(0));
+    assert!(client
+        .pending_identities(1, 10, 0)
+        .await
+        .expect("Failed to list pending identities")
+        .0
+        .is_empty());
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+#[serial_test::serial]
+async fn binary_proof_matches_json_proof() {
+    use signup_sequencer::client::SequencerClient;
+
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting binary proof test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let client = SequencerClient::new(
+        Url::parse(&format!("http://{local_addr}/")).expect("Failed to parse app URL"),
+    );
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+    client
+        .insert_identity(1, leaf)
+        .await
+        .expect("Failed to insert identity");
+
+    // Pending responses stay JSON, so polling works the same in both formats.
+    let mut binary = None;
+    for _ in 0..20 {
+        match client
+            .binary_inclusion_proof(1, leaf)
+            .await
+            .expect("Failed to fetch binary inclusion proof")
+        {
+            response @ InclusionProofResponse::Proof { .. } => {
+                binary = Some(response);
+                break;
+            }
+            InclusionProofResponse::Pending { .. } => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    let Some(InclusionProofResponse::Proof {
+        root: binary_root,
+        proof: binary_proof,
+        index: binary_index,
+        ..
+    }) = binary
+    else {
+        panic!("Identity was never included");
+    };
+    let InclusionProofResponse::Proof {
+        root, proof, index, ..
+    } = client
+        .inclusion_proof(1, leaf)
+        .await
+        .expect("Failed to fetch inclusion proof")
+    else {
+        panic!("Expected a JSON proof of the included identity");
+    };
+    assert_eq!(binary_root, root);
+    assert!(binary_proof == proof);
+    assert_eq!(binary_index, index);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn flat_proof_matches_json_proof() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting flat proof test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let proof = wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+
+    let body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] });
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri.clone() + "/inclusionProof")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(server::PROOF_FORMAT_HEADER, "flat")
+        .body(Body::from(body.to_string()))
+        .expect("Failed to create flat proof request");
+    let mut response = client
+        .request(request)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let flat: serde_json::Value =
+        serde_json::from_slice(&bytes).expect("Failed to parse flat proof");
+
+    assert_eq!(flat["root"], proof["root"]);
+    let branches = proof["proof"].as_array().expect("Proof must be a list");
+    let siblings = flat["siblings"]
+        .as_array()
+        .expect("Siblings must be a list");
+    let path_indices = flat["pathIndices"]
+        .as_array()
+        .expect("Path indices must be a list");
+    assert_eq!(siblings.len(), branches.len());
+    for ((branch, sibling), path_index) in branches.iter().zip(siblings).zip(path_indices) {
+        match path_index.as_u64() {
+            Some(0) => assert_eq!(&branch["Left"], sibling),
+            Some(1) => assert_eq!(&branch["Right"], sibling),
+            _ => panic!("Invalid path index {path_index}"),
+        }
+    }
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn inclusion_proof_is_served_over_json_rpc() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting JSON-RPC test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.json_rpc = true;
+    options.app.max_json_rpc_batch_size = 3;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let proof = wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+
+    let call = json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": "inclusionProof",
+        "params": { "groupId": 1, "identityCommitment": TEST_LEAVES[0] },
+    });
+    let (status, response) = post_json(&uri, &client, "/rpc", &call).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["jsonrpc"], "2.0");
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["result"], proof);
+    assert!(response.get("error").is_none());
+
+    // Errors of the sequencer and of the envelope use the JSON-RPC codes.
+    let batch = json!([
+        {
+            "jsonrpc": "2.0",
+            "id": "wrong-group",
+            "method": "inclusionProof",
+            "params": { "groupId": 2, "identityCommitment": TEST_LEAVES[0] },
+        },
+        { "jsonrpc": "2.0", "id": "unknown", "method": "deleteIdentity", "params": {} },
+        { "jsonrpc": "2.0", "method": "groupInfo", "params": { "groupId": 1 } },
+    ]);
+    let (status, response) = post_json(&uri, &client, "/rpc", &batch).await;
+    assert_eq!(status, StatusCode::OK);
+    let responses = response.as_array().expect("Batch response must be a list");
+    assert_eq!(responses.len(), 2, "Notifications must not be answered");
+    assert_eq!(responses[0]["id"], "wrong-group");
+    assert_eq!(responses[0]["error"]["code"], -32602);
+    assert_eq!(responses[0]["error"]["message"], "invalid group id");
+    assert!(responses[0].get("result").is_none());
+    assert_eq!(responses[1]["id"], "unknown");
+    assert_eq!(responses[1]["error"]["code"], -32601);
+
+    // Batches over the cap are refused as a whole.
+    let call = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "groupInfo",
+        "params": { "groupId": 1 },
+    });
+    let batch = serde_json::Value::Array(vec![call; 4]);
+    let (status, response) = post_json(&uri, &client, "/rpc", &batch).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["error"]["data"]["status"], 413);
+
+    let call = json!({
+        "jsonrpc": "2.0",
+        "id": 8,
+        "method": "groupInfo",
+        "params": { "groupId": 1 },
+    });
+    let (_, response) = post_json(&uri, &client, "/rpc", &call).await;
+    assert_eq!(response["result"]["root"], proof["root"]);
+    assert_eq!(response["result"]["leafCount"], 1);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn unconfirmed_proof_of_queued_identity_verifies_locally() {
+    use signup_sequencer::client::SequencerClient;
+
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting unconfirmed proof test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    // Keep the identity out of the tree for the duration of the test.
+    options.app.ethereum.refresh_rate = Duration::from_secs(3600);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let client = SequencerClient::new(
+        Url::parse(&format!("http://{local_addr}/")).expect("Failed to parse app URL"),
+    );
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    client
+        .insert_identity(1, leaf)
+        .await
+        .expect("Failed to insert identity");
+
+    let InclusionProofResponse::Proof {
+        root,
+        proof,
+        unconfirmed,
+        ..
+    } = client
+        .unconfirmed_inclusion_proof(1, leaf)
+        .await
+        .expect("Failed to fetch unconfirmed inclusion proof")
+    else {
+        panic!("Expected an unconfirmed proof of the queued identity");
+    };
+    assert!(unconfirmed);
+    assert_eq!(proof.root(leaf), root);
+    ref_tree.set(0, leaf);
+    assert_eq!(root, ref_tree.root());
+
+    // Strict requests keep waiting for the identity to be confirmed.
+    assert!(matches!(
+        client
+            .inclusion_proof(1, leaf)
+            .await
+            .expect("Failed to fetch inclusion proof"),
+        InclusionProofResponse::Pending { .. }
+    ));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn next_empty_proof_verifies_for_initial_leaf() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting next empty proof test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let initial_leaf = options.app.contracts.initial_leaf_value;
+    let mut ref_tree = PoseidonTree::new(poseidon_tree_depth(TREE_DEPTH), initial_leaf);
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+    ref_tree.set(
+        0,
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0"),
+    );
+
+    let (status, response) = get_json(&uri, &client, "/nextEmptyProof?groupId=1").await;
+    assert_eq!(status, StatusCode::OK);
+    let InclusionProofResponse::Proof {
+        root, proof, index, ..
+    } = serde_json::from_value(response).expect("Failed to parse proof response")
+    else {
+        panic!("Expected a proof of the next empty leaf");
+    };
+    assert_eq!(index, Some(1));
+    assert_eq!(root, ref_tree.root());
+    assert_eq!(proof.root(initial_leaf), root);
+    assert!(ref_tree.proof(1) == Some(proof));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn large_responses_are_compressed_when_accepted() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting response compression test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.server.compression_threshold = 1024;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+
+    let fetch_proof = |accept_encoding: Option<&'static str>| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(uri.clone() + "/inclusionProof")
+            .header("Content-Type", "application/json");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        let request = request
+            .body(construct_inclusion_proof_body(&leaf))
+            .expect("Failed to create inclusion proof hyper::Body");
+        client.request(request)
+    };
+
+    // Wait for the identity to be mined, so the response is a full proof.
+    let mut plain = None;
+    for _ in 0..20 {
+        let mut response = fetch_proof(None).await.expect("Failed to execute request.");
+        if response.status() == StatusCode::OK {
+            assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+            plain = Some(
+                hyper::body::to_bytes(response.body_mut())
+                    .await
+                    .expect("Failed to convert response body to bytes"),
+            );
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let plain = plain.expect("Failed to get inclusion proof");
+    assert!(
+        plain.len() > 1024,
+        "Proof of {} bytes is too small",
+        plain.len()
+    );
+
+    let mut response = fetch_proof(Some("gzip"))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_ENCODING),
+        Some(&header::HeaderValue::from_static("gzip"))
+    );
+    let compressed = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    assert!(compressed.len() < plain.len());
+    let mut decoded = Vec::new();
+    GzDecoder::new(compressed.as_ref())
+        .read_to_end(&mut decoded)
+        .expect("Failed to decode gzip response");
+    assert_eq!(decoded, plain);
+
+    // Small responses are sent as is.
+    let request = Request::builder()
+        .method("GET")
+        .uri(uri.clone() + "/pending?groupId=1")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .expect("Failed to create pending request");
+    let response = client
+        .request(request)
+        .await
+        .expect("Failed to execute request.");
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn request_bodies_over_the_limit_are_rejected() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting request body limit test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.server.max_request_body_bytes = 256;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let insert = |identity_commitment: &str, size: usize| {
+        // Trailing whitespace pads the body to `size` bytes.
+        let body = json!({
+            "groupId": 1,
+            "identityCommitment": identity_commitment,
+        })
+        .to_string();
+        let body = format!("{body:size$}");
+        assert_eq!(body.len(), size);
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri.clone() + "/insertIdentity")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .expect("Failed to create insert identity hyper::Body");
+        client.request(request)
+    };
+
+    let response = insert(TEST_LEAVES[0], 257)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = insert(TEST_LEAVES[1], 256)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn status_reports_the_signing_wallet() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting wallet status test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let request = Request::builder()
+        .method("GET")
+        .uri(uri + "/status")
+        .body(Body::empty())
+        .expect("Failed to create status request");
+    let mut response = client
+        .request(request)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let status: serde_json::Value =
+        serde_json::from_slice(&bytes).expect("Failed to parse status response");
+
+    let wallet = LocalWallet::from_bytes(options.app.ethereum.signing_key.as_bytes())
+        .expect("Invalid signing key");
+    let address: Address = serde_json::from_value(status["walletAddress"].clone())
+        .expect("Failed to parse wallet address");
+    assert_eq!(address, wallet.address());
+    let balance: U256 = serde_json::from_value(status["walletBalance"].clone())
+        .expect("Failed to parse wallet balance");
+    assert!(!balance.is_zero());
+    assert_eq!(status["committerPaused"], json!(false));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn routes_are_served_under_the_base_path() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting base path test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.server.base_path = "/sequencer/".to_owned();
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    test_insert_identity(&(uri.clone() + "/sequencer"), &client, TEST_LEAVES[0]).await;
+    let (status, _) = get_json(&uri, &client, "/sequencer/status").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[1] });
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get_json(&uri, &client, "/status").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get_json(&uri, &client, "/sequencerstatus").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn repeated_inserts_with_an_idempotency_key_return_the_original_response() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting idempotency key test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let insert = |identity_commitment: &str| {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri.clone() + "/insertIdentity")
+            .header("Content-Type", "application/json")
+            .header(server::IDEMPOTENCY_KEY, "retried-insert")
+            .body(construct_insert_identity_body(identity_commitment))
+            .expect("Failed to create insert identity hyper::Body");
+        let response = client.request(request);
+        async move {
+            let mut response = response.await.expect("Failed to execute request.");
+            let bytes = hyper::body::to_bytes(response.body_mut())
+                .await
+                .expect("Failed to convert response body to bytes");
+            (response.status(), bytes)
+        }
+    };
+
+    let original = insert(TEST_LEAVES[0]).await;
+    assert_eq!(original.0, StatusCode::OK);
+    let response: InsertIdentityResponse =
+        serde_json::from_slice(&original.1).expect("Failed to parse insert response");
+    assert_eq!(response.identity_index, 0);
+    assert_eq!(insert(TEST_LEAVES[0]).await, original);
+
+    // Without the key the repeated insert is a duplicate. The key is scoped to
+    // the commitment, so another client may use it for its own commitment.
+    let body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] });
+    let (status, _) = post_json(&uri, &client, "/insertIdentity", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let other = insert(TEST_LEAVES[1]).await;
+    assert_eq!(other.0, StatusCode::OK);
+    let response: InsertIdentityResponse =
+        serde_json::from_slice(&other.1).expect("Failed to parse insert response");
+    assert_eq!(response.identity_index, 1);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn read_only_instance_serves_proofs_but_refuses_inserts() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting read-only instance integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    // A regular instance commits an identity.
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let committed = wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    // A read-only instance picks it up from the chain, without a signing key.
+    options.app.read_only = true;
+    options.app.ethereum.signing_key = H256::zero();
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn read-only app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+
+    let served = wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+    assert_eq!(served["root"], committed["root"]);
+    assert_eq!(served["proof"], committed["proof"]);
+
+    let (status, _) = post_json(
+        &uri,
+        &client,
+        "/insertIdentity",
+        &json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[1] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn verify_proof_distinguishes_tampered_and_stale_proofs() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting proof verification test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    let first = wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+    let verify = |root: &serde_json::Value, proof: &serde_json::Value| {
+        json!({
+            "groupId": 1,
+            "identityCommitment": TEST_LEAVES[0],
+            "root": root,
+            "proof": proof,
+        })
+    };
+
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/proof/verify",
+        &verify(&first["root"], &first["proof"]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response, json!({ "valid": true, "root": "current" }));
+
+    let mut tampered = first["proof"].clone();
+    *tampered[0]
+        .as_object_mut()
+        .and_then(|branch| branch.values_mut().next())
+        .expect("Branch must be an object") = json!(Hash::from(1_u64));
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/proof/verify",
+        &verify(&first["root"], &tampered),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response, json!({ "valid": false, "root": "current" }));
+
+    // Once another identity is inserted, the first proof is against a stale root.
+    test_insert_identity(&uri, &client, TEST_LEAVES[1]).await;
+    wait_for_proof(&uri, &client, TEST_LEAVES[1]).await;
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/proof/verify",
+        &verify(&first["root"], &first["proof"]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response, json!({ "valid": true, "root": "historical" }));
+
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/proof/verify",
+        &verify(&json!(Hash::from(1_u64)), &first["proof"]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response, json!({ "valid": false, "root": "unknown" }));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn serves_over_unix_domain_socket() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting unix domain socket integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let socket_path =
+        std::env::temp_dir().join(format!("signup-sequencer-{}.sock", std::process::id()));
+    let app = App::new(options.app).await.expect("Failed to create App");
+    let app = spawn({
+        let socket_path = socket_path.clone();
+        async move {
+            server::bind_from_uds(Arc::new(app), options.server.server_config(), &socket_path)
+                .await
+                .expect("Failed to bind socket");
+        }
+    });
+
+    let mut stream = None;
+    for _ in 0..20 {
+        if let Ok(connected) = UnixStream::connect(&socket_path).await {
+            stream = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let stream = stream.expect("Failed to connect to socket");
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .expect("Failed to establish HTTP connection");
+    spawn(connection);
+
+    let req = Request::builder()
+        .method("GET")
+        .uri("/pending?groupId=1")
+        .header("Host", "localhost")
+        .body(Body::empty())
+        .expect("Failed to create GET request");
+    let mut response = sender
+        .send_request(req)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let pending: serde_json::Value =
+        serde_json::from_slice(&bytes).expect("Failed to parse response as json");
+    assert_eq!(pending, json!([]));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    assert!(!socket_path.exists(), "Socket file was not cleaned up");
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn serves_over_tls() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting TLS integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    // A self-signed certificate, trusted by the client below.
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()])
+        .expect("Failed to generate certificate");
+    let dir = std::env::temp_dir();
+    let tls_files = server::TlsFiles {
+        cert: dir.join(format!(
+            "signup-sequencer-tls-cert-{}.pem",
+            std::process::id()
+        )),
+        key:  dir.join(format!(
+            "signup-sequencer-tls-key-{}.pem",
+            std::process::id()
+        )),
+    };
+    std::fs::write(&tls_files.cert, cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&tls_files.key, cert.serialize_private_key_pem()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let local_addr = listener.local_addr().unwrap();
+    let app = App::new(options.app).await.expect("Failed to create App");
+    let app = spawn({
+        let tls_files = tls_files.clone();
+        async move {
+            server::bind_tls(Arc::new(app), options.server.server_config(), listener, tls_files)
+                .await
+                .expect("Failed to serve over TLS");
+        }
+    });
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(cert.serialize_der().unwrap()))
+        .expect("Failed to trust certificate");
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let stream = TcpStream::connect(local_addr)
+        .await
+        .expect("Failed to connect");
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect("localhost".try_into().unwrap(), stream)
+        .await
+        .expect("Failed to complete TLS handshake");
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(stream)
+        .await
+        .expect("Failed to establish HTTP connection");
+    spawn(connection);
+
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!(
+            "https://localhost:{}/pending?groupId=1",
+            local_addr.port()
+        ))
+        .body(Body::empty())
+        .expect("Failed to create GET request");
+    let mut response = sender
+        .send_request(req)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let pending: serde_json::Value =
+        serde_json::from_slice(&bytes).expect("Failed to parse response as json");
+    assert_eq!(pending, json!([]));
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    std::fs::remove_file(&tls_files.cert).unwrap();
+    std::fs::remove_file(&tls_files.key).unwrap();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn shutdown_drains_in_flight_requests() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting connection draining integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    // Start a request whose body is still being streamed when shutdown begins.
+    let stream = TcpStream::connect(local_addr)
+        .await
+        .expect("Failed to connect to app");
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .expect("Failed to establish HTTP connection");
+    spawn(connection);
+    let (mut body_sender, body) = Body::channel();
+    let req = Request::builder()
+        .method("POST")
+        .uri("/inclusionProof")
+        .header("Host", "localhost")
+        .header("Content-Type", "application/json")
+        .body(body)
+        .expect("Failed to create inclusion proof hyper::Body");
+    let in_flight = spawn(sender.send_request(req));
+    let request_body = json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] }).to_string();
+    let (head, tail) = request_body.split_at(10);
+    body_sender
+        .send_data(head.to_owned().into())
+        .await
+        .expect("Failed to send request body");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    shutdown();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // New connections are refused while the in-flight request drains.
+    assert!(TcpStream::connect(local_addr).await.is_err());
+
+    body_sender
+        .send_data(tail.to_owned().into())
+        .await
+        .expect("Failed to send request body");
+    drop(body_sender);
+    let response = in_flight
+        .await
+        .unwrap()
+        .expect("In-flight request was not completed");
+    // The commitment is unknown, so the request completes with a client error.
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn inclusion_proofs_are_signed() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting signed response integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.sign_responses = true;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri.clone() + "/inclusionProof")
+        .header("Content-Type", "application/json")
+        .body(construct_inclusion_proof_body(&leaf))
+        .expect("Failed to create inclusion proof hyper::Body");
+    let mut response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let signature: Signature = response
+        .headers()
+        .get(server::SIGNATURE_HEADER)
+        .expect("Response is not signed")
+        .to_str()
+        .expect("Signature is not ASCII")
+        .parse()
+        .expect("Failed to parse signature");
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let InclusionProofResponse::Proof {
+        root,
+        proof,
+        unconfirmed,
+        ..
+    } = serde_json::from_slice(&bytes).expect("Failed to parse inclusion proof")
+    else {
+        panic!("Expected an inclusion proof");
+    };
+
+    let signer = LocalWallet::from_bytes(options.app.ethereum.signing_key.as_bytes())
+        .expect("Failed to create wallet")
+        .address();
+    signature
+        .verify(
+            inclusion_proof_message(1, 0, &root, &proof, unconfirmed),
+            signer,
+        )
+        .expect("Signature does not verify against the signing key");
+    // The signature does not carry over to another group, leaf or confirmation
+    // state.
+    for (group_id, index, unconfirmed) in [
+        (2, 0, unconfirmed),
+        (1, 1, unconfirmed),
+        (1, 0, !unconfirmed),
+    ] {
+        assert!(signature
+            .verify(
+                inclusion_proof_message(group_id, index, &root, &proof, unconfirmed),
+                signer,
+            )
+            .is_err());
+    }
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn batch_insert_is_all_or_nothing() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting batch insert integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+
+    // Keep the committer from draining the queue while it is inspected.
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+    let _: serde_json::Value = provider
+        .request("evm_setAutomine", [false])
+        .await
+        .expect("Failed to disable automine");
+
+    // The initial leaf is not a valid commitment, so nothing is queued.
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/insertIdentities",
+        &json!({
+            "groupId": 1,
+            "identityCommitments": [
+                TEST_LEAVES[0],
+                options.app.contracts.initial_leaf_value,
+                TEST_LEAVES[1],
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response["queued"], json!(false));
+    let valid = response["results"]
+        .as_array()
+        .expect("Results must be an array")
+        .iter()
+        .map(|result| result["valid"].as_bool().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(valid, [true, false, true]);
+    let (_, pending) = get_json(&uri, &client, "/pending?groupId=1").await;
+    assert_eq!(pending, json!([]));
+
+    // Without the invalid commitment the whole batch is queued.
+    let (status, response) = post_json(
+        &uri,
+        &client,
+        "/insertIdentities",
+        &json!({
+            "groupId": 1,
+            "identityCommitments": [TEST_LEAVES[0], TEST_LEAVES[1]],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["queued"], json!(true));
+    for leaf in &TEST_LEAVES[..2] {
+        let (status, _) = post_json(
+            &uri,
+            &client,
+            "/identityStatus",
+            &json!({ "groupId": 1, "identityCommitment": leaf }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn inserts_are_refused_while_chain_is_unreachable() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting chain unavailable integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.chain_unavailable_timeout = 0;
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let mut ref_tree = PoseidonTree::new(
+        poseidon_tree_depth(TREE_DEPTH),
+        options.app.contracts.initial_leaf_value,
+    );
+    let client = Client::new();
+    let leaf =
+        Hash::from_str_radix(TEST_LEAVES[0], 16).expect("Failed to parse Hash from test leaf 0");
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    // Stop the chain and give the subscriber a chance to notice.
+    drop(chain);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let (status, _) = post_json(
+        &uri,
+        &client,
+        "/insertIdentity",
+        &json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[1] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // Proofs are still served from the in-memory tree.
+    test_inclusion_proof(&uri, &client, 0, &mut ref_tree, &leaf, false).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn denylisted_commitments_are_forbidden() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting commitment denylist integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+
+    let denylist =
+        std::env::temp_dir().join(format!("signup-sequencer-denylist-{}", std::process::id()));
+    std::fs::write(&denylist, format!("0x{}\n", TEST_LEAVES[0])).unwrap();
+
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.commitment_denylist = Some(denylist.clone());
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    let (status, _) = post_json(
+        &uri,
+        &client,
+        "/insertIdentity",
+        &json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[1]).await;
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    std::fs::remove_file(denylist).unwrap();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn admin_logs_lists_events_in_block_range() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting admin logs integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.admin_token = Some("secret".to_owned());
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    test_insert_identity(&uri, &client, TEST_LEAVES[1]).await;
+    wait_for_proof(&uri, &client, TEST_LEAVES[1]).await;
+
+    let get_logs = |query: &str, token: &str| {
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!("{uri}/admin/logs?{query}"))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .expect("Failed to create logs request");
+        let response = client.request(request);
+        async move {
+            let mut response = response.await.expect("Failed to execute request.");
+            let bytes = hyper::body::to_bytes(response.body_mut())
+                .await
+                .expect("Failed to convert response body to bytes");
+            let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+            (response.status(), json)
+        }
+    };
+
+    let (status, _) = get_logs("fromBlock=0&toBlock=1000", "wrong").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = get_logs("fromBlock=0&toBlock=1000000", "secret").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, events) = get_logs("fromBlock=0&toBlock=1000", "secret").await;
+    assert_eq!(status, StatusCode::OK);
+    let events = events.as_array().expect("Expected a list of events");
+    assert_eq!(events.len(), 2);
+    for (index, (event, leaf)) in events.iter().zip(&TEST_LEAVES[..2]).enumerate() {
+        let leaf = Hash::from_str_radix(leaf, 16).expect("Failed to parse Hash from test leaf");
+        assert_eq!(event["leaf"], json!(leaf));
+        assert_eq!(event["index"], json!(index));
+        assert_eq!(event["removed"], json!(false));
+        assert!(event["block"].as_u64().is_some());
+    }
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn root_check_tolerates_unconfirmed_events() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting root check integration test");
+
+    let (chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.ethereum.ingest_confirmations = 5;
+    options.app.ethereum.commit_tx_confirmations = 1;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+    options.app.ethereum.provider_poll_interval = Some(Duration::from_millis(500));
+    options.app.admin_token = Some("secret".to_owned());
+
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    let provider = Provider::<Http>::try_from(chain.endpoint())
+        .expect("Failed to initialize chain endpoint")
+        .interval(Duration::from_millis(500u64));
+    let _: serde_json::Value = provider
+        .request("evm_setIntervalMining", [0])
+        .await
+        .expect("Failed to disable interval mining");
+
+    let root_check = |token: &str| {
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!("{uri}/admin/rootCheck"))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .expect("Failed to create root check request");
+        let response = client.request(request);
+        async move {
+            let mut response = response.await.expect("Failed to execute request.");
+            let bytes = hyper::body::to_bytes(response.body_mut())
+                .await
+                .expect("Failed to convert response body to bytes");
+            let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+            (response.status(), json)
+        }
+    };
+
+    let (status, _) = root_check("wrong").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, check) = root_check("secret").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(check["match"], json!(true));
+    assert_eq!(check["localRoot"], check["onChainRoot"]);
+
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    mine_pending_transaction(&provider).await;
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "mined").await;
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(5)])
+        .await
+        .expect("Failed to mine blocks");
+    wait_for_status(&uri, &client, TEST_LEAVES[0], "confirmed").await;
+
+    // The contract moves on while the subscriber waits for confirmations, and
+    // the local root is an earlier one of the contract.
+    test_insert_identity(&uri, &client, TEST_LEAVES[1]).await;
+    mine_pending_transaction(&provider).await;
+    wait_for_status(&uri, &client, TEST_LEAVES[1], "mined").await;
+    let (_, check) = root_check("secret").await;
+    assert_eq!(check["match"], json!(true));
+    assert_ne!(check["localRoot"], check["onChainRoot"]);
+
+    let _: serde_json::Value = provider
+        .request("anvil_mine", [U256::from(5)])
+        .await
+        .expect("Failed to mine blocks");
+    wait_for_status(&uri, &client, TEST_LEAVES[1], "confirmed").await;
+    let (_, check) = root_check("secret").await;
+    assert_eq!(check["match"], json!(true));
+    assert_eq!(check["localRoot"], check["onChainRoot"]);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn mismatching_tree_starts_degraded_if_enabled() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting degraded startup integration test");
+
+    let database = std::env::temp_dir().join(format!(
+        "signup-sequencer-degraded-{}.db",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&database);
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    options.app.database.database =
+        Url::parse(&format!("sqlite://{}?mode=rwc", database.display()))
+            .expect("Failed to parse database URL");
+    options.app.ethereum.ingest_confirmations = 0;
+    options.app.ethereum.refresh_rate = Duration::from_secs(1);
+
+    // A regular instance commits an identity, caching its event.
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+    test_insert_identity(&uri, &client, TEST_LEAVES[0]).await;
+    wait_for_proof(&uri, &client, TEST_LEAVES[0]).await;
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    // Corrupt the cache, so the tree rebuilt from it does not match.
+    let pool = sqlx::SqlitePool::connect(options.app.database.database.as_str())
+        .await
+        .expect("Failed to open the database");
+    sqlx::query("UPDATE logs SET leaf = root")
+        .execute(&pool)
+        .await
+        .expect("Failed to corrupt the cache");
+    pool.close().await;
+
+    options.app.start_on_health_warning = true;
+    options.app.ethereum.cache_recovery_max_attempts = 0;
+    options.app.ethereum.cache_recovery_wipe = false;
+    options.app.admin_token = Some("secret".to_owned());
+    let (app, local_addr) = spawn_app(options.clone())
+        .await
+        .expect("Failed to spawn degraded app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+
+    let (status, health) = get_json(&uri, &client, "/health").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["degraded"], json!(true));
+
+    // The root of the tampered tree is none of the contract's.
+    let request = Request::builder()
+        .method("GET")
+        .uri(format!("{uri}/admin/rootCheck"))
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .expect("Failed to create root check request");
+    let mut response = client
+        .request(request)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let check: serde_json::Value =
+        serde_json::from_slice(&bytes).expect("Failed to parse root check");
+    assert_eq!(check["match"], json!(false));
+    let (status, _) = post_json(
+        &uri,
+        &client,
+        "/inclusionProof",
+        &json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[0] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let (status, _) = post_json(
+        &uri,
+        &client,
+        "/insertIdentity",
+        &json!({ "groupId": 1, "identityCommitment": TEST_LEAVES[1] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // Shutdown app and reset mock shutdown
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+    let _ = std::fs::remove_file(&database);
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn startup_rejects_unexpected_chain_id() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting chain id integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    // Anvil runs on chain id 31337.
+    options.app.ethereum.expected_chain_id = Some(1);
+
+    let Err(error) = App::new(options.app).await else {
+        panic!("App started on an unexpected chain");
+    };
+    assert!(error.to_string().contains("expected 1"));
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn startup_rejects_mismatched_initial_leaf() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting initial leaf integration test");
+
+    let mut options = Options::try_parse_from([""]).expect("Failed to create options");
+
+    let (chain, private_key, semaphore_address) =
+        spawn_mock_chain_with_initial_leaf(U256::from(42_u64))
+            .await
+            .expect("Failed to spawn ganache chain");
+
+    options.app.ethereum.ethereum_provider =
+        vec![Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint")];
+    options.app.contracts.semaphore_address = semaphore_address.into();
+    options.app.ethereum.signing_key = private_key;
+    options.app.contracts.initial_leaf_value = Hash::from(43_u64);
+
+    let Err(error) = App::new(options.app).await else {
+        panic!("App started with a mismatched initial leaf");
+    };
+    assert!(error
+        .to_string()
+        .contains("does not match the group's zero value"));
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn startup_rejects_batches_the_contract_does_not_accept() {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting commit batch size integration test");
+
+    let (_chain, mut options) = spawn_mock_chain_with_options().await;
+    // The legacy contract registers one identity per transaction.
+    options.app.commit_batch_size = 2;
+
+    let Err(error) = App::new(options.app).await else {
+        panic!("App started with a batch size the contract does not accept");
+    };
+    assert!(error
+        .to_string()
+        .contains("exceeds the 1 identities the contract accepts"));
+}
+
+#[instrument(skip_all)]
+async fn post_json(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    path: &str,
+    body: &serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri.to_owned() + path)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Failed to create POST request");
+    let mut response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (response.status(), json)
+}
+
+/// Polls `/inclusionProof` until `identity_commitment` is in the tree, and
+/// returns the proof response.
+#[instrument(skip_all)]
+async fn wait_for_proof(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    identity_commitment: &str,
+) -> serde_json::Value {
+    let body = json!({ "groupId": 1, "identityCommitment": identity_commitment });
+    for _ in 0..30 {
+        let (status, response) = post_json(uri, client, "/inclusionProof", &body).await;
+        if status == StatusCode::OK {
+            return response;
+        }
+        assert_eq!(status, StatusCode::ACCEPTED);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("Failed waiting for the inclusion proof of {identity_commitment}");
+}
+
+/// Returns the lifecycle status of `identity_commitment`.
+async fn identity_status(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    identity_commitment: &str,
+) -> String {
+    let body = json!({ "groupId": 1, "identityCommitment": identity_commitment });
+    let (status, response) = post_json(uri, client, "/identityStatus", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    response["status"]
+        .as_str()
+        .expect("Status must be a string")
+        .to_owned()
+}
+
+/// Polls `/identityStatus` until `identity_commitment` reaches `expected`.
+#[instrument(skip_all)]
+async fn wait_for_status(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    identity_commitment: &str,
+    expected: &str,
+) {
+    for _ in 0..30 {
+        if identity_status(uri, client, identity_commitment).await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("Failed waiting for {identity_commitment} to become {expected}");
+}
+
+/// Waits for a transaction to reach the mempool and mines a single block.
+#[instrument(skip_all)]
+async fn mine_pending_transaction(provider: &Provider<Http>) {
+    for _ in 0..30 {
+        let pool = provider
+            .txpool_status()
+            .await
+            .expect("Failed to read txpool status");
+        if !pool.pending.is_zero() {
+            let _: serde_json::Value = provider
+                .request("evm_mine", ())
+                .await
+                .expect("Failed to mine block");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    panic!("Failed waiting for a pending transaction");
+}
+
+#[instrument(skip_all)]
+async fn get_json(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    path: &str,
+) -> (StatusCode, serde_json::Value) {
+    let req = Request::builder()
+        .method("GET")
+        .uri(uri.to_owned() + path)
+        .body(Body::empty())
+        .expect("Failed to create GET request");
+    let mut response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (response.status(), json)
+}
+
+#[instrument(skip_all)]
+async fn wait_for_log_count(
+    provider: &Provider<Http>,
+    semaphore_address: H160,
+    expected_count: usize,
+) {
+    for i in 1..21 {
+        let filter = Filter::new()
+            .address(semaphore_address)
+            .from_block(BlockNumber::Earliest)
+            .to_block(BlockNumber::Latest);
+        let result: Vec<Log> = provider.request("eth_getLogs", [filter]).await.unwrap();
+
+        if result.len() >= expected_count {
+            info!(
+                "Got {} logs (vs expected {}), done in iteration {}: {:?}",
+                result.len(),
+                expected_count,
+                i,
+                result
+            );
+
+            // Getting a log event is not enough. The app waits for 1 transaction
+            // confirmation. It will arrive only after the first poll interval,
+            // which the test configures to 500ms.
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            return;
+        }
+
+        info!(
+            "Got {} logs (vs expected {}), waiting 1 second, iteration {}",
+            result.len(),
+            expected_count,
+            i
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    panic!("Failed waiting for {expected_count} log events");
+}
+
+#[instrument(skip_all)]
+async fn test_inclusion_proof(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    leaf_index: usize,
+    ref_tree: &mut PoseidonTree,
+    leaf: &Hash,
+    expect_failure: bool,
+) {
+    let mut success_response = None;
+    for i in 1..21 {
+        let body = construct_inclusion_proof_body(leaf);
+        info!(?uri, "Contacting");
+        let req = Request::builder()
+            .method("POST")
+            .uri(uri.to_owned() + "/inclusionProof")
+            .header("Content-Type", "application/json")
+            .body(body)
+            .expect("Failed to create inclusion proof hyper::Body");
+        let mut response = client
+            .request(req)
+            .await
+            .expect("Failed to execute request.");
+        if expect_failure {
+            assert!(!response.status().is_success());
+            return;
+        } else {
+            assert!(response.status().is_success());
+        }
+
+        let bytes = hyper::body::to_bytes(response.body_mut())
+            .await
+            .expect("Failed to convert response body to bytes");
+        let result = String::from_utf8(bytes.into_iter().collect())
+            .expect("Could not parse response bytes to utf-8");
+
+        if result == "\"pending\"" {
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            assert!(response.headers().contains_key(header::RETRY_AFTER));
+            info!("Got pending, waiting 1 second, iteration {}", i);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        } else {
+            success_response = Some(result);
+            break;
+        }
+    }
+
+    let result = success_response.expect("Failed to get success response");
+    let result_json = serde_json::from_str::<serde_json::Value>(&result)
+        .expect("Failed to parse response as json");
+
+    ref_tree.set(leaf_index, *leaf);
+    let proof = ref_tree.proof(leaf_index).expect("Ref tree malfunctioning");
+
+    let proof_json = json!({
+        "groupId": 1,
+        "root": ref_tree.root(),
+        "proof": proof.0.iter().map(|branch| match branch {
+            Branch::Left(hash) => json!({"Left": hash}),
+            Branch::Right(hash) => json!({"Right": hash}),
+        }).collect::<Vec<_>>(),
+        "index": leaf_index,
+    });
+
+    assert_eq!(result_json, proof_json);
+}
+
+#[instrument(skip_all)]
+async fn test_insert_identity(
+    uri: &str,
+    client: &Client<HttpConnector>,
+    identity_commitment: &str,
+) {
+    let body = construct_insert_identity_body(identity_commitment);
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri.to_owned() + "/insertIdentity")
+        .header("Content-Type", "application/json")
+        .body(body)
+        .expect("Failed to create insert identity hyper::Body");
+
+    let mut response = client
+        .request(req)
+        .await
+        .expect("Failed to execute request.");
+    let bytes = hyper::body::to_bytes(response.body_mut())
+        .await
+        .expect("Failed to convert response body to bytes");
+    let result = String::from_utf8(bytes.into_iter().collect())
+        .expect("Could not parse response bytes to utf-8");
+    if !response.status().is_success() {
+        panic!("Failed to insert identity: {result}");
+    }
+
+    serde_json::from_str::<InsertIdentityResponse>(&result)
+        .expect("Failed to parse insert response");
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InsertIdentityResponse {
+    identity_index: usize,
+}
+
+fn construct_inclusion_proof_body(identity_commitment: &Hash) -> Body {
+    Body::from(
+        json!({
+            "groupId": 1,
+            "identityCommitment": identity_commitment,
+        })
+        .to_string(),
+    )
+}
+
+fn construct_insert_identity_body(identity_commitment: &str) -> Body {
+    Body::from(
+        json!({
+            "groupId": 1,
+            "identityCommitment": identity_commitment,
+
+        })
+        .to_string(),
+    )
+}
+
+#[instrument(skip_all)]
+async fn spawn_app(options: Options) -> AnyhowResult<(JoinHandle<()>, SocketAddr)> {
+    let app = App::new(options.app).await.expect("Failed to create App");
+
+    let ip: IpAddr = match options.server.server.host() {
+        Some(Host::Ipv4(ip)) => ip.into(),
+        Some(Host::Ipv6(ip)) => ip.into(),
+        Some(_) => bail!("Cannot bind {}", options.server.server),
+        None => Ipv4Addr::LOCALHOST.into(),
+    };
+    let port = options.server.server.port().unwrap_or(9998);
+    let addr = SocketAddr::new(ip, port);
+    let listener = TcpListener::bind(addr).expect("Failed to bind random port");
+    let local_addr = listener.local_addr()?;
+
+    let app = spawn({
+        async move {
+            info!("App thread starting");
+            server::bind_from_listener(Arc::new(app), options.server.server_config(), listener)
+                .await
+                .expect("Failed to bind address");
+            info!("App thread stopping");
+        }
+    });
+
+    Ok((app, local_addr))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CompiledContract {
+    abi:      Abi,
+    bytecode: String,
+}
+
+fn deserialize_to_bytes(input: String) -> AnyhowResult<Bytes> {
+    if input.len() >= 2 && &input[0..2] == "0x" {
+        let bytes: Vec<u8> = hex::decode(&input[2..])?;
         Ok(bytes.into())
     } else {
         bail!("Expected 0x prefix")
@@ -486,7 +3065,32 @@ fn deserialize_to_bytes(input: String) -> AnyhowResult<Bytes> {
 }
 
 #[instrument(skip_all)]
+/// Spawns a mock chain along with options for an app using it, serving on a
+/// free local port. The chain stops once the returned instance is dropped.
+async fn spawn_mock_chain_with_options() -> (AnvilInstance, Options) {
+    let mut options = Options::try_parse_from([""]).expect("Failed to create options");
+    options.server.server = Url::parse("http://127.0.0.1:0/").expect("Failed to parse URL");
+
+    let (chain, private_key, semaphore_address) = spawn_mock_chain()
+        .await
+        .expect("Failed to spawn ganache chain");
+
+    options.app.ethereum.ethereum_provider =
+        vec![Url::parse(&chain.endpoint()).expect("Failed to parse ganache endpoint")];
+    options.app.contracts.semaphore_address = semaphore_address.into();
+    options.app.ethereum.signing_key = private_key;
+    (chain, options)
+}
+
 async fn spawn_mock_chain() -> AnyhowResult<(AnvilInstance, H256, Address)> {
+    spawn_mock_chain_with_initial_leaf(U256::from(0_u64)).await
+}
+
+/// Spawns a chain like [`spawn_mock_chain`], with a group created with
+/// `initial_leaf` as the value of its empty leaves.
+async fn spawn_mock_chain_with_initial_leaf(
+    initial_leaf: U256,
+) -> AnyhowResult<(AnvilInstance, H256, Address)> {
     let chain = Anvil::new().block_time(2u64).spawn();
     let private_key = H256::from_slice(&chain.keys()[0].to_be_bytes());
 
@@ -566,8 +3170,7 @@ async fn spawn_mock_chain() -> AnyhowResult<(AnvilInstance, H256, Address)> {
 
     // Create a group with id 1
     let group_id = U256::from(1_u64);
-    let depth = 21_u8;
-    let initial_leaf = U256::from(0_u64);
+    let depth = u8::try_from(TREE_DEPTH)?;
     semaphore_contract
         .method::<_, ()>("createGroup", (group_id, depth, initial_leaf))?
         .legacy()